@@ -0,0 +1,45 @@
+//! Compares the two [`ScopeMode`]s on a loop calling a function, which enters a scope on
+//! every iteration and resolves variables from the enclosing ones:
+//!
+//! ```text
+//! cargo bench --bench scope_modes
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use galevm::asm::assemble;
+use galevm::var::ScopeMode;
+use galevm::visit::{TokenProvider, VisitorCore, Vm};
+
+const LOOP: &str = r#"
+    fn num twice(x) {
+        x * 2;
+    }
+    let i = 0;
+    let total = 0;
+    while i < 2000 {
+        total = total + twice(i);
+        i = i + 1;
+    }
+"#;
+
+fn scope_modes(c: &mut Criterion) {
+    let chain = assemble(LOOP).unwrap();
+    let mut group = c.benchmark_group("scope_modes");
+    for mode in [ScopeMode::Shared, ScopeMode::SingleThreaded] {
+        group.bench_function(format!("{:?}", mode), |b| {
+            b.iter_batched(
+                || {
+                    let mut vm = Vm::with_scope_mode(mode);
+                    vm.load_chain(&mut chain.clone());
+                    vm
+                },
+                |mut vm| vm.process(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scope_modes);
+criterion_main!(benches);
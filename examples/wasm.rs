@@ -0,0 +1,129 @@
+//! Binding for running gale scripts in a browser, without any JS glue crates:
+//!
+//! ```text
+//! cargo build --release --example wasm --target wasm32-unknown-unknown
+//! ```
+//!
+//! The page provides `env.gale_host_now` (milliseconds since the unix epoch, like `Date.now`)
+//! and `env.gale_host_random` (a float in `0..1`, like `Math.random`) as imports.
+//! Strings cross the boundary through the linear memory: the page allocates room with
+//! `gale_alloc`, writes UTF-8 source into it and calls `gale_run` or `gale_eval`. Both return
+//! a pointer to a nul terminated result, which stays valid until the next call.
+//!
+//! ```js
+//! const { instance } = await WebAssembly.instantiateStreaming(fetch("wasm.wasm"), {
+//!     env: { gale_host_now: Date.now, gale_host_random: Math.random },
+//! });
+//! ```
+//!
+//! Panics abort on `wasm32-unknown-unknown`, so a failing script traps the instance,
+//! which then has to be instantiated again.
+
+use galevm::asm::assemble;
+use galevm::io::BufferedIo;
+use galevm::template::render_value;
+use galevm::visit::{VisitorCore, Vm};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::sync::Arc;
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn gale_host_now() -> f64;
+    fn gale_host_random() -> f64;
+}
+
+struct Session {
+    vm: Vm,
+    io: Arc<BufferedIo>,
+    result: CString,
+}
+
+thread_local! {
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+}
+
+fn with_session<T>(fun: impl FnOnce(&mut Session) -> T) -> T {
+    SESSION.with(|session| {
+        let mut session = session.borrow_mut();
+        let session = session.get_or_insert_with(|| {
+            #[cfg(target_arch = "wasm32")]
+            {
+                use galevm::platform;
+                use std::time::Duration;
+                platform::set_clock(|| Duration::from_millis(unsafe { gale_host_now() } as u64));
+                platform::set_random(|| (unsafe { gale_host_random() } * u64::MAX as f64) as u64);
+            }
+            let io = Arc::new(BufferedIo::new());
+            let mut vm = Vm::new();
+            vm.set_io(io.clone());
+            Session {
+                vm,
+                io,
+                result: CString::default(),
+            }
+        });
+        fun(session)
+    })
+}
+
+/// Reads a string the page wrote into memory allocated with [`gale_alloc`], freeing the memory
+///
+/// # Safety
+/// `ptr` has to be returned by `gale_alloc(len)`, and must not be used afterwards
+unsafe fn take_input(ptr: *mut u8, len: usize) -> String {
+    let bytes = Vec::from_raw_parts(ptr, len, len);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Stores the result of a call, so the page can read it until the next one
+fn finish(session: &mut Session, result: anyhow::Result<String>) -> *const u8 {
+    let text = result.unwrap_or_else(|err| format!("error: {}", err));
+    session.result = CString::new(text.replace('\0', "")).unwrap_or_default();
+    session.result.as_ptr() as *const u8
+}
+
+/// Allocates `len` bytes for the page to write a string into
+#[no_mangle]
+pub extern "C" fn gale_alloc(len: usize) -> *mut u8 {
+    let mut buf = vec![0u8; len].into_boxed_slice();
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+/// Runs a script, returning everything it printed. Declarations persist between runs
+///
+/// # Safety
+/// See [`take_input`]
+#[no_mangle]
+pub unsafe extern "C" fn gale_run(ptr: *mut u8, len: usize) -> *const u8 {
+    let src = take_input(ptr, len);
+    with_session(|session| {
+        let result = assemble(&src).map(|mut chain| {
+            session.vm.load_chain(&mut chain);
+            session.vm.process();
+            session.io.take_out()
+        });
+        finish(session, result)
+    })
+}
+
+/// Evaluates an expression against the declarations of the previous runs, see [`Vm::eval`]
+///
+/// # Safety
+/// See [`take_input`]
+#[no_mangle]
+pub unsafe extern "C" fn gale_eval(ptr: *mut u8, len: usize) -> *const u8 {
+    let src = take_input(ptr, len);
+    with_session(|session| {
+        let result = session.vm.eval(&src).map(|value| render_value(&value));
+        finish(session, result)
+    })
+}
+
+/// Drops the declarations of every previous run
+#[no_mangle]
+pub extern "C" fn gale_reset() {
+    SESSION.with(|session| session.borrow_mut().take());
+}
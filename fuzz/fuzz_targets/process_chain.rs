@@ -0,0 +1,18 @@
+//! Loads arbitrary token chains into a Vm without any std features, so programs
+//! can not exit the process. Errors in programs are still reported by panicking,
+//! so until they are turned into results every such panic shows up as a crash.
+
+#![no_main]
+
+use galevm::tks::TokenChain;
+use galevm::visit::{VisitorCore, Vm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|chain: TokenChain| {
+    let mut vm = Vm::new();
+    vm.set_max_call_depth(64);
+    // the static checker has to cope with any chain
+    let _ = vm.check(&chain);
+    vm.load_chain(&mut chain.clone());
+    vm.process();
+});
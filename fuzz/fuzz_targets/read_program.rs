@@ -0,0 +1,31 @@
+//! Reads malformed compiled programs, which has to fail with an error instead of panicking.
+//! Programs the validator accepts have to be readable
+
+#![no_main]
+
+use galevm::program::read_program;
+use galevm::spec::validate_program;
+use galevm::tks::TokenChain;
+use galevm::vm::Transmute;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let validated = validate_program(data);
+    let read = read_program(&mut &data[..]);
+    if let Ok(info) = validated {
+        // encrypted payloads are only read with a key
+        assert!(info.payload.is_none() || read.is_ok());
+    }
+    if let Ok(mut chain) = TokenChain::read_from(&mut &data[..]) {
+        // maps may be read in any order, so only a chain written back has to stay the same
+        let mut written = vec![];
+        chain.write(&mut written).unwrap();
+        assert_eq!(written.len(), chain.size());
+        let mut again = vec![];
+        TokenChain::read_from(&mut &written[..])
+            .unwrap()
+            .write(&mut again)
+            .unwrap();
+        assert_eq!(written, again);
+    }
+});
@@ -0,0 +1,1378 @@
+use crate::span::{SourceMap, Span};
+use crate::tks::{BinaryOp, Decimal, Expression, Keyword, Literal, Token, TokenChain, UnaryOp};
+use anyhow::bail;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+pub mod macros;
+
+/// Parses the pseudocode produced by [`disassemble`](crate::dasm::disassemble) back into a token chain.
+///
+/// Literal-only arrays are always assembled as [`Literal::Array`], and calls on capitalized
+/// receivers (`Point.new()`) are assembled as static calls.
+///
+/// Lines starting with `#` are preprocessor directives, see [`AssembleOptions`], and
+/// `macro name(params) { ... }` declares a macro, see the [`macros`] module. `//` and `/* */`
+/// comments are dropped, unless they are kept with [`AssembleOptions::comments`].
+///
+/// Besides escaped strings, raw strings `r"..."` and `r#"..."#` and `"""` text blocks can be
+/// used for text with backslashes, quotes or multiple lines, like JSON or SQL.
+///
+/// Ranges `a..b` and `a..=b` are assembled as instances of the `Range` structure, which
+/// `std::range` declares. Ranges without a start begin at `0`, and ranges without an end,
+/// like `value[2..]`, end at the largest `num`.
+pub fn assemble(src: &str) -> anyhow::Result<TokenChain> {
+    assemble_spanned(src).map(|(chain, _)| chain)
+}
+
+/// Same as [`assemble`], but also returns the source position of each top level token
+pub fn assemble_spanned(src: &str) -> anyhow::Result<(TokenChain, SourceMap)> {
+    assemble_spanned_with(src, &AssembleOptions::default())
+}
+
+/// Same as [`assemble`], with names defined for the preprocessor
+pub fn assemble_with(src: &str, options: &AssembleOptions) -> anyhow::Result<TokenChain> {
+    assemble_spanned_with(src, options).map(|(chain, _)| chain)
+}
+
+/// Same as [`assemble_spanned`], with names defined for the preprocessor
+pub fn assemble_spanned_with(
+    src: &str,
+    options: &AssembleOptions,
+) -> anyhow::Result<(TokenChain, SourceMap)> {
+    let (comments, lexemes) = _preprocess(lex(src, options.comments)?, options)?
+        .into_iter()
+        .partition::<Vec<_>, _>(|(lx, _)| matches!(lx, Lexeme::Comment(_)));
+    let mut asm = Assembler {
+        lx: macros::_expand_macros(lexemes)?,
+        pos: 0,
+        out: TokenChain::new(),
+        spans: SourceMap::new(),
+        comments: comments
+            .into_iter()
+            .filter_map(|(lx, span)| match lx {
+                Lexeme::Comment(text) => Some((text, span)),
+                _ => None,
+            })
+            .collect(),
+    };
+    asm.statements(false)?;
+    asm.comments_before(None);
+    Ok((asm.out, asm.spans))
+}
+
+/// Whether the source has preprocessor directives or macro declarations, which are replaced
+/// when it is assembled
+pub(crate) fn _has_expansions(src: &str) -> anyhow::Result<bool> {
+    let lexemes = lex(src, false)?;
+    Ok(lexemes.iter().enumerate().any(|(i, (lx, _))| match lx {
+        Lexeme::Directive(_) => true,
+        Lexeme::Word(word) => {
+            word == "macro"
+                && matches!(lexemes.get(i + 1), Some((Lexeme::Word(_), _)))
+                && matches!(lexemes.get(i + 2), Some((Lexeme::Punct("("), _)))
+        }
+        _ => false,
+    }))
+}
+
+/// How the source is laid out where the token chain does not say, used to format it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SourceLayout {
+    /// Comments in the order they are written, and whether code precedes them on their line
+    pub(crate) comments: VecDeque<(String, bool)>,
+    /// Forms of the strings written with each value, in the order they are written
+    pub(crate) strings: HashMap<String, VecDeque<StrForm>>,
+}
+
+pub(crate) fn _source_layout(src: &str) -> anyhow::Result<SourceLayout> {
+    let lines: Vec<Vec<char>> = src.split('\n').map(|it| it.chars().collect()).collect();
+    let mut layout = SourceLayout::default();
+    for (lx, span) in lex(src, true)? {
+        match lx {
+            Lexeme::Comment(text) => {
+                let before = &lines[span.line as usize - 1][..span.col as usize - 1];
+                let trailing = before.iter().any(|it| !it.is_whitespace());
+                layout.comments.push_back((text, trailing));
+            }
+            Lexeme::Str(value, form) => layout.strings.entry(value).or_default().push_back(form),
+            _ => {}
+        }
+    }
+    Ok(layout)
+}
+
+/// Builds a [`TokenChain`] from pseudocode written inline, instead of listing its tokens:
+///
+/// ```ignore
+/// let mut chain = gale_chain! {
+///     fn str greet(name) { return "Hello, " + name; }
+///     let greeting = greet("world");
+/// };
+/// vm.load_chain(&mut chain);
+/// ```
+///
+/// The pseudocode has to consist of valid Rust tokens, so comments are dropped and
+/// preprocessor directives, which need their own lines, can not be used. The chain is
+/// assembled with [`assemble`] when the macro is evaluated, panicking with the error
+/// if the pseudocode is invalid.
+#[macro_export]
+macro_rules! gale_chain {
+    ($($src:tt)*) => {
+        $crate::asm::assemble(stringify!($($src)*))
+            .unwrap_or_else(|err| panic!("Invalid gale_chain! pseudocode: {}", err))
+    };
+}
+
+/// Names defined before the source is preprocessed, like debug flags passed from the CLI.
+///
+/// The preprocessor handles lines starting with `#`:
+///
+/// ```text
+/// #define NAME value     replaces every following NAME word with the value
+/// #define NAME           defines NAME as a flag, which is replaced with nothing
+/// #undef NAME            forgets NAME
+/// #if NAME / #if !NAME   keeps the following lines only if NAME is (not) defined
+/// #elif NAME / #else     alternatives of the last #if
+/// #endif                 ends the last #if
+/// ```
+///
+/// Values are lexed like any other source, and names in them are replaced with their values
+/// at the point of the definition
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssembleOptions {
+    /// Defined names with the source they are replaced with, which is empty for flags
+    pub defines: BTreeMap<String, String>,
+    /// Whether `//` and `/* */` comments are kept as [`Token::Comment`] instead of being
+    /// dropped. Comments inside of a statement are moved after it, since the Vm only
+    /// expects them between statements
+    pub comments: bool,
+}
+
+impl AssembleOptions {
+    pub fn with_define<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_flag<N: Into<String>>(self, name: N) -> Self {
+        self.with_define(name, "")
+    }
+
+    pub fn with_comments(mut self) -> Self {
+        self.comments = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Word(String),
+    // sign is applied by the parser, so that `-9223372036854775808` can be written
+    Number(u64),
+    Float(f64),
+    Decimal(Decimal),
+    Str(String, StrForm),
+    Char(char),
+    Punct(&'static str),
+    /// Text of a line starting with `#`, after the `#`
+    Directive(String),
+    /// Whole text of a comment, only lexed when comments are kept
+    Comment(String),
+}
+
+/// How a string literal is written
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum StrForm {
+    /// `"..."` with escapes
+    Quoted,
+    /// `r"..."`, delimited by this many `#`s
+    Raw(usize),
+    /// `"""` text block
+    TextBlock,
+}
+
+/// Longer punctuation goes first, so it is matched before its prefixes
+const PUNCTS: &[&str] = &[
+    "::", "=>", "==", "!=", "&&", "||", "<<", ">>", "..=", "..", "{", "}", "(", ")", "[", "]", ";",
+    ",", ".", "=", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "!", "~", "@", "?", ":",
+];
+
+/// Lexes a numeric literal starting at `start`, returning it with the position after it.
+///
+/// Supports `0x`, `0o` and `0b` prefixes, `_` separators, floats with exponents and decimals
+/// with a `d` suffix, like `12.50d`.
+fn _lex_number(chars: &[char], start: usize, line: u32) -> anyhow::Result<(Lexeme, usize)> {
+    let mut i = start;
+    let radix = match (chars[i], chars.get(i + 1)) {
+        ('0', Some('x' | 'X')) => 16,
+        ('0', Some('o' | 'O')) => 8,
+        ('0', Some('b' | 'B')) => 2,
+        _ => 10,
+    };
+    let mut float = false;
+    if radix != 10 {
+        i += 2;
+        while i < chars.len() && (chars[i].is_digit(radix) || chars[i] == '_') {
+            i += 1;
+        }
+    } else {
+        while i < chars.len() {
+            match chars[i] {
+                '0'..='9' | '_' => {}
+                '.' if !float
+                    && chars
+                        .get(i + 1)
+                        .map(|it| it.is_ascii_digit())
+                        .unwrap_or(false) =>
+                {
+                    float = true
+                }
+                'e' | 'E' => {
+                    // the exponent needs digits, optionally preceded by a sign
+                    let sign = matches!(chars.get(i + 1), Some('-' | '+')) as usize;
+                    if !chars
+                        .get(i + 1 + sign)
+                        .map(|it| it.is_ascii_digit())
+                        .unwrap_or(false)
+                    {
+                        break;
+                    }
+                    float = true;
+                    i += sign;
+                }
+                _ => break,
+            }
+            i += 1;
+        }
+    }
+
+    let text: String = chars[start..i].iter().collect();
+    let word = |at: usize| at < chars.len() && (chars[at].is_alphanumeric() || chars[at] == '_');
+    if radix == 10 && chars.get(i) == Some(&'d') && !word(i + 1) {
+        return match text.parse() {
+            Ok(decimal) if !text.contains(['e', 'E']) => Ok((Lexeme::Decimal(decimal), i + 1)),
+            _ => bail!("Invalid decimal literal {}d at line {}!", text, line),
+        };
+    }
+    if word(i) {
+        bail!(
+            "Invalid number literal {}{} at line {}!",
+            text,
+            chars[i],
+            line
+        )
+    }
+    let digits: String = text
+        .chars()
+        .skip(if radix == 10 { 0 } else { 2 })
+        .filter(|it| *it != '_')
+        .collect();
+    let lexeme = if float {
+        digits.parse().ok().map(Lexeme::Float)
+    } else {
+        u64::from_str_radix(&digits, radix).ok().map(Lexeme::Number)
+    };
+    match lexeme {
+        Some(lexeme) => Ok((lexeme, i)),
+        None => bail!("Invalid number literal {} at line {}!", text, line),
+    }
+}
+
+/// Lexes a raw string `r"..."`, which can be delimited by `#`s like `r#"..."#` to contain
+/// quotes, starting at the first delimiter. Returns the lexeme and the position after it
+fn _lex_raw(chars: &[char], start: usize, line: u32) -> anyhow::Result<(Lexeme, usize)> {
+    let hashes = chars[start..].iter().take_while(|it| **it == '#').count();
+    if chars.get(start + hashes) != Some(&'"') {
+        bail!(
+            "Expected a quote after r{} at line {}!",
+            "#".repeat(hashes),
+            line
+        )
+    }
+    let mut closing = vec!['"'];
+    closing.extend(std::iter::repeat_n('#', hashes));
+    let from = start + hashes + 1;
+    match chars[from..]
+        .windows(closing.len())
+        .position(|it| it == closing.as_slice())
+    {
+        Some(len) => Ok((
+            Lexeme::Str(chars[from..from + len].iter().collect(), StrForm::Raw(hashes)),
+            from + len + closing.len(),
+        )),
+        None => bail!("Unterminated raw string at line {}!", line),
+    }
+}
+
+/// Lexes a `"""` text block starting after its opening quotes, returning the lexeme and the
+/// position after the closing quotes. Its content is raw, without the newline right after the
+/// opening quotes. If the closing quotes are on their own line, that line is dropped too,
+/// and its indentation is removed from every line, so blocks can be indented with the code
+fn _lex_text_block(chars: &[char], start: usize, line: u32) -> anyhow::Result<(Lexeme, usize)> {
+    let len = match chars[start..]
+        .windows(3)
+        .position(|it| it == ['"', '"', '"'])
+    {
+        Some(len) => len,
+        None => bail!("Unterminated text block at line {}!", line),
+    };
+    let text: String = chars[start..start + len].iter().collect();
+    let text = text.strip_prefix('\n').unwrap_or(&text);
+    let value = match text.rsplit_once('\n') {
+        Some((body, last)) if last.chars().all(|it| it == ' ' || it == '\t') => body
+            .split('\n')
+            .map(|it| {
+                let indent = it
+                    .chars()
+                    .take(last.chars().count())
+                    .take_while(|it| *it == ' ' || *it == '\t')
+                    .count();
+                it.chars().skip(indent).collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => text.to_string(),
+    };
+    Ok((Lexeme::Str(value, StrForm::TextBlock), start + len + 3))
+}
+
+/// Lexes the source, dropping comments unless `comments` is set
+fn lex(src: &str, comments: bool) -> anyhow::Result<Vec<(Lexeme, Span)>> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut out = vec![];
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let lexeme = if c == '\n' {
+            line += 1;
+            i += 1;
+            line_start = i;
+            continue;
+        } else if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '#' && chars[line_start..i].iter().all(|it| it.is_whitespace()) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            Lexeme::Directive(chars[start + 1..i].iter().collect())
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            Lexeme::Comment(chars[start..i].iter().collect())
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            // block comments do not nest
+            match chars[i + 2..].windows(2).position(|it| it == ['*', '/']) {
+                Some(end) => i += end + 4,
+                None => bail!("Unclosed block comment at line {}!", line),
+            }
+            Lexeme::Comment(chars[start..i].iter().collect())
+        } else if c == 'r' && matches!(chars.get(i + 1), Some('"' | '#')) {
+            let (lexeme, end) = _lex_raw(&chars, i + 1, line)?;
+            i = end;
+            lexeme
+        } else if chars[i..].starts_with(&['"', '"', '"']) {
+            let (lexeme, end) = _lex_text_block(&chars, i + 3, line)?;
+            i = end;
+            lexeme
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Lexeme::Word(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() {
+            let (lexeme, end) = _lex_number(&chars, i, line)?;
+            i = end;
+            lexeme
+        } else if c == '"' || c == '\'' {
+            let (value, end) = _unescape(&chars, i + 1, Some(c), line)?;
+            i = end + 1;
+            if c == '"' {
+                Lexeme::Str(value, StrForm::Quoted)
+            } else {
+                let mut iter = value.chars();
+                match (iter.next(), iter.next()) {
+                    (Some(ch), None) => Lexeme::Char(ch),
+                    _ => bail!("Invalid character literal at line {}!", line),
+                }
+            }
+        } else {
+            let punct = PUNCTS.iter().find(|p| {
+                p.chars()
+                    .enumerate()
+                    .all(|(off, pc)| chars.get(i + off) == Some(&pc))
+            });
+            match punct {
+                Some(p) => {
+                    i += p.len();
+                    Lexeme::Punct(p)
+                }
+                None => bail!("Unexpected character {:?} at line {}!", c, line),
+            }
+        };
+        let span = Span::new(line, (start - line_start + 1) as u32, (i - start) as u32);
+        if comments || !matches!(lexeme, Lexeme::Comment(_)) {
+            out.push((lexeme, span));
+        }
+        // strings and block comments may span multiple lines
+        for (off, ch) in chars[start..i].iter().enumerate() {
+            if *ch == '\n' {
+                line += 1;
+                line_start = start + off + 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// State of an `#if` the preprocessor is in
+struct Conditional {
+    /// Whether the lines of the current branch are kept
+    active: bool,
+    /// Whether one of the branches was already kept
+    taken: bool,
+    /// Whether the enclosing lines are kept
+    outer: bool,
+    seen_else: bool,
+    line: u32,
+}
+
+/// Applies the directives to the lexemes, removing them along with the lines of
+/// conditional branches that are not taken
+fn _preprocess(
+    lexemes: Vec<(Lexeme, Span)>,
+    options: &AssembleOptions,
+) -> anyhow::Result<Vec<(Lexeme, Span)>> {
+    let mut defines = HashMap::new();
+    for (name, value) in &options.defines {
+        let value = match lex(value, false) {
+            Ok(value) => value.into_iter().map(|(lx, _)| lx).collect::<Vec<_>>(),
+            Err(err) => bail!("Invalid definition of {}: {}", name, err),
+        };
+        if value.iter().any(|it| matches!(it, Lexeme::Directive(_))) {
+            bail!(
+                "Invalid definition of {}: it can not contain directives!",
+                name
+            )
+        }
+        defines.insert(name.clone(), value);
+    }
+
+    let mut out = Vec::with_capacity(lexemes.len());
+    let mut stack: Vec<Conditional> = vec![];
+    for (lexeme, span) in lexemes {
+        let active = stack.last().is_none_or(|it| it.active);
+        let text = match lexeme {
+            Lexeme::Directive(text) => text,
+            Lexeme::Word(word) if active && defines.contains_key(&word) => {
+                out.extend(defines[&word].iter().map(|lx| (lx.clone(), span)));
+                continue;
+            }
+            other => {
+                if active {
+                    out.push((other, span));
+                }
+                continue;
+            }
+        };
+        let line = span.line;
+        let text = text.trim();
+        let (directive, rest) = text.split_at(
+            text.find(|it: char| !it.is_alphanumeric() && it != '_')
+                .unwrap_or(text.len()),
+        );
+        let rest = rest.trim();
+        match directive {
+            "define" | "undef" if !active => {}
+            "define" => {
+                let (name, value) = rest.split_at(
+                    rest.find(|it: char| !it.is_alphanumeric() && it != '_')
+                        .unwrap_or(rest.len()),
+                );
+                if name.is_empty() {
+                    bail!("Expected a name after #define at line {}!", line)
+                }
+                let value = match lex(value, false) {
+                    Ok(value) => value,
+                    Err(err) => bail!("Invalid definition of {} at line {}: {}", name, line, err),
+                };
+                let mut expanded = vec![];
+                for (lx, _) in value {
+                    match lx {
+                        Lexeme::Word(word) if defines.contains_key(&word) => {
+                            expanded.extend(defines[&word].iter().cloned())
+                        }
+                        Lexeme::Directive(_) => bail!(
+                            "Invalid definition of {} at line {}: it can not contain directives!",
+                            name,
+                            line
+                        ),
+                        other => expanded.push(other),
+                    }
+                }
+                defines.insert(name.to_string(), expanded);
+            }
+            "undef" => {
+                defines.remove(rest);
+            }
+            "if" => {
+                let taken = active && _condition(rest, &defines, line)?;
+                stack.push(Conditional {
+                    active: taken,
+                    taken,
+                    outer: active,
+                    seen_else: false,
+                    line,
+                })
+            }
+            "elif" | "else" | "endif" => {
+                let top = match stack.last_mut() {
+                    Some(top) if !top.seen_else || directive == "endif" => top,
+                    _ => bail!("Unexpected #{} at line {}!", directive, line),
+                };
+                match directive {
+                    "endif" => {
+                        stack.pop();
+                    }
+                    "else" => {
+                        top.active = top.outer && !top.taken;
+                        top.taken = true;
+                        top.seen_else = true;
+                    }
+                    _ => {
+                        top.active = top.outer && !top.taken && _condition(rest, &defines, line)?;
+                        top.taken |= top.active;
+                    }
+                }
+            }
+            other => bail!(
+                "Unknown preprocessor directive #{} at line {}!",
+                other,
+                line
+            ),
+        }
+    }
+    match stack.last() {
+        Some(open) => bail!("Unclosed #if at line {}!", open.line),
+        None => Ok(out),
+    }
+}
+
+/// Evaluates the condition of an `#if` or `#elif`, which is a name optionally preceded by `!`
+fn _condition(
+    text: &str,
+    defines: &HashMap<String, Vec<Lexeme>>,
+    line: u32,
+) -> anyhow::Result<bool> {
+    let (negated, name) = match text.strip_prefix('!') {
+        Some(name) => (true, name.trim()),
+        None => (false, text),
+    };
+    if name.is_empty() || !name.chars().all(|it| it.is_alphanumeric() || it == '_') {
+        bail!("Expected a name as the condition at line {}!", line)
+    }
+    Ok(defines.contains_key(name) != negated)
+}
+
+/// Reads an escaped string until the `quote` character, or until the end without a quote,
+/// returning the value and the quote position
+fn _unescape(
+    chars: &[char],
+    mut i: usize,
+    quote: Option<char>,
+    line: u32,
+) -> anyhow::Result<(String, usize)> {
+    let mut value = String::new();
+    loop {
+        match chars.get(i) {
+            None if quote.is_none() => return Ok((value, i)),
+            None => bail!("Unterminated literal at line {}!", line),
+            Some(c) if Some(*c) == quote => return Ok((value, i)),
+            Some('\\') => {
+                i += 1;
+                value.push(match chars.get(i) {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(c @ ('\\' | '\'' | '"')) => *c,
+                    Some('x') => {
+                        let hex: String = chars.iter().skip(i + 1).take(2).collect();
+                        i += 2;
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(code) if hex.len() == 2 && code <= 0x7F => code as char,
+                            _ => bail!("Invalid ASCII escape \\x{} at line {}!", hex, line),
+                        }
+                    }
+                    Some('u') => {
+                        if chars.get(i + 1) != Some(&'{') {
+                            bail!("Expected {{ after unicode escape at line {}!", line)
+                        }
+                        let end = match chars[i..].iter().position(|c| *c == '}') {
+                            Some(end) => i + end,
+                            None => bail!("Unterminated unicode escape at line {}!", line),
+                        };
+                        let hex: String = chars[i + 2..end].iter().collect();
+                        i = end;
+                        let code = match u32::from_str_radix(&hex, 16) {
+                            Ok(code) if (1..=6).contains(&hex.len()) => code,
+                            _ => bail!("Invalid unicode escape \\u{{{}}} at line {}!", hex, line),
+                        };
+                        match char::from_u32(code) {
+                            Some(c) => c,
+                            None => {
+                                bail!("Invalid unicode escape \\u{{{}}} at line {}!", hex, line)
+                            }
+                        }
+                    }
+                    Some(c) => bail!("Unknown escape \\{} at line {}!", c, line),
+                    None => bail!("Unterminated literal at line {}!", line),
+                });
+            }
+            Some(c) => value.push(*c),
+        }
+        i += 1;
+    }
+}
+
+/// Replaces the escape sequences of string literals, like `\n` or `\u{1F600}`, with the
+/// characters they stand for
+pub fn unescape(text: &str) -> anyhow::Result<String> {
+    let chars: Vec<char> = text.chars().collect();
+    _unescape(&chars, 0, None, 1).map(|(value, _)| value)
+}
+
+/// Escapes the value so it can be written between the quotes of a string literal, which
+/// [`unescape`] reverses
+pub fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Precedence of `cond ? then : else`, which sits between assignment and ranges
+pub(crate) const TERNARY_PREC: u8 = 1;
+
+/// Precedence of ranges `a..b` and `a..=b`, which sits between the ternary and `||`
+pub(crate) const RANGE_PREC: u8 = 2;
+
+fn _binary_op(punct: &str) -> Option<(BinaryOp, u8)> {
+    Some(match punct {
+        "=" => (BinaryOp::Assign, 0),
+        "||" => (BinaryOp::Or, 3),
+        "&&" => (BinaryOp::And, 4),
+        "==" => (BinaryOp::Eq, 5),
+        "!=" => (BinaryOp::Neq, 5),
+        "<" => (BinaryOp::Lt, 6),
+        ">" => (BinaryOp::Gt, 6),
+        "|" => (BinaryOp::BitOr, 7),
+        "^" => (BinaryOp::BitXor, 8),
+        "&" => (BinaryOp::BitAnd, 9),
+        "<<" => (BinaryOp::BitLsh, 10),
+        ">>" => (BinaryOp::BitRsh, 10),
+        "+" => (BinaryOp::Add, 11),
+        "-" => (BinaryOp::Sub, 11),
+        "*" => (BinaryOp::Mul, 12),
+        "/" => (BinaryOp::Div, 12),
+        "%" => (BinaryOp::Mod, 12),
+        _ => return None,
+    })
+}
+
+/// Precedence the operator is parsed with, higher binds tighter
+pub(crate) fn _precedence(op: BinaryOp) -> u8 {
+    _binary_op(&op.to_string()).map_or(0, |(_, prec)| prec)
+}
+
+/// Ranges are instances of the `Range` structure of `std::range`, `a..b` is the same as
+/// `Range { start = a, end = b, inclusive = false }`
+fn _range(start: Token, end: Token, inclusive: bool) -> Token {
+    let field = |name: &str, value: Token| {
+        Token::Expression(Box::new(Expression::BinaryOp(
+            BinaryOp::Assign,
+            Token::Literal(Literal::Ident(name.to_string())),
+            value,
+        )))
+    };
+    Token::Expression(Box::new(Expression::Instantiate(
+        "Range".to_string(),
+        vec![
+            field("start", start),
+            field("end", end),
+            field("inclusive", Token::Literal(Literal::Bool(inclusive))),
+        ],
+    )))
+}
+
+fn _keyword(word: &str) -> Option<Keyword> {
+    Some(match word {
+        "export" => Keyword::Export,
+        "import" => Keyword::Import,
+        "let" => Keyword::Let,
+        "const" => Keyword::Const,
+        "fn" => Keyword::Function,
+        "return" => Keyword::Return,
+        "namespace" => Keyword::Namespace,
+        "test" => Keyword::Test,
+        "enum" => Keyword::Enum,
+        "static" => Keyword::Static,
+        "pub" => Keyword::Pub,
+        "priv" => Keyword::Priv,
+        "init" => Keyword::Init,
+        _ => return None,
+    })
+}
+
+struct Assembler {
+    lx: Vec<(Lexeme, Span)>,
+    pos: usize,
+    out: TokenChain,
+    spans: SourceMap,
+    /// Kept comments that were not emitted yet, in the order of the source
+    comments: VecDeque<(String, Span)>,
+}
+
+impl Assembler {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lx.get(self.pos).map(|(lx, _)| lx)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Lexeme> {
+        self.lx.get(self.pos + offset).map(|(lx, _)| lx)
+    }
+
+    fn line(&self) -> u32 {
+        self.lx
+            .get(self.pos)
+            .or_else(|| self.lx.last())
+            .map(|(_, span)| span.line)
+            .unwrap_or(1)
+    }
+
+    /// Pushes a token, spanning lexemes from `from` up to the last consumed one
+    fn emit(&mut self, tk: Token, from: usize) {
+        self.emit_at(tk, from, self.pos - 1)
+    }
+
+    fn emit_at(&mut self, tk: Token, from: usize, to: usize) {
+        let first = self.lx[from].1;
+        let last = self.lx[to].1;
+        // lexemes of macro bodies carry the span of the invocation, which starts before its arguments
+        let len = match (last.col + last.len).checked_sub(first.col) {
+            Some(len) if first.line == last.line && len > 0 => len,
+            _ => first.len,
+        };
+        self.spans
+            .insert(self.out.len(), Span::new(first.line, first.col, len));
+        self.out.push(tk);
+    }
+
+    fn next(&mut self) -> anyhow::Result<Lexeme> {
+        match self.lx.get(self.pos) {
+            Some((lx, _)) => {
+                self.pos += 1;
+                Ok(lx.to_owned())
+            }
+            None => bail!("Unexpected end of input!"),
+        }
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Lexeme::Punct(p)) if *p == punct)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.is_punct(punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> anyhow::Result<()> {
+        if !self.eat(punct) {
+            bail!(
+                "Expected {:?} at line {}, got {:?}!",
+                punct,
+                self.line(),
+                self.peek()
+            )
+        }
+        Ok(())
+    }
+
+    fn word(&mut self) -> anyhow::Result<String> {
+        match self.next()? {
+            Lexeme::Word(word) => Ok(word),
+            other => bail!("Expected a name at line {}, got {:?}!", self.line(), other),
+        }
+    }
+
+    fn path(&mut self) -> anyhow::Result<String> {
+        let mut path = self.word()?;
+        while self.eat("::") {
+            path.push_str("::");
+            path.push_str(&self.word()?);
+        }
+        Ok(path)
+    }
+
+    /// Emits the kept comments that start before the lexeme at `pos`, or all of them
+    fn comments_before(&mut self, pos: Option<usize>) {
+        let until = pos
+            .and_then(|pos| self.lx.get(pos))
+            .map(|(_, span)| (span.line, span.col));
+        while let Some((_, span)) = self.comments.front() {
+            if until.is_some_and(|until| (span.line, span.col) > until) {
+                return;
+            }
+            let (text, span) = self.comments.pop_front().unwrap();
+            self.spans.insert(self.out.len(), span);
+            self.out.push(Token::Comment(text));
+        }
+    }
+
+    fn statements(&mut self, nested: bool) -> anyhow::Result<()> {
+        while self.peek().is_some() {
+            self.comments_before(Some(self.pos));
+            if nested && self.is_punct("}") {
+                return Ok(());
+            }
+            self.statement()?;
+        }
+        if nested {
+            bail!("Unclosed block at line {}!", self.line())
+        }
+        Ok(())
+    }
+
+    /// Assembles a `{ ... }` block, or a `;` when the block was omitted
+    fn block(&mut self) -> anyhow::Result<()> {
+        if self.eat(";") {
+            return Ok(());
+        }
+        self.expect("{")?;
+        self.emit(Token::LBracket, self.pos - 1);
+        self.statements(true)?;
+        self.expect("}")?;
+        self.emit(Token::RBracket, self.pos - 1);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> anyhow::Result<()> {
+        let start = self.pos;
+        if self.eat("@") {
+            let name = self.word()?;
+            self.expect("(")?;
+            let value = match self.expression(false)? {
+                Token::Literal(lit) => lit,
+                other => bail!("Expected a literal attribute value, got {:?}!", other),
+            };
+            self.expect(")")?;
+            self.emit(Token::Attribute(name, value), start);
+            return Ok(());
+        }
+        if self.is_punct("{") {
+            return self.block();
+        }
+        if self.is_punct("<") && self.peek_at(1) == Some(&Lexeme::Word("end".to_string())) {
+            self.pos += 2;
+            self.expect(">")?;
+            self.emit(Token::End, start);
+            return Ok(());
+        }
+        if let Some(Lexeme::Word(word)) = self.peek().cloned() {
+            // `init` is only a keyword in front of a block, so it stays usable as a name
+            let init = word == "init" && self.peek_at(1) != Some(&Lexeme::Punct("{"));
+            if let Some(kw) = _keyword(&word).filter(|_| !init) {
+                self.pos += 1;
+                return self.keyword(kw);
+            }
+            match word.as_str() {
+                "if" | "elif" | "while" => {
+                    self.pos += 1;
+                    let kind = match word.as_str() {
+                        "if" => Expression::IfStmt,
+                        "elif" => Expression::ElifStmt,
+                        _ => Expression::WhileStmt,
+                    };
+                    self.emit(Token::Expression(Box::new(kind)), start);
+                    let condition_start = self.pos;
+                    let condition = self.expression(true)?;
+                    self.emit(condition, condition_start);
+                    return self.block();
+                }
+                "else" => {
+                    self.pos += 1;
+                    self.emit(Token::Expression(Box::new(Expression::ElseStmt)), start);
+                    return self.block();
+                }
+                "do" => {
+                    self.pos += 1;
+                    self.emit(Token::Expression(Box::new(Expression::DoWhileStmt)), start);
+                    if !self.is_punct("{") {
+                        bail!("Expected a do-while body at line {}!", self.line());
+                    }
+                    self.block()?;
+                    if self.word()? != "while" {
+                        bail!(
+                            "Expected while after do-while body at line {}!",
+                            self.line()
+                        );
+                    }
+                    let condition_start = self.pos;
+                    let condition = self.expression(false)?;
+                    self.emit(condition, condition_start);
+                    return self.expect(";");
+                }
+                "match" => {
+                    self.pos += 1;
+                    self.emit(Token::Expression(Box::new(Expression::MatchStmt)), start);
+                    let subject_start = self.pos;
+                    let subject = self.expression(true)?;
+                    self.emit(subject, subject_start);
+                    self.expect("{")?;
+                    self.emit(Token::LBracket, self.pos - 1);
+                    while !self.eat("}") {
+                        self.match_arm()?;
+                    }
+                    self.emit(Token::RBracket, self.pos - 1);
+                    return Ok(());
+                }
+                _ => {}
+            }
+            match self.peek_at(1) {
+                Some(Lexeme::Punct("{")) if self.is_struct_decl() => {
+                    self.pos += 1;
+                    self.emit(Token::Literal(Literal::Ident(word)), start);
+                    return self.block();
+                }
+                Some(Lexeme::Word(ty)) if word != "true" && word != "false" => {
+                    let ty = ty.to_owned();
+                    self.pos += 2;
+                    self.emit_at(Token::Literal(Literal::Ident(word)), start, start);
+                    self.emit(Token::Literal(Literal::TypeName(ty)), start + 1);
+                    if self.is_default_value() {
+                        let default_start = self.pos;
+                        let default = self.expression(false)?;
+                        self.emit(default, default_start);
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        let expr = self.expression(false)?;
+        self.emit(expr, start);
+        self.eat(";");
+        Ok(())
+    }
+
+    /// Assembles `Enum::Variant(binding) => { ... }`, where both the path
+    /// and the binding can be `_`, and only the last segment of the path is kept
+    fn match_arm(&mut self) -> anyhow::Result<()> {
+        let start = self.pos;
+        let path = self.path()?;
+        let pattern = path.rsplit("::").next().unwrap_or(&path).to_string();
+        self.emit(Token::Literal(Literal::TypeName(pattern)), start);
+        let binding = if self.eat("(") {
+            let binding = self.word()?;
+            self.expect(")")?;
+            binding
+        } else {
+            "_".to_string()
+        };
+        self.emit(Token::Literal(Literal::Ident(binding)), start);
+        self.expect("=>")?;
+        if !self.is_punct("{") {
+            bail!("Expected a match arm body at line {}!", self.line());
+        }
+        self.block()?;
+        self.eat(",");
+        Ok(())
+    }
+
+    /// Struct declarations are blocks that are not followed by a `;`
+    fn is_struct_decl(&self) -> bool {
+        let mut depth = 0;
+        for (offset, (lx, _)) in self.lx[self.pos + 1..].iter().enumerate() {
+            match lx {
+                Lexeme::Punct("{") => depth += 1,
+                Lexeme::Punct("}") => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.peek_at(offset + 2) != Some(&Lexeme::Punct(";"));
+                    }
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+
+    fn is_default_value(&self) -> bool {
+        match self.peek() {
+            Some(
+                Lexeme::Number(_)
+                | Lexeme::Float(_)
+                | Lexeme::Decimal(_)
+                | Lexeme::Str(..)
+                | Lexeme::Char(_),
+            ) => true,
+            Some(Lexeme::Word(word)) => word == "true" || word == "false",
+            Some(Lexeme::Punct(p)) => {
+                *p == "["
+                    || *p == "*"
+                    || (*p == "-"
+                        && matches!(
+                            self.peek_at(1),
+                            Some(Lexeme::Number(_) | Lexeme::Float(_) | Lexeme::Decimal(_))
+                        ))
+            }
+            Some(Lexeme::Directive(_) | Lexeme::Comment(_)) | None => false,
+        }
+    }
+
+    fn keyword(&mut self, kw: Keyword) -> anyhow::Result<()> {
+        if kw != Keyword::Function && self.peek() == Some(&Lexeme::Word("fn".to_string())) {
+            self.pos += 1;
+            self.emit(Token::Keyword(Keyword::Function), self.pos - 1);
+            self.emit_at(Token::Keyword(kw), self.pos - 2, self.pos - 2);
+            return self.function();
+        }
+        self.emit(Token::Keyword(kw), self.pos - 1);
+        let start = self.pos;
+        match kw {
+            Keyword::Let if self.is_punct("[") || self.is_punct("(") || self.is_punct("{") => {
+                self.pattern()?;
+                self.expect("=")?;
+                let start = self.pos;
+                let value = self.expression(false)?;
+                self.emit(value, start);
+            }
+            Keyword::Let | Keyword::Const | Keyword::Static => {
+                let name = self.word()?;
+                self.emit(Token::Literal(Literal::Ident(name)), start);
+                if self.eat(":") {
+                    let ty = self.word()?;
+                    self.emit(Token::Literal(Literal::TypeName(ty)), self.pos - 1);
+                }
+                self.expect("=")?;
+                let start = self.pos;
+                let value = self.expression(false)?;
+                self.emit(value, start);
+            }
+            Keyword::Export | Keyword::Import => {
+                let value = match self.peek() {
+                    Some(Lexeme::Word(_)) => Token::Literal(Literal::Ident(self.path()?)),
+                    _ => self.expression(false)?,
+                };
+                self.emit(value, start);
+            }
+            Keyword::Return => {
+                let value = self.expression(false)?;
+                self.emit(value, start);
+            }
+            Keyword::Function => return self.function(),
+            Keyword::Namespace => {
+                let name = self.word()?;
+                self.emit(Token::Literal(Literal::Ident(name)), start);
+                return self.block();
+            }
+            Keyword::Test => {
+                let name = match self.next()? {
+                    Lexeme::Str(name, _) => name,
+                    other => bail!(
+                        "Expected a test name at line {}, got {:?}!",
+                        self.line(),
+                        other
+                    ),
+                };
+                self.emit(Token::Literal(Literal::String(name)), start);
+                return self.block();
+            }
+            // modifiers of the member that follows
+            Keyword::Pub | Keyword::Priv => return Ok(()),
+            Keyword::Init => return self.block(),
+            Keyword::Enum => {
+                let name = self.word()?;
+                self.emit(Token::Literal(Literal::Ident(name)), start);
+                self.expect("{")?;
+                self.emit(Token::LBracket, self.pos - 1);
+                while !self.eat("}") {
+                    let variant_start = self.pos;
+                    let variant = self.word()?;
+                    self.emit(Token::Literal(Literal::Ident(variant)), variant_start);
+                    let ty = if self.eat("(") {
+                        let ty = self.word()?;
+                        self.expect(")")?;
+                        ty
+                    } else {
+                        "void".to_string()
+                    };
+                    self.emit(Token::Literal(Literal::TypeName(ty)), variant_start);
+                    if !self.is_punct("}") {
+                        self.expect(",")?;
+                    }
+                }
+                self.emit(Token::RBracket, self.pos - 1);
+                return Ok(());
+            }
+        }
+        self.expect(";")
+    }
+
+    /// Assembles a destructuring pattern, `[a, rest..]`, `(a, b)` or `{x, y}`
+    fn pattern(&mut self) -> anyhow::Result<()> {
+        let (opening, closing, tk) = match self.next()? {
+            Lexeme::Punct("[") => ("[", "]", Token::LSquare),
+            Lexeme::Punct("(") => ("(", ")", Token::LParen),
+            _ => ("{", "}", Token::LBracket),
+        };
+        self.emit(tk, self.pos - 1);
+        while !self.eat(closing) {
+            let start = self.pos;
+            let mut name = self.word()?;
+            if opening != "{" && self.eat("..") {
+                name.push_str("..");
+            }
+            self.emit(Token::Literal(Literal::Ident(name)), start);
+            if !self.is_punct(closing) {
+                self.expect(",")?;
+            }
+        }
+        let tk = match closing {
+            "]" => Token::RSquare,
+            ")" => Token::RParen,
+            _ => Token::RBracket,
+        };
+        self.emit(tk, self.pos - 1);
+        Ok(())
+    }
+
+    fn function(&mut self) -> anyhow::Result<()> {
+        let out_ty = self.word()?;
+        self.emit(Token::Literal(Literal::TypeName(out_ty)), self.pos - 1);
+        let name = match self.next()? {
+            Lexeme::Word(name) => Literal::Ident(name),
+            Lexeme::Str(native, _) => Literal::String(native),
+            other => bail!(
+                "Expected a function name at line {}, got {:?}!",
+                self.line(),
+                other
+            ),
+        };
+        self.emit(Token::Literal(name), self.pos - 1);
+        self.expect("(")?;
+        self.emit(Token::LParen, self.pos - 1);
+        while !self.eat(")") {
+            let param = self.word()?;
+            self.emit(Token::Literal(Literal::Ident(param)), self.pos - 1);
+            if !self.is_punct(")") {
+                self.expect(",")?;
+            }
+        }
+        self.emit(Token::RParen, self.pos - 1);
+        self.block()
+    }
+
+    /// Parses a binary expression, `no_struct` disables instantiation, like in `if` conditions
+    fn expression(&mut self, no_struct: bool) -> anyhow::Result<Token> {
+        self.binary(0, no_struct)
+    }
+
+    fn binary(&mut self, min_prec: u8, no_struct: bool) -> anyhow::Result<Token> {
+        let mut lhs = self.primary(no_struct)?;
+        while let Some(Lexeme::Punct(p)) = self.peek() {
+            // ternary binds looser than everything but assignment, and is right associative
+            if *p == "?" && min_prec <= TERNARY_PREC {
+                self.pos += 1;
+                let then = self.binary(TERNARY_PREC, no_struct)?;
+                self.expect(":")?;
+                let otherwise = self.binary(TERNARY_PREC, no_struct)?;
+                lhs = Token::Expression(Box::new(Expression::Ternary(lhs, then, otherwise)));
+                continue;
+            }
+            if (*p == ".." || *p == "..=") && min_prec <= RANGE_PREC {
+                let inclusive = *p == "..=";
+                self.pos += 1;
+                lhs = self.range(lhs, inclusive, no_struct)?;
+                continue;
+            }
+            let (op, prec) = match _binary_op(p) {
+                Some((op, prec)) if prec >= min_prec => (op, prec),
+                _ => break,
+            };
+            self.pos += 1;
+            // assignment is right associative, everything else is left associative
+            let next_prec = if op == BinaryOp::Assign {
+                prec
+            } else {
+                prec + 1
+            };
+            let rhs = self.binary(next_prec, no_struct)?;
+            lhs = Token::Expression(Box::new(Expression::BinaryOp(op, lhs, rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn list(&mut self, closing: &str) -> anyhow::Result<TokenChain> {
+        let mut values = TokenChain::new();
+        while !self.eat(closing) {
+            values.push(self.expression(false)?);
+            if !self.is_punct(closing) {
+                self.expect(",")?;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Parses the end of a range after its `..` or `..=`, ranges followed by a closing
+    /// bracket or a separator have no end, like `value[2..]`
+    fn range(&mut self, start: Token, inclusive: bool, no_struct: bool) -> anyhow::Result<Token> {
+        let open = matches!(
+            self.peek(),
+            None | Some(Lexeme::Punct("]" | ")" | "}" | "," | ";"))
+        );
+        let end = if open && !inclusive {
+            Token::Literal(Literal::Number(i64::MAX))
+        } else {
+            self.binary(RANGE_PREC + 1, no_struct)?
+        };
+        Ok(_range(start, end, inclusive))
+    }
+
+    /// Parses a value followed by any number of chained calls and indices, like `a.b(1)[0].c()`
+    fn primary(&mut self, no_struct: bool) -> anyhow::Result<Token> {
+        let mut value = self.atom(no_struct)?;
+        loop {
+            if self.eat("[") {
+                let index = self.expression(false)?;
+                self.expect("]")?;
+                value = Token::Expression(Box::new(Expression::Index(value, index)));
+            } else if self.is_punct(".")
+                && matches!(self.peek_at(1), Some(Lexeme::Word(_)))
+                && self.peek_at(2) == Some(&Lexeme::Punct("("))
+            {
+                self.pos += 1;
+                let name = self.word()?;
+                self.expect("(")?;
+                let params = self.list(")")?;
+                value = Token::Expression(Box::new(Expression::InvokeChained(value, name, params)));
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn atom(&mut self, no_struct: bool) -> anyhow::Result<Token> {
+        let line = self.line();
+        Ok(match self.next()? {
+            Lexeme::Number(n) => match i64::try_from(n) {
+                Ok(n) => Token::Literal(Literal::Number(n)),
+                Err(_) => bail!("Number {} at line {} does not fit into num!", n, line),
+            },
+            Lexeme::Float(f) => Token::Literal(Literal::Float(f)),
+            Lexeme::Decimal(d) => Token::Literal(Literal::Decimal(d)),
+            Lexeme::Str(s, _) => Token::Literal(Literal::String(s)),
+            Lexeme::Char(c) => Token::Literal(Literal::Char(c)),
+            Lexeme::Punct("-") => match self.next()? {
+                Lexeme::Number(n) => match 0i64.checked_sub_unsigned(n) {
+                    Some(n) => Token::Literal(Literal::Number(n)),
+                    None => bail!("Number -{} at line {} does not fit into num!", n, line),
+                },
+                Lexeme::Float(f) => Token::Literal(Literal::Float(-f)),
+                Lexeme::Decimal(d) => Token::Literal(Literal::Decimal(-d)),
+                _ => {
+                    // negating anything but a number literal is the same as `~`
+                    self.pos -= 1;
+                    let value = self.primary(no_struct)?;
+                    Token::Expression(Box::new(Expression::UnaryOp(UnaryOp::Rev, value)))
+                }
+            },
+            Lexeme::Punct("*") => Token::Literal(Literal::Void),
+            Lexeme::Punct(p @ (".." | "..=")) => {
+                self.range(Token::Literal(Literal::Number(0)), p == "..=", no_struct)?
+            }
+            Lexeme::Punct(p @ ("!" | "~")) => {
+                let op = if p == "!" { UnaryOp::Neg } else { UnaryOp::Rev };
+                let value = self.primary(no_struct)?;
+                Token::Expression(Box::new(Expression::UnaryOp(op, value)))
+            }
+            Lexeme::Punct("(") => {
+                let value = self.expression(false)?;
+                self.expect(")")?;
+                value
+            }
+            Lexeme::Punct("[") => {
+                let values = self.list("]")?;
+                let lits: Vec<Literal> = values
+                    .iter()
+                    .filter_map(|tk| match tk {
+                        Token::Literal(Literal::Ident(_)) => None,
+                        Token::Literal(lit) => Some(lit.to_owned()),
+                        _ => None,
+                    })
+                    .collect();
+                if lits.len() == values.len() {
+                    Token::Literal(Literal::Array(lits))
+                } else {
+                    Token::Expression(Box::new(Expression::Array(values)))
+                }
+            }
+            Lexeme::Word(word) if word == "true" || word == "false" => {
+                Token::Literal(Literal::Bool(word == "true"))
+            }
+            Lexeme::Word(_) => {
+                self.pos -= 1;
+                let path = self.path()?;
+                if self.eat(".") {
+                    let member = self.word()?;
+                    if self.eat("(") {
+                        let params = self.list(")")?;
+                        let receiver_static =
+                            path.contains("::") || path.starts_with(char::is_uppercase);
+                        return Ok(Token::Expression(Box::new(if receiver_static {
+                            Expression::InvokeStatic(format!("{}.{}", path, member), params)
+                        } else {
+                            Expression::InvokeInstance(path, member, params)
+                        })));
+                    }
+                    return Ok(Token::Expression(Box::new(Expression::InstanceAccess(
+                        path, member,
+                    ))));
+                }
+                if self.eat("(") {
+                    let params = self.list(")")?;
+                    Token::Expression(Box::new(Expression::InvokeStatic(path, params)))
+                } else if !no_struct && self.eat("{") {
+                    let fields = self.list("}")?;
+                    Token::Expression(Box::new(Expression::Instantiate(path, fields)))
+                } else if path.contains("::") {
+                    Token::Expression(Box::new(Expression::StaticAccess(
+                        path.split("::").map(|it| it.to_string()).collect(),
+                    )))
+                } else {
+                    Token::Literal(Literal::Ident(path))
+                }
+            }
+            other => bail!("Unexpected {:?} at line {}!", other, line),
+        })
+    }
+}
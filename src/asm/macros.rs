@@ -0,0 +1,243 @@
+//! Token level macros, expanded after preprocessing and before parsing:
+//!
+//! ```text
+//! macro repeat(times, body) {
+//!     let i = 0;
+//!     while i < times { body i = i + 1; }
+//! }
+//! repeat(3) { std::io::println("hi"); }
+//! ```
+//!
+//! An invocation is replaced with the body of the macro, where every parameter is replaced
+//! with the lexemes of its argument. A block following the invocation is passed as its last
+//! argument, without its braces. Names declared with `let` in the body are renamed for every
+//! expansion, so they do not clash with the names around the invocation. Other names are
+//! resolved where the macro is invoked.
+//!
+//! Macros are visible from their declaration up to the end of the enclosing block, and shadow
+//! functions of the same name. Method calls and paths like `a.repeat()` or `lib::repeat()`
+//! are never expanded.
+
+use super::Lexeme;
+use crate::span::Span;
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Limit of macros expanding into invocations of other macros, which stops recursive macros
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Lexeme>,
+    /// Names declared with `let` in the body
+    locals: HashSet<String>,
+}
+
+struct Expander {
+    /// Macros declared in every enclosing block
+    scopes: Vec<HashMap<String, Rc<Macro>>>,
+    out: Vec<(Lexeme, Span)>,
+    expansions: usize,
+}
+
+/// Removes macro declarations from the lexemes, and expands invocations of the macros
+pub(super) fn _expand_macros(lexemes: Vec<(Lexeme, Span)>) -> anyhow::Result<Vec<(Lexeme, Span)>> {
+    let mut expander = Expander {
+        scopes: vec![HashMap::new()],
+        out: Vec::with_capacity(lexemes.len()),
+        expansions: 0,
+    };
+    expander.expand(&lexemes, 0)?;
+    Ok(expander.out)
+}
+
+fn _is_punct(lexeme: Option<&(Lexeme, Span)>, punct: &str) -> bool {
+    matches!(lexeme, Some((Lexeme::Punct(p), _)) if *p == punct)
+}
+
+/// Position after the group opened at `start`, which is a `(`, `[` or `{`
+fn _group_end(lexemes: &[(Lexeme, Span)], start: usize) -> anyhow::Result<usize> {
+    let mut depth = 0usize;
+    for (i, (lexeme, _)) in lexemes.iter().enumerate().skip(start) {
+        match lexeme {
+            Lexeme::Punct("(" | "[" | "{") => depth += 1,
+            Lexeme::Punct(")" | "]" | "}") => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    bail!("Unclosed macro group at line {}!", lexemes[start].1.line)
+}
+
+/// Splits the contents of a group at its top level commas
+fn _split_args(lexemes: &[(Lexeme, Span)]) -> Vec<Vec<(Lexeme, Span)>> {
+    let mut args = vec![];
+    let mut current = vec![];
+    let mut depth = 0usize;
+    for (lexeme, span) in lexemes {
+        match lexeme {
+            Lexeme::Punct("(" | "[" | "{") => depth += 1,
+            Lexeme::Punct(")" | "]" | "}") => depth = depth.saturating_sub(1),
+            Lexeme::Punct(",") if depth == 0 => {
+                args.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push((lexeme.clone(), *span));
+    }
+    if !current.is_empty() || !args.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+impl Expander {
+    fn resolve(&self, name: &str) -> Option<Rc<Macro>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+    }
+
+    fn expand(&mut self, lexemes: &[(Lexeme, Span)], depth: usize) -> anyhow::Result<()> {
+        let mut i = 0;
+        while i < lexemes.len() {
+            let (lexeme, span) = &lexemes[i];
+            match lexeme {
+                Lexeme::Word(word)
+                    if word == "macro"
+                        && matches!(lexemes.get(i + 1), Some((Lexeme::Word(_), _)))
+                        && _is_punct(lexemes.get(i + 2), "(") =>
+                {
+                    i = self.declare(lexemes, i)?;
+                    continue;
+                }
+                Lexeme::Word(word)
+                    if _is_punct(lexemes.get(i + 1), "(")
+                        && !_is_punct(self.out.last(), ".")
+                        && !_is_punct(self.out.last(), "::") =>
+                {
+                    if let Some(mac) = self.resolve(word) {
+                        i = self.invoke(word, &mac, lexemes, i, depth)?;
+                        continue;
+                    }
+                }
+                Lexeme::Punct("{") => self.scopes.push(HashMap::new()),
+                Lexeme::Punct("}") if self.scopes.len() > 1 => {
+                    self.scopes.pop();
+                }
+                _ => {}
+            }
+            self.out.push((lexeme.clone(), *span));
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// Reads the declaration starting at `start`, returning the position after it
+    fn declare(&mut self, lexemes: &[(Lexeme, Span)], start: usize) -> anyhow::Result<usize> {
+        let line = lexemes[start].1.line;
+        let name = match &lexemes[start + 1].0 {
+            Lexeme::Word(name) => name.clone(),
+            _ => unreachable!(),
+        };
+        let params_end = _group_end(lexemes, start + 2)?;
+        let mut params = vec![];
+        for param in _split_args(&lexemes[start + 3..params_end - 1]) {
+            match param.as_slice() {
+                [(Lexeme::Word(param), _)] if !params.contains(param) => params.push(param.clone()),
+                _ => bail!(
+                    "Expected distinct parameter names of macro {} at line {}!",
+                    name,
+                    line
+                ),
+            }
+        }
+        if !_is_punct(lexemes.get(params_end), "{") {
+            bail!("Expected a body of macro {} at line {}!", name, line)
+        }
+        let end = _group_end(lexemes, params_end)?;
+        let body: Vec<Lexeme> = lexemes[params_end + 1..end - 1]
+            .iter()
+            .map(|(lexeme, _)| lexeme.clone())
+            .collect();
+        let locals = body
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Lexeme::Word(kw), Lexeme::Word(local)] if kw == "let" => Some(local.clone()),
+                _ => None,
+            })
+            .filter(|local| !params.contains(local))
+            .collect();
+        self.scopes.last_mut().unwrap().insert(
+            name,
+            Rc::new(Macro {
+                params,
+                body,
+                locals,
+            }),
+        );
+        Ok(end)
+    }
+
+    /// Expands the invocation starting at `start`, returning the position after it
+    fn invoke(
+        &mut self,
+        name: &str,
+        mac: &Macro,
+        lexemes: &[(Lexeme, Span)],
+        start: usize,
+        depth: usize,
+    ) -> anyhow::Result<usize> {
+        let span = lexemes[start].1;
+        if depth >= MAX_MACRO_DEPTH {
+            bail!(
+                "Expansion of macro {} at line {} is nested too deeply!",
+                name,
+                span.line
+            )
+        }
+        let mut end = _group_end(lexemes, start + 1)?;
+        let mut args = _split_args(&lexemes[start + 2..end - 1]);
+        if args.len() + 1 == mac.params.len() && _is_punct(lexemes.get(end), "{") {
+            let block_end = _group_end(lexemes, end)?;
+            args.push(lexemes[end + 1..block_end - 1].to_vec());
+            end = block_end;
+        }
+        if args.len() != mac.params.len() {
+            bail!(
+                "Macro {} expects {} argument(s), got {} at line {}!",
+                name,
+                mac.params.len(),
+                args.len(),
+                span.line
+            )
+        }
+
+        self.expansions += 1;
+        let mut expanded = Vec::with_capacity(mac.body.len());
+        for lexeme in &mac.body {
+            match lexeme {
+                Lexeme::Word(word) => {
+                    if let Some(index) = mac.params.iter().position(|it| it == word) {
+                        expanded.extend(args[index].iter().cloned());
+                    } else if mac.locals.contains(word) {
+                        let local = format!("__{}_{}_{}", name, self.expansions, word);
+                        expanded.push((Lexeme::Word(local), span));
+                    } else {
+                        expanded.push((lexeme.clone(), span));
+                    }
+                }
+                other => expanded.push((other.clone(), span)),
+            }
+        }
+        self.expand(&expanded, depth + 1)?;
+        Ok(end)
+    }
+}
@@ -0,0 +1,648 @@
+//! Structured form of a token chain, for tools that work with whole statements instead of
+//! the flat tokens the Vm reads.
+//!
+//! [`lift`] groups tokens into [`Stmt`] and [`Expr`] trees, with `if`, `elif` and `else`
+//! forming a single [`Stmt::If`], and [`lower`] turns them back into the same tokens.
+//! Lifting fails on chains that are not structured, like a condition without a block or
+//! an `else` without an `if`. Whitespace tokens are dropped.
+
+use crate::tks::{BinaryOp, Expression, Keyword, Literal, Token, TokenChain, UnaryOp};
+use anyhow::bail;
+
+pub type Block = Vec<Stmt>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    StaticAccess(Vec<String>),
+    InvokeStatic(String, Vec<Expr>),
+    Instantiate(String, Vec<Expr>),
+    InstanceAccess(String, String),
+    InvokeInstance(String, String, Vec<Expr>),
+    InvokeChained(Box<Expr>, String, Vec<Expr>),
+    Array(Vec<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// Expression, the value of which is pushed onto the stack
+    Expr(Expr),
+    /// `let`, `const` or `static` declaration
+    Declare {
+        kw: Keyword,
+        name: String,
+        ty: Option<String>,
+        value: Expr,
+    },
+    /// `let [a, rest..] = value;`, `let (a, b) = value;` or `let {x, y} = value;`
+    Destructure {
+        pattern: Pattern,
+        value: Expr,
+    },
+    Export(Expr),
+    Import(Expr),
+    Return(Expr),
+    Function {
+        /// `pub`, `priv` or `static` in front of the function
+        modifier: Option<Keyword>,
+        out_ty: String,
+        name: String,
+        /// Whether the name is a string, which declares an extern function
+        native: bool,
+        params: Vec<String>,
+        body: Block,
+    },
+    Namespace {
+        name: String,
+        body: Block,
+    },
+    Test {
+        name: String,
+        body: Block,
+    },
+    /// `init { ... }` block of a structure
+    Init(Block),
+    /// `pub` or `priv` in front of the member that follows
+    Visibility(Keyword),
+    Enum {
+        name: String,
+        /// Names of the variants with the types of their payloads
+        variants: Vec<(String, String)>,
+    },
+    Struct {
+        name: String,
+        body: Block,
+    },
+    /// `name type [default]` field of a structure
+    Field {
+        name: String,
+        ty: String,
+        default: Option<Literal>,
+    },
+    /// `if` with its `elif` branches, and the `else` block if there is one
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+    },
+    While {
+        cond: Expr,
+        body: Block,
+    },
+    DoWhile {
+        body: Block,
+        cond: Expr,
+    },
+    Match {
+        value: Expr,
+        arms: Vec<MatchArm>,
+    },
+    Block(Block),
+    Attribute(String, Literal),
+    End,
+    /// Comment of the source as written, see [`Token::Comment`]
+    Comment(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternKind {
+    /// `[a, rest..]`
+    Array,
+    /// `(a, b)`
+    Tuple,
+    /// `{x, y}`
+    Struct,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    /// Name of the variant, or `_` for any other one
+    pub pattern: String,
+    /// Name the payload is bound to, `_` to ignore it
+    pub binding: String,
+    pub body: Block,
+}
+
+/// Groups the tokens of a chain into statements
+pub fn lift(chain: &[Token]) -> anyhow::Result<Block> {
+    let mut lifter = Lifter { tks: chain, pos: 0 };
+    lifter.statements(false)
+}
+
+/// Turns statements back into the tokens the Vm reads
+pub fn lower(block: &[Stmt]) -> TokenChain {
+    let mut out = TokenChain::new();
+    for stmt in block {
+        stmt.lower_into(&mut out);
+    }
+    out
+}
+
+impl Expr {
+    /// Converts an expression token into a tree, failing on tokens that are not values
+    pub fn lift(tk: &Token) -> anyhow::Result<Expr> {
+        let expr = match tk {
+            Token::Literal(lit) => return Ok(Expr::Literal(lit.to_owned())),
+            Token::Expression(expr) => expr.as_ref(),
+            other => bail!("Expected an expression, got {:?}!", other),
+        };
+        Ok(match expr {
+            Expression::BinaryOp(op, lh, rh) => {
+                Expr::Binary(*op, Box::new(Expr::lift(lh)?), Box::new(Expr::lift(rh)?))
+            }
+            Expression::UnaryOp(op, value) => Expr::Unary(*op, Box::new(Expr::lift(value)?)),
+            Expression::StaticAccess(path) => Expr::StaticAccess(path.to_owned()),
+            Expression::InvokeStatic(name, params) => {
+                Expr::InvokeStatic(name.to_owned(), _lift_all(params)?)
+            }
+            Expression::Instantiate(name, fields) => {
+                Expr::Instantiate(name.to_owned(), _lift_all(fields)?)
+            }
+            Expression::InstanceAccess(receiver, field) => {
+                Expr::InstanceAccess(receiver.to_owned(), field.to_owned())
+            }
+            Expression::InvokeInstance(receiver, name, params) => {
+                Expr::InvokeInstance(receiver.to_owned(), name.to_owned(), _lift_all(params)?)
+            }
+            Expression::InvokeChained(receiver, name, params) => Expr::InvokeChained(
+                Box::new(Expr::lift(receiver)?),
+                name.to_owned(),
+                _lift_all(params)?,
+            ),
+            Expression::Array(values) => Expr::Array(_lift_all(values)?),
+            Expression::Ternary(cond, then, otherwise) => Expr::Ternary(
+                Box::new(Expr::lift(cond)?),
+                Box::new(Expr::lift(then)?),
+                Box::new(Expr::lift(otherwise)?),
+            ),
+            Expression::Index(value, index) => {
+                Expr::Index(Box::new(Expr::lift(value)?), Box::new(Expr::lift(index)?))
+            }
+            other => bail!("Expected an expression, got the {:?} statement!", other),
+        })
+    }
+
+    pub fn lower(&self) -> Token {
+        let expr = match self {
+            Expr::Literal(lit) => return Token::Literal(lit.to_owned()),
+            Expr::Binary(op, lh, rh) => Expression::BinaryOp(*op, lh.lower(), rh.lower()),
+            Expr::Unary(op, value) => Expression::UnaryOp(*op, value.lower()),
+            Expr::StaticAccess(path) => Expression::StaticAccess(path.to_owned()),
+            Expr::InvokeStatic(name, params) => {
+                Expression::InvokeStatic(name.to_owned(), _lower_all(params))
+            }
+            Expr::Instantiate(name, fields) => {
+                Expression::Instantiate(name.to_owned(), _lower_all(fields))
+            }
+            Expr::InstanceAccess(receiver, field) => {
+                Expression::InstanceAccess(receiver.to_owned(), field.to_owned())
+            }
+            Expr::InvokeInstance(receiver, name, params) => {
+                Expression::InvokeInstance(receiver.to_owned(), name.to_owned(), _lower_all(params))
+            }
+            Expr::InvokeChained(receiver, name, params) => {
+                Expression::InvokeChained(receiver.lower(), name.to_owned(), _lower_all(params))
+            }
+            Expr::Array(values) => Expression::Array(_lower_all(values)),
+            Expr::Ternary(cond, then, otherwise) => {
+                Expression::Ternary(cond.lower(), then.lower(), otherwise.lower())
+            }
+            Expr::Index(value, index) => Expression::Index(value.lower(), index.lower()),
+        };
+        Token::Expression(Box::new(expr))
+    }
+}
+
+fn _lift_all(tks: &[Token]) -> anyhow::Result<Vec<Expr>> {
+    tks.iter().map(Expr::lift).collect()
+}
+
+fn _lower_all(exprs: &[Expr]) -> TokenChain {
+    exprs.iter().map(Expr::lower).collect()
+}
+
+fn _ident(name: &str) -> Token {
+    Token::Literal(Literal::Ident(name.to_owned()))
+}
+
+fn _type_name(name: &str) -> Token {
+    Token::Literal(Literal::TypeName(name.to_owned()))
+}
+
+fn _expr(expr: Expression) -> Token {
+    Token::Expression(Box::new(expr))
+}
+
+fn _lower_block(block: &[Stmt], out: &mut TokenChain) {
+    out.push(Token::LBracket);
+    for stmt in block {
+        stmt.lower_into(out);
+    }
+    out.push(Token::RBracket);
+}
+
+impl Stmt {
+    /// Appends the tokens of this statement to `out`
+    pub fn lower_into(&self, out: &mut TokenChain) {
+        match self {
+            Stmt::Expr(expr) => out.push(expr.lower()),
+            Stmt::Declare {
+                kw,
+                name,
+                ty,
+                value,
+            } => {
+                out.extend([Token::Keyword(*kw), _ident(name)]);
+                out.extend(ty.as_deref().map(_type_name));
+                out.push(value.lower());
+            }
+            Stmt::Destructure { pattern, value } => {
+                let (opening, closing) = match pattern.kind {
+                    PatternKind::Array => (Token::LSquare, Token::RSquare),
+                    PatternKind::Tuple => (Token::LParen, Token::RParen),
+                    PatternKind::Struct => (Token::LBracket, Token::RBracket),
+                };
+                out.extend([Token::Keyword(Keyword::Let), opening]);
+                out.extend(pattern.names.iter().map(|name| _ident(name)));
+                out.extend([closing, value.lower()]);
+            }
+            Stmt::Export(value) => out.extend([Token::Keyword(Keyword::Export), value.lower()]),
+            Stmt::Import(value) => out.extend([Token::Keyword(Keyword::Import), value.lower()]),
+            Stmt::Return(value) => out.extend([Token::Keyword(Keyword::Return), value.lower()]),
+            Stmt::Function {
+                modifier,
+                out_ty,
+                name,
+                native,
+                params,
+                body,
+            } => {
+                out.push(Token::Keyword(Keyword::Function));
+                out.extend(modifier.map(Token::Keyword));
+                out.push(_type_name(out_ty));
+                out.push(if *native {
+                    Token::Literal(Literal::String(name.to_owned()))
+                } else {
+                    _ident(name)
+                });
+                out.push(Token::LParen);
+                out.extend(params.iter().map(|param| _ident(param)));
+                out.push(Token::RParen);
+                _lower_block(body, out);
+            }
+            Stmt::Namespace { name, body } => {
+                out.extend([Token::Keyword(Keyword::Namespace), _ident(name)]);
+                _lower_block(body, out);
+            }
+            Stmt::Test { name, body } => {
+                out.extend([
+                    Token::Keyword(Keyword::Test),
+                    Token::Literal(Literal::String(name.to_owned())),
+                ]);
+                _lower_block(body, out);
+            }
+            Stmt::Init(body) => {
+                out.push(Token::Keyword(Keyword::Init));
+                _lower_block(body, out);
+            }
+            Stmt::Visibility(kw) => out.push(Token::Keyword(*kw)),
+            Stmt::Enum { name, variants } => {
+                out.extend([Token::Keyword(Keyword::Enum), _ident(name), Token::LBracket]);
+                for (variant, ty) in variants {
+                    out.extend([_ident(variant), _type_name(ty)]);
+                }
+                out.push(Token::RBracket);
+            }
+            Stmt::Struct { name, body } => {
+                out.push(_ident(name));
+                _lower_block(body, out);
+            }
+            Stmt::Field { name, ty, default } => {
+                out.extend([_ident(name), _type_name(ty)]);
+                out.extend(default.clone().map(Token::Literal));
+            }
+            Stmt::If {
+                branches,
+                otherwise,
+            } => {
+                for (index, (cond, body)) in branches.iter().enumerate() {
+                    out.push(_expr(if index == 0 {
+                        Expression::IfStmt
+                    } else {
+                        Expression::ElifStmt
+                    }));
+                    out.push(cond.lower());
+                    _lower_block(body, out);
+                }
+                if let Some(body) = otherwise {
+                    out.push(_expr(Expression::ElseStmt));
+                    _lower_block(body, out);
+                }
+            }
+            Stmt::While { cond, body } => {
+                out.extend([_expr(Expression::WhileStmt), cond.lower()]);
+                _lower_block(body, out);
+            }
+            Stmt::DoWhile { body, cond } => {
+                out.push(_expr(Expression::DoWhileStmt));
+                _lower_block(body, out);
+                out.push(cond.lower());
+            }
+            Stmt::Match { value, arms } => {
+                out.extend([_expr(Expression::MatchStmt), value.lower(), Token::LBracket]);
+                for arm in arms {
+                    out.extend([_type_name(&arm.pattern), _ident(&arm.binding)]);
+                    _lower_block(&arm.body, out);
+                }
+                out.push(Token::RBracket);
+            }
+            Stmt::Block(body) => _lower_block(body, out),
+            Stmt::Attribute(name, value) => {
+                out.push(Token::Attribute(name.to_owned(), value.to_owned()))
+            }
+            Stmt::End => out.push(Token::End),
+            Stmt::Comment(text) => out.push(Token::Comment(text.to_owned())),
+        }
+    }
+}
+
+struct Lifter<'a> {
+    tks: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Lifter<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tks.get(self.pos)
+    }
+
+    fn next(&mut self) -> anyhow::Result<&'a Token> {
+        match self.tks.get(self.pos) {
+            Some(tk) => {
+                self.pos += 1;
+                Ok(tk)
+            }
+            None => bail!("Unexpected end of token chain!"),
+        }
+    }
+
+    fn expr(&mut self) -> anyhow::Result<Expr> {
+        Expr::lift(self.next()?)
+    }
+
+    fn ident(&mut self, what: &str) -> anyhow::Result<String> {
+        match self.next()? {
+            Token::Literal(Literal::Ident(name)) => Ok(name.to_owned()),
+            other => bail!("Expected {}, got {:?}!", what, other),
+        }
+    }
+
+    fn type_name(&mut self, what: &str) -> anyhow::Result<String> {
+        match self.next()? {
+            Token::Literal(Literal::TypeName(name)) => Ok(name.to_owned()),
+            other => bail!("Expected {}, got {:?}!", what, other),
+        }
+    }
+
+    fn expect(&mut self, tk: Token) -> anyhow::Result<()> {
+        match self.next()? {
+            next if *next == tk => Ok(()),
+            other => bail!("Expected {:?}, got {:?}!", tk, other),
+        }
+    }
+
+    /// Lifts statements until the end of the chain, or until the `}` of a `nested` block
+    fn statements(&mut self, nested: bool) -> anyhow::Result<Block> {
+        let mut block = Block::new();
+        while let Some(tk) = self.peek() {
+            match tk {
+                Token::RBracket if nested => return Ok(block),
+                Token::Whitespace => self.pos += 1,
+                _ => block.push(self.statement()?),
+            }
+        }
+        if nested {
+            bail!("Unclosed block at the end of token chain!")
+        }
+        Ok(block)
+    }
+
+    fn block(&mut self, of: &str) -> anyhow::Result<Block> {
+        if self.peek() != Some(&Token::LBracket) {
+            bail!("Expected a block after {}, got {:?}!", of, self.peek())
+        }
+        self.pos += 1;
+        let block = self.statements(true)?;
+        self.pos += 1;
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> anyhow::Result<Stmt> {
+        let tk = self.next()?;
+        Ok(match tk {
+            Token::Keyword(kw) => return self.keyword(*kw),
+            Token::Literal(Literal::Ident(name)) => match self.peek() {
+                Some(Token::LBracket) => Stmt::Struct {
+                    name: name.to_owned(),
+                    body: self.block("a structure name")?,
+                },
+                Some(Token::Literal(Literal::TypeName(ty))) => {
+                    self.pos += 1;
+                    let default = match self.peek() {
+                        Some(Token::Literal(Literal::Ident(_) | Literal::TypeName(_))) => None,
+                        Some(Token::Literal(lit)) => {
+                            self.pos += 1;
+                            Some(lit.to_owned())
+                        }
+                        _ => None,
+                    };
+                    Stmt::Field {
+                        name: name.to_owned(),
+                        ty: ty.to_owned(),
+                        default,
+                    }
+                }
+                _ => Stmt::Expr(Expr::Literal(Literal::Ident(name.to_owned()))),
+            },
+            Token::Expression(expr) => match expr.as_ref() {
+                Expression::IfStmt => {
+                    let cond = self.expr()?;
+                    let mut branches = vec![(cond, self.block("if")?)];
+                    let mut otherwise = None;
+                    while let Some(Token::Expression(next)) = self.peek() {
+                        match next.as_ref() {
+                            Expression::ElifStmt => {
+                                self.pos += 1;
+                                let cond = self.expr()?;
+                                branches.push((cond, self.block("elif")?));
+                            }
+                            Expression::ElseStmt => {
+                                self.pos += 1;
+                                otherwise = Some(self.block("else")?);
+                                break;
+                            }
+                            _ => break,
+                        }
+                    }
+                    Stmt::If {
+                        branches,
+                        otherwise,
+                    }
+                }
+                Expression::ElifStmt | Expression::ElseStmt => {
+                    bail!("Expected an if before {:?}!", expr)
+                }
+                Expression::WhileStmt => {
+                    let cond = self.expr()?;
+                    Stmt::While {
+                        cond,
+                        body: self.block("while")?,
+                    }
+                }
+                Expression::DoWhileStmt => {
+                    let body = self.block("do")?;
+                    Stmt::DoWhile {
+                        body,
+                        cond: self.expr()?,
+                    }
+                }
+                Expression::MatchStmt => {
+                    let value = self.expr()?;
+                    self.expect(Token::LBracket)?;
+                    let mut arms = vec![];
+                    while self.peek() != Some(&Token::RBracket) {
+                        let pattern = self.type_name("a match arm pattern")?;
+                        let binding = self.ident("a match arm binding")?;
+                        arms.push(MatchArm {
+                            pattern,
+                            binding,
+                            body: self.block("a match arm")?,
+                        });
+                    }
+                    self.pos += 1;
+                    Stmt::Match { value, arms }
+                }
+                _ => Stmt::Expr(Expr::lift(tk)?),
+            },
+            Token::Literal(lit) => Stmt::Expr(Expr::Literal(lit.to_owned())),
+            Token::LBracket => {
+                self.pos -= 1;
+                Stmt::Block(self.block("a statement")?)
+            }
+            Token::Attribute(name, value) => Stmt::Attribute(name.to_owned(), value.to_owned()),
+            Token::End => Stmt::End,
+            Token::Comment(text) => Stmt::Comment(text.to_owned()),
+            other => bail!("Unexpected {:?} at the start of a statement!", other),
+        })
+    }
+
+    fn keyword(&mut self, kw: Keyword) -> anyhow::Result<Stmt> {
+        Ok(match kw {
+            Keyword::Let
+                if matches!(
+                    self.peek(),
+                    Some(Token::LSquare | Token::LParen | Token::LBracket)
+                ) =>
+            {
+                let (kind, closing) = match self.next()? {
+                    Token::LSquare => (PatternKind::Array, Token::RSquare),
+                    Token::LParen => (PatternKind::Tuple, Token::RParen),
+                    _ => (PatternKind::Struct, Token::RBracket),
+                };
+                let mut names = vec![];
+                while self.peek() != Some(&closing) {
+                    names.push(self.ident("a name in the pattern")?);
+                }
+                self.pos += 1;
+                Stmt::Destructure {
+                    pattern: Pattern { kind, names },
+                    value: self.expr()?,
+                }
+            }
+            Keyword::Let | Keyword::Const | Keyword::Static => {
+                let name = self.ident("a name of the declaration")?;
+                let ty = match self.peek() {
+                    Some(Token::Literal(Literal::TypeName(ty))) => {
+                        self.pos += 1;
+                        Some(ty.to_owned())
+                    }
+                    _ => None,
+                };
+                Stmt::Declare {
+                    kw,
+                    name,
+                    ty,
+                    value: self.expr()?,
+                }
+            }
+            Keyword::Export => Stmt::Export(self.expr()?),
+            Keyword::Import => Stmt::Import(self.expr()?),
+            Keyword::Return => Stmt::Return(self.expr()?),
+            Keyword::Function => {
+                let modifier = match self.peek() {
+                    Some(Token::Keyword(modifier)) => {
+                        self.pos += 1;
+                        Some(*modifier)
+                    }
+                    _ => None,
+                };
+                let out_ty = self.type_name("a function output type")?;
+                let (name, native) = match self.next()? {
+                    Token::Literal(Literal::Ident(name)) => (name.to_owned(), false),
+                    Token::Literal(Literal::String(name)) => (name.to_owned(), true),
+                    other => bail!("Expected a function name, got {:?}!", other),
+                };
+                self.expect(Token::LParen)?;
+                let mut params = vec![];
+                while self.peek() != Some(&Token::RParen) {
+                    params.push(self.ident("a parameter name")?);
+                }
+                self.pos += 1;
+                Stmt::Function {
+                    modifier,
+                    out_ty,
+                    name,
+                    native,
+                    params,
+                    body: self.block("a function declaration")?,
+                }
+            }
+            Keyword::Namespace => Stmt::Namespace {
+                name: self.ident("a namespace name")?,
+                body: self.block("a namespace name")?,
+            },
+            Keyword::Test => {
+                let name = match self.next()? {
+                    Token::Literal(Literal::String(name)) => name.to_owned(),
+                    other => bail!("Expected a test name, got {:?}!", other),
+                };
+                Stmt::Test {
+                    name,
+                    body: self.block("a test name")?,
+                }
+            }
+            Keyword::Init => Stmt::Init(self.block("init")?),
+            Keyword::Pub | Keyword::Priv => Stmt::Visibility(kw),
+            Keyword::Enum => {
+                let name = self.ident("an enum name")?;
+                self.expect(Token::LBracket)?;
+                let mut variants = vec![];
+                while self.peek() != Some(&Token::RBracket) {
+                    let variant = self.ident("an enum variant")?;
+                    variants.push((variant, self.type_name("a variant payload type")?));
+                }
+                self.pos += 1;
+                Stmt::Enum { name, variants }
+            }
+        })
+    }
+}
@@ -0,0 +1,13 @@
+use galevm::lsp::Server;
+use std::io::{stdin, stdout};
+use std::process;
+
+fn main() -> anyhow::Result<()> {
+    let mut server = Server::new(stdout().lock());
+    server.run(stdin().lock())?;
+    // exiting without a shutdown request first is an error of the client
+    if !server.is_shutdown() {
+        process::exit(1)
+    }
+    Ok(())
+}
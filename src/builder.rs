@@ -0,0 +1,225 @@
+//! Fluent construction of token chains, for hosts generating programs instead of
+//! parsing them. Blocks are passed as closures, so brackets always match and every
+//! statement has its tokens in the order the Vm reads them:
+//!
+//! ```ignore
+//! let chain = ChainBuilder::new()
+//!     .let_var("i", 0)
+//!     .while_loop(binary(BinaryOp::Lt, ident("i"), 3), |body| {
+//!         body.call("std::io::println", [ident("i")])
+//!             .assign("i", binary(BinaryOp::Add, ident("i"), 1))
+//!     })
+//!     .build();
+//! ```
+
+use crate::marshal::IntoLiteral;
+use crate::tks::{BinaryOp, Expression, Keyword, Literal, Token, TokenChain, UnaryOp};
+
+/// Value usable as an operand or a statement, either a token or a Rust value
+/// converted into a literal
+pub trait IntoToken {
+    fn into_token(self) -> Token;
+}
+
+impl IntoToken for Token {
+    fn into_token(self) -> Token {
+        self
+    }
+}
+
+impl<T: IntoLiteral> IntoToken for T {
+    fn into_token(self) -> Token {
+        Token::Literal(self.into_literal())
+    }
+}
+
+/// Reference to a variable, constant or function parameter
+pub fn ident<S: Into<String>>(name: S) -> Token {
+    Token::Literal(Literal::Ident(name.into()))
+}
+
+pub fn binary<L: IntoToken, R: IntoToken>(op: BinaryOp, lh: L, rh: R) -> Token {
+    Token::Expression(Box::new(Expression::BinaryOp(
+        op,
+        lh.into_token(),
+        rh.into_token(),
+    )))
+}
+
+pub fn unary<V: IntoToken>(op: UnaryOp, value: V) -> Token {
+    Token::Expression(Box::new(Expression::UnaryOp(op, value.into_token())))
+}
+
+/// Call of the function at `path`, like `std::io::println`, used as a value
+pub fn invoke<S, I>(path: S, args: I) -> Token
+where
+    S: Into<String>,
+    I: IntoIterator,
+    I::Item: IntoToken,
+{
+    Token::Expression(Box::new(Expression::InvokeStatic(
+        path.into(),
+        args.into_iter().map(IntoToken::into_token).collect(),
+    )))
+}
+
+/// Builds a chain statement by statement, see the [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChainBuilder {
+    chain: TokenChain,
+}
+
+impl ChainBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn build(self) -> TokenChain {
+        self.chain
+    }
+
+    /// Appends a raw token, for statements without a dedicated method
+    pub fn token<T: IntoToken>(mut self, token: T) -> Self {
+        self.chain.push(token.into_token());
+        self
+    }
+
+    /// Appends a value as a statement, which pushes it onto the stack
+    pub fn expr<T: IntoToken>(self, value: T) -> Self {
+        self.token(value)
+    }
+
+    /// `let name = value;`
+    pub fn let_var<S: Into<String>, T: IntoToken>(self, name: S, value: T) -> Self {
+        self.declare(Keyword::Let, name.into(), None, value)
+    }
+
+    /// `let name: ty = value;`
+    pub fn let_typed<S: Into<String>, Ty: Into<String>, T: IntoToken>(
+        self,
+        name: S,
+        ty: Ty,
+        value: T,
+    ) -> Self {
+        self.declare(Keyword::Let, name.into(), Some(ty.into()), value)
+    }
+
+    /// `const name = value;`
+    pub fn const_var<S: Into<String>, T: IntoToken>(self, name: S, value: T) -> Self {
+        self.declare(Keyword::Const, name.into(), None, value)
+    }
+
+    fn declare<T: IntoToken>(
+        self,
+        kw: Keyword,
+        name: String,
+        ty: Option<String>,
+        value: T,
+    ) -> Self {
+        let builder = self.token(Token::Keyword(kw)).token(ident(name));
+        match ty {
+            Some(ty) => builder.token(Token::Literal(Literal::TypeName(ty))),
+            None => builder,
+        }
+        .token(value)
+    }
+
+    /// `name = value;`
+    pub fn assign<S: Into<String>, T: IntoToken>(self, name: S, value: T) -> Self {
+        self.token(binary(BinaryOp::Assign, ident(name), value))
+    }
+
+    /// `path(args);`, see [`invoke`] for calls used as values
+    pub fn call<S, I>(self, path: S, args: I) -> Self
+    where
+        S: Into<String>,
+        I: IntoIterator,
+        I::Item: IntoToken,
+    {
+        self.token(invoke(path, args))
+    }
+
+    /// `return value;`
+    pub fn ret<T: IntoToken>(self, value: T) -> Self {
+        self.token(Token::Keyword(Keyword::Return)).token(value)
+    }
+
+    /// `if cond { ... }`
+    pub fn if_block<T, F>(self, cond: T, body: F) -> Self
+    where
+        T: IntoToken,
+        F: FnOnce(ChainBuilder) -> ChainBuilder,
+    {
+        self.token(Token::Expression(Box::new(Expression::IfStmt)))
+            .token(cond)
+            .block(body)
+    }
+
+    /// `elif cond { ... }`, following an [`if_block`](Self::if_block)
+    pub fn elif_block<T, F>(self, cond: T, body: F) -> Self
+    where
+        T: IntoToken,
+        F: FnOnce(ChainBuilder) -> ChainBuilder,
+    {
+        self.token(Token::Expression(Box::new(Expression::ElifStmt)))
+            .token(cond)
+            .block(body)
+    }
+
+    /// `else { ... }`, following an [`if_block`](Self::if_block) or an
+    /// [`elif_block`](Self::elif_block)
+    pub fn else_block<F>(self, body: F) -> Self
+    where
+        F: FnOnce(ChainBuilder) -> ChainBuilder,
+    {
+        self.token(Token::Expression(Box::new(Expression::ElseStmt)))
+            .block(body)
+    }
+
+    /// `while cond { ... }`
+    pub fn while_loop<T, F>(self, cond: T, body: F) -> Self
+    where
+        T: IntoToken,
+        F: FnOnce(ChainBuilder) -> ChainBuilder,
+    {
+        self.token(Token::Expression(Box::new(Expression::WhileStmt)))
+            .token(cond)
+            .block(body)
+    }
+
+    /// `fn void name(params) { ... }`
+    pub fn function<S, F>(self, name: S, params: &[&str], body: F) -> Self
+    where
+        S: Into<String>,
+        F: FnOnce(ChainBuilder) -> ChainBuilder,
+    {
+        self.function_returning(name, "void", params, body)
+    }
+
+    /// `fn ty name(params) { ... }`
+    pub fn function_returning<S, Ty, F>(self, name: S, ty: Ty, params: &[&str], body: F) -> Self
+    where
+        S: Into<String>,
+        Ty: Into<String>,
+        F: FnOnce(ChainBuilder) -> ChainBuilder,
+    {
+        let mut builder = self
+            .token(Token::Keyword(Keyword::Function))
+            .token(Token::Literal(Literal::TypeName(ty.into())))
+            .token(ident(name))
+            .token(Token::LParen);
+        for param in params {
+            builder = builder.token(ident(*param));
+        }
+        builder.token(Token::RParen).block(body)
+    }
+
+    fn block<F>(self, body: F) -> Self
+    where
+        F: FnOnce(ChainBuilder) -> ChainBuilder,
+    {
+        let mut builder = self.token(Token::LBracket);
+        builder.chain.extend(body(ChainBuilder::new()).build());
+        builder.token(Token::RBracket)
+    }
+}
@@ -0,0 +1,119 @@
+//! On-disk cache of compiled sources, so running an unchanged file skips lexing and parsing.
+//!
+//! Every source file has a single entry, named after its path. The entry starts with a key
+//! hashed from the source text, the compiler options, the version of the crate and the
+//! program format, followed by the program itself, see [`crate::program`]. An entry with
+//! a different key is stale, and is replaced when the source is compiled again.
+
+use crate::asm::{assemble_spanned_with, AssembleOptions};
+use crate::fnv::{_fnv1a, FNV_OFFSET};
+use crate::program::{read_program, write_program, Program};
+use crate::vm::FORMAT_VERSION;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Directory the CLI keeps its cache in, next to the compiled source
+pub const DEFAULT_CACHE_DIR: &str = ".gale-cache";
+
+/// Compiled programs stored in a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileCache {
+    dir: PathBuf,
+    options: AssembleOptions,
+}
+
+impl CompileCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            options: AssembleOptions::default(),
+        }
+    }
+
+    /// Sets the options sources are compiled with. They are a part of the key of every entry,
+    /// so that compiling with different options does not reuse programs
+    pub fn with_options(mut self, options: AssembleOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Key of the entry compiled from `src` with the options of this cache
+    pub fn key(&self, src: &str) -> u64 {
+        let mut hash = _fnv1a(FNV_OFFSET, &FORMAT_VERSION.to_le_bytes());
+        hash = _fnv1a(hash, env!("CARGO_PKG_VERSION").as_bytes());
+        for (name, value) in &self.options.defines {
+            for part in [name, value] {
+                hash = _fnv1a(hash, &(part.len() as u64).to_le_bytes());
+                hash = _fnv1a(hash, part.as_bytes());
+            }
+        }
+        if self.options.comments {
+            hash = _fnv1a(hash, b"comments");
+        }
+        _fnv1a(hash, src.as_bytes())
+    }
+
+    /// Path of the entry for the source at `path`, which does not have to exist
+    pub fn entry_path(&self, path: &Path) -> PathBuf {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let name = _fnv1a(FNV_OFFSET, path.to_string_lossy().as_bytes());
+        self.dir.join(format!("{:016x}.galb", name))
+    }
+
+    /// Compiles the source at `path`, or reads it from the cache if it did not change.
+    ///
+    /// Failing to store the compiled program is not an error, the next call compiles it again
+    pub fn compile(&self, path: &Path) -> anyhow::Result<Program> {
+        let src = fs::read_to_string(path)?;
+        let key = self.key(&src);
+        if let Some(program) = self.get(path, key) {
+            return Ok(program);
+        }
+        let (chain, source_map) = assemble_spanned_with(&src, &self.options)?;
+        let mut program = Program::new(chain, source_map);
+        let _ = self.put(path, key, &mut program);
+        Ok(program)
+    }
+
+    /// Reads the entry for the source at `path`, if it exists and was stored with `key`.
+    /// Unreadable entries are treated as missing
+    pub fn get(&self, path: &Path, key: u64) -> Option<Program> {
+        let mut reader = BufReader::new(File::open(self.entry_path(path)).ok()?);
+        let mut stored = [0u8; 8];
+        reader.read_exact(&mut stored).ok()?;
+        if u64::from_le_bytes(stored) != key {
+            return None;
+        }
+        read_program(&mut reader).ok()
+    }
+
+    /// Stores `program` as the entry for the source at `path`, replacing the previous one
+    pub fn put(&self, path: &Path, key: u64, program: &mut Program) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let entry = self.entry_path(path);
+        // written next to the entry first, so that readers never see half of a program
+        let temp = entry.with_extension(format!("{}.tmp", std::process::id()));
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        writer.write_all(&key.to_le_bytes())?;
+        write_program(&mut writer, program)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp, &entry).map_err(|err| {
+            let _ = fs::remove_file(&temp);
+            err.into()
+        })
+    }
+
+    /// Removes every entry of this cache
+    pub fn clear(&self) -> anyhow::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,679 @@
+use crate::snapshot::VmStateSnapshot;
+use crate::span::{SourceMap, Span};
+use crate::tks::{BinaryOp, Expression, Keyword, Literal, Token, TokenChain};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub enum DiagnosticKind {
+    UndefinedVariable,
+    UnknownFunction,
+    UnknownStructure,
+    ArityMismatch,
+    ConstReassignment,
+    UnbalancedBrackets,
+    /// Found by [`typecheck`](crate::typecheck::typecheck), like the kinds below
+    TypeMismatch,
+    ArgumentTypeMismatch,
+    ReturnTypeMismatch,
+    OperandTypeMismatch,
+}
+
+/// A single problem found by [`check`], `index` points at the top level token it was found in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub index: usize,
+    pub span: Option<Span>,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Looks up the source position of this diagnostic's token
+    pub fn locate(mut self, source_map: &SourceMap) -> Self {
+        self.span = source_map.get(self.index);
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{:?} at {}: {}", self.kind, span, self.message),
+            None => write!(
+                f,
+                "{:?} at token #{}: {}",
+                self.kind, self.index, self.message
+            ),
+        }
+    }
+}
+
+/// Statically checks the token chain against an environment without executing anything.
+///
+/// Lookups mirror the Vm: function bodies only see their parameters, their own locals
+/// and the imports of the global scope.
+pub fn check(chain: &TokenChain, env: &VmStateSnapshot) -> Vec<Diagnostic> {
+    let unbalanced = _check_brackets(chain);
+    if !unbalanced.is_empty() {
+        return unbalanced;
+    }
+
+    let global = env.scopes.get("global").cloned().unwrap_or_default();
+    let mut scope = CheckScope::default();
+    scope.vars.extend(global.variables.keys().cloned());
+    scope.consts.extend(global.constants.keys().cloned());
+    scope
+        .fns
+        .extend(global.functions.iter().map(|it| (it.to_owned(), None)));
+    scope
+        .imports
+        .extend(global.imports.values().flatten().cloned());
+
+    let mut checker = Checker {
+        tks: chain,
+        pos: 0,
+        env,
+        scopes: vec![scope],
+        structs: HashMap::new(),
+        enums: env
+            .enums
+            .iter()
+            .map(|(name, variants)| (name.to_owned(), variants.iter().cloned().collect()))
+            .collect(),
+        statics: env.statics.keys().cloned().collect(),
+        current_struct: None,
+        namespaces: HashMap::new(),
+        namespace_path: vec![],
+        diagnostics: vec![],
+    };
+    checker.statements(false);
+    checker.diagnostics
+}
+
+pub(crate) fn _check_brackets(chain: &TokenChain) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut open: Vec<(usize, &Token)> = vec![];
+    for (index, tk) in chain.iter().enumerate() {
+        let opening = match tk {
+            Token::LBracket | Token::LParen | Token::LSquare => {
+                open.push((index, tk));
+                continue;
+            }
+            Token::RBracket => Token::LBracket,
+            Token::RParen => Token::LParen,
+            Token::RSquare => Token::LSquare,
+            _ => continue,
+        };
+        match open.pop() {
+            Some((_, tk)) if *tk == opening => {}
+            _ => diagnostics.push(Diagnostic {
+                index,
+                span: None,
+                kind: DiagnosticKind::UnbalancedBrackets,
+                message: format!("Unexpected closing {:?}!", tk),
+            }),
+        }
+    }
+    diagnostics.extend(open.into_iter().map(|(index, tk)| Diagnostic {
+        index,
+        span: None,
+        kind: DiagnosticKind::UnbalancedBrackets,
+        message: format!("Unclosed {:?}!", tk),
+    }));
+    diagnostics
+}
+
+/// Names visible in a single scope, functions map to their arity if it is known
+#[derive(Debug, Clone, Default)]
+struct CheckScope {
+    vars: HashSet<String>,
+    consts: HashSet<String>,
+    fns: HashMap<String, Option<usize>>,
+    imports: HashSet<String>,
+}
+
+impl CheckScope {
+    fn has_value(&self, name: &str) -> bool {
+        self.vars.contains(name) || self.consts.contains(name) || self.imports.contains(name)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct CheckStruct {
+    fields: HashSet<String>,
+    statics: HashSet<String>,
+    static_fns: HashMap<String, Option<usize>>,
+}
+
+struct Checker<'a> {
+    tks: &'a [Token],
+    pos: usize,
+    env: &'a VmStateSnapshot,
+    scopes: Vec<CheckScope>,
+    structs: HashMap<String, CheckStruct>,
+    /// Variant names of every enum
+    enums: HashMap<String, HashSet<String>>,
+    /// Statics are visible in every scope
+    statics: HashSet<String>,
+    current_struct: Option<String>,
+    namespaces: HashMap<String, CheckScope>,
+    namespace_path: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, kind: DiagnosticKind, message: String) {
+        self.diagnostics.push(Diagnostic {
+            index: self.pos.saturating_sub(1),
+            span: None,
+            kind,
+            message,
+        })
+    }
+
+    fn scope(&mut self) -> &mut CheckScope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tks.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let tk = self.tks.get(self.pos);
+        self.pos += 1;
+        tk
+    }
+
+    fn statements(&mut self, nested: bool) {
+        while let Some(tk) = self.peek() {
+            if nested && *tk == Token::RBracket {
+                return;
+            }
+            self.statement();
+        }
+    }
+
+    /// Checks a `{ ... }` block in the current scope
+    fn block(&mut self) {
+        if self.peek() != Some(&Token::LBracket) {
+            return;
+        }
+        self.next();
+        self.statements(true);
+        self.next();
+    }
+
+    fn statement(&mut self) {
+        let tk = match self.next() {
+            Some(tk) => tk,
+            None => return,
+        };
+        match tk {
+            Token::Keyword(kw) => self.keyword(*kw),
+            Token::Literal(Literal::Ident(name)) => match self.peek() {
+                Some(Token::LBracket) => self.structure(name),
+                Some(Token::Literal(Literal::TypeName(_))) => {
+                    self.next();
+                    if let Some(Token::Literal(lit)) = self.peek() {
+                        if !matches!(lit, Literal::Ident(_)) {
+                            self.next();
+                        }
+                    }
+                    if let Some(current) = self.current_struct.clone() {
+                        self.structs
+                            .entry(current)
+                            .or_default()
+                            .fields
+                            .insert(name.to_owned());
+                    }
+                }
+                _ => self.expression(tk),
+            },
+            Token::Expression(expr) => match expr.as_ref() {
+                Expression::IfStmt | Expression::ElifStmt | Expression::WhileStmt => {
+                    if let Some(condition) = self.next() {
+                        self.expression(condition);
+                    }
+                    self.block()
+                }
+                Expression::ElseStmt => self.block(),
+                Expression::DoWhileStmt => {
+                    self.block();
+                    if let Some(condition) = self.next() {
+                        self.expression(condition);
+                    }
+                }
+                Expression::MatchStmt => self.match_arms(),
+                _ => self.expression(tk),
+            },
+            Token::LBracket => {
+                self.pos -= 1;
+                self.block()
+            }
+            _ => self.expression(tk),
+        }
+    }
+
+    /// Checks the matched value and the arms, payloads are bound in the current scope like the Vm does
+    fn match_arms(&mut self) {
+        if let Some(value) = self.next() {
+            self.expression(value);
+        }
+        if self.peek() != Some(&Token::LBracket) {
+            return;
+        }
+        self.next();
+        while let Some(Token::Literal(Literal::TypeName(_))) = self.peek() {
+            self.next();
+            if let Some(Token::Literal(Literal::Ident(binding))) = self.next() {
+                if binding != "_" {
+                    self.scope().vars.insert(binding.to_owned());
+                }
+            }
+            self.block();
+        }
+        self.next();
+    }
+
+    /// Takes the names bound by a destructuring pattern, without the `..` of its rest
+    fn pattern(&mut self) -> Vec<String> {
+        self.next();
+        let mut names = vec![];
+        while let Some(Token::Literal(Literal::Ident(name))) = self.peek() {
+            self.next();
+            let name = name.strip_suffix("..").unwrap_or(name);
+            if name != "_" {
+                names.push(name.to_owned());
+            }
+        }
+        self.next();
+        names
+    }
+
+    fn enumeration(&mut self) {
+        let name = match self.next() {
+            Some(Token::Literal(Literal::Ident(name))) => name.to_owned(),
+            _ => return,
+        };
+        let mut variants = HashSet::new();
+        if self.peek() == Some(&Token::LBracket) {
+            self.next();
+            while let Some(tk) = self.next() {
+                match tk {
+                    Token::RBracket => break,
+                    Token::Literal(Literal::Ident(variant)) => {
+                        variants.insert(variant.to_owned());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.enums.insert(name, variants);
+    }
+
+    fn structure(&mut self, name: &str) {
+        self.structs.entry(name.to_owned()).or_default();
+        let cached = self.current_struct.replace(name.to_owned());
+        self.scopes.push(CheckScope::default());
+        self.block();
+        let scope = self.scopes.pop().unwrap();
+        let structure = self.structs.get_mut(name).unwrap();
+        structure.statics.extend(scope.vars);
+        structure.statics.extend(scope.consts);
+        self.current_struct = cached;
+    }
+
+    fn keyword(&mut self, kw: Keyword) {
+        match kw {
+            Keyword::Let
+                if matches!(
+                    self.peek(),
+                    Some(Token::LSquare | Token::LParen | Token::LBracket)
+                ) =>
+            {
+                let names = self.pattern();
+                if let Some(value) = self.next() {
+                    self.expression(value);
+                }
+                self.scope().vars.extend(names);
+            }
+            Keyword::Let | Keyword::Const | Keyword::Static => {
+                let name = match self.next() {
+                    Some(Token::Literal(Literal::Ident(name))) => name,
+                    _ => return,
+                };
+                if let Some(Token::Literal(Literal::TypeName(_))) = self.peek() {
+                    self.next();
+                }
+                if let Some(value) = self.next() {
+                    self.expression(value);
+                }
+                if self.scope().consts.contains(name) {
+                    self.report(
+                        DiagnosticKind::ConstReassignment,
+                        format!("Constant {} is declared again!", name),
+                    );
+                }
+                match kw {
+                    Keyword::Let => self.scope().vars.insert(name.to_owned()),
+                    Keyword::Static => self.statics.insert(name.to_owned()),
+                    _ => self.scope().consts.insert(name.to_owned()),
+                };
+            }
+            Keyword::Import => {
+                if let Some(Token::Literal(Literal::Ident(path))) = self.next() {
+                    let name = path.rsplit_once("::").map(|it| it.1).unwrap_or(path);
+                    self.scope().imports.insert(name.to_owned());
+                }
+            }
+            Keyword::Export => {
+                self.next();
+            }
+            Keyword::Return => {
+                if let Some(value) = self.next() {
+                    self.expression(value);
+                }
+            }
+            Keyword::Function => self.function(),
+            Keyword::Namespace => self.namespace(),
+            Keyword::Test => self.test(),
+            Keyword::Enum => self.enumeration(),
+            Keyword::Pub | Keyword::Priv => {}
+            // declarations of the block belong to the structure
+            Keyword::Init => self.block(),
+        }
+    }
+
+    /// Test bodies are checked like functions without parameters
+    fn test(&mut self) {
+        self.next();
+        let mut scope = CheckScope::default();
+        scope.imports.extend(self.scopes[0].imports.iter().cloned());
+        self.scopes.push(scope);
+        self.block();
+        self.scopes.pop();
+    }
+
+    /// Namespace bodies are checked in their own scope, which is kept for `path::name` lookups
+    fn namespace(&mut self) {
+        let name = match self.next() {
+            Some(Token::Literal(Literal::Ident(name))) => name.to_owned(),
+            _ => return,
+        };
+        self.namespace_path.push(name);
+        let path = self.namespace_path.join("::");
+        let scope = self.namespaces.remove(&path).unwrap_or_default();
+        self.scopes.push(scope);
+        self.block();
+        let scope = self.scopes.pop().unwrap();
+        self.namespaces.insert(path, scope);
+        self.namespace_path.pop();
+    }
+
+    fn function(&mut self) {
+        if let Some(Token::Keyword(_)) = self.peek() {
+            self.next();
+        }
+        // output type
+        self.next();
+        let name = match self.next() {
+            Some(Token::Literal(Literal::Ident(name))) => name.to_owned(),
+            _ => return,
+        };
+        let mut params = vec![];
+        if self.peek() == Some(&Token::LParen) {
+            self.next();
+            while let Some(tk) = self.next() {
+                match tk {
+                    Token::RParen => break,
+                    Token::Literal(Literal::Ident(param)) => params.push(param.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+        let arity = if params.contains(&"varargs".to_string()) {
+            None
+        } else {
+            Some(params.len())
+        };
+
+        let is_inst = params.first().map(|it| it == "this").unwrap_or(false);
+        match self.current_struct.clone() {
+            Some(structure) if !is_inst => {
+                self.structs
+                    .entry(structure)
+                    .or_default()
+                    .static_fns
+                    .insert(name, arity);
+            }
+            Some(_) => {}
+            None => {
+                self.scope().fns.insert(name, arity);
+            }
+        }
+
+        let mut scope = CheckScope::default();
+        scope.consts.extend(params.iter().cloned());
+        scope.imports.extend(self.scopes[0].imports.iter().cloned());
+        if is_inst {
+            scope.vars.insert("this".to_string());
+        }
+        let cached = self.current_struct.take();
+        self.scopes.push(scope);
+        self.block();
+        self.scopes.pop();
+        self.current_struct = cached;
+    }
+
+    fn expression(&mut self, tk: &Token) {
+        match tk {
+            Token::Literal(Literal::Ident(name)) => {
+                if !self.scope().has_value(name) && !self.statics.contains(name) {
+                    self.report(
+                        DiagnosticKind::UndefinedVariable,
+                        format!("Variable {} is not defined!", name),
+                    )
+                }
+            }
+            Token::Expression(expr) => self.expr(expr),
+            _ => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) {
+        match expr {
+            Expression::BinaryOp(BinaryOp::Assign, Token::Literal(Literal::Ident(name)), rh) => {
+                self.expression(rh);
+                if self.scope().consts.contains(name) {
+                    self.report(
+                        DiagnosticKind::ConstReassignment,
+                        format!("Can not assign to constant {}!", name),
+                    )
+                } else {
+                    self.scope().vars.insert(name.to_owned());
+                }
+            }
+            Expression::BinaryOp(_, lh, rh) => {
+                self.expression(lh);
+                self.expression(rh);
+            }
+            Expression::UnaryOp(_, value) => self.expression(value),
+            Expression::Ternary(condition, then, otherwise) => {
+                self.expression(condition);
+                self.expression(then);
+                self.expression(otherwise);
+            }
+            Expression::Index(value, index) => {
+                self.expression(value);
+                self.expression(index);
+            }
+            Expression::StaticAccess(path) => {
+                let (scope, name) = path.split_at(path.len().saturating_sub(1));
+                let scope = scope.join("::");
+                let name = name.first().cloned().unwrap_or_default();
+                if let Some(variants) = self.enums.get(&scope) {
+                    if !variants.contains(&name) {
+                        self.report(
+                            DiagnosticKind::UndefinedVariable,
+                            format!("Enum {} has no variant {}!", scope, name),
+                        )
+                    }
+                    return;
+                }
+                let found = match self.structs.get(&scope) {
+                    Some(structure) => structure.statics.contains(&name),
+                    None if self.namespaces.contains_key(&scope) => {
+                        let namespace = &self.namespaces[&scope];
+                        namespace.vars.contains(&name) || namespace.consts.contains(&name)
+                    }
+                    None => self
+                        .env
+                        .scopes
+                        .get(&scope)
+                        .map(|it| {
+                            it.variables.contains_key(&name) || it.constants.contains_key(&name)
+                        })
+                        .unwrap_or(false),
+                };
+                if !found {
+                    self.report(
+                        DiagnosticKind::UndefinedVariable,
+                        format!("Static value {} is not defined!", path.join("::")),
+                    )
+                }
+            }
+            Expression::InvokeStatic(name, params) => {
+                for param in params {
+                    self.expression(param);
+                }
+                if let Some((scope, variant)) = name.rsplit_once("::") {
+                    if let Some(variants) = self.enums.get(scope) {
+                        if !variants.contains(variant) {
+                            self.report(
+                                DiagnosticKind::UnknownFunction,
+                                format!("Enum {} has no variant {}!", scope, variant),
+                            )
+                        } else if params.len() > 1 {
+                            self.report(
+                                DiagnosticKind::ArityMismatch,
+                                format!(
+                                    "Variant {} takes a single payload, but got {}!",
+                                    name,
+                                    params.len()
+                                ),
+                            )
+                        }
+                        return;
+                    }
+                }
+                match self.resolve_fn(name) {
+                    None => self.report(
+                        DiagnosticKind::UnknownFunction,
+                        format!("Could not find function {}!", name),
+                    ),
+                    Some(Some(arity)) if arity != params.len() => self.report(
+                        DiagnosticKind::ArityMismatch,
+                        format!(
+                            "Function {} expects {} arg(s), but got {}!",
+                            name,
+                            arity,
+                            params.len()
+                        ),
+                    ),
+                    _ => {}
+                }
+            }
+            Expression::Instantiate(name, fields) => {
+                if !self.structs.contains_key(name) && !self.env.structs.contains(name) {
+                    self.report(
+                        DiagnosticKind::UnknownStructure,
+                        format!("Could not find structure {}!", name),
+                    )
+                }
+                for field in fields {
+                    match field.as_expr() {
+                        Some(Expression::BinaryOp(
+                            BinaryOp::Assign,
+                            Token::Literal(Literal::Ident(field)),
+                            value,
+                        )) => {
+                            self.expression(value);
+                            let known = self
+                                .structs
+                                .get(name)
+                                .map(|it| it.fields.contains(field))
+                                .unwrap_or(true);
+                            if !known {
+                                self.report(
+                                    DiagnosticKind::UndefinedVariable,
+                                    format!("Structure {} has no field {}!", name, field),
+                                )
+                            }
+                        }
+                        _ => self.expression(field),
+                    }
+                }
+            }
+            Expression::InstanceAccess(receiver, _) => {
+                self.expression(&Token::Literal(Literal::Ident(receiver.to_owned())))
+            }
+            Expression::InvokeInstance(receiver, _, params) => {
+                self.expression(&Token::Literal(Literal::Ident(receiver.to_owned())));
+                for param in params {
+                    self.expression(param);
+                }
+            }
+            Expression::InvokeChained(receiver, _, params) => {
+                self.expression(receiver);
+                for param in params {
+                    self.expression(param);
+                }
+            }
+            Expression::Array(values) => {
+                for value in values {
+                    self.expression(value);
+                }
+            }
+            Expression::IfStmt
+            | Expression::ElseStmt
+            | Expression::ElifStmt
+            | Expression::WhileStmt
+            | Expression::DoWhileStmt
+            | Expression::MatchStmt => {}
+        }
+    }
+
+    /// Returns the arity of function if it could be found, which is `None` for host functions
+    fn resolve_fn(&mut self, name: &str) -> Option<Option<usize>> {
+        if let Some((structure, fnc)) = name.rsplit_once('.') {
+            if let Some(structure) = self.structs.get(structure) {
+                return structure.static_fns.get(fnc).copied();
+            }
+            return self.env_fn(structure, fnc);
+        }
+        if let Some((scope, fnc)) = name.rsplit_once("::") {
+            if let Some(namespace) = self.namespaces.get(scope) {
+                return namespace.fns.get(fnc).copied();
+            }
+            return self.env_fn(scope, fnc);
+        }
+        let scope = self.scope();
+        if let Some(arity) = scope.fns.get(name) {
+            return Some(*arity);
+        }
+        if scope.imports.contains(name) {
+            return Some(None);
+        }
+        None
+    }
+
+    fn env_fn(&self, scope: &str, name: &str) -> Option<Option<usize>> {
+        self.env
+            .scopes
+            .get(scope)
+            .filter(|it| it.functions.iter().any(|f| f == name))
+            .map(|_| None)
+    }
+}
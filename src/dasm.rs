@@ -0,0 +1,335 @@
+use crate::asm::escape;
+use crate::tks::{Expression, Keyword, Literal, Token, TokenChain};
+
+/// Renders a token chain back into readable pseudocode
+pub fn disassemble(chain: &TokenChain) -> String {
+    let mut dasm = Disassembler {
+        tks: chain,
+        pos: 0,
+        indent: 0,
+        out: String::new(),
+    };
+    dasm.statements(None);
+    dasm.out
+}
+
+/// Renders a single token as an inline expression
+pub fn render_token(tk: &Token) -> String {
+    match tk {
+        Token::Whitespace => " ".to_string(),
+        Token::LBracket => "{".to_string(),
+        Token::RBracket => "}".to_string(),
+        Token::LParen => "(".to_string(),
+        Token::RParen => ")".to_string(),
+        Token::LSquare => "[".to_string(),
+        Token::RSquare => "]".to_string(),
+        Token::Literal(lit) => render_literal(lit),
+        Token::Keyword(kw) => kw.to_string(),
+        Token::Expression(expr) => render_expr(expr),
+        Token::Attribute(name, value) => format!("@{}({})", name, render_literal(value)),
+        Token::End => "<end>".to_string(),
+        Token::Comment(text) => text.to_owned(),
+    }
+}
+
+/// Renders a literal the way it would be written in source
+pub fn render_literal(lit: &Literal) -> String {
+    match lit {
+        Literal::String(v) => format!("\"{}\"", escape(v)),
+        Literal::Char('\'') => "'\\''".to_string(),
+        Literal::Char(v) => format!("'{}'", escape(&v.to_string())),
+        Literal::Float(v) => format!("{:?}", v),
+        Literal::Decimal(v) => format!("{}d", v),
+        Literal::Array(v) => format!("[{}]", _join(v.iter().map(render_literal))),
+        Literal::Enum(path, payload) if **payload == Literal::Void => path.to_owned(),
+        Literal::Enum(path, payload) => format!("{}({})", path, render_literal(payload)),
+        _ => lit.to_string(),
+    }
+}
+
+fn render_expr(expr: &Expression) -> String {
+    match expr {
+        Expression::BinaryOp(op, lh, rh) => {
+            format!("{} {} {}", _operand(lh), op, _operand(rh))
+        }
+        Expression::UnaryOp(op, v) => format!("{}{}", op, _operand(v)),
+        Expression::StaticAccess(path) => path.join("::"),
+        Expression::InvokeStatic(name, params) => {
+            format!("{}({})", name, _join(params.iter().map(render_token)))
+        }
+        Expression::Instantiate(name, fields) if fields.is_empty() => format!("{} {{}}", name),
+        Expression::Instantiate(name, fields) => {
+            format!("{} {{ {} }}", name, _join(fields.iter().map(render_token)))
+        }
+        Expression::InstanceAccess(receiver, field) => format!("{}.{}", receiver, field),
+        Expression::InvokeInstance(receiver, name, params) => format!(
+            "{}.{}({})",
+            receiver,
+            name,
+            _join(params.iter().map(render_token))
+        ),
+        Expression::InvokeChained(receiver, name, params) => format!(
+            "{}.{}({})",
+            _operand(receiver),
+            name,
+            _join(params.iter().map(render_token))
+        ),
+        Expression::Array(values) => format!("[{}]", _join(values.iter().map(render_token))),
+        Expression::Ternary(condition, then, otherwise) => format!(
+            "{} ? {} : {}",
+            _operand(condition),
+            _operand(then),
+            _operand(otherwise)
+        ),
+        Expression::Index(value, index) => {
+            format!("{}[{}]", _operand(value), render_token(index))
+        }
+        Expression::IfStmt => "if".to_string(),
+        Expression::ElseStmt => "else".to_string(),
+        Expression::ElifStmt => "elif".to_string(),
+        Expression::WhileStmt => "while".to_string(),
+        Expression::DoWhileStmt => "do".to_string(),
+        Expression::MatchStmt => "match".to_string(),
+    }
+}
+
+fn _operand(tk: &Token) -> String {
+    match tk.as_expr() {
+        Some(Expression::BinaryOp(..) | Expression::Ternary(..)) => {
+            format!("({})", render_token(tk))
+        }
+        _ => render_token(tk),
+    }
+}
+
+fn _join<I>(iter: I) -> String
+where
+    I: Iterator<Item = String>,
+{
+    iter.collect::<Vec<String>>().join(", ")
+}
+
+struct Disassembler<'a> {
+    tks: &'a [Token],
+    pos: usize,
+    indent: usize,
+    out: String,
+}
+
+impl<'a> Disassembler<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tks.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let tk = self.tks.get(self.pos);
+        self.pos += 1;
+        tk
+    }
+
+    fn next_str(&mut self) -> String {
+        match self.next() {
+            Some(tk) => render_token(tk),
+            None => "<end>".to_string(),
+        }
+    }
+
+    fn line(&mut self, line: String) {
+        self.out.push_str(&"    ".repeat(self.indent));
+        self.out.push_str(&line);
+        self.out.push('\n');
+    }
+
+    /// Renders statements until the end of chain, or until the `closing` token
+    fn statements(&mut self, closing: Option<&Token>) {
+        while let Some(tk) = self.peek() {
+            if Some(tk) == closing {
+                return;
+            }
+            self.statement();
+        }
+    }
+
+    /// Renders `{ ... }` block, header of which is already provided
+    fn block(&mut self, header: String) {
+        if self.peek() != Some(&Token::LBracket) {
+            self.line(format!("{};", header));
+            return;
+        }
+        self.next();
+        if header.is_empty() {
+            self.line("{".to_string());
+        } else {
+            self.line(format!("{} {{", header));
+        }
+        self.indent += 1;
+        self.statements(Some(&Token::RBracket));
+        self.indent -= 1;
+        self.next();
+        self.line("}".to_string());
+    }
+
+    /// Renders a `{ ... }` block of other tokens than statements, like enum variants
+    fn braced(&mut self, header: String, body: impl FnOnce(&mut Self)) {
+        if self.peek() != Some(&Token::LBracket) {
+            self.line(format!("{};", header));
+            return;
+        }
+        self.next();
+        self.line(format!("{} {{", header));
+        self.indent += 1;
+        body(self);
+        self.statements(Some(&Token::RBracket));
+        self.indent -= 1;
+        self.next();
+        self.line("}".to_string());
+    }
+
+    /// Renders a destructuring pattern, opening token of which is the next one
+    fn pattern(&mut self) -> String {
+        let opening = self.next_str();
+        let mut names = vec![];
+        while let Some(Token::Literal(Literal::Ident(name))) = self.peek() {
+            self.next();
+            names.push(name.to_owned());
+        }
+        let closing = self.next_str();
+        format!("{}{}{}", opening, names.join(", "), closing)
+    }
+
+    fn statement(&mut self) {
+        let tk = self.next().unwrap();
+        match tk {
+            Token::Keyword(kw) => self.keyword(*kw),
+            Token::Literal(Literal::Ident(name)) => match self.peek() {
+                Some(Token::LBracket) => self.block(name.to_owned()),
+                Some(Token::Literal(Literal::TypeName(ty))) => {
+                    self.next();
+                    match self.peek() {
+                        Some(Token::Literal(lit)) if !matches!(lit, Literal::Ident(_)) => {
+                            let default = self.next_str();
+                            self.line(format!("{} {} {}", name, ty, default))
+                        }
+                        _ => self.line(format!("{} {}", name, ty)),
+                    }
+                }
+                _ => self.line(format!("{};", name)),
+            },
+            Token::Expression(expr) => match expr.as_ref() {
+                Expression::IfStmt | Expression::ElifStmt | Expression::WhileStmt => {
+                    let condition = self.next_str();
+                    self.block(format!("{} {}", render_expr(expr), condition))
+                }
+                Expression::ElseStmt => self.block("else".to_string()),
+                Expression::DoWhileStmt => {
+                    self.block("do".to_string());
+                    let condition = self.next_str();
+                    self.out.truncate(self.out.len() - 1);
+                    self.out.push_str(&format!(" while {};\n", condition));
+                }
+                Expression::MatchStmt => {
+                    let value = self.next_str();
+                    self.braced(format!("match {}", value), |dasm| {
+                        while let Some(Token::Literal(Literal::TypeName(pattern))) = dasm.peek() {
+                            dasm.next();
+                            let header = match dasm.next() {
+                                Some(Token::Literal(Literal::Ident(binding))) if binding != "_" => {
+                                    format!("{}({}) =>", pattern, binding)
+                                }
+                                _ => format!("{} =>", pattern),
+                            };
+                            dasm.block(header);
+                        }
+                    })
+                }
+                _ => self.line(format!("{};", render_expr(expr))),
+            },
+            Token::Literal(lit) => self.line(format!("{};", render_literal(lit))),
+            Token::Attribute(..) => self.line(render_token(tk)),
+            Token::LBracket => {
+                self.pos -= 1;
+                self.block(String::new());
+            }
+            Token::Whitespace => {}
+            _ => self.line(render_token(tk)),
+        }
+    }
+
+    fn keyword(&mut self, kw: Keyword) {
+        match kw {
+            Keyword::Let
+                if matches!(
+                    self.peek(),
+                    Some(Token::LSquare | Token::LParen | Token::LBracket)
+                ) =>
+            {
+                let pattern = self.pattern();
+                let value = self.next_str();
+                self.line(format!("{} {} = {};", kw, pattern, value))
+            }
+            Keyword::Let | Keyword::Const | Keyword::Static => {
+                let mut name = self.next_str();
+                if let Some(Token::Literal(Literal::TypeName(_))) = self.peek() {
+                    name = format!("{}: {}", name, self.next_str());
+                }
+                let value = self.next_str();
+                self.line(format!("{} {} = {};", kw, name, value))
+            }
+            Keyword::Export | Keyword::Import | Keyword::Return => {
+                let value = self.next_str();
+                self.line(format!("{} {};", kw, value))
+            }
+            Keyword::Namespace | Keyword::Test => {
+                let name = self.next_str();
+                self.block(format!("{} {}", kw, name))
+            }
+            Keyword::Init => self.block(kw.to_string()),
+            Keyword::Pub | Keyword::Priv => {
+                let at = self.out.len() + self.indent * 4;
+                self.statement();
+                let at = at.min(self.out.len());
+                self.out.insert_str(at, &format!("{} ", kw));
+            }
+            Keyword::Enum => {
+                let name = self.next_str();
+                self.braced(format!("enum {}", name), |dasm| {
+                    while let Some(Token::Literal(Literal::Ident(variant))) = dasm.peek() {
+                        dasm.next();
+                        match dasm.next() {
+                            Some(Token::Literal(Literal::TypeName(ty))) if ty != "void" => {
+                                dasm.line(format!("{}({}),", variant, ty))
+                            }
+                            _ => dasm.line(format!("{},", variant)),
+                        }
+                    }
+                })
+            }
+            Keyword::Function => {
+                let mut header = kw.to_string();
+                if let Some(Token::Keyword(modifier)) = self.peek() {
+                    self.next();
+                    header = format!("{} {}", modifier, header);
+                }
+                let out_ty = self.next_str();
+                let name = self.next_str();
+                let mut params = vec![];
+                if self.peek() == Some(&Token::LParen) {
+                    self.next();
+                    while let Some(tk) = self.next() {
+                        if tk == &Token::RParen {
+                            break;
+                        }
+                        params.push(render_token(tk));
+                    }
+                }
+                self.block(format!(
+                    "{} {} {}({})",
+                    header,
+                    out_ty,
+                    name,
+                    params.join(", ")
+                ))
+            }
+        }
+    }
+}
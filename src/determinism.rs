@@ -0,0 +1,143 @@
+//! Sources of randomness, time and identifiers of a Vm, which can be made reproducible
+//! with [`Vm::set_deterministic`](crate::visit::Vm::set_deterministic).
+
+use crate::platform;
+use rand::rngs::StdRng;
+#[cfg(not(target_arch = "wasm32"))]
+use rand::rngs::ThreadRng;
+use rand::{RngCore, SeedableRng};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// State of a deterministic Vm, shared by all of its clones
+#[derive(Debug)]
+pub struct Deterministic {
+    seed: u64,
+    rng: Mutex<StdRng>,
+    /// Milliseconds since the unix epoch
+    clock: AtomicU64,
+    ids: AtomicU64,
+}
+
+impl Deterministic {
+    /// Starts a PRNG from `seed`, with the virtual clock at the unix epoch
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            clock: AtomicU64::new(0),
+            ids: AtomicU64::new(0),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn now(&self) -> Duration {
+        Duration::from_millis(self.clock.load(Ordering::SeqCst))
+    }
+
+    pub fn set_now(&self, since_epoch: Duration) {
+        self.clock
+            .store(since_epoch.as_millis() as u64, Ordering::SeqCst)
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.clock
+            .fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    fn next_id(&self) -> u64 {
+        self.ids.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// Randomness, time and identifiers used by a Vm, real ones unless it is deterministic
+#[derive(Debug, Clone, Default)]
+pub struct Entropy(Option<Arc<Deterministic>>);
+
+impl Entropy {
+    pub fn deterministic(seed: u64) -> Self {
+        Self(Some(Arc::new(Deterministic::new(seed))))
+    }
+
+    pub fn state(&self) -> Option<&Deterministic> {
+        self.0.as_deref()
+    }
+
+    pub fn rng(&self) -> VmRng {
+        match &self.0 {
+            Some(state) => VmRng::Seeded(state.clone()),
+            #[cfg(not(target_arch = "wasm32"))]
+            None => VmRng::Thread(rand::thread_rng()),
+            #[cfg(target_arch = "wasm32")]
+            None => VmRng::Host(StdRng::seed_from_u64(platform::random_u64())),
+        }
+    }
+
+    /// Time since the unix epoch, as told by the virtual clock in deterministic mode
+    pub fn now(&self) -> Duration {
+        match &self.0 {
+            Some(state) => state.now(),
+            None => platform::now(),
+        }
+    }
+
+    /// Blocks the thread, or only advances the virtual clock in deterministic mode
+    pub fn sleep(&self, by: Duration) {
+        match &self.0 {
+            Some(state) => state.advance(by),
+            None => platform::sleep(by),
+        }
+    }
+
+    /// Identifier for temporary scopes, which counts up in deterministic mode
+    pub fn next_id(&self) -> u64 {
+        match &self.0 {
+            Some(state) => state.next_id(),
+            None => platform::random_u64(),
+        }
+    }
+}
+
+/// Random number generator of a Vm, see [`Entropy::rng`]
+pub enum VmRng {
+    #[cfg(not(target_arch = "wasm32"))]
+    Thread(ThreadRng),
+    /// Seeded from the host, as `wasm32` has no thread local generator
+    #[cfg(target_arch = "wasm32")]
+    Host(StdRng),
+    Seeded(Arc<Deterministic>),
+}
+
+impl VmRng {
+    fn with<T>(&mut self, fun: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            VmRng::Thread(rng) => fun(rng),
+            #[cfg(target_arch = "wasm32")]
+            VmRng::Host(rng) => fun(rng),
+            VmRng::Seeded(state) => fun(&mut *state.rng.lock().unwrap()),
+        }
+    }
+}
+
+impl RngCore for VmRng {
+    fn next_u32(&mut self) -> u32 {
+        self.with(|rng| rng.next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.with(|rng| rng.next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.with(|rng| rng.fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.with(|rng| rng.try_fill_bytes(dest))
+    }
+}
@@ -0,0 +1,241 @@
+//! Rendering of problems as excerpts of their source with the offending part underlined,
+//! in the style of rustc:
+//!
+//! ```text
+//! error[UnknownFunction]: Could not find function std::io::printn!
+//!  --> main.gale:3:5
+//!   |
+//! 3 |     std::io::printn("hi");
+//!   |     ^^^^^^^^^^^^^^^^^^^^^
+//!   |
+//!   = help: did you mean std::io::println?
+//! ```
+//!
+//! Errors of the Vm about names it can not find suggest similar names with [`did_you_mean`],
+//! which are rendered as the help of the report.
+
+use crate::check::Diagnostic;
+use crate::span::Span;
+use crate::warn::Warning;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// A problem to render, converted from a [`Diagnostic`], a [`Warning`] or an error
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub severity: Severity,
+    /// Kind or code of the problem, shown in brackets after the severity
+    pub code: Option<String>,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Name of the source, like its path
+    pub origin: Option<String>,
+    /// Suggestion shown below the excerpt
+    pub help: Option<String>,
+}
+
+impl Report {
+    pub fn new<S: Into<String>>(severity: Severity, message: S) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            span: None,
+            origin: None,
+            help: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_origin<S: ToString>(mut self, origin: S) -> Self {
+        self.origin = Some(origin.to_string());
+        self
+    }
+
+    pub fn with_help<S: Into<String>>(mut self, help: S) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl From<&Diagnostic> for Report {
+    fn from(diagnostic: &Diagnostic) -> Self {
+        Self {
+            code: Some(format!("{:?}", diagnostic.kind)),
+            span: diagnostic.span,
+            ..Report::new(Severity::Error, diagnostic.message.clone())
+        }
+    }
+}
+
+impl From<&Warning> for Report {
+    fn from(warning: &Warning) -> Self {
+        Self {
+            code: Some(warning.code.to_string()),
+            span: warning.span,
+            ..Report::new(Severity::Warning, warning.message.clone())
+        }
+    }
+}
+
+impl From<&anyhow::Error> for Report {
+    /// Errors of the assembler only tell the line, which is underlined as a whole
+    fn from(err: &anyhow::Error) -> Self {
+        let message = err.to_string();
+        let line = message.rfind(" at line ").and_then(|at| {
+            let digits = &message[at + " at line ".len()..];
+            let end = digits
+                .find(|it: char| !it.is_ascii_digit())
+                .unwrap_or(digits.len());
+            digits[..end].parse::<u32>().ok()
+        });
+        // suggestions of `did_you_mean` are moved below the excerpt
+        let (message, help) = match message.split_once(" Did you mean ") {
+            Some((message, rest)) => (message.to_string(), Some(format!("did you mean {}", rest))),
+            None => (message, None),
+        };
+        Self {
+            // the column and length are filled in by `render`, which knows the source
+            span: line.map(|line| Span::new(line, 0, 0)),
+            help,
+            ..Report::new(Severity::Error, message)
+        }
+    }
+}
+
+/// Levenshtein distance between two names, where swapping two adjacent characters also
+/// counts as a single edit, since it is a common typo
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // rows of the distances for the two previous characters of `a`, and the current one
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            current[j] = (previous[j - 1] + cost)
+                .min(previous[j] + 1)
+                .min(current[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+/// Up to three of the `candidates` closest to `name`, nearest first. Candidates further
+/// than a third of the length of `name` away are not similar enough to suggest
+pub fn suggest<I>(name: &str, candidates: I) -> Vec<String>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let limit = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, String)> = candidates
+        .into_iter()
+        .filter(|it| it.as_ref() != name)
+        .filter_map(|it| {
+            let distance = edit_distance(name, it.as_ref());
+            (distance <= limit).then(|| (distance, it.as_ref().to_string()))
+        })
+        .collect();
+    close.sort();
+    close.dedup_by(|a, b| a.1 == b.1);
+    close.into_iter().take(3).map(|(_, it)| it).collect()
+}
+
+/// Sentence appended to errors about an unknown `name`, like ` Did you mean value?`,
+/// or nothing if none of the `candidates` are close to it. See [`suggest`]
+pub fn did_you_mean<I>(name: &str, candidates: I) -> String
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    match suggest(name, candidates).as_slice() {
+        [] => String::new(),
+        [only] => format!(" Did you mean {}?", only),
+        [rest @ .., last] => format!(" Did you mean {} or {}?", rest.join(", "), last),
+    }
+}
+
+/// Renders a problem with the line of `source` it points at. Problems without a span,
+/// or with one outside of the source, are rendered without an excerpt
+pub fn render<R: Into<Report>>(source: &str, report: R) -> String {
+    let report = report.into();
+    let mut out = match &report.code {
+        Some(code) => format!("{}[{}]: {}\n", report.severity, code, report.message),
+        None => format!("{}: {}\n", report.severity, report.message),
+    };
+    let line = report.span.and_then(|span| {
+        Some((
+            span,
+            source.lines().nth(span.line.checked_sub(1)? as usize)?,
+        ))
+    });
+    let gutter = line.map_or(0, |(span, _)| span.line.to_string().len());
+    let pad = " ".repeat(gutter);
+    let location = match (&report.origin, report.span) {
+        (Some(origin), Some(span)) => Some(format!("{}:{}", origin, _column(span, line))),
+        (Some(origin), None) => Some(origin.to_owned()),
+        (None, Some(span)) => Some(_column(span, line).to_string()),
+        (None, None) => None,
+    };
+    if let Some(location) = location {
+        out.push_str(&format!("{}--> {}\n", pad, location));
+    }
+    if let Some((span, text)) = line {
+        let span = _column(span, line);
+        let chars: Vec<char> = text.chars().collect();
+        let start = (span.col as usize - 1).min(chars.len());
+        let len = (span.len as usize).clamp(1, (chars.len() - start).max(1));
+        // tabs are kept, so the carets line up with the excerpt
+        let indent: String = chars[..start]
+            .iter()
+            .map(|it| if *it == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!("{} |\n", pad));
+        out.push_str(&format!("{} | {}\n", span.line, text));
+        out.push_str(&format!("{} | {}{}\n", pad, indent, "^".repeat(len)));
+    }
+    if let Some(help) = &report.help {
+        if line.is_some() {
+            out.push_str(&format!("{} |\n", pad));
+        }
+        out.push_str(&format!("{} = help: {}\n", pad, help));
+    }
+    out
+}
+
+/// Span with a column, which spans of whole lines get at their first non-blank character
+fn _column(span: Span, line: Option<(Span, &str)>) -> Span {
+    match line {
+        Some((_, text)) if span.col == 0 => {
+            let trimmed = text.trim_start();
+            let col = text[..text.len() - trimmed.len()].chars().count() as u32 + 1;
+            Span::new(span.line, col, trimmed.trim_end().chars().count() as u32)
+        }
+        _ => Span::new(span.line, span.col.max(1), span.len),
+    }
+}
@@ -1,19 +1,64 @@
 use crate::stdlib::__core_feature;
+use crate::stdlib::arr::__arr_feature;
+use crate::stdlib::bytes::__bytes_feature;
+use crate::stdlib::hash::__hash_feature;
+use crate::stdlib::chars::__char_feature;
+use crate::stdlib::csv::__csv_feature;
+use crate::stdlib::datetime::__datetime_feature;
+use crate::stdlib::id::__id_feature;
+use crate::stdlib::set::__set_feature;
+use crate::stdlib::collections::__collections_feature;
+use crate::stdlib::iter::__iter_feature;
+use crate::stdlib::range::__range_feature;
+use crate::stdlib::matrix::__matrix_feature;
+use crate::stdlib::decimal::__decimal_feature;
+use crate::stdlib::dbg::__dbg_feature;
+use crate::stdlib::files::__files_feature;
 use crate::stdlib::io::__io_feature;
+use crate::stdlib::log::__log_feature;
 use crate::stdlib::math::__math_feature;
 use crate::stdlib::mem::__mem_feature;
+use crate::stdlib::path::__path_feature;
 use crate::stdlib::prelude::__prelude_features;
+use crate::stdlib::random::__rand_feature;
+use crate::stdlib::reflect::__reflect_feature;
 use crate::stdlib::strs::__str_feature;
+use crate::stdlib::test::__test_feature;
+use crate::stdlib::time::__time_feature;
 use crate::visit::Visitor;
+use anyhow::bail;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum StdFeature {
     Core,
     IO,
     Math,
     Strings,
     Memory,
-    Prelude
+    Prelude,
+    Reflect,
+    Arrays,
+    Bytes,
+    Hash,
+    Chars,
+    Log,
+    Test,
+    Rand,
+    Time,
+    Debug,
+    Files,
+    Path,
+    Csv,
+    DateTime,
+    Id,
+    Sets,
+    Collections,
+    Iter,
+    Range,
+    Matrix,
+    Decimal,
 }
 
 impl StdFeature {
@@ -24,7 +69,170 @@ impl StdFeature {
             StdFeature::Math => __math_feature(visitor),
             StdFeature::Strings => __str_feature(visitor),
             StdFeature::Memory => __mem_feature(visitor),
-            StdFeature::Prelude => __prelude_features(visitor)
+            StdFeature::Prelude => __prelude_features(visitor),
+            StdFeature::Reflect => __reflect_feature(visitor),
+            StdFeature::Arrays => __arr_feature(visitor),
+            StdFeature::Bytes => __bytes_feature(visitor),
+            StdFeature::Hash => __hash_feature(visitor),
+            StdFeature::Chars => __char_feature(visitor),
+            StdFeature::Log => __log_feature(visitor),
+            StdFeature::Test => __test_feature(visitor),
+            StdFeature::Rand => __rand_feature(visitor),
+            StdFeature::Time => __time_feature(visitor),
+            StdFeature::Debug => __dbg_feature(visitor),
+            StdFeature::Files => __files_feature(visitor),
+            StdFeature::Path => __path_feature(visitor),
+            StdFeature::Csv => __csv_feature(visitor),
+            StdFeature::DateTime => __datetime_feature(visitor),
+            StdFeature::Id => __id_feature(visitor),
+            StdFeature::Sets => __set_feature(visitor),
+            StdFeature::Collections => __collections_feature(visitor),
+            StdFeature::Iter => __iter_feature(visitor),
+            StdFeature::Range => __range_feature(visitor),
+            StdFeature::Matrix => __matrix_feature(visitor),
+            StdFeature::Decimal => __decimal_feature(visitor),
         }
     }
-}
\ No newline at end of file
+
+    /// Scope the functions of this feature are declared in, prelude only re-exports other features
+    pub fn scope(&self) -> Option<&'static str> {
+        Some(match *self {
+            StdFeature::Core => "std",
+            StdFeature::IO => "std::io",
+            StdFeature::Math => "std::math",
+            StdFeature::Strings => "std::str",
+            StdFeature::Memory => "std::mem",
+            StdFeature::Prelude => return None,
+            StdFeature::Reflect => "std::reflect",
+            StdFeature::Arrays => "std::arr",
+            StdFeature::Bytes => "std::bytes",
+            StdFeature::Hash => "std::hash",
+            StdFeature::Chars => "std::char",
+            StdFeature::Log => "std::log",
+            StdFeature::Test => "std::test",
+            StdFeature::Rand => "std::rand",
+            StdFeature::Time => "std::time",
+            StdFeature::Debug => "std::dbg",
+            StdFeature::Files => "std::io::file",
+            StdFeature::Path => "std::path",
+            StdFeature::Csv => "std::csv",
+            StdFeature::DateTime => "std::datetime",
+            StdFeature::Id => "std::id",
+            StdFeature::Sets => "std::set",
+            StdFeature::Collections => "std::collections",
+            StdFeature::Iter => "std::iter",
+            StdFeature::Range => "std::range",
+            StdFeature::Matrix => "std::math::matrix",
+            StdFeature::Decimal => "std::decimal",
+        })
+    }
+
+    /// Feature which declares the provided scope
+    pub fn from_scope(scope: &str) -> Option<StdFeature> {
+        StdFeature::ALL
+            .iter()
+            .copied()
+            .find(|feature| feature.scope() == Some(scope))
+    }
+
+    pub const ALL: [StdFeature; 27] = [
+        StdFeature::Core,
+        StdFeature::IO,
+        StdFeature::Math,
+        StdFeature::Strings,
+        StdFeature::Memory,
+        StdFeature::Prelude,
+        StdFeature::Reflect,
+        StdFeature::Arrays,
+        StdFeature::Bytes,
+        StdFeature::Hash,
+        StdFeature::Chars,
+        StdFeature::Log,
+        StdFeature::Test,
+        StdFeature::Rand,
+        StdFeature::Time,
+        StdFeature::Debug,
+        StdFeature::Files,
+        StdFeature::Path,
+        StdFeature::Csv,
+        StdFeature::DateTime,
+        StdFeature::Id,
+        StdFeature::Sets,
+        StdFeature::Collections,
+        StdFeature::Iter,
+        StdFeature::Range,
+        StdFeature::Matrix,
+        StdFeature::Decimal,
+    ];
+}
+
+impl Display for StdFeature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StdFeature::Core => "core",
+            StdFeature::IO => "io",
+            StdFeature::Math => "math",
+            StdFeature::Strings => "strings",
+            StdFeature::Memory => "memory",
+            StdFeature::Prelude => "prelude",
+            StdFeature::Reflect => "reflect",
+            StdFeature::Arrays => "arrays",
+            StdFeature::Bytes => "bytes",
+            StdFeature::Hash => "hash",
+            StdFeature::Chars => "chars",
+            StdFeature::Log => "log",
+            StdFeature::Test => "test",
+            StdFeature::Rand => "rand",
+            StdFeature::Time => "time",
+            StdFeature::Debug => "debug",
+            StdFeature::Files => "files",
+            StdFeature::Path => "path",
+            StdFeature::Csv => "csv",
+            StdFeature::DateTime => "datetime",
+            StdFeature::Id => "id",
+            StdFeature::Sets => "sets",
+            StdFeature::Collections => "collections",
+            StdFeature::Iter => "iter",
+            StdFeature::Range => "range",
+            StdFeature::Matrix => "matrix",
+            StdFeature::Decimal => "decimal",
+        })
+    }
+}
+
+impl FromStr for StdFeature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "core" => StdFeature::Core,
+            "io" => StdFeature::IO,
+            "math" => StdFeature::Math,
+            "str" | "strings" => StdFeature::Strings,
+            "mem" | "memory" => StdFeature::Memory,
+            "prelude" => StdFeature::Prelude,
+            "reflect" => StdFeature::Reflect,
+            "arr" | "arrays" => StdFeature::Arrays,
+            "bytes" => StdFeature::Bytes,
+            "hash" => StdFeature::Hash,
+            "char" | "chars" => StdFeature::Chars,
+            "log" => StdFeature::Log,
+            "test" => StdFeature::Test,
+            "rand" | "random" => StdFeature::Rand,
+            "time" => StdFeature::Time,
+            "dbg" | "debug" => StdFeature::Debug,
+            "fs" | "file" | "files" => StdFeature::Files,
+            "path" => StdFeature::Path,
+            "csv" => StdFeature::Csv,
+            "datetime" | "date" => StdFeature::DateTime,
+            "id" | "uuid" => StdFeature::Id,
+            "set" | "sets" => StdFeature::Sets,
+            "collections" => StdFeature::Collections,
+            "iter" | "iterators" => StdFeature::Iter,
+            "range" | "ranges" => StdFeature::Range,
+            "matrix" | "matrices" => StdFeature::Matrix,
+            "decimal" | "decimals" => StdFeature::Decimal,
+            _ => bail!("Unknown std feature {}!", s),
+        })
+    }
+}
@@ -0,0 +1,485 @@
+//! Canonical formatting of gale source, used by `gale fmt`.
+//!
+//! The source is assembled with its comments kept, [lifted](crate::ast::lift) into statements
+//! and printed again with four spaces of indentation, one statement per line and opening
+//! braces on the line of their header:
+//!
+//! ```text
+//! // sums the fields
+//! fn num sum(p) {
+//!     return p.x + p.y;
+//! }
+//!
+//! if total > 3 {
+//!     std::io::println(total);
+//! } else {
+//!     total = 0;
+//! }
+//! ```
+//!
+//! Items with bodies, like functions, structures and namespaces, are separated from the
+//! statements around them by an empty line, and empty bodies are printed as `{}`. Comments
+//! inside of a statement are moved after it, see [`AssembleOptions::comments`]. Comments
+//! written after code stay on the line of the statement before them, like `let a = 1; // one`.
+//! Raw strings and text blocks are printed as they were written, with text blocks indented one
+//! level deeper than their statement.
+//! Sources with preprocessor directives or macros can not be formatted, since assembling them
+//! replaces those.
+
+use crate::asm::{
+    _has_expansions, _precedence, _source_layout, assemble_with, escape, AssembleOptions,
+    SourceLayout, StrForm, RANGE_PREC, TERNARY_PREC,
+};
+use crate::ast::{lift, Expr, PatternKind, Stmt};
+use crate::dasm::render_literal;
+use crate::tks::{BinaryOp, Literal};
+use anyhow::bail;
+
+/// Formats the source, failing if it can not be assembled
+pub fn format(src: &str) -> anyhow::Result<String> {
+    if _has_expansions(src)? {
+        bail!("Can not format source with preprocessor directives or macros!")
+    }
+    let chain = assemble_with(src, &AssembleOptions::default().with_comments())?;
+    let mut printer = Printer::new(_source_layout(src)?);
+    printer.statements(&lift(&chain)?);
+    Ok(printer.out)
+}
+
+/// Whether formatting the source would not change it
+pub fn is_formatted(src: &str) -> anyhow::Result<bool> {
+    Ok(format(src)? == src)
+}
+
+/// Prints statements as formatted source
+pub fn format_block(block: &[Stmt]) -> String {
+    let mut printer = Printer::new(SourceLayout::default());
+    printer.statements(block);
+    printer.out
+}
+
+/// Functions, structures and other statements with bodies, which get empty lines around them
+fn _is_item(stmt: &Stmt) -> bool {
+    matches!(
+        stmt,
+        Stmt::Function { .. }
+            | Stmt::Namespace { .. }
+            | Stmt::Test { .. }
+            | Stmt::Init(_)
+            | Stmt::Enum { .. }
+            | Stmt::Struct { .. }
+    )
+}
+
+/// Statements that belong to the one after them
+fn _is_attached(stmt: &Stmt) -> bool {
+    matches!(
+        stmt,
+        Stmt::Comment(_) | Stmt::Attribute(..) | Stmt::Visibility(_)
+    )
+}
+
+/// Bounds of a range written as `a..b` or `a..=b`, which is assembled as a `Range` structure
+fn _range(expr: &Expr) -> Option<(&Expr, &Expr, bool)> {
+    let fields = match expr {
+        Expr::Instantiate(name, fields) if name == "Range" => fields,
+        _ => return None,
+    };
+    let field = |index: usize, name: &str| match fields.get(index) {
+        Some(Expr::Binary(BinaryOp::Assign, field, value))
+            if **field == Expr::Literal(Literal::Ident(name.to_string())) =>
+        {
+            Some(value.as_ref())
+        }
+        _ => None,
+    };
+    match (
+        fields.len(),
+        field(0, "start"),
+        field(1, "end"),
+        field(2, "inclusive"),
+    ) {
+        (3, Some(start), Some(end), Some(Expr::Literal(Literal::Bool(inclusive)))) => {
+            Some((start, end, *inclusive))
+        }
+        _ => None,
+    }
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+    layout: SourceLayout,
+}
+
+impl Printer {
+    fn new(layout: SourceLayout) -> Self {
+        Self {
+            out: String::new(),
+            indent: 0,
+            layout,
+        }
+    }
+
+    /// Renders an expression with only the parentheses its operators need
+    fn expr(&mut self, expr: &Expr) -> String {
+        if let Some((start, end, inclusive)) = _range(expr) {
+            let start = self.operand(start, RANGE_PREC + 1);
+            return match end {
+                Expr::Literal(Literal::Number(i64::MAX)) if !inclusive => format!("{}..", start),
+                _ if inclusive => format!("{}..={}", start, self.operand(end, RANGE_PREC + 1)),
+                _ => format!("{}..{}", start, self.operand(end, RANGE_PREC + 1)),
+            };
+        }
+        match expr {
+            Expr::Literal(lit) => self.literal(lit),
+            Expr::Binary(op, lh, rh) => {
+                let prec = _precedence(*op);
+                // assignment is right associative, everything else is left associative
+                let (left, right) = match op {
+                    BinaryOp::Assign => (prec + 1, prec),
+                    _ => (prec, prec + 1),
+                };
+                format!(
+                    "{} {} {}",
+                    self.operand(lh, left),
+                    op,
+                    self.operand(rh, right)
+                )
+            }
+            Expr::Unary(op, value) => format!("{}{}", op, self.operand(value, u8::MAX)),
+            Expr::StaticAccess(path) => path.join("::"),
+            Expr::InvokeStatic(name, params) => format!("{}({})", name, self.list(params)),
+            Expr::Instantiate(name, fields) if fields.is_empty() => format!("{} {{}}", name),
+            Expr::Instantiate(name, fields) => format!("{} {{ {} }}", name, self.list(fields)),
+            Expr::InstanceAccess(receiver, field) => format!("{}.{}", receiver, field),
+            Expr::InvokeInstance(receiver, name, params) => {
+                format!("{}.{}({})", receiver, name, self.list(params))
+            }
+            Expr::InvokeChained(receiver, name, params) => format!(
+                "{}.{}({})",
+                self.operand(receiver, u8::MAX),
+                name,
+                self.list(params)
+            ),
+            Expr::Array(values) => format!("[{}]", self.list(values)),
+            Expr::Index(value, index) => {
+                format!("{}[{}]", self.operand(value, u8::MAX), self.expr(index))
+            }
+            Expr::Ternary(cond, then, otherwise) => format!(
+                "{} ? {} : {}",
+                self.operand(cond, TERNARY_PREC + 1),
+                self.operand(then, TERNARY_PREC),
+                self.operand(otherwise, TERNARY_PREC)
+            ),
+        }
+    }
+
+    /// Renders an operand, in parentheses if its operator binds looser than `min`
+    fn operand(&mut self, expr: &Expr, min: u8) -> String {
+        let prec = match expr {
+            Expr::Binary(op, ..) => _precedence(*op),
+            Expr::Ternary(..) => TERNARY_PREC,
+            _ if _range(expr).is_some() => RANGE_PREC,
+            _ => u8::MAX,
+        };
+        if prec < min {
+            format!("({})", self.expr(expr))
+        } else {
+            self.expr(expr)
+        }
+    }
+
+    fn list(&mut self, exprs: &[Expr]) -> String {
+        exprs
+            .iter()
+            .map(|it| self.expr(it))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Renders a literal, with its strings in the form they were written in
+    fn literal(&mut self, lit: &Literal) -> String {
+        match lit {
+            Literal::String(value) => self.string(value),
+            Literal::Array(values) => {
+                let values: Vec<_> = values.iter().map(|it| self.literal(it)).collect();
+                format!("[{}]", values.join(", "))
+            }
+            _ => render_literal(lit),
+        }
+    }
+
+    /// Renders a string as a raw string or text block if it was written as one. Text blocks
+    /// are indented one level deeper than the statement they are in
+    fn string(&mut self, value: &str) -> String {
+        let form = self
+            .layout
+            .strings
+            .get_mut(value)
+            .and_then(|it| it.pop_front());
+        match form {
+            Some(StrForm::Raw(hashes)) => {
+                let hashes = "#".repeat(hashes);
+                format!("r{}\"{}\"{}", hashes, value, hashes)
+            }
+            Some(StrForm::TextBlock) => {
+                let indent = "    ".repeat(self.indent + 1);
+                let mut out = "\"\"\"\n".to_string();
+                for line in value.split('\n') {
+                    if !line.is_empty() {
+                        out.push_str(&indent);
+                        out.push_str(line);
+                    }
+                    out.push('\n');
+                }
+                format!("{}{}\"\"\"", out, indent)
+            }
+            _ => format!("\"{}\"", escape(value)),
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        self.out.push_str(&"    ".repeat(self.indent));
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn statements(&mut self, block: &[Stmt]) {
+        let mut previous: Option<&Stmt> = None;
+        // `pub` or `priv` in front of the next statement
+        let mut modifiers = String::new();
+        for (index, stmt) in block.iter().enumerate() {
+            if let Stmt::Comment(text) = stmt {
+                // comments written after code stay on the line of the statement before them
+                if self.is_trailing(text) && previous.is_some() && modifiers.is_empty() {
+                    self.out.pop();
+                    self.out.push_str(&format!(" {}\n", text));
+                    continue;
+                }
+            }
+            if let Some(previous) = previous.filter(|it| !_is_attached(it)) {
+                // comments and attributes are separated along with the item they belong to
+                let subject = block[index..]
+                    .iter()
+                    .find(|it| !_is_attached(it))
+                    .unwrap_or(stmt);
+                if _is_item(previous) || _is_item(subject) {
+                    self.out.push('\n');
+                }
+            }
+            previous = Some(stmt);
+            match stmt {
+                Stmt::Visibility(kw) => modifiers.push_str(&format!("{} ", kw)),
+                _ => self.statement(&std::mem::take(&mut modifiers), stmt),
+            }
+        }
+    }
+
+    /// Whether the comment was written after code on its line. Comments are looked up in the
+    /// order they are written, skipping those that were not printed
+    fn is_trailing(&mut self, text: &str) -> bool {
+        let comments = &mut self.layout.comments;
+        match comments.iter().position(|(it, _)| it == text) {
+            Some(at) => comments
+                .drain(..=at)
+                .next_back()
+                .is_some_and(|(_, trailing)| trailing),
+            None => false,
+        }
+    }
+
+    /// Prints `header { ... }`, or `header {}` for an empty body
+    fn braced(&mut self, header: &str, body: &[Stmt]) {
+        let open = if header.is_empty() {
+            "{".to_string()
+        } else {
+            format!("{} {{", header)
+        };
+        if body.is_empty() {
+            self.line(&format!("{}}}", open));
+            return;
+        }
+        self.line(&open);
+        self.body(body);
+        self.line("}");
+    }
+
+    /// Prints bodies that follow each other, each opened on the closing line of the one
+    /// before it, like `} elif cond {` or `} else {}`, and `tail` after the last one
+    fn chained(&mut self, modifiers: &str, parts: &[(String, &[Stmt])], tail: &str) {
+        let mut line = modifiers.to_string();
+        for (header, body) in parts {
+            line.push_str(&format!("{} {{", header));
+            if body.is_empty() {
+                line.push_str("} ");
+                continue;
+            }
+            self.line(&line);
+            self.body(body);
+            line = "} ".to_string();
+        }
+        self.line(&format!("{}{}", line.trim_end(), tail));
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        self.indent += 1;
+        self.statements(body);
+        self.indent -= 1;
+    }
+
+    /// Prints `export`, `import` or `return` with its value
+    fn keyword(&mut self, modifiers: &str, kw: &str, value: &Expr) {
+        let value = self.expr(value);
+        self.line(&format!("{}{} {};", modifiers, kw, value))
+    }
+
+    fn statement(&mut self, modifiers: &str, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => {
+                let expr = self.expr(expr);
+                self.line(&format!("{}{};", modifiers, expr))
+            }
+            Stmt::Declare {
+                kw,
+                name,
+                ty,
+                value,
+            } => {
+                let name = match ty {
+                    Some(ty) => format!("{}: {}", name, ty),
+                    None => name.to_owned(),
+                };
+                let value = self.expr(value);
+                self.line(&format!("{}{} {} = {};", modifiers, kw, name, value))
+            }
+            Stmt::Destructure { pattern, value } => {
+                let (opening, closing) = match pattern.kind {
+                    PatternKind::Array => ("[", "]"),
+                    PatternKind::Tuple => ("(", ")"),
+                    PatternKind::Struct => ("{", "}"),
+                };
+                let value = self.expr(value);
+                self.line(&format!(
+                    "{}let {}{}{} = {};",
+                    modifiers,
+                    opening,
+                    pattern.names.join(", "),
+                    closing,
+                    value
+                ))
+            }
+            Stmt::Export(value) => self.keyword(modifiers, "export", value),
+            Stmt::Import(value) => self.keyword(modifiers, "import", value),
+            Stmt::Return(value) => self.keyword(modifiers, "return", value),
+            Stmt::Function {
+                modifier,
+                out_ty,
+                name,
+                native,
+                params,
+                body,
+            } => {
+                let modifier = modifier.map(|it| format!("{} ", it)).unwrap_or_default();
+                let name = if *native {
+                    self.string(name)
+                } else {
+                    name.to_owned()
+                };
+                self.braced(
+                    &format!(
+                        "{}{}fn {} {}({})",
+                        modifiers,
+                        modifier,
+                        out_ty,
+                        name,
+                        params.join(", ")
+                    ),
+                    body,
+                )
+            }
+            Stmt::Namespace { name, body } => {
+                self.braced(&format!("{}namespace {}", modifiers, name), body)
+            }
+            Stmt::Test { name, body } => {
+                let name = self.string(name);
+                self.braced(&format!("{}test {}", modifiers, name), body)
+            }
+            Stmt::Init(body) => self.braced(&format!("{}init", modifiers), body),
+            Stmt::Visibility(kw) => self.line(&format!("{}{}", modifiers, kw)),
+            Stmt::Enum { name, variants } => {
+                let header = format!("{}enum {}", modifiers, name);
+                if variants.is_empty() {
+                    return self.line(&format!("{} {{}}", header));
+                }
+                self.line(&format!("{} {{", header));
+                self.indent += 1;
+                for (variant, ty) in variants {
+                    match ty.as_str() {
+                        "void" => self.line(&format!("{},", variant)),
+                        _ => self.line(&format!("{}({}),", variant, ty)),
+                    }
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+            Stmt::Struct { name, body } => self.braced(&format!("{}{}", modifiers, name), body),
+            Stmt::Field { name, ty, default } => match default {
+                Some(default) => {
+                    let default = self.literal(default);
+                    self.line(&format!("{}{} {} {}", modifiers, name, ty, default))
+                }
+                None => self.line(&format!("{}{} {}", modifiers, name, ty)),
+            },
+            Stmt::If {
+                branches,
+                otherwise,
+            } => {
+                let mut parts = vec![];
+                for (index, (cond, body)) in branches.iter().enumerate() {
+                    let kw = if index == 0 { "if" } else { "elif" };
+                    let cond = self.expr(cond);
+                    parts.push((format!("{} {}", kw, cond), body.as_slice()));
+                }
+                if let Some(otherwise) = otherwise {
+                    parts.push(("else".to_string(), otherwise.as_slice()));
+                }
+                self.chained(modifiers, &parts, "");
+            }
+            Stmt::While { cond, body } => {
+                let cond = self.expr(cond);
+                self.braced(&format!("{}while {}", modifiers, cond), body)
+            }
+            Stmt::DoWhile { body, cond } => {
+                let cond = self.expr(cond);
+                let parts = [("do".to_string(), body.as_slice())];
+                self.chained(modifiers, &parts, &format!(" while {};", cond))
+            }
+            Stmt::Match { value, arms } => {
+                let value = self.expr(value);
+                let header = format!("{}match {}", modifiers, value);
+                if arms.is_empty() {
+                    return self.line(&format!("{} {{}}", header));
+                }
+                self.line(&format!("{} {{", header));
+                self.indent += 1;
+                for arm in arms {
+                    let header = match arm.binding.as_str() {
+                        "_" => format!("{} =>", arm.pattern),
+                        binding => format!("{}({}) =>", arm.pattern, binding),
+                    };
+                    self.braced(&header, &arm.body);
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+            Stmt::Block(body) => self.braced(modifiers.trim_end(), body),
+            Stmt::Attribute(name, value) => {
+                let value = self.literal(value);
+                self.line(&format!("{}@{}({})", modifiers, name, value))
+            }
+            Stmt::End => self.line(&format!("{}<end>", modifiers)),
+            Stmt::Comment(text) => self.line(&format!("{}{}", modifiers, text)),
+        }
+    }
+}
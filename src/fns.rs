@@ -1,25 +1,163 @@
-use std::cmp::max;
+use crate::manifest::ExternSignature;
+use crate::structs::StructureInstance;
 use crate::tks::{Literal, TokenChain};
 use crate::var::ContainingScope;
-use crate::visit::{Scope, Visitor};
+use crate::visit::{Scope, ScopeProvider, Visitor};
 use crate::vm::Transmute;
-use rand::RngCore;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
-use std::io::Cursor;
-use std::sync::Mutex;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
 use anyhow::bail;
 use lazy_static::lazy_static;
 
 pub type Parameters = Vec<Literal>;
+pub type Metadata = HashMap<String, Literal>;
 pub type DynExecutable = dyn Fn(Parameters) -> Literal + Sync + Send;
+pub type DynNativeExecutable = dyn Fn(&mut dyn ScopeProvider, Parameters) -> Literal + Sync + Send;
 
 lazy_static! {
-    pub static ref EXTERN_FNS: Mutex<Vec<Box<DynExecutable>>> = Mutex::new(Vec::new());
+    pub static ref EXTERN_FNS: Mutex<FnRegistry<DynExecutable>> = Mutex::new(FnRegistry::new());
+    pub static ref NATIVE_FNS: Mutex<FnRegistry<DynNativeExecutable>> = Mutex::new(FnRegistry::new());
+}
+
+/// Bits of a handler holding the slot index, the remaining bits hold the slot generation
+const HANDLER_INDEX_BITS: u32 = usize::BITS / 2;
+const HANDLER_INDEX_MASK: usize = (1 << HANDLER_INDEX_BITS) - 1;
+
+struct RegistrySlot<F: ?Sized> {
+    generation: usize,
+    fun: Option<Arc<F>>,
+}
+
+/// Host functions bound by handlers. Handlers never change while a function is registered,
+/// even if it is replaced. Slots of removed functions are reused, but a reused slot gets
+/// a new generation, so handlers of removed functions never point to other functions.
+pub struct FnRegistry<F: ?Sized> {
+    slots: Vec<RegistrySlot<F>>,
+    free: Vec<usize>,
+}
+
+impl<F: ?Sized> FnRegistry<F> {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![],
+            free: vec![],
+        }
+    }
+
+    /// Handlers start at 1, so 0 is never a valid handler
+    fn handler(index: usize, generation: usize) -> usize {
+        (generation << HANDLER_INDEX_BITS) | (index + 1)
+    }
+
+    fn slot(&self, handler: usize) -> Option<&RegistrySlot<F>> {
+        let index = (handler & HANDLER_INDEX_MASK).checked_sub(1)?;
+        self.slots
+            .get(index)
+            .filter(|slot| slot.generation == handler >> HANDLER_INDEX_BITS && slot.fun.is_some())
+    }
+
+    pub fn register(&mut self, fun: Arc<F>) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.fun = Some(fun);
+                Self::handler(index, slot.generation)
+            }
+            None => {
+                self.slots.push(RegistrySlot { generation: 0, fun: Some(fun) });
+                Self::handler(self.slots.len() - 1, 0)
+            }
+        }
+    }
+
+    pub fn get(&self, handler: usize) -> Option<Arc<F>> {
+        self.slot(handler).and_then(|slot| slot.fun.clone())
+    }
+
+    /// Swaps the function bound by `handler`, returning false if it is not registered
+    pub fn replace(&mut self, handler: usize, fun: Arc<F>) -> bool {
+        if self.slot(handler).is_none() {
+            return false
+        }
+        self.slots[(handler & HANDLER_INDEX_MASK) - 1].fun = Some(fun);
+        true
+    }
+
+    /// Removes the function bound by `handler`, freeing its slot for later registrations
+    pub fn remove(&mut self, handler: usize) -> Option<Arc<F>> {
+        self.slot(handler)?;
+        let index = (handler & HANDLER_INDEX_MASK) - 1;
+        let slot = &mut self.slots[index];
+        slot.generation = (slot.generation + 1) & (usize::MAX >> HANDLER_INDEX_BITS);
+        self.free.push(index);
+        slot.fun.take()
+    }
+
+    /// Amount of registered functions
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<F: ?Sized> Default for FnRegistry<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Registers a host function, returning the handler pointer it can be bound by.
+/// Closures capturing host state can be registered as well, see [`stateful`] for mutable state
+pub fn register_extern_fn<F>(fun: F) -> usize
+where
+    F: Fn(Parameters) -> Literal + Sync + Send + 'static,
+{
+    EXTERN_FNS.lock().unwrap().register(Arc::new(fun))
+}
+
+/// Registers a host function which has access to the visitor, see [`register_extern_fn`]
+pub fn register_native_fn<F>(fun: F) -> usize
+where
+    F: Fn(&mut dyn ScopeProvider, Parameters) -> Literal + Sync + Send + 'static,
+{
+    NATIVE_FNS.lock().unwrap().register(Arc::new(fun))
+}
+
+/// Binds another function to the handler of a registered host function,
+/// returning false if nothing is registered under it
+pub fn replace_extern_fn<F>(handler: usize, fun: F) -> bool
+where
+    F: Fn(Parameters) -> Literal + Sync + Send + 'static,
+{
+    EXTERN_FNS.lock().unwrap().replace(handler, Arc::new(fun))
+}
+
+/// See [`replace_extern_fn`]
+pub fn replace_native_fn<F>(handler: usize, fun: F) -> bool
+where
+    F: Fn(&mut dyn ScopeProvider, Parameters) -> Literal + Sync + Send + 'static,
+{
+    NATIVE_FNS.lock().unwrap().replace(handler, Arc::new(fun))
+}
+
+/// Wraps a handler mutating its own state, so it can be registered as an extern function.
+/// Calls are serialized, a handler calling back into itself will deadlock
+pub fn stateful<F>(fun: F) -> impl Fn(Parameters) -> Literal + Sync + Send
+where
+    F: FnMut(Parameters) -> Literal + Send,
+{
+    let fun = Mutex::new(fun);
+    move |params| fun.lock().unwrap()(params)
 }
 
 #[inline]
 pub fn import_globals<V>(scope: &mut ContainingScope, visitor: &mut V) where V: Visitor {
-    for (from, imports) in visitor.get_scope("global".to_string()).lock().unwrap().imports() {
+    for (from, imports) in visitor.get_scope("global".to_string()).imports() {
         for import in imports {
             scope.import(&from, &import);
         }
@@ -29,14 +167,76 @@ pub fn import_globals<V>(scope: &mut ContainingScope, visitor: &mut V) where V:
 #[derive(Debug, Clone, PartialEq)]
 pub enum StaticFnType {
     Standard(StaticFn),
-    Extern(ExternFn)
+    Extern(ExternFn),
+    Native(NativeFn)
 }
 
 impl StaticFnType {
+    /// Metadata attached to this function, host functions carry none
+    pub fn meta(&self) -> Metadata {
+        match self {
+            StaticFnType::Standard(std) => std.meta().to_owned(),
+            _ => Metadata::new()
+        }
+    }
+
+    pub fn param_names(&self) -> &[String] {
+        match self {
+            StaticFnType::Standard(std) => &std.param_names,
+            StaticFnType::Extern(ext) => &ext.param_names,
+            StaticFnType::Native(native) => &native.param_names
+        }
+    }
+
+    pub fn out_ty(&self) -> &str {
+        match self {
+            StaticFnType::Standard(std) => &std.out_ty,
+            StaticFnType::Extern(ext) => &ext.out_ty,
+            StaticFnType::Native(native) => &native.out_ty
+        }
+    }
+
+    /// Types of the parameters, which are only declared for typed extern functions
+    pub fn param_types(&self) -> Vec<String> {
+        match self {
+            StaticFnType::Extern(ext) => ext.param_types.clone(),
+            other => vec!["unknown".to_string(); other.param_names().len()]
+        }
+    }
+
+    /// Handler of a host function, gale functions have none
+    pub fn handler(&self) -> Option<usize> {
+        match self {
+            StaticFnType::Standard(_) => None,
+            StaticFnType::Extern(ext) => Some(ext.handler),
+            StaticFnType::Native(native) => Some(native.handler)
+        }
+    }
+
+    /// Removes the host function from its registry, returning false for gale functions
+    /// and functions that were already removed
+    pub fn unregister(&self) -> bool {
+        match self {
+            StaticFnType::Standard(_) => false,
+            StaticFnType::Extern(ext) => EXTERN_FNS.lock().unwrap().remove(ext.handler).is_some(),
+            StaticFnType::Native(native) => NATIVE_FNS.lock().unwrap().remove(native.handler).is_some()
+        }
+    }
+
+    /// Signature of a host function called by `path`, gale functions have none
+    pub fn host_signature(&self, path: String) -> Option<ExternSignature> {
+        match self {
+            StaticFnType::Standard(_) => None,
+            StaticFnType::Extern(ext) => Some(ExternSignature::new(path, ext.param_names.clone(), ext.out_ty.clone())),
+            StaticFnType::Native(native) => Some(ExternSignature::new(path, native.param_names.clone(), native.out_ty.clone()))
+        }
+    }
+
     pub fn call<V>  (&self, params: Parameters, visitor: Option<&mut V>) -> Literal where V: Visitor {
         match self {
             StaticFnType::Standard(std) => std.call(params, visitor.unwrap()),
-            StaticFnType::Extern(ext) => ext.call(params)
+            StaticFnType::Extern(ext) => ext.call(params),
+            StaticFnType::Native(native) => native.call(params, visitor.unwrap())
         }
     }
 }
@@ -45,27 +245,33 @@ impl Transmute for StaticFnType {
     fn size(&mut self) -> usize {
         1 + match self {
             StaticFnType::Standard(std) => std.size(),
-            StaticFnType::Extern(ext) => ext.size()
+            StaticFnType::Extern(ext) => ext.size(),
+            StaticFnType::Native(native) => native.size()
         }
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
         match self {
             StaticFnType::Standard(std) => {
-                0x01u8.write(buf)?;
-                std.write(buf)
+                0x01u8.write_to(buf)?;
+                std.write_to(buf)
             }
             StaticFnType::Extern(ext) => {
-                0x01u8.write(buf)?;
-                ext.write(buf)
+                0x02u8.write_to(buf)?;
+                ext.write_to(buf)
+            }
+            StaticFnType::Native(native) => {
+                0x03u8.write_to(buf)?;
+                native.write_to(buf)
             }
         }
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self> where Self: Sized {
-        match u8::read(buf)? {
-            0x01 => Ok(StaticFnType::Standard(StaticFn::read(buf)?)),
-            0x02 => Ok(StaticFnType::Extern(ExternFn::read(buf)?)),
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self> where Self: Sized {
+        match u8::read_from(buf)? {
+            0x01 => Ok(StaticFnType::Standard(StaticFn::read_from(buf)?)),
+            0x02 => Ok(StaticFnType::Extern(ExternFn::read_from(buf)?)),
+            0x03 => Ok(StaticFnType::Native(NativeFn::read_from(buf)?)),
             _ => bail!("Invalid static fn id provided!")
         }
     }
@@ -76,29 +282,32 @@ pub struct StaticFn {
     out_ty: String,
     param_names: Vec<String>,
     chain: TokenChain,
+    meta: Metadata,
 }
 
 impl Transmute for StaticFn {
     fn size(&mut self) -> usize {
-        self.out_ty.size() + self.param_names.len() + 4 + self.chain.len()
+        self.out_ty.size() + self.param_names.size() + self.chain.size() + self.meta.size()
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        self.out_ty.write(buf)?;
-        self.param_names.write(buf)?;
-        self.chain.write(buf)?;
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.out_ty.write_to(buf)?;
+        self.param_names.write_to(buf)?;
+        self.chain.write_to(buf)?;
+        self.meta.write_to(buf)?;
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
         Ok(StaticFn::new(
-            String::read(buf)?,
-            Vec::read(buf)?,
-            TokenChain::read(buf)?,
-        ))
+            String::read_from(buf)?,
+            Vec::read_from(buf)?,
+            TokenChain::read_from(buf)?,
+        )
+        .with_meta(HashMap::read_from(buf)?))
     }
 }
 
@@ -108,9 +317,19 @@ impl StaticFn {
             out_ty,
             param_names,
             chain,
+            meta: Default::default(),
         }
     }
 
+    pub fn with_meta(mut self, meta: Metadata) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn meta(&self) -> &Metadata {
+        &self.meta
+    }
+
     pub fn call<V>(&self, params: Parameters, visitor: &mut V) -> Literal
     where
         V: Visitor,
@@ -130,18 +349,168 @@ impl StaticFn {
 
         import_globals(&mut scope, visitor);
 
-        // creating scope
-        let cached = visitor.scope_name();
-        let name = format!("static_fn_0x{:2x}", rand::thread_rng().next_u64());
-        visitor.push_scope_level(Scope::InstanceFunction);
-        visitor.push_scope(name.clone(), scope);
+        let name = format!("static_fn_0x{:2x}", visitor.entropy().next_id());
+        let (output, _) = _call_chain(visitor, Scope::StaticFunction, name, scope, &self.chain);
+        if self.out_ty != "unknown" && !output.type_str(&self.out_ty) {
+            panic!(
+                "Invalid output provided! Expected output of type {:?}",
+                self.out_ty
+            )
+        };
+
+        output
+    }
+}
+
+/// Instance function called on structures owned by a scope once the scope is dropped
+pub const DROP_FN: &str = "__drop";
+
+#[inline]
+pub(crate) fn _call_chain<V>(
+    visitor: &mut V,
+    level: Scope,
+    name: String,
+    scope: ContainingScope,
+    chain: &TokenChain,
+) -> (Literal, ContainingScope)
+where
+    V: Visitor,
+{
+    // values passed by the caller are owned by the caller
+    let inherited = scope.declared_values();
+
+    // creating scope
+    let cached = visitor.scope_name();
+    visitor.push_scope_level(level);
+    visitor.push_scope(name.clone(), scope);
+
+    visitor.move_scope(name.clone());
+
+    // processing tokens
+    visitor.process_isolated(&mut chain.clone());
+    // void functions do not push anything on the stack
+    let output = visitor.pop_stack().unwrap_or(Literal::Void);
+
+    // changing scopes back
+    visitor.move_scope(cached);
+    let scope = visitor.drop_scope(name);
+    visitor.pop_scope_level();
+    _drop_owned(visitor, &scope, &inherited, &output);
+
+    (output, scope)
+}
+
+/// Calls [`DROP_FN`] on every structure owned by a dropped scope, in the order of their names.
+///
+/// Structures are values, so copies of the `inherited` ones and the `output`, which is moved
+/// to the caller, are not owned by the scope
+pub(crate) fn _drop_owned<V>(
+    visitor: &mut V,
+    scope: &ContainingScope,
+    inherited: &BTreeMap<String, Literal>,
+    output: &Literal,
+) where
+    V: Visitor,
+{
+    for (name, value) in scope.declared_values() {
+        if inherited.contains_key(&name) || value == *output || inherited.values().any(|it| *it == value) {
+            continue;
+        }
+        let droppable = match &value {
+            Literal::Struct(instance) => visitor
+                .resolve_type(&instance.type_name())
+                .map(|it| it.get_inst_fn(DROP_FN).is_some())
+                .unwrap_or(false),
+            _ => false,
+        };
+        if droppable {
+            visitor.call_inst_fn_on(value, DROP_FN.to_string(), vec![]);
+        }
+    }
+}
 
-        visitor.move_scope(name.clone());
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstFn {
+    out_ty: String,
+    param_names: Vec<String>,
+    chain: TokenChain,
+    meta: Metadata,
+}
+
+impl Transmute for InstFn {
+    fn size(&mut self) -> usize {
+        self.out_ty.size() + self.param_names.size() + self.chain.size() + self.meta.size()
+    }
 
-        // processing tokens
-        visitor.load_chain(&mut self.chain.clone());
-        visitor.process();
-        let output = visitor.pop_stack();
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.out_ty.write_to(buf)?;
+        self.param_names.write_to(buf)?;
+        self.chain.write_to(buf)?;
+        self.meta.write_to(buf)?;
+        Ok(())
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(InstFn::new(
+            String::read_from(buf)?,
+            Vec::read_from(buf)?,
+            TokenChain::read_from(buf)?,
+        )
+        .with_meta(HashMap::read_from(buf)?))
+    }
+}
+
+impl InstFn {
+    pub fn new(out_ty: String, param_names: Vec<String>, chain: TokenChain) -> Self {
+        Self {
+            out_ty,
+            param_names,
+            chain,
+            meta: Default::default(),
+        }
+    }
+
+    pub fn with_meta(mut self, meta: Metadata) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn meta(&self) -> &Metadata {
+        &self.meta
+    }
+
+    /// Calls this function with `this` bound to `instance`.
+    ///
+    /// `this` is a mutable variable inside of the function, and its final value is written
+    /// back to the `receiver` binding once the call is finished. Temporary receivers, like
+    /// results of other calls, have no binding
+    pub fn call<V>(&self, receiver: Option<&str>, instance: StructureInstance, params: Parameters, visitor: &mut V) -> Literal
+    where
+        V: Visitor,
+    {
+        // first parameter is always `this`
+        let param_names = &self.param_names[1..];
+        if !param_names.contains(&"varargs".to_string()) && params.len() != param_names.len() {
+            panic!(
+                "Invalid amount of arguments supplied! Expected {} arg(s)!",
+                param_names.len()
+            );
+        };
+
+        // preparing scope and injecting arguments
+        let mut scope = ContainingScope::new();
+        scope.add_var("this", Literal::Struct(Box::new(instance.clone())));
+        for (param, value) in param_names.iter().zip(params) {
+            scope.add_const(param, value);
+        }
+
+        import_globals(&mut scope, visitor);
+
+        let name = format!("inst_fn_0x{:2x}", visitor.entropy().next_id());
+        let (output, scope) = _call_chain(visitor, Scope::InstanceFunction, name, scope, &self.chain);
         if self.out_ty != "unknown" && !output.type_str(&self.out_ty) {
             panic!(
                 "Invalid output provided! Expected output of type {:?}",
@@ -149,10 +518,16 @@ impl StaticFn {
             )
         };
 
-        // changing scopes back
-        visitor.move_scope(cached);
-        visitor.drop_scope(name);
-        visitor.pop_scope_level();
+        // writing `this` back to where it came from
+        let this = scope.get_var("this");
+        if let (Some(receiver), Some(Literal::Struct(this))) = (receiver, this) {
+            if *this != instance {
+                if visitor.resolve_var(receiver).is_err() {
+                    panic!("Can not mutate constant {}!", receiver)
+                }
+                visitor.add_var(receiver.to_string(), Literal::Struct(this));
+            }
+        }
 
         output
     }
@@ -162,6 +537,8 @@ impl StaticFn {
 pub struct ExternFn {
     out_ty: String,
     param_names: Vec<String>,
+    /// Expected type of every parameter, `unknown` accepts any value
+    param_types: Vec<String>,
     handler: usize
 }
 
@@ -169,11 +546,25 @@ impl ExternFn {
     pub fn new(out_ty: String, param_names: Vec<String>, handler: usize) -> Self {
         Self {
             out_ty,
+            param_types: vec!["unknown".to_string(); param_names.len()],
             param_names,
             handler
         }
     }
 
+    pub fn with_param_types(mut self, param_types: Vec<String>) -> Self {
+        self.param_types = param_types;
+        self
+    }
+
+    pub fn param_types(&self) -> &[String] {
+        &self.param_types
+    }
+
+    pub fn handler(&self) -> usize {
+        self.handler
+    }
+
     pub fn call(&self, params: Parameters) -> Literal
     {
         if !self.param_names.contains(&"varargs".to_string()) && params.len() != self.param_names.len() {
@@ -182,53 +573,159 @@ impl ExternFn {
                 self.param_names.len()
             );
         };
+        let params = params
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| match (self.param_names.get(index), self.param_types.get(index)) {
+                (Some(name), Some(ty)) if name != "varargs" => _coerce_param(name, ty, value),
+                _ => value
+            })
+            .collect::<Parameters>();
+
+        // the lock is released before calling, so handlers can register or call other functions
+        let fun = EXTERN_FNS.lock().unwrap().get(self.handler);
+        match fun {
+            Some(fun) => fun(params),
+            None => panic!("Extern function with handler 0x{:x} was unregistered!", self.handler)
+        }
+    }
+}
 
-        let fun = &EXTERN_FNS.lock().unwrap()[max(0, self.handler - 1)];
-        fun.call((params, ))
+/// Makes sure an argument of an extern function has the declared type,
+/// numbers are widened to floats or decimals where those are expected
+fn _coerce_param(name: &str, ty: &str, value: Literal) -> Literal {
+    match value {
+        Literal::Number(num) if ty == "float" => Literal::Float(num as f64),
+        Literal::Number(num) if ty == "decimal" => Literal::Decimal(num.into()),
+        value if ty == "unknown" || value.type_str(ty) => value,
+        other => panic!(
+            "Invalid argument {} supplied! Expected value of type {}, got {} {}",
+            name,
+            ty,
+            other.this_type(),
+            other
+        )
     }
 }
 
+/// Type of an extern function parameter as written in [`extern_fns!`], `unknown` if it was omitted
+#[doc(hidden)]
+pub fn _param_type(ty: &[&str]) -> String {
+    ty.first().copied().unwrap_or("unknown").to_string()
+}
+
 impl Transmute for ExternFn {
     fn size(&mut self) -> usize {
-        self.out_ty.size() + self.param_names.size() + (self.handler as u64).size()
+        self.out_ty.size() + self.param_names.size() + self.param_types.size() + (self.handler as u64).size()
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        self.out_ty.write(buf)?;
-        self.param_names.write(buf)?;
-        (self.handler as u64).write(buf)?;
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.out_ty.write_to(buf)?;
+        self.param_names.write_to(buf)?;
+        self.param_types.write_to(buf)?;
+        (self.handler as u64).write_to(buf)?;
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self> where Self: Sized {
-        let out_ty = String::read(buf)?;
-        let param_names = Vec::<String>::read(buf)?;
-        let handler = u64::read(buf)?;
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self> where Self: Sized {
+        let out_ty = String::read_from(buf)?;
+        let param_names = Vec::<String>::read_from(buf)?;
+        let param_types = Vec::<String>::read_from(buf)?;
+        if param_types.len() != param_names.len() {
+            bail!("Expected {} parameter types, got {}!", param_names.len(), param_types.len())
+        }
+        let handler = u64::read_from(buf)?;
         Ok(Self {
             out_ty,
             param_names,
+            param_types,
             handler: handler as usize
         })
 
     }
 }
 
+/// A host function that has access to the visitor it is called from
+#[derive(Debug, Clone, PartialEq)]
+pub struct NativeFn {
+    out_ty: String,
+    param_names: Vec<String>,
+    handler: usize
+}
+
+impl NativeFn {
+    pub fn new(out_ty: String, param_names: Vec<String>, handler: usize) -> Self {
+        Self {
+            out_ty,
+            param_names,
+            handler
+        }
+    }
+
+    pub fn call<V>(&self, params: Parameters, visitor: &mut V) -> Literal
+    where
+        V: Visitor,
+    {
+        if !self.param_names.contains(&"varargs".to_string()) && params.len() != self.param_names.len() {
+            panic!(
+                "Invalid amount of arguments supplied! Expected {} arg(s)!",
+                self.param_names.len()
+            );
+        };
+
+        // the lock is released before calling, so natives can call back into the visitor
+        let fun = NATIVE_FNS.lock().unwrap().get(self.handler);
+        match fun {
+            Some(fun) => fun(visitor, params),
+            None => panic!("Native function with handler 0x{:x} was unregistered!", self.handler)
+        }
+    }
+}
+
+impl Transmute for NativeFn {
+    fn size(&mut self) -> usize {
+        self.out_ty.size() + self.param_names.size() + (self.handler as u64).size()
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.out_ty.write_to(buf)?;
+        self.param_names.write_to(buf)?;
+        (self.handler as u64).write_to(buf)?;
+        Ok(())
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self> where Self: Sized {
+        let out_ty = String::read_from(buf)?;
+        let param_names = Vec::<String>::read_from(buf)?;
+        let handler = u64::read_from(buf)?;
+        Ok(Self {
+            out_ty,
+            param_names,
+            handler: handler as usize
+        })
+    }
+}
+
 #[macro_export]
 macro_rules! extern_fns {
     ($vm:ident {
         $(
-            extern fn $name:ident ($($param:ident),* $(,)*) -> $out_ty:ident;
+            extern fn $name:ident ($($param:ident $(: $pty:ident)?),* $(,)*) -> $out_ty:ident $(= $handler:expr)?;
         )*
     }) => {
         {
-            let mut __extfns = &mut $crate::fns::EXTERN_FNS.lock().unwrap();
             #[allow(unused_imports)]
             use $crate::visit::ScopeProvider;
             $(
-                __extfns.push(Box::new($name));
-                $vm.add_extern_fn(stringify!($name).to_string(), stringify!($out_ty).to_string(), vec![$(stringify!($param).to_string()),*], __extfns.len());
+                let __ptr = $crate::fns::register_extern_fn($crate::__extern_handler!($name $(, $handler)?));
+                $vm.add_typed_extern_fn(
+                    stringify!($name).to_string(),
+                    stringify!($out_ty).to_string(),
+                    vec![$(stringify!($param).to_string()),*],
+                    vec![$($crate::fns::_param_type(&[$(stringify!($pty))?])),*],
+                    __ptr
+                );
             )*
-            drop(__extfns);
         }
     };
 
@@ -236,27 +733,52 @@ macro_rules! extern_fns {
         $(
             scope $scope:literal {
                 $(
-                    extern fn $name:ident ($($param:ident),* $(,)*) -> $out_ty:ident;
+                    extern fn $name:ident ($($param:ident $(: $pty:ident)?),* $(,)*) -> $out_ty:ident $(= $handler:expr)?;
+                )*
+                $(
+                    native fn $nname:ident ($($nparam:ident),* $(,)*) -> $nout_ty:ident $(= $nhandler:expr)?;
                 )*
             }
         )*
     }) => {
         {
-            let mut __extfns = &mut $crate::fns::EXTERN_FNS.lock().unwrap();
             $(
                 let mut scope = $crate::var::ContainingScope::new();
                 $(
                     scope.export(stringify!($name));
-                    __extfns.push(Box::new($name));
-                    scope.add_extern_fn(stringify!($name), stringify!($out_ty).to_string(), vec![$(stringify!($param).to_string()),*], __extfns.len());
+                    let __ptr = $crate::fns::register_extern_fn($crate::__extern_handler!($name $(, $handler)?));
+                    scope.add_typed_extern_fn(
+                        stringify!($name),
+                        stringify!($out_ty).to_string(),
+                        vec![$(stringify!($param).to_string()),*],
+                        vec![$($crate::fns::_param_type(&[$(stringify!($pty))?])),*],
+                        __ptr
+                    );
+                )*
+                $(
+                    scope.export(stringify!($nname));
+                    let __ptr = $crate::fns::register_native_fn($crate::__extern_handler!($nname $(, $nhandler)?));
+                    scope.add_native_fn(stringify!($nname), stringify!($nout_ty).to_string(), vec![$(stringify!($nparam).to_string()),*], __ptr);
                 )*
                 $vm.push_scope($scope.to_string(), scope);
             )*
-            drop(__extfns);
         }
     }
 }
 
+/// Picks the handler of a function declared in [`extern_fns!`], which is either
+/// the expression after `=` or a function of the same name
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __extern_handler {
+    ($name:ident) => {
+        $name
+    };
+    ($name:ident, $handler:expr) => {
+        $handler
+    };
+}
+
 #[macro_export]
 macro_rules! unwrap_args {
     ($params:ident => ($($lit:ident),* $(,)*)) => {
@@ -264,7 +786,7 @@ macro_rules! unwrap_args {
             let mut vec = std::collections::VecDeque::from($params.to_owned());
             (
                     $(
-                    match vec.pop_back().unwrap() {
+                    match vec.pop_front().unwrap() {
                         $crate::tks::Literal::$lit(val) => val.to_owned(),
                         _ => panic!("Expected {} literal!", stringify!($lit))
                     }
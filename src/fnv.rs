@@ -0,0 +1,12 @@
+//! 64 bit FNV-1a, which unlike the hasher of the standard library stays the same
+//! between compiler releases, so its hashes can be stored and compared later.
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Continues `hash` with `bytes`, start from [`FNV_OFFSET`] to hash from scratch
+pub(crate) fn _fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
@@ -0,0 +1,74 @@
+//! Host values programs refer to by a number, like collections, iterators and open files.
+//!
+//! Literals are always passed by value, so values that copies of a literal have to share,
+//! or that can not be literals at all, are kept in the [`Handles`] of the Vm, and programs
+//! only hold their handle. Every Vm has a table of its own, so handles of one Vm do not
+//! reach the values of another, and a handle only resolves to a value of the type it was
+//! created for. Values are dropped once they are removed, or when the Vm is
+//! [reset](crate::visit::Vm::reset).
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Host values of a Vm by their handles, shared by the clones of the Vm
+#[derive(Clone, Default)]
+pub struct Handles(Arc<Mutex<HandleTable>>);
+
+#[derive(Default)]
+struct HandleTable {
+    last: i64,
+    values: HashMap<i64, Box<dyn Any + Send>>,
+}
+
+impl Handles {
+    /// Stores the value under a new handle. Handles of removed values are not reused
+    pub fn insert<T: Any + Send>(&self, value: T) -> i64 {
+        let mut table = self.0.lock().unwrap();
+        table.last += 1;
+        let handle = table.last;
+        table.values.insert(handle, Box::new(value));
+        handle
+    }
+
+    /// Runs `fun` on the value of the handle, `None` if the handle does not hold a `T`.
+    /// The table is locked until `fun` returns, so it must not use the table itself
+    pub fn with<T: Any + Send, R>(&self, handle: i64, fun: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut table = self.0.lock().unwrap();
+        let value = table.values.get_mut(&handle)?.downcast_mut::<T>()?;
+        Some(fun(value))
+    }
+
+    /// Takes the value out of the table, `None` if the handle does not hold a `T`
+    pub fn remove<T: Any + Send>(&self, handle: i64) -> Option<T> {
+        let mut table = self.0.lock().unwrap();
+        if !table.values.get(&handle)?.is::<T>() {
+            return None;
+        }
+        let value = table.values.remove(&handle)?;
+        value.downcast().ok().map(|it| *it)
+    }
+
+    /// Amount of values in the table
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every value, closing the files among them
+    pub fn clear(&self) {
+        // values are dropped after the lock is released, in case dropping one uses the table
+        let values = std::mem::take(&mut self.0.lock().unwrap().values);
+        drop(values);
+    }
+}
+
+impl Debug for Handles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handles").field("len", &self.len()).finish()
+    }
+}
@@ -0,0 +1,106 @@
+//! Where programs write their output and read their input from, see [`Vm::set_io`](crate::visit::Vm::set_io).
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+/// Sink and source of the `std::io` functions. Backends are shared between
+/// clones of a Vm, so they are used through a shared reference
+pub trait IoBackend: Debug + Send + Sync {
+    /// Writes text to the standard output, as is
+    fn write_out(&self, text: &str);
+    /// Writes text to the standard error, as is
+    fn write_err(&self, text: &str);
+    /// Reads a single line from the standard input without its line terminator,
+    /// or `None` at the end of input
+    fn read_in(&self) -> Option<String>;
+}
+
+/// Shared handle to an [`IoBackend`]
+pub type SharedIo = Arc<dyn IoBackend>;
+
+/// Real standard streams of the process, used by default
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StdIo;
+
+impl IoBackend for StdIo {
+    fn write_out(&self, text: &str) {
+        let mut out = std::io::stdout().lock();
+        let _ = out.write_all(text.as_bytes());
+        let _ = out.flush();
+    }
+
+    fn write_err(&self, text: &str) {
+        let _ = std::io::stderr().lock().write_all(text.as_bytes());
+    }
+
+    fn read_in(&self) -> Option<String> {
+        let mut line = String::new();
+        match std::io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(_trim_line(line)),
+        }
+    }
+}
+
+/// Collects output in memory and reads input from queued lines,
+/// useful for tests and hosts that display output themselves
+#[derive(Debug, Default)]
+pub struct BufferedIo {
+    out: Mutex<String>,
+    err: Mutex<String>,
+    input: Mutex<VecDeque<String>>,
+}
+
+impl BufferedIo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a backend, that reads lines of `input`
+    pub fn with_input(input: &str) -> Self {
+        let io = Self::new();
+        input.lines().for_each(|line| io.push_input(line));
+        io
+    }
+
+    /// Queues a line to be read by the program
+    pub fn push_input(&self, line: &str) {
+        self.input.lock().unwrap().push_back(line.to_string());
+    }
+
+    /// Takes everything written to the output so far
+    pub fn take_out(&self) -> String {
+        std::mem::take(&mut *self.out.lock().unwrap())
+    }
+
+    /// Takes everything written to the error output so far
+    pub fn take_err(&self) -> String {
+        std::mem::take(&mut *self.err.lock().unwrap())
+    }
+}
+
+impl IoBackend for BufferedIo {
+    fn write_out(&self, text: &str) {
+        self.out.lock().unwrap().push_str(text);
+    }
+
+    fn write_err(&self, text: &str) {
+        self.err.lock().unwrap().push_str(text);
+    }
+
+    fn read_in(&self) -> Option<String> {
+        self.input.lock().unwrap().pop_front()
+    }
+}
+
+fn _trim_line(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
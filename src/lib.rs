@@ -1,5 +1,3 @@
-#![feature(fn_traits)]
-#![feature(box_patterns)]
 
 extern crate core;
 
@@ -13,6 +11,37 @@ pub mod visit;
 pub mod vm;
 pub mod stdlib;
 pub mod features;
+pub mod io;
+pub mod ast;
+pub mod asm;
+pub mod builder;
+pub mod cache;
+pub mod check;
+pub mod dasm;
+pub mod determinism;
+pub mod diagnostics;
+pub mod fmt;
+pub(crate) mod fnv;
+pub mod handles;
+pub mod runtime;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod manifest;
+pub mod marshal;
+pub mod platform;
+pub mod program;
+pub mod snapshot;
+pub mod span;
+pub mod spec;
+pub mod statics;
+pub mod stream;
+pub mod structs;
+pub mod template;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod trace;
+pub mod typecheck;
+pub mod warn;
 
 pub trait ToResult<T> {
     fn to_result(&self) -> anyhow::Result<T>;
@@ -34,11 +63,26 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::tks::{BinaryOp, Expression, Keyword, Literal, Token};
-    use crate::visit::{ScopeProvider, Visitor, Vm};
-    use std::time::Instant;
+    use crate::tks::{BinaryOp, Expression, Keyword, Literal, Token, TokenChain};
+    use crate::visit::{InterceptAction, LiteralStack, ScopeProvider, Visitor, VisitorCore, Vm};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
     use crate::{extern_fns, Parameters};
     use crate::features::StdFeature;
+    use crate::asm::{assemble, assemble_spanned};
+    use crate::check::DiagnosticKind;
+    use crate::dasm::{disassemble, render_token};
+    use crate::trace::TraceConfig;
+    use crate::span::Span;
+    use crate::warn::{WarningCode, WarningLevel};
+    use crate::var::{ContainingScope, ScopeMode, ScopedValue};
+    use crate::runtime::SharedRuntime;
+    use crate::vm::Transmute;
+    use crate::testing::assert_roundtrip;
+    use crate::io::BufferedIo;
+    use crate::determinism::Entropy;
+    use rand::RngCore;
+    use crate::stdlib::log::Level;
 
     #[test]
     fn test_exprs() {
@@ -270,13 +314,55 @@ mod tests {
         vm.process();
     }
 
+    #[test]
+    fn test_unwrap_args_order() {
+        // mixed signatures like `powf(value: float, pow: num)` need the arguments in call order
+        let params = vec![Literal::Float(2.5), Literal::Number(3), Literal::String("last".to_string())];
+        let (value, pow, name) = crate::unwrap_args!(params => (Float, Number, String));
+        assert_eq!(value, 2.5);
+        assert_eq!(pow, 3);
+        assert_eq!(name, "last");
+    }
+
+    #[test]
+    fn test_equality() {
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble("let flag = false; let same = flag == false; let differs = flag != true;").unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("same"), Some(Literal::Bool(true)));
+        assert_eq!(vm.get_global("differs"), Some(Literal::Bool(true)));
+
+        vm.load_chain(&mut assemble(
+            r#"
+            let numbers = [1 == 1, 1 != 2, 1 == 1.0, 2.5 != 2, 1 == 1d, 1d == 1];
+            let floats = [1.5 == 1.5, 1.5 != 2.5, 2.0 == 2, 1.5 == 1.5d, 1.5d == 1.5, 0.1 != 0.2d];
+            let texts = ["a" == "a", "a" != "b", "a" == 'a', 'a' == "a", 'a' == 'a', 'a' != 'b'];
+            let bools = [true == true, true != false];
+            "#,
+        ).unwrap());
+        vm.process();
+        for name in ["numbers", "floats", "texts", "bools"] {
+            let Some(Literal::Array(checks)) = vm.get_global(name) else { panic!("{} is not an array", name) };
+            assert!(checks.iter().all(|it| *it == Literal::Bool(true)), "{}: {:?}", name, checks);
+        }
+
+        for source in ["let wrong = 1 == \"1\";", "let wrong = 'a' != 1.5;", "let wrong = true == 1;"] {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            assert!(crate::stdlib::test::_panic_message(err.as_ref()).contains("Invalid operand provided!"));
+        }
+    }
+
     #[test]
     fn test_sleep() {
         let mut vm = Vm::new();
         vm.add_std_feature(StdFeature::Prelude);
         let mut chain = vec![
             Token::Expression(Box::new(Expression::InvokeStatic("println".to_string(), vec![Token::Literal(Literal::String("Hello!".to_string()))]))),
-            Token::Expression(Box::new(Expression::InvokeStatic("sleep".to_string(), vec![Token::Literal(Literal::Number(5))]))),
+            Token::Expression(Box::new(Expression::InvokeStatic("sleep".to_string(), vec![
+                Token::Expression(Box::new(Expression::InvokeStatic("secs".to_string(), vec![Token::Literal(Literal::Number(5))]))),
+            ]))),
             Token::Expression(Box::new(Expression::InvokeStatic("println".to_string(), vec![Token::Literal(Literal::String("Hello again!".to_string()))])))
         ];
         vm.load_chain(&mut chain);
@@ -303,6 +389,4574 @@ mod tests {
         vm.process();
     }
 
+    #[test]
+    fn test_inst_fns() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Prelude);
+        // Point {
+        //     x num
+        //     fn void move_by(this, dx) {
+        //         this.x = this.x + dx;
+        //         return *;
+        //     }
+        // }
+        // let point = Point { x = 1 };
+        // point.move_by(5);
+        let mut chain = vec![
+            Token::Literal(Literal::Ident("Point".to_string())),
+            Token::LBracket,
+            Token::Literal(Literal::Ident("x".to_string())),
+            Token::Literal(Literal::TypeName("num".to_string())),
+            Token::Keyword(Keyword::Function),
+            Token::Literal(Literal::TypeName("void".to_string())),
+            Token::Literal(Literal::Ident("move_by".to_string())),
+            Token::LParen,
+            Token::Literal(Literal::Ident("this".to_string())),
+            Token::Literal(Literal::Ident("dx".to_string())),
+            Token::RParen,
+            Token::LBracket,
+            Token::Expression(Box::new(Expression::BinaryOp(
+                BinaryOp::Assign,
+                Token::Expression(Box::new(Expression::InstanceAccess("this".to_string(), "x".to_string()))),
+                Token::Expression(Box::new(Expression::BinaryOp(
+                    BinaryOp::Add,
+                    Token::Expression(Box::new(Expression::InstanceAccess("this".to_string(), "x".to_string()))),
+                    Token::Literal(Literal::Ident("dx".to_string())),
+                ))),
+            ))),
+            Token::Keyword(Keyword::Return),
+            Token::Literal(Literal::Void),
+            Token::RBracket,
+            Token::RBracket,
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("point".to_string())),
+            Token::Expression(Box::new(Expression::Instantiate("Point".to_string(), vec![
+                Token::Expression(Box::new(Expression::BinaryOp(
+                    BinaryOp::Assign,
+                    Token::Literal(Literal::Ident("x".to_string())),
+                    Token::Literal(Literal::Number(1)),
+                ))),
+            ]))),
+            Token::Expression(Box::new(Expression::InvokeInstance(
+                "point".to_string(),
+                "move_by".to_string(),
+                vec![Token::Literal(Literal::Number(5))],
+            ))),
+            Token::Expression(Box::new(Expression::InvokeStatic("debug".to_string(), vec![
+                Token::Literal(Literal::Ident("point".to_string()))
+            ]))),
+        ];
+        vm.load_chain(&mut chain);
+        vm.process();
+        match vm.resolve_var("point").unwrap() {
+            Literal::Struct(point) => assert_eq!(point.get_field("x"), Some(Literal::Number(6))),
+            other => panic!("Expected a struct, got {:?}", other),
+        }
+    }
+
+    fn point_chain(fields: TokenChain) -> TokenChain {
+        // Point {
+        //     x num
+        //     y num 10
+        // }
+        // let point = Point { ...fields };
+        vec![
+            Token::Literal(Literal::Ident("Point".to_string())),
+            Token::LBracket,
+            Token::Literal(Literal::Ident("x".to_string())),
+            Token::Literal(Literal::TypeName("num".to_string())),
+            Token::Literal(Literal::Ident("y".to_string())),
+            Token::Literal(Literal::TypeName("num".to_string())),
+            Token::Literal(Literal::Number(10)),
+            Token::RBracket,
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("point".to_string())),
+            Token::Expression(Box::new(Expression::Instantiate("Point".to_string(), fields))),
+        ]
+    }
+
+    #[test]
+    fn test_struct_defaults() {
+        let mut vm = Vm::new();
+        let mut chain = point_chain(vec![
+            Token::Expression(Box::new(Expression::BinaryOp(
+                BinaryOp::Assign,
+                Token::Literal(Literal::Ident("x".to_string())),
+                Token::Literal(Literal::Number(1)),
+            ))),
+        ]);
+        vm.load_chain(&mut chain);
+        vm.process();
+        match vm.resolve_var("point").unwrap() {
+            Literal::Struct(point) => {
+                assert_eq!(point.get_field("x"), Some(Literal::Number(1)));
+                assert_eq!(point.get_field("y"), Some(Literal::Number(10)));
+            }
+            other => panic!("Expected a struct, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing values")]
+    fn test_struct_missing_field() {
+        let mut vm = Vm::new();
+        let mut chain = point_chain(vec![]);
+        vm.load_chain(&mut chain);
+        vm.process();
+    }
+
+    #[test]
+    fn test_reflect() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Reflect);
+        let mut chain = point_chain(vec![
+            Token::Expression(Box::new(Expression::BinaryOp(
+                BinaryOp::Assign,
+                Token::Literal(Literal::Ident("x".to_string())),
+                Token::Literal(Literal::Number(1)),
+            ))),
+        ]);
+        // let ty = std::reflect::type_of(point);
+        // let fields = std::reflect::fields_of(point);
+        // let y = std::reflect::call_dynamic("std::reflect::get_field_dynamic", [point, "y"]);
+        chain.extend(vec![
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("ty".to_string())),
+            Token::Expression(Box::new(Expression::InvokeStatic(
+                "std::reflect::type_of".to_string(),
+                vec![Token::Literal(Literal::Ident("point".to_string()))],
+            ))),
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("fields".to_string())),
+            Token::Expression(Box::new(Expression::InvokeStatic(
+                "std::reflect::fields_of".to_string(),
+                vec![Token::Literal(Literal::Ident("point".to_string()))],
+            ))),
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("y".to_string())),
+            Token::Expression(Box::new(Expression::InvokeStatic(
+                "std::reflect::call_dynamic".to_string(),
+                vec![
+                    Token::Literal(Literal::String("std::reflect::get_field_dynamic".to_string())),
+                    Token::Expression(Box::new(Expression::Array(vec![
+                        Token::Literal(Literal::Ident("point".to_string())),
+                        Token::Literal(Literal::String("y".to_string())),
+                    ]))),
+                ],
+            ))),
+        ]);
+        vm.load_chain(&mut chain);
+        vm.process();
+        assert_eq!(vm.resolve_var("ty").unwrap(), Literal::TypeName("Point".to_string()));
+        assert_eq!(
+            vm.resolve_var("fields").unwrap(),
+            Literal::Array(vec![Literal::String("x".to_string()), Literal::String("y".to_string())])
+        );
+        assert_eq!(vm.resolve_var("y").unwrap(), Literal::Number(10));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Reflect);
+        // @doc("Says hello")
+        // fn void hello() { return *; }
+        // let doc = std::reflect::fn_meta("hello", "doc");
+        let mut chain = vec![
+            Token::Attribute("doc".to_string(), Literal::String("Says hello".to_string())),
+            Token::Keyword(Keyword::Function),
+            Token::Literal(Literal::TypeName("void".to_string())),
+            Token::Literal(Literal::Ident("hello".to_string())),
+            Token::LParen,
+            Token::RParen,
+            Token::LBracket,
+            Token::Keyword(Keyword::Return),
+            Token::Literal(Literal::Void),
+            Token::RBracket,
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("doc".to_string())),
+            Token::Expression(Box::new(Expression::InvokeStatic(
+                "std::reflect::fn_meta".to_string(),
+                vec![
+                    Token::Literal(Literal::String("hello".to_string())),
+                    Token::Literal(Literal::String("doc".to_string())),
+                ],
+            ))),
+        ];
+        vm.load_chain(&mut chain);
+        vm.process();
+        assert_eq!(vm.resolve_var("doc").unwrap(), Literal::String("Says hello".to_string()));
+        assert_eq!(
+            vm.resolve_fn("hello").unwrap().meta().get("doc"),
+            Some(&Literal::String("Says hello".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_interceptors() {
+        let mut vm = Vm::new();
+        let visited = Arc::new(Mutex::new(0));
+        let counter = visited.clone();
+        vm.add_interceptor(Box::new(move |_, _| {
+            *counter.lock().unwrap() += 1;
+            InterceptAction::Continue
+        }));
+        vm.add_interceptor(Box::new(|_, tk| match tk {
+            Token::Literal(Literal::Number(200)) => InterceptAction::Replace(Token::Literal(Literal::Number(100))),
+            Token::Literal(Literal::Number(300)) => InterceptAction::Skip,
+            _ => InterceptAction::Continue,
+        }));
+        let mut chain = vec![
+            Token::Literal(Literal::Number(200)),
+            Token::Literal(Literal::Number(300)),
+        ];
+        vm.load_chain(&mut chain);
+        vm.process();
+        assert_eq!(*visited.lock().unwrap(), 2);
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(100));
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut vm = Vm::new();
+        vm.enable_trace(TraceConfig {
+            tokens: false,
+            buffered: true,
+            ..Default::default()
+        });
+        // fn num one() { return 1; }
+        // let value = one();
+        let mut chain = vec![
+            Token::Keyword(Keyword::Function),
+            Token::Literal(Literal::TypeName("num".to_string())),
+            Token::Literal(Literal::Ident("one".to_string())),
+            Token::LParen,
+            Token::RParen,
+            Token::LBracket,
+            Token::Keyword(Keyword::Return),
+            Token::Literal(Literal::Number(1)),
+            Token::RBracket,
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("value".to_string())),
+            Token::Expression(Box::new(Expression::InvokeStatic("one".to_string(), vec![]))),
+        ];
+        vm.load_chain(&mut chain);
+        vm.process();
+        let trace: Vec<String> = vm.disable_trace().iter().map(|it| it.to_string()).collect();
+        assert_eq!(trace[0], "call one");
+        assert!(trace.contains(&"  push 1".to_string()));
+        assert!(trace.contains(&"  pop 1".to_string()));
+        assert_eq!(trace[trace.len() - 2..], ["push 1".to_string(), "pop 1".to_string()]);
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::IO);
+        let mut chain = vec![
+            Token::Keyword(Keyword::Const),
+            Token::Literal(Literal::Ident("constant".to_string())),
+            Token::Literal(Literal::Number(500)),
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("mutable".to_string())),
+            Token::Literal(Literal::String("Hello, World!".to_string())),
+            Token::Literal(Literal::Bool(true)),
+        ];
+        vm.load_chain(&mut chain);
+        vm.process();
+        let state = vm.dump_state();
+        assert_eq!(state, vm.dump_state());
+        assert_eq!(state.stack, vec![Literal::Bool(true)]);
+        assert_eq!(state.pending_tokens, 0);
+        assert_eq!(state.current.constants.get("constant"), Some(&Literal::Number(500)));
+        assert_eq!(
+            state.scopes["global"].variables.get("mutable"),
+            Some(&Literal::String("Hello, World!".to_string()))
+        );
+        assert_eq!(state.scopes["std::io"].functions, vec!["debug", "eprintln", "fmt", "print", "println", "read_line"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use crate::tks::UnaryOp;
+
+        let chain: TokenChain = vec![
+            Token::Keyword(Keyword::Let),
+            Token::Literal(Literal::Ident("value".to_string())),
+            Token::Expression(Box::new(Expression::BinaryOp(
+                BinaryOp::Add,
+                Token::Literal(Literal::Number(2)),
+                Token::Expression(Box::new(Expression::UnaryOp(
+                    UnaryOp::Neg,
+                    Token::Literal(Literal::Float(1.5)),
+                ))),
+            ))),
+            Token::Attribute(
+                "doc".to_string(),
+                Literal::Array(vec![Literal::String("a".to_string())]),
+            ),
+        ];
+        let json = serde_json::to_string(&chain).unwrap();
+        let back: TokenChain = serde_json::from_str(&json).unwrap();
+        assert_eq!(chain, back);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let chain = vec![
+            Token::Keyword(Keyword::Const),
+            Token::Literal(Literal::Ident("constant".to_string())),
+            Token::Expression(Box::new(Expression::BinaryOp(
+                BinaryOp::Add,
+                Token::Literal(Literal::Number(200)),
+                Token::Literal(Literal::Number(300)),
+            ))),
+            Token::Keyword(Keyword::Function),
+            Token::Literal(Literal::TypeName("void".to_string())),
+            Token::Literal(Literal::Ident("say_hello".to_string())),
+            Token::LParen,
+            Token::Literal(Literal::Ident("name".to_string())),
+            Token::RParen,
+            Token::LBracket,
+            Token::Expression(Box::new(Expression::InvokeStatic(
+                "println".to_string(),
+                vec![Token::Literal(Literal::String("Hello".to_string()))],
+            ))),
+            Token::Keyword(Keyword::Return),
+            Token::Literal(Literal::Void),
+            Token::RBracket,
+        ];
+        assert_eq!(
+            disassemble(&chain),
+            "const constant = 200 + 300;\n\
+             fn void say_hello(name) {\n    \
+             println(\"Hello\");\n    \
+             return *;\n\
+             }\n"
+        );
+        assert_eq!(disassemble(&point_chain(vec![])).lines().next(), Some("Point {"));
+    }
+
+    #[test]
+    fn test_assemble() {
+        let chain = assemble(
+            r#"
+            @doc("Says hello")
+            fn void say_hello(name) {
+                let greeting = fmt("Hello, {}", name);
+                println(greeting);
+                return *;
+            }
+            const value = 2 + 3 * -4;
+            if value < 0 {
+                say_hello("World!");
+            } else {
+                say_hello('?');
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(&chain[..3], &[
+            Token::Attribute("doc".to_string(), Literal::String("Says hello".to_string())),
+            Token::Keyword(Keyword::Function),
+            Token::Literal(Literal::TypeName("void".to_string())),
+        ]);
+        assert!(chain.contains(&Token::Expression(Box::new(Expression::BinaryOp(
+            BinaryOp::Add,
+            Token::Literal(Literal::Number(2)),
+            Token::Expression(Box::new(Expression::BinaryOp(
+                BinaryOp::Mul,
+                Token::Literal(Literal::Number(3)),
+                Token::Literal(Literal::Number(-4)),
+            ))),
+        )))));
+
+        let mut struct_chain = point_chain(vec![Token::Expression(Box::new(Expression::BinaryOp(
+            BinaryOp::Assign,
+            Token::Literal(Literal::Ident("x".to_string())),
+            Token::Literal(Literal::Float(1.0)),
+        )))]);
+        struct_chain.push(Token::Expression(Box::new(Expression::InvokeInstance(
+            "point".to_string(),
+            "move_by".to_string(),
+            vec![Token::Expression(Box::new(Expression::StaticAccess(vec![
+                "Point".to_string(),
+                "ORIGIN".to_string(),
+            ])))],
+        ))));
+        for chain in [chain, struct_chain] {
+            assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Prelude);
+        let chain = assemble(
+            r#"
+            fn num add(a, b) {
+                return a + b;
+            }
+            const limit = 10;
+            let value = add(1, limit);
+            limit = add(value);
+            println(missing);
+            unknown_fn();
+            std::io::println(Missing { x = 1 });
+            "#,
+        )
+        .unwrap();
+        let kinds: Vec<DiagnosticKind> = vm.check(&chain).iter().map(|it| it.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                DiagnosticKind::ArityMismatch,
+                DiagnosticKind::ConstReassignment,
+                DiagnosticKind::UndefinedVariable,
+                DiagnosticKind::UnknownFunction,
+                DiagnosticKind::UnknownStructure,
+            ]
+        );
+
+        let unbalanced = vec![Token::LBracket, Token::LParen, Token::RBracket];
+        let diagnostics = vm.check(&unbalanced);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|it| it.kind == DiagnosticKind::UnbalancedBrackets));
+        assert!(vm.check(&point_chain(vec![])).is_empty());
+        let unknown_field = point_chain(vec![Token::Expression(Box::new(Expression::BinaryOp(
+            BinaryOp::Assign,
+            Token::Literal(Literal::Ident("z".to_string())),
+            Token::Literal(Literal::Number(1)),
+        )))]);
+        assert_eq!(vm.check(&unknown_field).len(), 1);
+    }
+
+    #[test]
+    fn test_spans() {
+        let (mut chain, source_map) = assemble_spanned(
+            "let value = 2 + 3;\n\
+             const name = missing;",
+        )
+        .unwrap();
+        assert_eq!(source_map.get(0), Some(Span::new(1, 1, 3)));
+        assert_eq!(source_map.get(2), Some(Span::new(1, 13, 5)));
+        assert_eq!(source_map.get(4), Some(Span::new(2, 7, 4)));
+
+        let mut vm = Vm::new();
+        let diagnostic = vm.check(&chain)[0].clone().locate(&source_map);
+        assert_eq!(diagnostic.span, Some(Span::new(2, 14, 7)));
+
+        chain.truncate(3);
+        vm.enable_trace(TraceConfig {
+            stack: false,
+            buffered: true,
+            ..Default::default()
+        });
+        vm.load_spanned(&mut chain, &source_map);
+        vm.process();
+        let spans: Vec<Option<Span>> = vm.disable_trace().iter().map(|it| it.span).collect();
+        assert_eq!(spans, vec![Some(Span::new(1, 1, 3))]);
+    }
+
+    fn warnings_chain() -> TokenChain {
+        assemble(
+            r#"
+            import std::io::print;
+            let value = 1;
+            let value = 2;
+            let text = "value: " + value;
+            import std::math::min;
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::IO);
+        vm.load_chain(&mut warnings_chain());
+        vm.process();
+        let codes: Vec<WarningCode> = vm.take_warnings().iter().map(|it| it.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                WarningCode::ShadowedVariable,
+                WarningCode::ImplicitConversion,
+                WarningCode::UndeclaredFeature,
+                WarningCode::UnusedImport,
+                WarningCode::UnusedImport,
+            ]
+        );
+
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::IO);
+        vm.set_warning_level(WarningLevel::Allow);
+        vm.set_code_warning_level(WarningCode::ShadowedVariable, WarningLevel::Warn);
+        vm.load_chain(&mut warnings_chain());
+        vm.process();
+        assert_eq!(vm.warnings().len(), 1);
+        assert_eq!(vm.warnings()[0].code, WarningCode::ShadowedVariable);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failure")]
+    fn test_denied_warnings() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::IO);
+        vm.set_warning_level(WarningLevel::Deny);
+        vm.load_chain(&mut warnings_chain());
+        vm.process();
+    }
+
+    #[test]
+    fn test_imports_used_in_functions() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Math);
+        vm.set_warning_level(WarningLevel::Deny);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::math::pow;
+            import std::math::vec_sum;
+            fn num main() {
+                return pow(2, vec_sum([1, 2]));
+            }
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.run_main(vec![]).unwrap(), 8);
+        assert!(vm.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(
+            r#"
+            const x = 1;
+            fn num twice(x) {
+                return x * 2;
+            }
+            twice(5);
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(10));
+
+        let mut scope = ContainingScope::new();
+        scope.add_imported("value", ScopedValue::Constant(Literal::Number(1)));
+        scope.add_var("value", Literal::Number(2));
+        assert_eq!(scope.get_var("value"), Some(Literal::Number(2)));
+        assert!(scope.declares("value"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can not redeclare x")]
+    fn test_const_redeclaration() {
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble("const x = 1; const x = 2;").unwrap());
+        vm.process();
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let src = r#"
+            namespace mylib {
+                const VERSION = 2;
+                fn num double(x) {
+                    return x * 2;
+                }
+                namespace inner {
+                    fn num triple(x) {
+                        return x * 3;
+                    }
+                }
+            }
+            import mylib::double;
+            mylib::double(mylib::VERSION);
+            mylib::inner::triple(1);
+            double(5);
+            "#;
+        let mut chain = assemble(src).unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+
+        let mut vm = Vm::new();
+        assert!(vm.check(&chain).is_empty());
+        vm.load_chain(&mut chain);
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(10));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(3));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(4));
+
+        let state = vm.dump_state();
+        assert_eq!(state.scopes["mylib"].functions, vec!["double"]);
+        assert_eq!(state.scopes["mylib::inner"].functions, vec!["triple"]);
+
+        let diagnostics = vm.check(&assemble("mylib::missing();").unwrap());
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::UnknownFunction);
+    }
+
+    fn library_chain() -> TokenChain {
+        assemble(
+            r#"
+            fn num square(x) {
+                return x * x;
+            }
+            fn num hidden() {
+                return 0;
+            }
+            export square;
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_libraries() {
+        let mut vm = Vm::new();
+        vm.add_library("mathx", library_chain()).unwrap();
+        assert!(vm.add_library("mathx", library_chain()).is_err());
+        vm.load_chain(&mut assemble("import mathx::square; square(4);").unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(16));
+        assert_eq!(vm.scope_name(), "global");
+    }
+
+    #[test]
+    #[should_panic(expected = "Failure")]
+    fn test_library_exports() {
+        let mut vm = Vm::new();
+        vm.add_library("mathx", library_chain()).unwrap();
+        vm.load_chain(&mut assemble("import mathx::hidden;").unwrap());
+        vm.process();
+    }
+
+    #[test]
+    fn test_reload_library() {
+        let library = |version: i64| {
+            assemble(&format!(
+                r#"
+                let loads = 0;
+                loads = loads + 1;
+                fn num version() {{
+                    return {};
+                }}
+                export version;
+                "#,
+                version
+            ))
+            .unwrap()
+        };
+        let mut vm = Vm::new();
+        vm.add_library("plugin", library(1)).unwrap();
+        vm.load_chain(&mut assemble("import plugin::version; let total = version();").unwrap());
+        vm.process();
+
+        vm.reload_library("plugin", library(2)).unwrap();
+        assert!(vm.reload_library("missing", library(3)).is_err());
+        vm.load_chain(&mut assemble("total = total + version();").unwrap());
+        vm.process();
+        let state = vm.dump_state();
+        assert_eq!(state.scopes["global"].variables["total"], Literal::Number(3));
+        assert_eq!(state.scopes["plugin"].variables["loads"], Literal::Number(1));
+    }
+
+    #[test]
+    fn test_shared_runtime() {
+        let mut runtime = SharedRuntime::new(&[StdFeature::Prelude]);
+        runtime.add_library("mathx", library_chain()).unwrap();
+        assert!(runtime.scopes().contains(&"std::io".to_string()));
+
+        let mut first = runtime.new_vm();
+        let mut second = runtime.new_vm();
+        assert!(Arc::ptr_eq(
+            &first.shared_scope("std::io").unwrap(),
+            &second.shared_scope("std::io").unwrap()
+        ));
+        first.add_std_feature(StdFeature::IO);
+        assert!(Arc::ptr_eq(
+            &first.shared_scope("std::io").unwrap(),
+            &second.shared_scope("std::io").unwrap()
+        ));
+
+        first.load_chain(&mut assemble(r#"let value = fmt("{}", 1);"#).unwrap());
+        first.process();
+        second.load_chain(&mut assemble("import mathx::square; let value = square(3);").unwrap());
+        second.process();
+        assert_eq!(first.resolve_var("value").unwrap(), Literal::String("1".to_string()));
+        assert_eq!(second.resolve_var("value").unwrap(), Literal::Number(9));
+    }
+
+    #[test]
+    fn test_shared_runtime_isolation() {
+        let mut runtime = SharedRuntime::new(&[StdFeature::Math]);
+        runtime.add_library("mathx", library_chain()).unwrap();
+        let mut first = runtime.new_vm();
+        let mut second = runtime.new_vm();
+        for (source, message) in [
+            (
+                "namespace std { namespace math { fn num pow(value, pow) { return 0; } } }",
+                "Namespace std belongs to the standard library and can not be reopened!",
+            ),
+            (
+                "namespace mathx { fn num square(x) { return 0; } }",
+                "Namespace mathx is shared with other Vms and can not be reopened!",
+            ),
+        ] {
+            first.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| first.process())).unwrap_err();
+            let err = crate::stdlib::test::_panic_message(err.as_ref());
+            assert!(err.contains(message), "{}", err);
+            first.reset();
+        }
+
+        second.load_chain(&mut assemble(
+            "import std::math::pow; import mathx::square; let value = pow(2, 3) + square(3);",
+        ).unwrap());
+        second.process();
+        assert_eq!(second.resolve_var("value").unwrap(), Literal::Number(17));
+    }
+
+    #[test]
+    fn test_arr() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Arrays);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::arr::arr_map;
+            import std::arr::arr_filter;
+            import std::arr::arr_reduce;
+            import std::arr::arr_sort_by;
+            fn num double(x) {
+                return x * 2;
+            }
+            fn bool is_odd(x) {
+                return x % 2 > 0;
+            }
+            fn num sum(acc, x) {
+                return acc + x;
+            }
+            fn num descending(a, b) {
+                return b - a;
+            }
+            let values = [3, 1, 4, 5];
+            arr_map(values, "double");
+            arr_filter(values, "is_odd");
+            arr_reduce(values, 0, "sum");
+            arr_sort_by(values, "descending");
+            "#,
+        ).unwrap());
+        vm.process();
+        let array = |values: &[i64]| Literal::Array(values.iter().copied().map(Literal::Number).collect());
+        assert_eq!(vm.pop_stack().unwrap(), array(&[5, 4, 3, 1]));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(13));
+        assert_eq!(vm.pop_stack().unwrap(), array(&[3, 1, 5]));
+        assert_eq!(vm.pop_stack().unwrap(), array(&[6, 2, 8, 10]));
+    }
+
+    #[test]
+    fn test_string_builder() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble(
+            r#"
+            let builder = std::str::builder_new();
+            let i = 0;
+            while i < 3 {
+                std::str::builder_push(builder, i);
+                std::str::builder_push(builder, ", ");
+                i = i + 1;
+            }
+            std::str::builder_len(builder);
+            std::str::builder_build(builder);
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("0, 1, 2, ".to_string()));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(9));
+        // building a builder frees it, like freeing it without building it does
+        assert!(vm.handles().is_empty());
+        vm.load_chain(&mut assemble("let unused = std::str::builder_new(); std::str::builder_free(unused);").unwrap());
+        vm.process();
+        assert!(vm.handles().is_empty());
+
+        // builders of other Vms can not be reached
+        let mut other = Vm::new();
+        other.add_std_feature(StdFeature::Strings);
+        other.load_chain(&mut assemble("std::str::builder_new();").unwrap());
+        other.process();
+        vm.load_chain(&mut assemble(&format!("std::str::builder_len({});", other.pop_stack().unwrap())).unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert!(crate::stdlib::test::_panic_message(err.as_ref()).contains("Invalid string builder handle"));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Bytes);
+        vm.load_chain(&mut assemble(
+            r#"
+            let data = std::bytes::from_str("Man");
+            std::bytes::base64_encode(data);
+            std::bytes::base64_encode(std::bytes::slice(data, 0, 2));
+            data = std::bytes::write_u32(data, 3, 48879);
+            std::bytes::hex_encode(data);
+            std::bytes::read_u32(data, 3);
+            std::bytes::to_str(std::bytes::slice(data, 0, 3));
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("Man".to_string()));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(48879));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("4d616e0000beef".to_string()));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("TWE=".to_string()));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("TWFu".to_string()));
+
+        let mut buf = vec![];
+        let mut bytes = Literal::Bytes(vec![0, 1, 255]);
+        bytes.write(&mut buf).unwrap();
+        assert_eq!(Literal::read(&mut std::io::Cursor::new(buf)).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Hash);
+        vm.load_chain(&mut assemble(
+            r#"
+            std::hash::sha256("abc");
+            std::hash::sha256("");
+            std::hash::crc32("123456789");
+            std::hash::fnv("a");
+            std::hash::hash(1);
+            std::hash::hash("1");
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_ne!(vm.pop_stack().unwrap(), vm.pop_stack().unwrap());
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(0xaf63dc4c8601ec8cu64 as i64));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(0xcbf43926));
+        assert_eq!(
+            vm.pop_stack().unwrap(),
+            Literal::String("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string())
+        );
+        assert_eq!(
+            vm.pop_stack().unwrap(),
+            Literal::String("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string())
+        );
+    }
+
+    #[test]
+    fn test_utf8_strings() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble(
+            r#"
+            const text = "héllo😀";
+            std::str::len_chars(text);
+            std::str::byte_len(text);
+            std::str::char_at(text, 5);
+            std::str::substr(text, 1, 3);
+            std::str::byte_substr(text, 0, 3);
+            std::str::chars("añ");
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Array(vec![Literal::Char('a'), Literal::Char('ñ')]));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("hé".to_string()));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("él".to_string()));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Char('😀'));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(10));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(6));
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        let chain = assemble(
+            "[0xFF, 0b1010, 0o17, 1_000_000, -9223372036854775808, 0x7fff_ffff_ffff_ffff, 1e9, 2.5E-3, -1.5, 1_0.2_5];",
+        )
+        .unwrap();
+        assert_eq!(chain, vec![Token::Literal(Literal::Array(vec![
+            Literal::Number(255),
+            Literal::Number(10),
+            Literal::Number(15),
+            Literal::Number(1_000_000),
+            Literal::Number(i64::MIN),
+            Literal::Number(i64::MAX),
+            Literal::Float(1e9),
+            Literal::Float(2.5e-3),
+            Literal::Float(-1.5),
+            Literal::Float(10.25),
+        ]))]);
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+
+        for invalid in ["0x;", "0b102;", "12abc;", "1e;", "9223372036854775808;", "0xffffffffffffffffff;"] {
+            assert!(assemble(invalid).is_err(), "{} should not assemble", invalid);
+        }
+    }
+
+    #[test]
+    fn test_chars() {
+        let chain = assemble(r"['\n', '\t', '\u{1F600}', '\x41', '\\', '\'', '\0'];").unwrap();
+        assert_eq!(chain, vec![Token::Literal(Literal::Array(vec![
+            Literal::Char('\n'),
+            Literal::Char('\t'),
+            Literal::Char('😀'),
+            Literal::Char('A'),
+            Literal::Char('\\'),
+            Literal::Char('\''),
+            Literal::Char('\0'),
+        ]))]);
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        for invalid in [r"'\q';", r"'ὠ0';", r"'\u{110000}';", r"'\x80';", r#""\z";"#] {
+            assert!(assemble(invalid).is_err(), "{} should not assemble", invalid);
+        }
+
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Chars);
+        vm.load_chain(&mut assemble(
+            r"
+            std::char::is_digit('7');
+            std::char::is_alpha('ж');
+            std::char::is_whitespace('\t');
+            std::char::to_upper('a');
+            std::char::to_upper('ß');
+            std::char::to_num('7');
+            std::char::from_code(std::char::code('A'));
+            ",
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Char('A'));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(7));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("SS".to_string()));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Char('A'));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(true));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(true));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(true));
+    }
+
+    #[test]
+    fn test_ternary() {
+        let chain = assemble("let x = a > 1 ? b ? 1 : 2 : -3;").unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        for invalid in ["a ? 1;", "a ? : 2;", "a : 2;"] {
+            assert!(assemble(invalid).is_err(), "{} should not assemble", invalid);
+        }
+
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble(
+            r#"
+            let builder = std::str::builder_new();
+            let a = 5;
+            let x = a > 3 ? "big" : std::str::builder_push(builder, "evaluated");
+            let y = a < 3 ? 1 : a > 4 ? 2 : 3;
+            [x, y];
+            (a > 3 ? 10 : 20) + 1;
+            std::str::builder_len(builder);
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(0));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(11));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Array(vec![Literal::String("big".to_string()), Literal::Number(2)]));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble(
+            r#"
+            let builder = std::str::builder_new();
+            let a = 5;
+            a < 3 && std::str::builder_push(builder, "and");
+            a > 3 || std::str::builder_push(builder, "or");
+            a > 3 && a < 10;
+            a < 3 || a > 10;
+            std::str::builder_len(builder);
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(0));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(false));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(true));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(true));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(false));
+    }
+
+    #[test]
+    fn test_loop_variants() {
+        let chain = assemble(
+            r#"
+            let i = 0;
+            do {
+                i = i + 1;
+            } while i < 3;
+            while i < 0 {
+                i = 100;
+            } else {
+                i = i * 10;
+            }
+            "#,
+        ).unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        assert!(assemble("do i = 1; while false;").is_err());
+        assert!(assemble("do {} until false;").is_err());
+
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(
+            r#"
+            let once = 0;
+            do {
+                once = once + 1;
+            } while once > 5;
+            let count = 0;
+            do {
+                let step = 1;
+                count = count + step;
+            } while count < 4;
+            let skipped = 0;
+            while skipped > 0 {
+                skipped = 50;
+            }
+            let fallback = 0;
+            while fallback > 0 {
+                fallback = 1;
+            } else {
+                fallback = 2;
+            }
+            let looped = 3;
+            while looped > 0 {
+                if looped > 1 {
+                    looped = looped - 1;
+                } else {
+                    looped = looped - 1;
+                }
+            } else {
+                looped = 100;
+            }
+            [once, count, skipped, fallback, looped];
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Array(vec![
+            Literal::Number(1),
+            Literal::Number(4),
+            Literal::Number(0),
+            Literal::Number(2),
+            Literal::Number(0),
+        ]));
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut vm = Vm::new();
+        assert!(vm.pop_stack().is_err());
+
+        // assignment does not push anything, so its value can not be consumed
+        let mut assign = Token::Expression(Box::new(Expression::BinaryOp(
+            BinaryOp::Assign,
+            Token::Literal(Literal::Ident("x".to_string())),
+            Token::Literal(Literal::Number(1)),
+        )));
+        assert!(assign.as_lit_advanced(&mut vm, "Expected a value!").is_err());
+        assert!(Token::LBracket.as_lit_advanced(&mut vm, "Expected a value!").is_err());
+
+        vm.load_chain(&mut assemble(
+            r#"
+            fn void nothing() {
+                let y = 1;
+            }
+            let x = nothing();
+            [x];
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Array(vec![Literal::Void]));
+        assert!(vm.pop_stack().is_err());
+    }
+
+    const RECURSIVE_DEPTH: &str = r#"
+        fn num depth(n) {
+            n > 0 ? global::depth(n - 1) + 1 : 0;
+        }
+    "#;
+
+    #[test]
+    fn test_call_depth() {
+        let mut vm = Vm::new();
+        vm.set_max_call_depth(16);
+        vm.load_chain(&mut assemble(&format!("{} depth(15);", RECURSIVE_DEPTH)).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(15));
+    }
+
+    #[test]
+    #[should_panic(expected = "Failure")]
+    fn test_call_depth_exceeded() {
+        let mut vm = Vm::new();
+        vm.set_max_call_depth(32);
+        vm.load_chain(&mut assemble(&format!("{} depth(100000);", RECURSIVE_DEPTH)).unwrap());
+        vm.process();
+    }
+
+    #[test]
+    fn test_default_call_depth() {
+        use crate::visit::DEFAULT_MAX_CALL_DEPTH;
+        // test threads only have 2 MiB of stack, far less than the default limit needs
+        let mut vm = Vm::new();
+        let depth = DEFAULT_MAX_CALL_DEPTH - 1;
+        vm.load_chain(&mut assemble(&format!("{} depth({});", RECURSIVE_DEPTH, depth)).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(depth as i64));
+
+        let mut vm = Vm::new();
+        let depth = DEFAULT_MAX_CALL_DEPTH * 4;
+        vm.load_chain(&mut assemble(&format!("{} depth({});", RECURSIVE_DEPTH, depth)).unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert_eq!(crate::stdlib::test::_panic_message(err.as_ref()), "Failure");
+    }
+
+    #[test]
+    fn test_scope_modes() {
+        let chain = assemble(
+            r#"
+            import std::str::len_chars;
+            fn num twice(x) {
+                x * 2;
+            }
+            let i = 0;
+            let total = 0;
+            while i < 2000 {
+                total = total + twice(i);
+                i = i + 1;
+            }
+            [total, len_chars("héllo")];
+            "#,
+        ).unwrap();
+
+        let mut results = vec![];
+        for mode in [ScopeMode::Shared, ScopeMode::SingleThreaded] {
+            let mut vm = Vm::with_scope_mode(mode);
+            vm.add_std_feature(StdFeature::Strings);
+            assert_eq!(vm.shared_scope("global").is_some(), mode == ScopeMode::Shared);
+            vm.load_chain(&mut chain.clone());
+            vm.process();
+            results.push((vm.pop_stack().unwrap(), vm.dump_state()));
+        }
+        assert_eq!(results[0].0, Literal::Array(vec![Literal::Number(3998000), Literal::Number(5)]));
+        assert_eq!(results[0], results[1]);
+
+        let mut runtime_vm = Vm::with_scope_mode(ScopeMode::SingleThreaded);
+        runtime_vm.add_library("mathx", library_chain()).unwrap();
+        runtime_vm.reload_library("mathx", library_chain()).unwrap();
+        runtime_vm.load_chain(&mut assemble("import mathx::square; let value = square(3);").unwrap());
+        runtime_vm.process();
+        assert_eq!(runtime_vm.dump_state().scopes["global"].variables["value"], Literal::Number(9));
+    }
+
+    /// Random literal, nesting arrays and structures at most `depth` levels deep
+    fn random_literal(rng: &mut rand::rngs::StdRng, depth: u32) -> Literal {
+        use rand::Rng;
+        let string = |rng: &mut rand::rngs::StdRng| {
+            let len = rng.gen_range(0..16);
+            (0..len).map(|_| rng.gen::<char>()).collect::<String>()
+        };
+        match rng.gen_range(0..if depth == 0 { 8 } else { 10 }) {
+            0 => Literal::Number(rng.gen()),
+            1 => Literal::Float(rng.gen()),
+            2 => Literal::String(string(rng)),
+            3 => Literal::Char(rng.gen()),
+            4 => Literal::Ident(string(rng)),
+            5 => Literal::Bool(rng.gen()),
+            6 => Literal::Bytes((0..rng.gen_range(0..16)).map(|_| rng.gen()).collect()),
+            7 => Literal::Void,
+            8 => Literal::Array((0..rng.gen_range(0..4)).map(|_| random_literal(rng, depth - 1)).collect()),
+            _ => {
+                let mut template = crate::structs::StructureTemplate::new(string(rng));
+                let mut fields = vec![];
+                for i in 0..rng.gen_range(0..4) {
+                    let name = format!("field{}", i);
+                    template.add_inst_var(&name, "unknown".to_string(), None);
+                    fields.push((name, random_literal(rng, depth - 1)));
+                }
+                let mut instance = crate::structs::StructureInstance::from_template(&template);
+                for (name, value) in fields {
+                    instance.set_field(&template, &name, value).unwrap();
+                }
+                Literal::Struct(Box::new(instance))
+            }
+        }
+    }
+
+    #[test]
+    fn test_transmute_roundtrip() {
+        use crate::tks::UnaryOp;
+        use crate::span::SourceMap;
+        use std::collections::HashMap;
+
+        let literals = vec![
+            Literal::Number(i64::MIN),
+            Literal::Float(-0.5),
+            Literal::String(String::new()),
+            Literal::String("Grüße, 世界".to_string()),
+            // longer than a u16 length prefix could describe
+            Literal::String("x".repeat(70_000)),
+            Literal::Char('ß'),
+            Literal::Char('🦀'),
+            Literal::Ident("value".to_string()),
+            Literal::Bool(true),
+            Literal::TypeName("num".to_string()),
+            Literal::Array(vec![Literal::Number(1), Literal::Array(vec![Literal::Void])]),
+            Literal::Bytes(vec![0, 1, 255]),
+            Literal::Enum("Maybe::Some".to_string(), Box::new(Literal::Number(1))),
+            Literal::Void,
+        ];
+        for lit in &literals {
+            assert_roundtrip(lit.clone());
+        }
+
+        let ident = |name: &str| Token::Literal(Literal::Ident(name.to_string()));
+        let expressions = vec![
+            Expression::BinaryOp(BinaryOp::BitLsh, ident("a"), Token::Literal(Literal::Number(2))),
+            Expression::UnaryOp(UnaryOp::Rev, ident("a")),
+            Expression::StaticAccess(vec!["std".to_string(), "math".to_string(), "PI".to_string()]),
+            Expression::InvokeStatic("std::io::println".to_string(), vec![ident("a")]),
+            Expression::Instantiate("Point".to_string(), vec![Token::Expression(Box::new(
+                Expression::BinaryOp(BinaryOp::Assign, ident("x"), Token::Literal(Literal::Number(1))),
+            ))]),
+            Expression::InstanceAccess("point".to_string(), "x".to_string()),
+            Expression::InvokeInstance("point".to_string(), "len".to_string(), vec![]),
+            Expression::InvokeChained(
+                Token::Expression(Box::new(Expression::InvokeInstance("point".to_string(), "moved".to_string(), vec![]))),
+                "len".to_string(),
+                vec![],
+            ),
+            Expression::Array(vec![Token::Literal(Literal::Number(1)), ident("b")]),
+            Expression::Ternary(ident("a"), Token::Literal(Literal::Char('y')), Token::Literal(Literal::Char('n'))),
+            Expression::IfStmt,
+            Expression::ElseStmt,
+            Expression::ElifStmt,
+            Expression::WhileStmt,
+            Expression::DoWhileStmt,
+            Expression::MatchStmt,
+        ];
+        let keywords = [
+            Keyword::Export, Keyword::Import, Keyword::Let, Keyword::Const,
+            Keyword::Function, Keyword::Return, Keyword::Namespace, Keyword::Test,
+            Keyword::Enum, Keyword::Static, Keyword::Pub, Keyword::Priv, Keyword::Init,
+        ];
+        let mut tokens = vec![
+            Token::Whitespace, Token::LBracket, Token::RBracket, Token::LParen, Token::RParen,
+            Token::LSquare, Token::RSquare, Token::End,
+            Token::Attribute("deprecated".to_string(), Literal::String("use other".to_string())),
+        ];
+        tokens.extend(literals.iter().cloned().map(Token::Literal));
+        tokens.extend(keywords.iter().copied().map(Token::Keyword));
+        tokens.extend(expressions.into_iter().map(|it| Token::Expression(Box::new(it))));
+        for tk in &tokens {
+            assert_roundtrip(tk.clone());
+        }
+        assert_roundtrip(tokens.clone());
+
+        // maps are written in key order, so equal maps always produce equal bytes
+        let map: HashMap<String, Literal> = (0..32)
+            .map(|i| (format!("key{}", i), Literal::Number(i)))
+            .collect();
+        assert_roundtrip(map.clone());
+        let (mut first, mut second) = (vec![], vec![]);
+        map.clone().write(&mut first).unwrap();
+        map.into_iter().collect::<HashMap<_, _>>().write(&mut second).unwrap();
+        assert_eq!(first, second);
+        assert_roundtrip(HashMap::<String, Vec<String>>::new());
+        assert_roundtrip(vec![Some(vec!["a".to_string()]), None]);
+
+        let mut scope = ContainingScope::new();
+        scope.add_var("count", Literal::Number(3));
+        scope.add_const("name", Literal::String("gale".to_string()));
+        scope.add_static_fn("id", "unknown".to_string(), vec!["v".to_string()], tokens.clone());
+        scope.add_extern_fn("ext", "num".to_string(), vec![], 1);
+        scope.add_native_fn("native", "str".to_string(), vec!["varargs".to_string()], 2);
+        scope.export("count");
+        scope.import("std::io", "println");
+        assert_roundtrip(scope);
+
+        let mut template = crate::structs::StructureTemplate::new("Point".to_string());
+        template.add_inst_var("x", "num".to_string(), Some(Literal::Number(0)));
+        template.add_inst_fn("len", "num".to_string(), vec!["this".to_string()], tokens, Default::default());
+        assert_roundtrip(template);
+
+        let mut source_map = SourceMap::new();
+        source_map.insert(2, Span::new(1, 5, 3));
+        assert_roundtrip(source_map);
+    }
+
+    #[test]
+    fn test_transmute_random() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x6A1E);
+        for _ in 0..512 {
+            let lit = random_literal(&mut rng, 3);
+            // NaN floats do not equal themselves
+            if format!("{:?}", lit).contains("NaN") {
+                continue;
+            }
+            assert_roundtrip(Token::Literal(lit));
+        }
+    }
+
+    #[test]
+    fn test_transmute_program() {
+        use crate::vm::{read_header, write_header, FORMAT_VERSION, HEADER_SIZE};
+        let mut chain = assemble(
+            r#"
+            fn num sum(a, b) { return a + b; }
+            const base = 1;
+            let total = 0;
+            let i = 0;
+            while i < 3 {
+                total = total + global::sum(base, i);
+                i = i + 1;
+            }
+            let kind = total > 5 ? 'b' : 's';
+            [total, kind, -i, "done"];
+            "#,
+        ).unwrap();
+
+        let mut buf = vec![];
+        write_header(&mut buf).unwrap();
+        chain.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), HEADER_SIZE + chain.size());
+
+        let mut cursor = std::io::Cursor::new(buf.clone());
+        assert_eq!(read_header(&mut cursor).unwrap(), FORMAT_VERSION);
+        let mut read = TokenChain::read(&mut cursor).unwrap();
+        assert_eq!(read, chain);
+
+        let mut vm = Vm::new();
+        vm.load_chain(&mut read);
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Array(vec![
+            Literal::Number(6), Literal::Char('b'), Literal::Number(-3), Literal::String("done".to_string()),
+        ]));
+
+        // invalid headers and truncated input are errors, not panics
+        assert!(read_header(&mut std::io::Cursor::new(b"ELF\x7f\x00\x01".to_vec())).is_err());
+        let mut future = buf.clone();
+        future[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        assert!(read_header(&mut std::io::Cursor::new(future)).is_err());
+        for len in (HEADER_SIZE..buf.len()).step_by(7) {
+            let mut cursor = std::io::Cursor::new(buf[..len].to_vec());
+            read_header(&mut cursor).unwrap();
+            assert!(TokenChain::read(&mut cursor).is_err(), "read truncated chain of {} bytes", len);
+        }
+    }
+
+    /// Reader which hands out a single byte per call, like a slow socket would
+    struct Trickle<R>(R);
+
+    impl<R: std::io::Read> std::io::Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match buf.first_mut() {
+                Some(byte) => self.0.read(std::slice::from_mut(byte)),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transmute_streaming() {
+        use crate::vm::{read_header, write_header};
+        use std::io::{BufReader, BufWriter, Read, Write};
+        let mut chain = assemble(
+            r#"
+            fn str greet(name) { return "Hello, " + name; }
+            let names = ["gale", 'c', 2.5];
+            global::greet("world");
+            "#,
+        ).unwrap();
+
+        // writing to a file and reading it back without buffering the whole program
+        let path = std::env::temp_dir().join(format!("galevm_stream_{}.galb", std::process::id()));
+        let mut writer = BufWriter::new(std::fs::File::create(&path).unwrap());
+        write_header(&mut writer).unwrap();
+        chain.write_to(&mut writer).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        let mut reader = BufReader::new(std::fs::File::open(&path).unwrap());
+        read_header(&mut reader).unwrap();
+        assert_eq!(TokenChain::read_from(&mut reader).unwrap(), chain);
+        std::fs::remove_file(&path).unwrap();
+
+        // streams may also be trait objects, and may return less than asked for
+        let mut bytes = vec![];
+        chain.write_to(&mut bytes as &mut dyn Write).unwrap();
+        assert_eq!(bytes.len(), chain.size());
+        let mut trickle = Trickle(bytes.as_slice());
+        let mut read = TokenChain::read_from(&mut trickle as &mut dyn Read).unwrap();
+        assert_eq!(read, chain);
+        assert!(TokenChain::read_from(&mut Trickle(&bytes[..bytes.len() - 1])).is_err());
+
+        let mut vm = Vm::new();
+        vm.load_chain(&mut read);
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("Hello, world".to_string()));
+    }
+
+    #[test]
+    fn test_program_container() {
+        use crate::program::{read_program, read_program_with, write_program, write_program_with, Compression, Program, ProgramOptions};
+        let source = (0..64).map(|i| format!("let value{} = {} * 2;\n", i, i)).collect::<String>();
+        let (chain, source_map) = assemble_spanned(&source).unwrap();
+        let program = Program::new(chain, source_map);
+
+        let mut plain = vec![];
+        write_program(&mut plain, &mut program.clone()).unwrap();
+        assert_eq!(read_program(&mut plain.as_slice()).unwrap(), program);
+        // unknown flags are rejected
+        let mut flagged = plain.clone();
+        flagged[6] = 0x80;
+        assert!(read_program(&mut flagged.as_slice()).is_err());
+
+        let mut compressions = vec![Compression::None];
+        if cfg!(feature = "deflate") {
+            compressions.push(Compression::Deflate);
+        }
+        if cfg!(feature = "zstd") {
+            compressions.push(Compression::Zstd);
+        }
+        for compression in compressions {
+            let options = ProgramOptions { compression, ..Default::default() };
+            let mut bytes = vec![];
+            write_program_with(&mut bytes, &mut program.clone(), &options).unwrap();
+            if compression != Compression::None {
+                assert!(bytes.len() * 2 < plain.len(), "{} did not compress the program", compression);
+            }
+            assert_eq!(read_program(&mut bytes.as_slice()).unwrap(), program);
+        }
+
+        let key = [7u8; 32];
+        let options = ProgramOptions { key: Some(key), ..Default::default() };
+        let mut sealed = vec![];
+        let written = write_program_with(&mut sealed, &mut program.clone(), &options);
+        if !cfg!(feature = "encrypt") {
+            assert!(written.unwrap_err().to_string().contains("`encrypt` feature"));
+            return;
+        }
+        written.unwrap();
+        assert_eq!(read_program_with(&mut sealed.as_slice(), &options).unwrap(), program);
+        assert!(read_program(&mut sealed.as_slice()).unwrap_err().to_string().contains("no key"));
+        let wrong = ProgramOptions { key: Some([8u8; 32]), ..options.clone() };
+        assert!(read_program_with(&mut sealed.as_slice(), &wrong).is_err());
+        // neither the payload nor the flags can be changed without the key
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        assert!(read_program_with(&mut tampered.as_slice(), &options).is_err());
+        let mut tampered = sealed.clone();
+        tampered[6] |= 0x01;
+        assert!(read_program_with(&mut tampered.as_slice(), &options).is_err());
+        // programs that are not encrypted could be swapped in for sealed ones
+        let mut unsealed = vec![];
+        write_program(&mut unsealed, &mut program.clone()).unwrap();
+        assert!(read_program_with(&mut unsealed.as_slice(), &options).unwrap_err().to_string().contains("not encrypted"));
+        let mut stripped = sealed.clone();
+        stripped[6] &= !crate::program::FLAG_ENCRYPTED;
+        assert!(read_program_with(&mut stripped.as_slice(), &options).is_err());
+    }
+
+    #[test]
+    fn test_program_manifest() {
+        use crate::manifest::{ExternSignature, Manifest, Version};
+        use crate::program::{read_program_with, write_program, Program, ProgramOptions};
+        use crate::visit::GlobalScope;
+        let mut host = Vm::new();
+        host.add_std_feature(StdFeature::IO);
+        host.add_std_feature(StdFeature::Math);
+        extern_fns!(host {
+            scope "host" {
+                extern fn add(a, b) -> num;
+            }
+        });
+        let (chain, source_map) = assemble_spanned(
+            r#"
+            import std::math::max;
+            let sum = host::add(1, std::math::pow(2, 3));
+            std::io::println(std::str::stringify(sum));
+            "#,
+        ).unwrap();
+        let mut manifest = Manifest::infer(&chain, &host.capabilities());
+        assert_eq!(manifest.features, vec![StdFeature::IO, StdFeature::Math, StdFeature::Strings]);
+        assert_eq!(manifest.extern_fns, vec![ExternSignature::new(
+            "host::add".to_string(), vec!["a".to_string(), "b".to_string()], "num".to_string(),
+        )]);
+        manifest.entrypoint = Some("main".to_string());
+
+        let mut bytes = vec![];
+        write_program(&mut bytes, &mut Program::new(chain, source_map).with_manifest(manifest.clone())).unwrap();
+        let read = |host: crate::manifest::HostCapabilities| {
+            read_program_with(&mut bytes.as_slice(), &ProgramOptions { host: Some(host), ..Default::default() })
+        };
+
+        // strings are missing from the host
+        let err = read(host.capabilities()).unwrap_err().to_string();
+        assert_eq!(err, "Program needs std::str, but the host disabled the strings feature!");
+        host.add_std_feature(StdFeature::Strings);
+        let program = read(host.capabilities()).unwrap();
+        assert_eq!(program.manifest, manifest);
+
+        let mut capabilities = host.capabilities();
+        capabilities.vm_version = Version::new(0, 0, 1);
+        assert!(read(capabilities).unwrap_err().to_string().contains("needs VM version"));
+        let mut capabilities = host.capabilities();
+        capabilities.extern_fns.retain(|it| it.name != "host::add");
+        assert!(read(capabilities).unwrap_err().to_string().contains("does not provide it"));
+        let mut capabilities = host.capabilities();
+        capabilities.extern_fns.iter_mut().for_each(|it| if it.name == "host::add" { it.params.pop(); });
+        assert!(read(capabilities).unwrap_err().to_string().contains("but the host provides host::add(a) -> num"));
+
+        // features unknown to this VM are reported by their name
+        let mut future = bytes.clone();
+        let at = future.windows(4).position(|it| it == b"math").unwrap();
+        future[at..at + 4].copy_from_slice(b"nets");
+        let err = read_program_with(&mut future.as_slice(), &ProgramOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Program needs std feature nets, which this VM does not know!");
+    }
+
+    #[test]
+    fn test_run_main() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Arrays);
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble(
+            r#"
+            fn num main(args) {
+                return std::arr::arr_len(args) + 1;
+            }
+            fn str greet(name) {
+                return std::str::stringify(name);
+            }
+            fn void quiet() { }
+            fn num answer() {
+                return 42;
+            }
+            "#,
+        ).unwrap());
+        vm.process();
+
+        assert_eq!(vm.run_main(vec!["a".to_string(), "b".to_string()]).unwrap(), 3);
+        assert_eq!(vm.run_main(vec![]).unwrap(), 1);
+        assert_eq!(vm.run_entrypoint("quiet", vec!["ignored".to_string()]).unwrap(), 0);
+        assert_eq!(vm.run_entrypoint("answer", vec![]).unwrap(), 42);
+        assert_eq!(
+            vm.run_function("greet", vec![Literal::String("gale".to_string())]).unwrap(),
+            Literal::String("gale".to_string())
+        );
+
+        let err = vm.run_function("greet", vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Function greet expects 1 arg(s), got 0!");
+        assert!(vm.run_entrypoint("greet", vec![]).unwrap_err().to_string().contains("should return a number"));
+        assert!(vm.run_function("missing", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_marshal() {
+        use crate::marshal::{param, FromLiteral, IntoLiteral, MAP_TYPE};
+        use crate::structs::StructureInstance;
+        use std::collections::HashMap;
+
+        fn marshal_roundtrip<T: IntoLiteral + FromLiteral + Clone + PartialEq + std::fmt::Debug>(value: T) {
+            assert_eq!(T::from_literal(value.clone().into_literal()).unwrap(), value);
+        }
+        marshal_roundtrip(-12i8);
+        marshal_roundtrip(u32::MAX);
+        marshal_roundtrip(2.5f64);
+        marshal_roundtrip("gale".to_string());
+        marshal_roundtrip('g');
+        marshal_roundtrip(true);
+        marshal_roundtrip(vec![Some(1usize), None, Some(3)]);
+        marshal_roundtrip(HashMap::from([("a".to_string(), vec![1i64]), ("b".to_string(), vec![])]));
+
+        assert_eq!(HashMap::from([("x".to_string(), 1)]).into_literal().this_type(), MAP_TYPE);
+        assert_eq!(Vec::<u8>::from_literal(Literal::Bytes(vec![1, 2])).unwrap(), vec![1, 2]);
+        assert_eq!(f32::from_literal(Literal::Number(3)).unwrap(), 3.0);
+        assert_eq!(u8::from_literal(Literal::Number(256)).unwrap_err().to_string(), "Number 256 does not fit into u8!");
+        assert_eq!(i64::from_literal(Literal::String("1".to_string())).unwrap_err().to_string(), "Expected num, got str 1!");
+
+        fn scale(params: Parameters) -> Literal {
+            let point: StructureInstance = param(&params, 0).unwrap();
+            let factor: i64 = param(&params, 1).unwrap();
+            StructureInstance::builder("Point")
+                .field("x", point.field::<i64>("x").unwrap() * factor)
+                .field("y", point.field::<i64>("y").unwrap() * factor)
+                .build()
+                .into_literal()
+        }
+
+        let mut vm = Vm::new();
+        extern_fns!(vm {
+            extern fn scale(point, factor) -> unknown;
+        });
+        vm.load_chain(&mut assemble(
+            r#"
+            fn num sum(point) {
+                return point.x + point.y;
+            }
+            "#,
+        ).unwrap());
+        vm.process();
+
+        let point = StructureInstance::builder("Point").field("x", 2).field("y", 3).build();
+        let scaled: StructureInstance = vm.run_function_as("scale", vec![point.into_literal(), 10.into_literal()]).unwrap();
+        assert_eq!((scaled.field::<i32>("x").unwrap(), scaled.field::<i32>("y").unwrap()), (20, 30));
+        assert!(scaled.field::<i32>("z").is_err());
+        assert_eq!(vm.run_function_as::<u64>("sum", vec![scaled.into_literal()]).unwrap(), 50);
+        assert!(param::<i64>(&vec![], 0).is_err());
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Vec2 {
+        x: i64,
+        y: i64,
+        label: Option<String>,
+    }
+
+    impl Vec2 {
+        fn dot(&self, other: Vec2) -> i64 {
+            self.x * other.x + self.y * other.y
+        }
+
+        fn name(&self) -> String {
+            self.label.clone().unwrap_or_else(|| format!("({}, {})", self.x, self.y))
+        }
+
+        fn scale(&mut self, factor: i64) {
+            self.x *= factor;
+            self.y *= factor;
+        }
+    }
+
+    crate::native_struct! {
+        Vec2 {
+            x: i64,
+            y: i64,
+            label: Option<String>,
+        }
+        fn dot(&self, other: Vec2) -> i64;
+        fn name(&self) -> String;
+        fn scale(&mut self, factor: i64);
+    }
+
+    #[test]
+    fn test_native_struct() {
+        use crate::marshal::{FromLiteral, IntoLiteral};
+        use crate::register_native_struct;
+
+        let mut vm = Vm::new();
+        register_native_struct!(vm, Vec2);
+        let template = vm.resolve_type("Vec2").unwrap();
+        assert_eq!(template.get_inst_var_type("x"), Some("num".to_string()));
+        assert_eq!(template.inst_fn_names(), vec!["dot", "name", "scale"]);
+
+        vm.load_chain(&mut assemble(
+            r#"
+            let a = Vec2 { x = 1, y = 2, label = * };
+            let b = Vec2 { x = 3, y = 4, label = "b" };
+            a.scale(10);
+            let dot = a.dot(b);
+            let name = b.name();
+            let unnamed = a.name();
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.resolve_var("dot").unwrap(), Literal::Number(110));
+        assert_eq!(vm.resolve_var("name").unwrap(), Literal::String("b".to_string()));
+        assert_eq!(vm.resolve_var("unnamed").unwrap(), Literal::String("(10, 20)".to_string()));
+        let a = Vec2::from_literal(vm.resolve_var("a").unwrap()).unwrap();
+        assert_eq!(a, Vec2 { x: 10, y: 20, label: None });
+
+        // instances made by the host can be passed back and forth
+        let c = Vec2 { x: 2, y: 0, label: None };
+        assert_eq!(vm.run_function_as::<i64>("native::Vec2::dot", vec![c.clone().into_literal(), a.into_literal()]).unwrap(), 20);
+        let err = Vec2::from_literal(crate::structs::StructureInstance::builder("Other").build().into_literal()).unwrap_err();
+        assert_eq!(err.to_string(), "Expected structure Vec2, got Other!");
+    }
+
+    #[test]
+    fn test_typed_externs() {
+        use crate::fns::StaticFnType;
+
+        fn typed_add(params: Parameters) -> Literal {
+            let (a, b) = crate::unwrap_args!(params => (Float, Float));
+            Literal::Float(a + b)
+        }
+
+        fn typed_repeat(params: Parameters) -> Literal {
+            match (&params[0], &params[1]) {
+                (Literal::String(str), Literal::Number(times)) => Literal::String(str.repeat(*times as usize)),
+                _ => unreachable!(),
+            }
+        }
+
+        let mut vm = Vm::new();
+        extern_fns!(vm {
+            extern fn typed_add(a: float, b: float) -> float;
+            extern fn typed_repeat(str: str, times: num) -> str;
+        });
+        match vm.resolve_fn("typed_repeat").unwrap() {
+            StaticFnType::Extern(ext) => assert_eq!(ext.param_types(), ["str", "num"]),
+            other => panic!("Expected an extern fn, got {:?}", other),
+        }
+
+        // numbers are widened to floats
+        vm.load_chain(&mut assemble(r#"
+            let sum = typed_add(1, 2.5);
+            let repeated = typed_repeat("ab", 3);
+        "#).unwrap());
+        vm.process();
+        assert_eq!(vm.resolve_var("sum").unwrap(), Literal::Float(3.5));
+        assert_eq!(vm.resolve_var("repeated").unwrap(), Literal::String("ababab".to_string()));
+
+        let mut bad = vm.clone();
+        bad.load_chain(&mut assemble(r#"let oops = typed_repeat(3, "ab");"#).unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<String>().unwrap(),
+            "Invalid argument str supplied! Expected value of type str, got num 3"
+        );
+
+        // types survive serialization
+        let fnc = vm.resolve_fn("typed_add").unwrap();
+        assert_roundtrip(fnc);
+    }
+
+    #[test]
+    fn test_extern_closures() {
+        use crate::fns::stateful;
+        use crate::visit::GlobalScope;
+
+        struct Store {
+            rows: Mutex<Vec<String>>,
+        }
+
+        impl Store {
+            fn insert(&self, params: Parameters) -> Literal {
+                let mut rows = self.rows.lock().unwrap();
+                rows.push(params[0].to_string());
+                Literal::Number(rows.len() as i64)
+            }
+        }
+
+        let store = Arc::new(Store { rows: Mutex::new(vec![]) });
+        let prefix = "row".to_string();
+        let mut calls = 0;
+        let mut vm = Vm::new();
+        let insert_store = store.clone();
+        extern_fns!(vm {
+            scope "db" {
+                extern fn insert(row: str) -> num = move |params| insert_store.insert(params);
+                extern fn label(index: num) -> str = move |params| Literal::String(format!("{}#{}", prefix, params[0]));
+                extern fn counter() -> num = stateful(move |_| {
+                    calls += 1;
+                    Literal::Number(calls)
+                });
+            }
+        });
+        vm.load_chain(&mut assemble(r#"
+            db::insert("first");
+            let count = db::insert(db::label(7));
+            db::counter();
+            let calls = db::counter();
+        "#).unwrap());
+        vm.process();
+
+        assert_eq!(vm.resolve_var("count").unwrap(), Literal::Number(2));
+        assert_eq!(vm.resolve_var("calls").unwrap(), Literal::Number(2));
+        assert_eq!(*store.rows.lock().unwrap(), vec!["first", "row#7"]);
+    }
+
+    #[test]
+    fn test_unregister_externs() {
+        use crate::fns::{register_extern_fn, FnRegistry, EXTERN_FNS};
+        use crate::visit::GlobalScope;
+
+        // slots are reused, but stale handlers never reach the new function
+        let mut registry = FnRegistry::<dyn Fn() -> i32 + Send + Sync>::new();
+        let first = registry.register(Arc::new(|| 1));
+        assert!(registry.replace(first, Arc::new(|| 2)));
+        assert_eq!(registry.get(first).unwrap()(), 2);
+        assert!(registry.remove(first).is_some());
+        let second = registry.register(Arc::new(|| 3));
+        assert_ne!(first, second);
+        assert!(registry.get(first).is_none());
+        assert!(!registry.replace(first, Arc::new(|| 4)));
+        assert_eq!(registry.get(second).unwrap()(), 3);
+        assert_eq!(registry.len(), 1);
+
+        let mut vm = Vm::new();
+        extern_fns!(vm {
+            scope "plugin" {
+                extern fn version() -> num = |_| Literal::Number(1);
+                extern fn name() -> str = |_| Literal::String("plugin".to_string());
+            }
+        });
+        vm.load_chain(&mut assemble(r#"
+            import plugin::version;
+            let before = version();
+        "#).unwrap());
+        vm.process();
+        assert_eq!(vm.resolve_var("before").unwrap(), Literal::Number(1));
+
+        // the imported copy calls the new handler
+        let handler = vm.resolve_fn("plugin::version").unwrap().handler().unwrap();
+        vm.replace_extern_fn("plugin::version", |_| Literal::Number(2)).unwrap();
+        assert_eq!(vm.resolve_fn("plugin::version").unwrap().handler(), Some(handler));
+        assert_eq!(vm.run_function("version", vec![]).unwrap(), Literal::Number(2));
+
+        vm.remove_extern_fn("plugin::version").unwrap();
+        assert!(EXTERN_FNS.lock().unwrap().get(handler).is_none());
+        assert!(vm.resolve_fn("version").is_err());
+        assert!(vm.resolve_fn("plugin::version").is_err());
+        assert!(vm.remove_extern_fn("plugin::version").is_err());
+        assert!(!vm.get_scope("global".to_string()).imports().contains_key("plugin"));
+        let reused = register_extern_fn(|_| Literal::Void);
+        assert_ne!(reused, handler);
+
+        let name = vm.resolve_fn("plugin::name").unwrap().handler().unwrap();
+        let scope = vm.remove_scope("plugin").unwrap();
+        assert!(scope.is_exported("name"));
+        assert!(!vm.has_scope("plugin"));
+        assert!(EXTERN_FNS.lock().unwrap().get(name).is_none());
+        assert_eq!(vm.remove_scope("global").unwrap_err().to_string(), "Can not remove scope global while it is in use!");
+        assert!(vm.remove_scope("plugin").is_err());
+    }
+
+    #[test]
+    fn test_remove_shared_extern_fn() {
+        use crate::fns::EXTERN_FNS;
+        let runtime = SharedRuntime::new(&[StdFeature::Math]);
+        let mut first = runtime.new_vm();
+        let mut second = runtime.new_vm();
+        let handler = second.resolve_fn("std::math::pow").unwrap().handler().unwrap();
+        first.remove_extern_fn("std::math::pow").unwrap();
+        assert!(first.resolve_fn("std::math::pow").is_err());
+        assert!(!Arc::ptr_eq(
+            &first.shared_scope("std::math").unwrap(),
+            &second.shared_scope("std::math").unwrap()
+        ));
+
+        // the other Vms keep calling the shared handler
+        assert!(EXTERN_FNS.lock().unwrap().get(handler).is_some());
+        second.load_chain(&mut assemble("import std::math::pow; let value = pow(2, 3);").unwrap());
+        second.process();
+        assert_eq!(second.resolve_var("value").unwrap(), Literal::Number(8));
+        assert!(runtime.new_vm().resolve_fn("std::math::pow").is_ok());
+    }
+
+    #[test]
+    fn test_scope_introspection() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Math);
+        vm.set_global("threshold", Literal::Number(10)).unwrap();
+        vm.load_chain(&mut assemble(r#"
+            import std::math::max;
+            const limit = 3;
+            let result = threshold * limit;
+            namespace util {
+                let inner = true;
+            }
+        "#).unwrap());
+        vm.process();
+
+        assert_eq!(vm.list_scopes(), vec!["global", "std::math", "util"]);
+        assert_eq!(vm.get_global("result"), Some(Literal::Number(30)));
+        assert_eq!(vm.get_global("limit"), Some(Literal::Number(3)));
+        assert_eq!(vm.get_global("missing"), None);
+        let vars = vm.list_vars("global").unwrap();
+        assert_eq!(vars.keys().collect::<Vec<_>>(), vec!["limit", "result", "threshold"]);
+        assert_eq!(vm.list_vars("util").unwrap().get("inner"), Some(&Literal::Bool(true)));
+        assert!(vm.list_vars("nowhere").is_err());
+
+        vm.set_global("result", Literal::String("overridden".to_string())).unwrap();
+        assert_eq!(vm.get_global("result"), Some(Literal::String("overridden".to_string())));
+        assert_eq!(vm.set_global("limit", Literal::Number(5)).unwrap_err().to_string(), "Can not reassign constant limit!");
+    }
+
+    #[test]
+    fn test_io_backend() {
+        let io = Arc::new(BufferedIo::with_input("first\nsecond"));
+        let mut vm = Vm::new();
+        vm.set_io(io.clone());
+        vm.add_std_feature(StdFeature::IO);
+        vm.load_chain(&mut assemble(r#"
+            import std::io::print;
+            import std::io::println;
+            import std::io::eprintln;
+            import std::io::debug;
+            import std::io::read_line;
+            let first = read_line();
+            let second = read_line();
+            print(first);
+            println(" and " + second);
+            debug(read_line());
+            eprintln("done");
+        "#).unwrap());
+        vm.process();
+
+        assert_eq!(io.take_out(), "first and second\nvoid\n");
+        assert_eq!(io.take_err(), "done\n");
+        assert_eq!(io.take_out(), "");
+
+        // clones share the backend, so nested calls write to the same buffer
+        let mut clone = vm.clone();
+        clone.load_chain(&mut assemble("println(first);").unwrap());
+        clone.process();
+        assert_eq!(io.take_out(), "first\n");
+    }
+
+    #[test]
+    fn test_log() {
+        let io = Arc::new(BufferedIo::new());
+        let mut vm = Vm::new();
+        vm.set_io(io.clone());
+        vm.add_std_feature(StdFeature::Log);
+        vm.load_chain(&mut assemble(r#"
+            import std::log::info;
+            info("starting");
+        "#).unwrap());
+        vm.process();
+        assert_eq!(io.take_err(), "[INFO] starting\n");
+
+        let logs = Arc::new(Mutex::new(vec![]));
+        let sink = logs.clone();
+        vm.set_logger(Box::new(move |level, msg| sink.lock().unwrap().push((level, msg.to_string()))));
+        vm.load_chain(&mut assemble(r#"
+            import std::log::trace;
+            import std::log::warn;
+            import std::log::error;
+            trace("entering");
+            warn("low memory");
+            error("out of memory");
+        "#).unwrap());
+        vm.process();
+        assert_eq!(io.take_err(), "");
+        assert_eq!(*logs.lock().unwrap(), vec![
+            (Level::Trace, "entering".to_string()),
+            (Level::Warn, "low memory".to_string()),
+            (Level::Error, "out of memory".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_script_tests() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Test);
+        let chain = assemble(r#"
+            import std::test::assert;
+            import std::test::assert_eq;
+            import std::test::assert_type;
+            fn num double(x) {
+                return x * 2;
+            }
+            test "doubles" {
+                assert_eq(double(2), 4);
+                assert_type(double(2), "num");
+            }
+            test "fails" {
+                let doubled = double(2);
+                assert(doubled > 4, "math is broken");
+            }
+            namespace util {
+                fn num half(x) {
+                    return x / 2;
+                }
+                test "halves" {
+                    assert_eq(half(8), 4);
+                }
+            }
+            test "wrong type" {
+                assert_type("text", "num");
+            }
+        "#).unwrap();
+        assert_eq!(disassemble(&chain).matches("test \"").count(), 4);
+        vm.load_chain(&mut chain.clone());
+        vm.process();
+
+        assert_eq!(vm.tests().iter().map(|it| it.scope.as_str()).collect::<Vec<_>>(), vec!["global", "global", "util", "global"]);
+        let report = vm.run_tests();
+        assert_eq!((report.passed(), report.failed()), (2, 2));
+        assert_eq!(report.results[1].failure.as_deref(), Some("Assertion failed: math is broken"));
+        assert_eq!(
+            report.results[3].failure.as_deref(),
+            Some("Assertion failed: expected value of type num, got str text")
+        );
+        assert!(report.to_string().ends_with("test result: FAILED. 2 passed; 2 failed"));
+        // the vm is still usable after failed tests
+        assert_eq!(vm.run_function("double", vec![Literal::Number(3)]).unwrap(), Literal::Number(6));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let run = |seed: u64| {
+            let mut vm = Vm::new();
+            vm.set_deterministic(seed);
+            vm.add_std_feature(StdFeature::Core);
+            vm.add_std_feature(StdFeature::Rand);
+            vm.add_std_feature(StdFeature::Time);
+            vm.enable_trace(TraceConfig { buffered: true, ..Default::default() });
+            vm.load_chain(&mut assemble(r#"
+                import std::sleep;
+                import std::rand::random_range;
+                import std::time::millis;
+                import std::time::now_millis;
+                fn num roll() {
+                    return random_range(1, 7);
+                }
+                let first = roll();
+                let second = roll();
+                sleep(millis(1500));
+                let time = now_millis();
+            "#).unwrap());
+            vm.process();
+            vm.deterministic().unwrap().advance(Duration::from_secs(2));
+            (vm.get_global("first").unwrap(), vm.get_global("second").unwrap(), vm.get_global("time").unwrap(), vm.disable_trace(), vm)
+        };
+        let (first, second, time, trace, vm) = run(42);
+        let (first_again, second_again, _, trace_again, _) = run(42);
+        assert_eq!((&first, &second), (&first_again, &second_again));
+        assert_eq!(trace, trace_again);
+        assert!(matches!(first, Literal::Number(1..=6)));
+        assert_eq!(time, Literal::Number(1500));
+        assert_eq!(vm.deterministic().unwrap().now(), Duration::from_millis(3500));
+        assert_eq!(vm.deterministic().unwrap().seed(), 42);
+
+        let draw = |seed| Entropy::deterministic(seed).rng().next_u64();
+        assert_eq!(draw(1), draw(1));
+        assert_ne!(draw(1), draw(2));
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_tokens() {
+        use arbitrary::{Arbitrary, Unstructured};
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3883);
+        let mut generated = 0;
+        for _ in 0..256 {
+            let bytes: Vec<u8> = (0..rng.gen_range(0..512)).map(|_| rng.gen()).collect();
+            let mut chain = match TokenChain::arbitrary(&mut Unstructured::new(&bytes)) {
+                Ok(chain) => chain,
+                Err(_) => continue,
+            };
+            generated += chain.len();
+            // floats may be NaN, so written bytes are compared instead of the values
+            let mut written = vec![];
+            chain.write(&mut written).unwrap();
+            assert_eq!(written.len(), chain.size());
+            let mut again = vec![];
+            TokenChain::read(&mut std::io::Cursor::new(written.clone())).unwrap().write(&mut again).unwrap();
+            assert_eq!(written, again);
+        }
+        assert!(generated > 0);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_transmute_roundtrip(
+            chain in crate::testing::token_chain(),
+            instance in crate::testing::structure_instance(),
+            template in crate::testing::structure_template(),
+            enum_template in crate::testing::enum_template(),
+            scope in crate::testing::containing_scope(),
+            manifest in crate::testing::manifest(),
+            source_map in crate::testing::source_map(),
+        ) {
+            assert_roundtrip(chain);
+            assert_roundtrip(instance);
+            assert_roundtrip(template);
+            assert_roundtrip(enum_template);
+            assert_roundtrip(scope);
+            assert_roundtrip(manifest);
+            assert_roundtrip(source_map);
+        }
+    }
+
+    #[test]
+    fn test_typed_bindings() {
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(r#"
+            let ratio: float = 2;
+            const limit: num = 10;
+            let count = 1;
+            count = count + limit;
+            let result = *;
+            result = "done";
+            let count = "redeclared";
+        "#).unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("ratio"), Some(Literal::Float(2.0)));
+        assert_eq!(vm.get_global("count"), Some(Literal::String("redeclared".to_string())));
+        assert_eq!(vm.shared_scope("global").unwrap().lock().unwrap().var_type("result"), Some("str"));
+
+        let fail = |source: &str| {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            err.downcast_ref::<String>().unwrap().to_owned()
+        };
+        assert_eq!(fail("let wrong: str = 1;"), "Can not assign num 1 to wrong, which is of type str!");
+        assert_eq!(fail("result = 2;"), "Can not assign num 2 to result, which is of type str!");
+        assert_eq!(fail("ratio = true;"), "Can not assign bool true to ratio, which is of type float!");
+
+        let chain = assemble("let ratio: float = 2;").unwrap();
+        assert_eq!(disassemble(&chain), "let ratio: float = 2;\n");
+    }
+
+    #[test]
+    fn test_typecheck() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble("import std::str::char_at; let greeting = \"hi\";").unwrap());
+        vm.process();
+        let chain = assemble(
+            r#"
+            fn num half(value) {
+                return value / 2;
+            }
+            fn str name() {
+                return 1;
+            }
+            let first = char_at(greeting, 0);
+            let second = std::str::substr(greeting, "0", 1);
+            let count: num = half(4) + 1;
+            let wrong = greeting - 1;
+            if count {
+                count = -greeting;
+            }
+            count = !true;
+            "#,
+        )
+        .unwrap();
+        let diagnostics = vm.typecheck(&chain);
+        let messages: Vec<(DiagnosticKind, &str)> = diagnostics.iter().map(|it| (it.kind, it.message.as_str())).collect();
+        assert_eq!(
+            messages,
+            vec![
+                (DiagnosticKind::ReturnTypeMismatch, "Function returns num, but is declared to return str!"),
+                (DiagnosticKind::ArgumentTypeMismatch, "Argument #2 of std::str::substr has to be num, got str!"),
+                (DiagnosticKind::OperandTypeMismatch, "Operator - can not be applied to str and num!"),
+                (DiagnosticKind::OperandTypeMismatch, "Operator ~ can not be applied to str!"),
+                (DiagnosticKind::TypeMismatch, "Can not assign bool to count, which is of type num!"),
+            ]
+        );
+
+        // everything is known to be fine, or unknown until execution
+        let valid = assemble("fn num twice(value) { return value * 2; } let doubled: float = twice(2);").unwrap();
+        assert!(vm.typecheck(&valid).is_empty());
+    }
+
+    #[test]
+    fn test_enums() {
+        let source = r#"
+            enum Outcome {
+                Done(num),
+                Failed(str),
+                Pending,
+            }
+            let first = Outcome::Done(5);
+            let second = Outcome::Pending;
+            let total = 0;
+            let label = "";
+            match first {
+                Outcome::Done(value) => {
+                    total = value + 1;
+                }
+                _ => {
+                    total = -1;
+                }
+            }
+            match second {
+                Failed(reason) => {
+                    label = reason;
+                }
+                Pending => {
+                    label = "pending";
+                }
+            }
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        let first = Literal::Enum("Outcome::Done".to_string(), Box::new(Literal::Number(5)));
+        assert_eq!(first.to_string(), "Outcome::Done(5)");
+        assert_eq!(first.this_type(), "Outcome");
+        assert_eq!(vm.get_global("first"), Some(first.clone()));
+        assert_eq!(vm.get_global("second").unwrap().to_string(), "Outcome::Pending");
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(6)));
+        assert_eq!(vm.get_global("label"), Some(Literal::String("pending".to_string())));
+        assert_roundtrip(first);
+
+        let fail = |source: &str| {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            err.downcast_ref::<String>().unwrap().to_owned()
+        };
+        assert!(fail("Outcome::Done(\"five\");").contains("Invalid payload five provided for variant Outcome::Done!"));
+        assert!(fail("match first { Failed(reason) => {} }").contains("No arm of match covers Outcome::Done!"));
+
+        let chain = assemble(
+            r#"
+            let wrong = Outcome::Failed(1);
+            let missing = Outcome::Lost;
+            match first {
+                Done(value) => {
+                    let text: str = value;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let kinds: Vec<DiagnosticKind> = vm.check(&chain).iter().map(|it| it.kind).collect();
+        assert_eq!(kinds, vec![DiagnosticKind::UndefinedVariable]);
+        let messages: Vec<String> = vm.typecheck(&chain).into_iter().map(|it| it.message).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Payload of Outcome::Failed has to be str, got num!",
+                "Can not assign num to text, which is of type str!",
+            ]
+        );
+
+        let chain = assemble(source).unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+    }
+
+    #[test]
+    fn test_destructuring() {
+        let source = r#"
+            Point {
+                x num
+                y num
+            }
+            fn num sum(point) {
+                let {x, y} = point;
+                return x + y;
+            }
+            let [first, _, rest..] = [1, 2, 3, 4];
+            let (left, right) = ["l", "r"];
+            let {x, y} = Point { x = 3, y = 4 };
+            let total = sum(Point { x = 5, y = 6 });
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("first"), Some(Literal::Number(1)));
+        assert_eq!(vm.get_global("_"), None);
+        assert_eq!(vm.get_global("rest"), Some(Literal::Array(vec![Literal::Number(3), Literal::Number(4)])));
+        assert_eq!(vm.get_global("right"), Some(Literal::String("r".to_string())));
+        assert_eq!(vm.get_global("y"), Some(Literal::Number(4)));
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(11)));
+
+        let fail = |source: &str| {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            err.downcast_ref::<String>().unwrap().to_owned()
+        };
+        assert!(fail("let [a, b] = [1, 2, 3];").contains("Can not destructure array of 3 value(s) into 2 name(s)!"));
+        assert!(fail("let [a, b, c..] = [1];").contains("Can not destructure array of 1 value(s) into at least 2 name(s)!"));
+        assert!(fail("let {x, z} = Point { x = 1, y = 2 };").contains("Structure Point has no field z to destructure!"));
+        assert!(fail("let [a] = 1;").contains("Expected an array to destructure, got 1!"));
+
+        let chain = assemble(source).unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        assert!(vm.check(&assemble("let [a, b..] = [1]; let c = a; let d = b;").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_statics() {
+        let source = r#"
+            static calls: num = 0;
+            fn num count() {
+                static seen = "first";
+                calls = calls + 1;
+                return calls;
+            }
+            count();
+            count();
+            let local = calls;
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        assert_eq!(vm.get_static("calls"), Some(Literal::Number(2)));
+        assert_eq!(vm.get_static("seen"), Some(Literal::String("first".to_string())));
+        assert_eq!(vm.get_global("local"), Some(Literal::Number(2)));
+        assert_eq!(vm.get_global("calls"), None);
+        assert_eq!(vm.dump_state().statics.len(), 2);
+
+        // clones share statics, also on other threads
+        let mut other = vm.clone();
+        std::thread::spawn(move || {
+            other.load_chain(&mut assemble("count();").unwrap());
+            other.process();
+        })
+        .join()
+        .unwrap();
+        assert_eq!(vm.get_static("calls"), Some(Literal::Number(3)));
+
+        let mut bad = vm.clone();
+        bad.load_chain(&mut assemble("calls = \"many\";").unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+        assert_eq!(err.downcast_ref::<String>().unwrap(), "Can not assign str many to calls, which is of type num!");
+
+        let chain = assemble("fn void reset() { calls = true; seen = 1; }").unwrap();
+        assert!(vm.check(&chain).is_empty());
+        let messages: Vec<String> = vm.typecheck(&chain).into_iter().map(|it| it.message).collect();
+        assert_eq!(messages, vec!["Can not assign bool to calls, which is of type num!"]);
+
+        let chain = assemble(source).unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+    }
+
+    #[test]
+    fn test_chained_calls() {
+        let source = r#"
+            Rect {
+                width num 0
+                height num 0
+                fn num area(this) {
+                    return this.width * this.height;
+                }
+            }
+            RectBuilder {
+                width num 1
+                height num 1
+                fn Self width(this, width) {
+                    this.width = width;
+                    return this;
+                }
+                fn RectBuilder height(this, height) {
+                    this.height = height;
+                    return this;
+                }
+                fn Rect build(this) {
+                    return Rect { width = this.width, height = this.height };
+                }
+            }
+            let builder = RectBuilder {};
+            let area = builder.width(3).height(4).build().area();
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("area"), Some(Literal::Number(12)));
+        // only the named receiver is updated, the temporary one is dropped
+        match vm.get_global("builder").unwrap() {
+            Literal::Struct(builder) => {
+                assert_eq!(builder.get_field("width"), Some(Literal::Number(3)));
+                assert_eq!(builder.get_field("height"), Some(Literal::Number(1)));
+            }
+            other => panic!("Expected a struct, got {:?}", other),
+        }
+
+        let chain = assemble(source).unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        assert!(vm.check(&chain).is_empty());
+    }
+
+    #[test]
+    fn test_access_modifiers() {
+        let source = r#"
+            Account {
+                pub owner str
+                priv balance num 0
+                priv let fee = 2;
+                pub fn num deposit(this, amount) {
+                    this.balance = this.balance + amount - Account::fee;
+                    return this.checked();
+                }
+                priv fn num checked(this) {
+                    return this.balance;
+                }
+            }
+            let account = Account { owner = "me" };
+            let total = account.deposit(10);
+            let owner = account.owner;
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(8)));
+        assert_eq!(vm.get_global("owner"), Some(Literal::String("me".to_string())));
+
+        let fail = |source: &str| {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            err.downcast_ref::<String>().unwrap().to_owned()
+        };
+        assert!(fail("let stolen = account.balance;").contains("balance of structure Account is private!"));
+        assert!(fail("account.balance = 100;").contains("balance of structure Account is private!"));
+        assert!(fail("let fee = Account::fee;").contains("fee of structure Account is private!"));
+        assert!(fail("let {balance} = account;").contains("balance of structure Account is private!"));
+        // instance functions report errors through the Vm
+        let mut bad = vm.clone();
+        bad.load_chain(&mut assemble("account.checked();").unwrap());
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).is_err());
+
+        let chain = assemble(source).unwrap();
+        assert!(disassemble(&chain).contains("    priv balance num 0\n"));
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+    }
+
+    #[test]
+    fn test_struct_init() {
+        let source = r#"
+            static inits = 0;
+            Circle {
+                radius num 1
+                fn num square(value) {
+                    return value * value;
+                }
+                init {
+                    const unit_area = Circle.square(3) * 2;
+                    let sizes = [1, 2, 3];
+                    inits = inits + 1;
+                }
+            }
+            let first = Circle { radius = 2 };
+            let second = Circle { };
+            let area = Circle::unit_area;
+            let sizes = Circle::sizes;
+            let init = 5;
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("area"), Some(Literal::Number(18)));
+        assert_eq!(vm.get_global("init"), Some(Literal::Number(5)));
+        assert_eq!(vm.get_global("sizes"), Some(Literal::Array(vec![Literal::Number(1), Literal::Number(2), Literal::Number(3)])));
+        assert_eq!(vm.get_static("inits"), Some(Literal::Number(1)));
+
+        let fail = |source: &str| {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            err.downcast_ref::<String>().unwrap().to_owned()
+        };
+        assert!(fail("init { let x = 1; }").contains("Initializer blocks can only be declared inside of a structure!"));
+
+        let chain = assemble(source).unwrap();
+        assert!(disassemble(&chain).contains("    init {\n"));
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        assert!(vm.check(&chain).is_empty());
+        assert!(vm.typecheck(&chain).is_empty());
+    }
+
+    #[test]
+    fn test_drop_hooks() {
+        let source = r#"
+            static closed = 0;
+            Handle {
+                id num
+                fn void __drop(this) {
+                    let copy = this;
+                    closed = closed * 10 + this.id;
+                }
+                fn num get(this) {
+                    return this.id;
+                }
+            }
+            fn Handle open() {
+                let first = Handle { id = 1 };
+                let second = Handle { id = 2 };
+                let kept = Handle { id = 3 };
+                let id = first.get();
+                return kept;
+            }
+            fn num close(handle) {
+                return handle.id;
+            }
+            let handle = open();
+            let id = close(handle);
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        // returned and passed values are owned by the caller, the digits are closed ids in order
+        assert_eq!(vm.get_static("closed"), Some(Literal::Number(12)));
+        assert_eq!(vm.get_global("id"), Some(Literal::Number(3)));
+
+        let library = assemble("let handle = Handle { id = 4 };").unwrap();
+        vm.add_library("handles", library).unwrap();
+        vm.remove_scope("handles").unwrap();
+        assert_eq!(vm.get_static("closed"), Some(Literal::Number(124)));
+    }
+
+    #[test]
+    fn test_custom_visitor() {
+        use crate::determinism::Entropy;
+        use crate::fns::StaticFnType;
+        use crate::handles::Handles;
+        use crate::io::SharedIo;
+        use crate::structs::{EnumTemplate, StructureTemplate};
+        use crate::var::ScopeGuard;
+        use crate::visit::{GlobalScope, Scope, TokenProvider, Visitable};
+        use std::collections::BTreeMap;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Visits the top level tokens on its own, and leaves everything else to a Vm
+        #[derive(Clone)]
+        struct Counting {
+            vm: Vm,
+            visited: Arc<AtomicUsize>,
+        }
+
+        macro_rules! delegate {
+            () => {};
+            (fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*) $(-> $out:ty)?; $($rest:tt)*) => {
+                fn $name(&mut self $(, $arg: $ty)*) $(-> $out)? {
+                    self.vm.$name($($arg),*)
+                }
+                delegate! { $($rest)* }
+            };
+            (fn $name:ident(&self $(, $arg:ident: $ty:ty)*) $(-> $out:ty)?; $($rest:tt)*) => {
+                fn $name(&self $(, $arg: $ty)*) $(-> $out)? {
+                    self.vm.$name($($arg),*)
+                }
+                delegate! { $($rest)* }
+            };
+        }
+
+        impl TokenProvider for Counting {
+            delegate! {
+                fn next_token(&mut self) -> anyhow::Result<Token>;
+                fn peek_token(&mut self) -> anyhow::Result<Token>;
+                fn add_token(&mut self, tk: Token);
+                fn insert_token(&mut self, tk: Token, at: usize);
+            }
+        }
+
+        impl ScopeProvider for Counting {
+            delegate! {
+                fn add_std_feature(&mut self, feature: StdFeature);
+                fn resolve_var(&self, name: &str) -> anyhow::Result<Literal>;
+                fn resolve_const(&self, name: &str) -> anyhow::Result<Literal>;
+                fn visible_values(&self) -> BTreeMap<String, Literal>;
+                fn visible_fns(&self) -> BTreeMap<String, StaticFnType>;
+                fn stack_values(&self) -> Vec<Literal>;
+                fn current_scope_name(&self) -> String;
+                fn import(&mut self, from: String, name: String);
+                fn export(&mut self, name: String);
+                fn warn(&mut self, code: WarningCode, message: String);
+                fn add_var(&mut self, name: String, var: Literal);
+                fn declare_var(&mut self, name: String, var: Literal, ty: Option<String>);
+                fn add_const(&mut self, name: String, var: Literal);
+                fn declare_static(&mut self, name: String, var: Literal, ty: Option<String>);
+                fn add_static_fn(&mut self, name: String, output_ty: String, param_names: Vec<String>, tks: TokenChain);
+                fn add_extern_fn(&mut self, name: String, output_ty: String, param_names: Vec<String>, ptr: usize);
+                fn add_typed_extern_fn(&mut self, name: String, output_ty: String, param_names: Vec<String>, param_types: Vec<String>, ptr: usize);
+                fn add_attr(&mut self, name: String, value: Literal);
+                fn io(&self) -> SharedIo;
+                fn handles(&self) -> Handles;
+                fn log(&self, level: Level, message: &str);
+                fn entropy(&self) -> Entropy;
+                fn add_test(&mut self, name: String, chain: TokenChain);
+                fn current_struct_name(&self) -> Option<String>;
+                fn add_struct_name(&mut self, name: String);
+                fn pop_struct_name(&mut self) -> Option<String>;
+                fn add_struct(&mut self, template: StructureTemplate);
+                fn resolve_type(&self, name: &str) -> anyhow::Result<StructureTemplate>;
+                fn add_enum(&mut self, template: EnumTemplate);
+                fn resolve_enum(&self, name: &str) -> anyhow::Result<EnumTemplate>;
+                fn add_inst_var(&mut self, name: String, ty: String, default: Option<Literal>);
+                fn check_access(&self, structure: &str, member: &str) -> anyhow::Result<()>;
+                fn add_struct_init(&mut self, chain: TokenChain);
+                fn init_struct(&mut self, name: String);
+                fn add_inst_fn(&mut self, name: String, output_ty: String, param_names: Vec<String>, tks: TokenChain);
+                fn call_static_fn(&mut self, name: String, params: TokenChain) -> Literal;
+                fn resolve_fn(&self, name: &str) -> anyhow::Result<StaticFnType>;
+                fn call_ptr_fn(&mut self, ptr: usize, params: TokenChain) -> Literal;
+                fn call_inst_fn(&mut self, receiver: String, name: String, params: TokenChain) -> Literal;
+                fn call_inst_fn_on(&mut self, instance: Literal, name: String, params: TokenChain) -> Literal;
+            }
+        }
+
+        impl GlobalScope for Counting {
+            delegate! {
+                fn push_scope_level(&mut self, scope: Scope);
+                fn pop_scope_level(&mut self) -> Scope;
+                fn scope_level(&mut self) -> Scope;
+                fn push_scope(&mut self, name: String, scope: ContainingScope);
+            }
+        }
+
+        impl LiteralStack for Counting {
+            delegate! {
+                fn push_stack(&mut self, value: Literal);
+                fn pop_stack(&mut self) -> anyhow::Result<Literal>;
+                fn move_scope(&mut self, name: String);
+                fn scope_name(&self) -> String;
+                fn drop_scope(&mut self, name: String) -> ContainingScope;
+                fn get_scope(&self, name: String) -> ScopeGuard<'_>;
+                fn has_scope(&self, name: &str) -> bool;
+                fn is_shared_scope(&self, name: &str) -> bool;
+            }
+        }
+
+        impl VisitorCore for Counting {
+            fn process(&mut self) {
+                while let Ok(mut tk) = self.peek_token() {
+                    self.next_token().unwrap();
+                    self.visited.fetch_add(1, Ordering::SeqCst);
+                    self.visit(&mut tk);
+                }
+            }
+
+            fn process_isolated(&mut self, chain: &mut TokenChain) {
+                let mut queued = vec![];
+                while let Ok(tk) = self.peek_token() {
+                    self.next_token().unwrap();
+                    queued.push(tk);
+                }
+                self.load_chain(chain);
+                self.process();
+                self.load_chain(&mut queued);
+            }
+
+            delegate! {
+                fn process_until(&mut self, until: usize);
+                fn process_between(&mut self, from: usize, to: usize);
+            }
+        }
+
+        impl Visitor for Counting {
+            fn visit<V>(&mut self, visitable: &mut V)
+            where
+                V: Visitable,
+            {
+                if let Err(err) = visitable.visit(self) {
+                    panic!("Found errors while visiting token!: {:?}", err)
+                }
+            }
+        }
+
+        let source = r#"
+            Counter {
+                priv count num 0
+                fn Self bump(this) {
+                    this.count = this.count + 1;
+                    return this;
+                }
+                fn num get(this) {
+                    return this.count;
+                }
+            }
+            fn num twice(value) {
+                return value * 2;
+            }
+            let counter = Counter { };
+            let value = counter.bump().bump().get();
+            let doubled = twice(value);
+            if doubled > 3 {
+                doubled = doubled + 1;
+            }
+        "#;
+        let mut counting = Counting { vm: Vm::new(), visited: Default::default() };
+        // processing only needs the object safe part
+        let core: &mut dyn VisitorCore = &mut counting;
+        core.load_chain(&mut assemble(source).unwrap());
+        core.process();
+        assert_eq!(counting.vm.get_global("value"), Some(Literal::Number(2)));
+        assert_eq!(counting.vm.get_global("doubled"), Some(Literal::Number(5)));
+        assert!(counting.visited.load(Ordering::SeqCst) > 0);
+
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        assert_eq!(vm.dump_state().scopes, counting.vm.dump_state().scopes);
+    }
+
+    #[test]
+    fn test_token_source() {
+        let source = r#"
+            fn num twice(value) {
+                return value > 2 ? value * 2 : value;
+            }
+            let total = 0;
+            let i = 0;
+            while i < 4 {
+                total = total + twice(i);
+                i = i + 1;
+            }
+        "#;
+        let pulled = Arc::new(Mutex::new(0));
+        let counter = pulled.clone();
+        let tokens = assemble(source).unwrap().into_iter().inspect(move |_| *counter.lock().unwrap() += 1);
+        let mut vm = Vm::new();
+        vm.set_source(tokens);
+        vm.process();
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(9)));
+        assert_eq!(*pulled.lock().unwrap(), assemble(source).unwrap().len());
+
+        // a channel feeds the Vm like a REPL, every `process` runs what arrived so far
+        let (sender, receiver) = std::sync::mpsc::channel();
+        vm.set_source(std::iter::from_fn(move || receiver.try_recv().ok()));
+        for tk in assemble("let doubled = twice(total);").unwrap() {
+            sender.send(tk).unwrap();
+        }
+        vm.process();
+        assert_eq!(vm.get_global("doubled"), Some(Literal::Number(18)));
+        vm.clear_source();
+
+        vm.feed(&mut assemble("let fed = doubled + 1;").unwrap().into_iter());
+        vm.process();
+        assert_eq!(vm.get_global("fed"), Some(Literal::Number(19)));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut vm = Vm::new();
+        vm.set_max_call_depth(8);
+        let source = r#"
+            fn num broken(n) {
+                n > 0 ? global::broken(n - 1) : missing();
+            }
+            let kept = depth(3);
+            1 + 2;
+        "#;
+        vm.load_chain(&mut assemble(&format!("{} {}", RECURSIVE_DEPTH, source)).unwrap());
+        vm.process();
+        // runs continue where the previous ones ended
+        vm.load_chain(&mut assemble("let next = kept + 1;").unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("next"), Some(Literal::Number(4)));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(3));
+
+        vm.push_stack(Literal::Number(1));
+        vm.clear_stack();
+        assert!(vm.pop_stack().is_err());
+
+        // an interrupted run leaves its calls, scopes and tokens behind
+        vm.load_chain(&mut assemble("let failed = broken(3); let after = 1;").unwrap());
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).is_err());
+        assert_ne!(vm.dump_state().scope_levels, vec![crate::visit::Scope::Global]);
+
+        vm.reset();
+        let state = vm.dump_state();
+        assert_eq!(state.scope_levels, vec![crate::visit::Scope::Global]);
+        assert_eq!(state.pending_tokens, 0);
+        assert_eq!(vm.scope_name(), "global");
+        assert_eq!(vm.get_global("after"), None);
+        vm.load_chain(&mut assemble("let again = depth(6);").unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("again"), Some(Literal::Number(6)));
+        assert_eq!(vm.get_global("kept"), Some(Literal::Number(3)));
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut vm = Vm::new();
+        let source = r#"
+            const limit = 10;
+            let count = 3;
+            fn num scaled(n) { return n * 10; }
+            4;
+        "#;
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+
+        assert_eq!(vm.eval("limit * 2").unwrap(), Literal::Number(20));
+        assert_eq!(vm.eval("count").unwrap(), Literal::Number(3));
+        assert_eq!(vm.eval("scaled(count) + 1;").unwrap(), Literal::Number(31));
+        assert_eq!(vm.eval("count > 2 ? \"many\" : \"few\"").unwrap(), Literal::String("many".to_string()));
+        assert!(vm.eval("unknown").is_err());
+        assert!(vm.eval("let other = 1;").is_err());
+        assert!(vm.eval("count +").is_err());
+
+        // assignments persist, while the stack is left as it was
+        assert_eq!(vm.eval("count = count + 1; count").unwrap(), Literal::Number(4));
+        assert_eq!(vm.get_global("count"), Some(Literal::Number(4)));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(4));
+        assert!(vm.pop_stack().is_err());
+    }
+
+    #[test]
+    fn test_template() {
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(r#"let name = "world"; const port = 8080; let tags = [1, 2];"#).unwrap());
+        vm.process();
+
+        let rendered = crate::template::render(&mut vm, "hello {{ name }}!\nport = {{port + 1}}\ntags = {{ tags }}").unwrap();
+        assert_eq!(rendered, "hello world!\nport = 8081\ntags = [1, 2]");
+        assert_eq!(crate::template::render(&mut vm, "no blocks {{ \"{{\" }}").unwrap(), "no blocks {{");
+
+        let err = crate::template::render(&mut vm, "first\n  {{ missing }}").unwrap_err();
+        assert!(err.to_string().contains("2:3"), "{}", err);
+        let err = crate::template::render(&mut vm, "{{ name").unwrap_err();
+        assert!(err.to_string().contains("Unclosed"), "{}", err);
+    }
+
+    #[test]
+    fn test_platform() {
+        use crate::platform;
+        // host functions only stand in for the missing services of wasm32
+        platform::set_clock(|| Duration::ZERO);
+        platform::set_random(|| 0);
+        assert!(platform::now() > Duration::from_secs(1_600_000_000));
+        assert_ne!(platform::random_u64(), platform::random_u64());
+
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Time);
+        vm.load_chain(&mut assemble("let started = std::time::now_secs();").unwrap());
+        vm.process();
+        match vm.get_global("started") {
+            Some(Literal::Number(secs)) => assert!(secs > 1_600_000_000),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validate_program() {
+        use crate::program::{write_program, write_program_with, Compression, Program, ProgramOptions};
+        use crate::spec::{validate_program, MAX_DEPTH};
+        let (chain, source_map) = assemble_spanned("let value = -(1 + 2); fn num twice(n) { return n * 2; }").unwrap();
+        let program = Program::new(chain.clone(), source_map.clone());
+        let mut compressions = vec![Compression::None];
+        if cfg!(feature = "deflate") {
+            compressions.push(Compression::Deflate);
+        }
+        for compression in compressions {
+            let mut bytes = vec![];
+            let options = ProgramOptions { compression, ..Default::default() };
+            write_program_with(&mut bytes, &mut program.clone(), &options).unwrap();
+            let info = validate_program(&bytes).unwrap();
+            assert_eq!(info.format_version, crate::vm::FORMAT_VERSION);
+            assert_eq!(info.compression, compression);
+            assert!(!info.encrypted);
+            let payload = info.payload.unwrap();
+            assert_eq!(payload.tokens, chain.len());
+            assert_eq!(payload.spans, source_map.len());
+            assert_eq!(payload.size, chain.clone().size() + source_map.clone().size());
+            assert!(payload.depth >= 3);
+        }
+
+        let mut bytes = vec![];
+        write_program(&mut bytes, &mut Program::new(vec![Token::Literal(Literal::String("hi".to_string()))], Default::default())).unwrap();
+        assert_eq!(validate_program(&bytes).unwrap().payload.unwrap().tokens, 1);
+        let corrupt = |at: usize, byte: u8| {
+            let mut corrupt = bytes.clone();
+            let at = corrupt.len() - at;
+            corrupt[at] = byte;
+            validate_program(&corrupt).unwrap_err().to_string()
+        };
+        assert!(corrupt(11, 0x0D).contains("Unknown literal tag"));
+        assert!(corrupt(6, 0xFF).contains("UTF-8"));
+        assert!(corrupt(7, 0x09).contains("exceeds"));
+        assert!(validate_program(&bytes[..bytes.len() - 1]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(validate_program(&trailing).unwrap_err().to_string().contains("trailing"));
+
+        // nesting is bounded before a reader would recurse into it
+        let head = bytes.len() - 16;
+        let mut deep = bytes[..head].to_vec();
+        deep.extend(1u32.to_be_bytes());
+        for _ in 0..MAX_DEPTH * 2 {
+            deep.extend([0x09, 0x01, 0x00]);
+        }
+        deep.push(0x00);
+        deep.extend(0u32.to_be_bytes());
+        assert!(validate_program(&deep).unwrap_err().to_string().contains("nested deeper"));
+
+        let commented = crate::asm::assemble_with("// note\nlet a = 1;", &crate::asm::AssembleOptions::default().with_comments()).unwrap();
+        assert!(matches!(commented[0], Token::Comment(_)));
+        let mut bytes = vec![];
+        write_program(&mut bytes, &mut Program::new(commented.clone(), Default::default())).unwrap();
+        assert_eq!(validate_program(&bytes).unwrap().payload.unwrap().tokens, commented.len());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_validate_program(
+            chain in crate::testing::token_chain(),
+            source_map in crate::testing::source_map(),
+            manifest in crate::testing::manifest(),
+        ) {
+            use crate::program::{write_program, Program};
+            let mut bytes = vec![];
+            let mut program = Program::new(chain.clone(), source_map.clone()).with_manifest(manifest.clone());
+            write_program(&mut bytes, &mut program).unwrap();
+            let info = crate::spec::validate_program(&bytes).unwrap();
+            assert_eq!(info.manifest, manifest);
+            assert_eq!(info.payload.unwrap().tokens, chain.len());
+        }
+    }
+
+    #[test]
+    fn test_versioned_programs() {
+        use crate::manifest::Manifest;
+        use crate::program::{read_program, Program};
+        use crate::spec::validate_program;
+        use crate::vm::{FORMAT_VERSION, MAGIC, MIN_FORMAT_VERSION};
+        let (chain, source_map) = assemble_spanned("let value = -(1 + 2); fn num twice(n) { return n * 2; }").unwrap();
+        let legacy = |version: u16, chain: &TokenChain, with_map: bool| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(version.to_be_bytes());
+            if version >= 2 {
+                bytes.push(0x00);
+            }
+            if version >= 3 {
+                Manifest::default().write_to(&mut bytes).unwrap();
+            }
+            chain.clone().write_to(&mut bytes).unwrap();
+            if with_map {
+                source_map.clone().write_to(&mut bytes).unwrap();
+            }
+            bytes
+        };
+        for version in MIN_FORMAT_VERSION..=FORMAT_VERSION {
+            let bytes = legacy(version, &chain, true);
+            assert_eq!(read_program(&mut bytes.as_slice()).unwrap(), Program::new(chain.clone(), source_map.clone()));
+            let info = validate_program(&bytes).unwrap();
+            assert_eq!(info.format_version, version);
+            assert_eq!(info.payload.unwrap().tokens, chain.len());
+        }
+        let bare = legacy(1, &chain, false);
+        assert_eq!(read_program(&mut bare.as_slice()).unwrap(), Program::new(chain.clone(), Default::default()));
+
+        // tags are only read by the versions that have them
+        let (newer, _) = assemble_spanned("enum Shape { Circle(num) } let shape = Shape::Circle(1);").unwrap();
+        assert!(read_program(&mut legacy(5, &newer, true).as_slice()).is_ok());
+        let err = read_program(&mut legacy(4, &newer, true).as_slice()).unwrap_err().to_string();
+        assert!(err.contains("not a part of format version 4"), "{}", err);
+        let (indexed, _) = assemble_spanned("let last = values[-1];").unwrap();
+        assert!(read_program(&mut legacy(6, &indexed, true).as_slice()).is_ok());
+        let err = read_program(&mut legacy(5, &indexed, true).as_slice()).unwrap_err().to_string();
+        assert!(err.contains("not a part of format version 5"), "{}", err);
+        let (exact, _) = assemble_spanned("let price = 1.50d;").unwrap();
+        assert!(read_program(&mut legacy(7, &exact, true).as_slice()).is_ok());
+        let err = read_program(&mut legacy(6, &exact, true).as_slice()).unwrap_err().to_string();
+        assert!(err.contains("not a part of format version 6"), "{}", err);
+        let mut future = legacy(FORMAT_VERSION, &chain, true);
+        future[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        assert!(read_program(&mut future.as_slice()).unwrap_err().to_string().contains("newer"));
+        let mut unknown = legacy(FORMAT_VERSION, &vec![Token::Keyword(Keyword::Let)], false);
+        unknown.extend(0u32.to_be_bytes());
+        let at = unknown.len() - 5;
+        unknown[at] = 0x7F;
+        assert!(read_program(&mut unknown.as_slice()).unwrap_err().to_string().contains("Unknown keyword tag 0x7f"));
+        assert!(validate_program(&unknown).unwrap_err().to_string().contains("Unknown keyword tag 0x7f"));
+    }
+
+    #[test]
+    fn test_compile_cache() {
+        use crate::asm::AssembleOptions;
+        use crate::cache::CompileCache;
+        let root = std::env::temp_dir().join(format!("galevm_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let source = root.join("main.gale");
+        std::fs::write(&source, "let a = 1 + 2;").unwrap();
+        let cache = CompileCache::new(root.join("cache"));
+
+        // the first compilation stores the program, the second one reads it back
+        let compiled = cache.compile(&source).unwrap();
+        let key = cache.key("let a = 1 + 2;");
+        assert!(cache.entry_path(&source).exists());
+        assert_eq!(cache.get(&source, key), Some(compiled.clone()));
+        assert_eq!(cache.compile(&source).unwrap(), compiled);
+
+        // changing the source or the options invalidates the entry
+        std::fs::write(&source, "let a = 3;").unwrap();
+        assert_eq!(cache.get(&source, cache.key("let a = 3;")), None);
+        let changed = cache.compile(&source).unwrap();
+        assert_ne!(changed, compiled);
+        assert_eq!(cache.get(&source, cache.key("let a = 3;")), Some(changed));
+        assert_eq!(cache.get(&source, key), None);
+        let other = CompileCache::new(root.join("cache"))
+            .with_options(AssembleOptions::default().with_flag("DEBUG"));
+        assert_ne!(other.key("let a = 3;"), cache.key("let a = 3;"));
+        assert_eq!(other.get(&source, other.key("let a = 3;")), None);
+
+        // broken entries are compiled again
+        std::fs::write(cache.entry_path(&source), b"garbage").unwrap();
+        assert_eq!(cache.compile(&source).unwrap().chain, assemble("let a = 3;").unwrap());
+
+        cache.clear().unwrap();
+        assert!(!cache.dir().exists());
+        cache.clear().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_preprocessor() {
+        use crate::asm::{assemble_with, AssembleOptions};
+        let src = r#"
+            #define LIMIT 10
+            #define TWICE LIMIT * 2
+            let a = TWICE;
+            #if DEBUG
+                let mode = "debug";
+                #if VERBOSE
+                    let verbose = true;
+                #endif
+            #elif RELEASE
+                let mode = "release";
+            #else
+                let mode = "default";
+            #endif
+            #if !DEBUG
+            let checked = false;
+            #endif
+            #undef LIMIT
+            let LIMIT = 1;
+        "#;
+
+        let release = AssembleOptions::default().with_flag("RELEASE");
+        assert_eq!(
+            assemble_with(src, &release).unwrap(),
+            assemble(r#"let a = 10 * 2; let mode = "release"; let checked = false; let LIMIT = 1;"#)
+                .unwrap()
+        );
+        let debug = AssembleOptions::default()
+            .with_flag("DEBUG")
+            .with_flag("RELEASE")
+            .with_define("VERBOSE", "");
+        assert_eq!(
+            assemble_with(src, &debug).unwrap(),
+            assemble(r#"let a = 10 * 2; let mode = "debug"; let verbose = true; let LIMIT = 1;"#)
+                .unwrap()
+        );
+        assert_eq!(
+            assemble(src).unwrap(),
+            assemble(r#"let a = 10 * 2; let mode = "default"; let checked = false; let LIMIT = 1;"#)
+                .unwrap()
+        );
+
+        // defined values are lexed like sources, and replace words outside of directives
+        let options = AssembleOptions::default().with_define("GREETING", r#""hi" + "!""#);
+        assert_eq!(
+            assemble_with(r#"let a = GREETING; let b = "GREETING";"#, &options).unwrap(),
+            assemble(r#"let a = "hi" + "!"; let b = "GREETING";"#).unwrap()
+        );
+        let mut vm = Vm::new();
+        vm.define("LEVEL", "3");
+        assert_eq!(vm.eval("LEVEL + 1").unwrap(), Literal::Number(4));
+
+        for (src, error) in [
+            ("#if DEBUG\nlet a = 1;", "Unclosed #if at line 1!"),
+            ("let a = 1;\n#endif", "Unexpected #endif at line 2!"),
+            ("#if A\n#else\n#else\n#endif", "Unexpected #else at line 3!"),
+            ("#include \"a.gale\"", "Unknown preprocessor directive #include at line 1!"),
+            ("#if 1 + 2\n#endif", "Expected a name as the condition at line 1!"),
+            ("#define\n", "Expected a name after #define at line 1!"),
+            ("let a = 1; # 2", "Unexpected character '#' at line 1!"),
+        ] {
+            assert_eq!(assemble(src).unwrap_err().to_string(), error, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_macros() {
+        let source = r#"
+            macro repeat(times, body) {
+                let i = 0;
+                while i < times { body i = i + 1; }
+            }
+            macro square(x) { (x) * (x) }
+            let total = 0;
+            let i = 100;
+            repeat(3) { total = total + square(i - 98); }
+            repeat(2) { repeat(2) { total = total + 1; } }
+            let squared = square(1 + 2);
+        "#;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(source).unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(16)));
+        assert_eq!(vm.get_global("squared"), Some(Literal::Number(9)));
+        // names declared by the macro do not clash with the ones around it
+        assert_eq!(vm.get_global("i"), Some(Literal::Number(100)));
+
+        // macros end with their block, and are not expanded in method calls and paths
+        assert_eq!(
+            assemble("{ macro one() { 1 } let a = one(); } let b = one(); c.one(); lib::one();").unwrap(),
+            assemble("{ let a = 1; } let b = one(); c.one(); lib::one();").unwrap()
+        );
+        // `macro` stays usable as a name
+        assert_eq!(assemble("let macro = 1;").unwrap().len(), 3);
+
+        for (src, error) in [
+            ("macro two(a, b) { a + b }\ntwo(1);", "Macro two expects 2 argument(s), got 1 at line 2!"),
+            ("macro bad(a, a) { a }", "Expected distinct parameter names of macro bad at line 1!"),
+            ("macro bad(a + 1) { a }", "Expected distinct parameter names of macro bad at line 1!"),
+            ("macro bad(a);", "Expected a body of macro bad at line 1!"),
+            ("macro bad(a) { a", "Unclosed macro group at line 1!"),
+            ("macro loop() { loop() }\nloop();", "Expansion of macro loop at line 2 is nested too deeply!"),
+        ] {
+            assert_eq!(assemble(src).unwrap_err().to_string(), error, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_gale_chain() {
+        let chain = crate::gale_chain! {
+            const constant = 200 + 300;
+            let mutable_var = "Hello, World!";
+        };
+        assert_eq!(
+            chain,
+            vec![
+                Token::Keyword(Keyword::Const),
+                Token::Literal(Literal::Ident(String::from("constant"))),
+                Token::Expression(Box::new(Expression::BinaryOp(
+                    BinaryOp::Add,
+                    Token::Literal(Literal::Number(200)),
+                    Token::Literal(Literal::Number(300)),
+                ))),
+                Token::Keyword(Keyword::Let),
+                Token::Literal(Literal::Ident(String::from("mutable_var"))),
+                Token::Literal(Literal::String(String::from("Hello, World!"))),
+            ]
+        );
+
+        // spacing added by the Rust tokenizer does not change the meaning
+        let src = r#"
+            fn str greet(name) { return "Hello,\t" + name; }
+            let values = [-9223372036854775808, 'c', 2.5e3, 0x1F];
+            let greeting = global::greet("world");
+            let check = 2 * 2 > 3 && !false;
+        "#;
+        let mut chain = crate::gale_chain! {
+            fn str greet(name) { return "Hello,\t" + name; }
+            let values = [-9223372036854775808, 'c', 2.5e3, 0x1F];
+            let greeting = global::greet("world");
+            let check = 2 * 2 > 3 && !false;
+        };
+        assert_eq!(chain, assemble(src).unwrap());
+        let mut vm = Vm::new();
+        vm.load_chain(&mut chain);
+        vm.process();
+        assert_eq!(vm.get_global("greeting"), Some(Literal::String("Hello,\tworld".to_string())));
+
+        let err = std::panic::catch_unwind(|| crate::gale_chain!(let = ;)).unwrap_err();
+        assert!(err.downcast_ref::<String>().unwrap().starts_with("Invalid gale_chain! pseudocode: "));
+    }
+
+    #[test]
+    fn test_chain_builder() {
+        use crate::builder::{binary, ident, invoke, unary, ChainBuilder, IntoToken};
+        use crate::tks::UnaryOp;
+        let chain = ChainBuilder::new()
+            .function_returning("scaled", "num", &["n", "by"], |body| {
+                body.ret(binary(BinaryOp::Mul, ident("n"), ident("by")))
+            })
+            .function("nothing", &[], |body| body)
+            .const_var("limit", 3)
+            .let_var("i", 0)
+            .let_typed("total", "num", 0)
+            .let_var("label", "none")
+            .while_loop(binary(BinaryOp::Lt, ident("i"), ident("limit")), |body| {
+                body.assign("total", binary(BinaryOp::Add, ident("total"), invoke("scaled", [ident("i"), 10i64.into_token()])))
+                    .assign("i", binary(BinaryOp::Add, ident("i"), 1))
+            })
+            .if_block(unary(UnaryOp::Neg, true), |body| body.assign("label", "never"))
+            .elif_block(binary(BinaryOp::Gt, ident("total"), 20), |body| body.assign("label", "many"))
+            .else_block(|body| body.assign("label", "few"))
+            .call("nothing", Vec::<Token>::new())
+            .build();
+        let src = r#"
+            fn num scaled(n, by) { return n * by; }
+            fn void nothing() {}
+            const limit = 3;
+            let i = 0;
+            let total: num = 0;
+            let label = "none";
+            while i < limit {
+                total = total + scaled(i, 10);
+                i = i + 1;
+            }
+            if !true { label = "never"; } elif total > 20 { label = "many"; } else { label = "few"; }
+            nothing();
+        "#;
+        assert_eq!(chain, assemble(src).unwrap());
+
+        let mut vm = Vm::new();
+        vm.load_chain(&mut chain.clone());
+        vm.process();
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(30)));
+        assert_eq!(vm.get_global("label"), Some(Literal::String("many".to_string())));
+    }
+
+    #[test]
+    fn test_ast() {
+        use crate::ast::{lift, lower, Expr, Stmt};
+        let source = r#"
+            @doc("shapes")
+            namespace shapes {
+                Circle {
+                    radius num 1
+                    label str
+                    pub fn num area(this) { return this.radius * this.radius * 3; }
+                    init { static made = made + 1; }
+                }
+            }
+            enum Outcome { Done(num), Pending }
+            fn str describe(value, fallback) {
+                match value {
+                    Outcome::Done(n) => { return "done"; }
+                    _ => { return fallback; }
+                }
+            }
+            let [first, rest..] = [1, 2, 3];
+            let total: num = 0;
+            if total > 1 { total = 1; } elif total < 0 { total = 0; } else { total = -1; }
+            if false { total = 2; }
+            while total < 10 { total = total + first; }
+            do { total = total - 1; } while total > 5;
+            let point = Point { x = 1, y = total > 2 ? [first, 2] : [] };
+            point.move(1).length();
+            std::io::println(describe(Outcome::Pending, "pending"));
+            export total;
+            test "totals" { import std::test; }
+            <end>
+        "#;
+        let chain = assemble(source).unwrap();
+        let ast = lift(&chain).unwrap();
+        assert_eq!(lower(&ast), chain);
+        assert_eq!(ast.len(), 16);
+        match &ast[6] {
+            Stmt::If { branches, otherwise } => {
+                assert_eq!(branches.len(), 2);
+                assert_eq!(branches[1].0, Expr::lift(&assemble("total < 0;").unwrap()[0]).unwrap());
+                assert_eq!(otherwise.as_ref().map(Vec::len), Some(1));
+            }
+            other => panic!("Expected an if statement, got {:?}", other),
+        }
+        assert!(matches!(&ast[7], Stmt::If { branches, otherwise: None } if branches.len() == 1));
+
+        for (chain, error) in [
+            (vec![Token::Expression(Box::new(Expression::ElseStmt)), Token::LBracket, Token::RBracket], "Expected an if before ElseStmt!"),
+            (vec![Token::Expression(Box::new(Expression::WhileStmt)), Token::Literal(Literal::Bool(true))], "Expected a block after while, got None!"),
+            (vec![Token::LBracket, Token::End], "Unclosed block at the end of token chain!"),
+            (vec![Token::RParen], "Unexpected RParen at the start of a statement!"),
+            (vec![Token::Keyword(Keyword::Return), Token::Expression(Box::new(Expression::IfStmt))], "Expected an expression, got the IfStmt statement!"),
+        ] {
+            assert_eq!(lift(&chain).unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        use crate::check::{Diagnostic, DiagnosticKind};
+        use crate::diagnostics::{render, Report};
+        use crate::span::Span;
+        use crate::warn::{Warning, WarningCode};
+        let source = "let x = 1;\n\tstd::io::printn(x);\n";
+        let diagnostic = Diagnostic {
+            index: 4,
+            span: Some(Span::new(2, 2, 15)),
+            kind: DiagnosticKind::UnknownFunction,
+            message: "Could not find function std::io::printn!".to_string(),
+        };
+        let report = Report::from(&diagnostic)
+            .with_origin("main.gale")
+            .with_help("did you mean std::io::println?");
+        assert_eq!(
+            render(source, report),
+            "error[UnknownFunction]: Could not find function std::io::printn!\n \
+             --> main.gale:2:2\n  |\n2 | \tstd::io::printn(x);\n  | \t^^^^^^^^^^^^^^^\n  |\n  \
+             = help: did you mean std::io::println?\n"
+        );
+
+        let err = assemble("let x = 1;\n    let = 2;").unwrap_err();
+        let rendered = render("let x = 1;\n    let = 2;", Report::from(&err));
+        assert!(rendered.ends_with(" --> 2:5\n  |\n2 |     let = 2;\n  |     ^^^^^^^^\n"));
+
+        let warning = Warning {
+            code: WarningCode::UnusedImport,
+            span: None,
+            message: "Unused import std::io!".to_string(),
+        };
+        assert_eq!(
+            render(source, Report::from(&warning).with_origin("main.gale")),
+            "warning[W0003]: Unused import std::io!\n--> main.gale\n"
+        );
+        // spans past the end of the source are rendered without an excerpt
+        let outside = Report::from(&diagnostic).with_span(Span::new(9, 1, 1));
+        assert!(!render(source, outside).contains('|'));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        use crate::diagnostics::{did_you_mean, edit_distance, suggest, Report};
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("valeu", "value"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(suggest("cout", ["count", "amount", "cost", "cout"]), vec!["cost", "count"]);
+        assert_eq!(did_you_mean("x", Vec::<String>::new()), "");
+
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::IO);
+        vm.load_chain(&mut assemble(r#"
+            Point { x num }
+            const limit = 3;
+            let value = 1;
+            fn void greet() {}
+            fn void grate() {}
+        "#).unwrap());
+        vm.process();
+        let err = vm.eval("valeu").unwrap_err();
+        assert_eq!(err.to_string(), "Could not find variable or constant valeu! Did you mean value?");
+        let report = Report::from(&err);
+        assert_eq!(report.message, "Could not find variable or constant valeu!");
+        assert_eq!(report.help.as_deref(), Some("did you mean value?"));
+        assert_eq!(
+            vm.resolve_fn("great").unwrap_err().to_string(),
+            "Could not find function great in current scope! Did you mean greet?"
+        );
+        assert_eq!(
+            vm.resolve_fn("std::io::printn").unwrap_err().to_string(),
+            "Could not find function printn in scope std::io! Did you mean print, println or eprintln?"
+        );
+        assert_eq!(vm.resolve_fn("std::oi::print").unwrap_err().to_string(), "Could not find scope std::oi! Did you mean std::io?");
+        assert_eq!(vm.resolve_type("Pont").unwrap_err().to_string(), "Could not find structure Pont! Did you mean Point?");
+        assert_eq!(vm.resolve_type("Shape").unwrap_err().to_string(), "Could not find structure Shape!");
+    }
+
+    #[test]
+    fn test_dbg() {
+        use crate::marshal::MAP_TYPE;
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Debug);
+        vm.load_chain(&mut assemble(r#"
+            const limit = 3;
+            let value = 1;
+            fn num twice(n) { return n * 2; }
+            namespace inner {
+                let hidden = true;
+                let location = std::dbg::where();
+            }
+            let visible = std::dbg::scope();
+            let location = std::dbg::where();
+            fn map probe(arg) { let local = 5; return std::dbg::scope(); }
+            let probed = probe(1);
+            let stack = std::dbg::stack();
+        "#).unwrap());
+        vm.process();
+        let visible = match vm.get_global("visible") {
+            Some(Literal::Struct(it)) => it,
+            other => panic!("Expected a map, got {:?}!", other),
+        };
+        assert_eq!(visible.type_name(), MAP_TYPE);
+        assert_eq!(visible.field_names(), vec!["limit", "twice", "value"]);
+        assert_eq!(visible.field::<i64>("limit").unwrap(), 3);
+        assert_eq!(visible.field::<String>("twice").unwrap(), "fn(n) -> num");
+        assert_eq!(vm.get_global("location"), Some(Literal::String("global".to_string())));
+        assert_eq!(vm.eval("inner::location").unwrap(), Literal::String("inner".to_string()));
+        match vm.get_global("probed") {
+            Some(Literal::Struct(it)) => {
+                assert!(it.field_names().contains(&"arg".to_string()));
+                assert!(it.field_names().contains(&"local".to_string()));
+            }
+            other => panic!("Expected a map, got {:?}!", other),
+        }
+        assert_eq!(vm.get_global("stack"), Some(Literal::Array(vec![])));
+    }
+
+    #[cfg(feature = "lsp")]
+    #[test]
+    fn test_lsp() {
+        use crate::lsp::{completions, definition, diagnostics, read_message, symbols, Server, SymbolKind};
+        use crate::span::Span;
+        use serde_json::json;
+        let source = "fn num twice(n) {\n    return n * 2;\n}\nPoint { x num }\nenum Shape { Circle(num) }\nlet value = twice(1);\nwhile value > 3 { value = 0; }\n";
+        let (chain, source_map) = assemble_spanned(source).unwrap();
+        let symbols = symbols(&chain, &source_map);
+        let names: Vec<(&str, SymbolKind)> = symbols.iter().map(|it| (it.name.as_str(), it.kind)).collect();
+        assert_eq!(names, vec![
+            ("twice", SymbolKind::Function),
+            ("Point", SymbolKind::Structure),
+            ("Shape", SymbolKind::Enum),
+            ("value", SymbolKind::Variable),
+        ]);
+        assert_eq!(definition(&symbols, "lib::twice").unwrap().span, Some(Span::new(1, 8, 5)));
+        assert!(definition(&symbols, "value").is_none());
+
+        let env = {
+            let mut vm = Vm::new();
+            vm.add_std_feature(StdFeature::IO);
+            vm.dump_state()
+        };
+        let scoped = completions(&env, &symbols, "std::io::pri");
+        assert!(scoped.contains_key("println") && !scoped.contains_key("twice"));
+        let all = completions(&env, &symbols, "tw");
+        assert_eq!(all.get("twice"), Some(&SymbolKind::Function));
+        assert_eq!(all.get("std::io::println"), Some(&SymbolKind::Function));
+
+        let vm = Vm::new();
+        let reports = diagnostics(&vm, "unknown(1);\n");
+        assert_eq!(reports[0].code.as_deref(), Some("UnknownFunction"));
+        assert_eq!(diagnostics(&vm, "let = 1;")[0].span, Some(Span::new(1, 0, 0)));
+
+        let open = json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": { "uri": "file:///a.gale", "text": source }
+        }});
+        let goto = json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/definition", "params": {
+            "textDocument": { "uri": "file:///a.gale" }, "position": { "line": 5, "character": 14 }
+        }});
+        let mut out = vec![];
+        let mut server = Server::new(&mut out);
+        assert!(server.handle(open).unwrap());
+        assert!(server.handle(goto).unwrap());
+        assert!(!server.handle(json!({ "jsonrpc": "2.0", "method": "exit" })).unwrap());
+        assert!(!server.is_shutdown());
+        let mut out = out.as_slice();
+        let published = read_message(&mut out).unwrap().unwrap();
+        assert_eq!(published["params"]["diagnostics"], json!([]));
+        let found = read_message(&mut out).unwrap().unwrap();
+        assert_eq!(found["result"]["range"]["start"], json!({ "line": 0, "character": 7 }));
+        assert!(read_message(&mut out).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_comments() {
+        use crate::asm::{assemble_spanned_with, assemble_with, AssembleOptions};
+        use crate::dasm::disassemble;
+        let src = r#"
+            // a point
+            Point {
+                x num // horizontal
+                /* vertical */ y num
+            }
+            enum Outcome { Done(num) }
+            fn num sum(p) {
+                return p.x /* and */ + p.y;
+            }
+            let total = sum(Point { x = 1, y = 2 });
+            let outcome = Outcome::Done(total);
+            match outcome {
+                // the value
+                Outcome::Done(v) => { total = v + 1; }
+            }
+            /* multi
+               line */
+        "#;
+
+        // comments are dropped by default
+        let stripped = assemble(src).unwrap();
+        assert!(!stripped.contains(&Token::Comment("// a point".to_string())));
+        assert_eq!(
+            stripped,
+            assemble(&src.replace("// the value", "").replace("/* vertical */", "")).unwrap()
+        );
+
+        let options = AssembleOptions::default().with_comments();
+        let (mut chain, spans) = assemble_spanned_with(src, &options).unwrap();
+        let comments: Vec<(usize, &Token)> = chain
+            .iter()
+            .enumerate()
+            .filter(|(_, tk)| matches!(tk, Token::Comment(_)))
+            .collect();
+        assert_eq!(comments.len(), 6);
+        assert_eq!(chain[0], Token::Comment("// a point".to_string()));
+        assert_eq!(spans.get(0), Some(crate::span::Span::new(2, 13, 10)));
+        // comments inside of a statement are moved after it
+        let ret = chain.iter().position(|tk| *tk == Token::Keyword(Keyword::Return)).unwrap();
+        assert_eq!(chain[ret + 2], Token::Comment("/* and */".to_string()));
+        assert_eq!(
+            chain.last(),
+            Some(&Token::Comment("/* multi\n               line */".to_string()))
+        );
+        // without the comments, the chain is the same
+        let without: TokenChain = chain
+            .iter()
+            .filter(|tk| !matches!(tk, Token::Comment(_)))
+            .cloned()
+            .collect();
+        assert_eq!(without, stripped);
+        assert!(disassemble(&chain).contains("// horizontal\n"));
+
+        // the Vm skips them
+        let mut vm = Vm::new();
+        vm.load_chain(&mut chain);
+        vm.process();
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(4)));
+
+        // the lifted statements keep them too
+        let lifted = crate::ast::lift(&chain).unwrap();
+        assert_eq!(lifted[0], crate::ast::Stmt::Comment("// a point".to_string()));
+        assert_eq!(crate::ast::lower(&lifted), chain);
+
+        assert!(assemble("let a = 1; /* open")
+            .unwrap_err()
+            .to_string()
+            .contains("Unclosed block comment at line 1!"));
+        assert_eq!(
+            assemble_with("let a = 4 / 2; // half", &options).unwrap(),
+            vec![
+                Token::Keyword(Keyword::Let),
+                Token::Literal(Literal::Ident("a".to_string())),
+                Token::Expression(Box::new(Expression::BinaryOp(
+                    BinaryOp::Div,
+                    Token::Literal(Literal::Number(4)),
+                    Token::Literal(Literal::Number(2))
+                ))),
+                Token::Comment("// half".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fmt() {
+        use crate::fmt::{format, is_formatted};
+        let messy = r#"
+            // a point
+            pub Point { x num   y num 0 }
+            enum Outcome{Done(num),Pending}
+            @inline(true)
+            fn   num sum(p){return p.x/* and */+p.y;}
+            let total=sum(Point{x=1,y=2});let [first, rest..] = [1, 2, 3];
+            if total>3{total=0;}elif total==3{total=1;}else{}
+            do{total=total+1;}while total<5;
+            match Outcome::Done(total){Done(v)=>{total=v;} _=>{}}
+            namespace util { fn void noop() {} }
+        "#;
+        let formatted = format(messy).unwrap();
+        assert_eq!(
+            formatted,
+            r#"// a point
+pub Point {
+    x num
+    y num 0
+}
+
+enum Outcome {
+    Done(num),
+    Pending,
+}
+
+@inline(true)
+fn num sum(p) {
+    return p.x + p.y; /* and */
+}
+
+let total = sum(Point { x = 1, y = 2 });
+let [first, rest..] = [1, 2, 3];
+if total > 3 {
+    total = 0;
+} elif total == 3 {
+    total = 1;
+} else {}
+do {
+    total = total + 1;
+} while total < 5;
+match Outcome::Done(total) {
+    Done(v) => {
+        total = v;
+    }
+    _ => {}
+}
+
+namespace util {
+    fn void noop() {}
+}
+"#
+        );
+        // formatting is idempotent, and keeps the meaning of the source
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert!(is_formatted(&formatted).unwrap());
+        assert!(!is_formatted(messy).unwrap());
+        assert_eq!(assemble(&formatted).unwrap(), assemble(messy).unwrap());
+
+        for src in [
+            "let a = 1;",
+            "fn void f() {} fn void g() {}",
+            "Point { x num } let p = Point { x = 1 };",
+            "let x = ((1 + 2) * 3) - (4 - 5); a = b = !(c && d) ? e : (f ? g : h);",
+        ] {
+            let once = format(src).unwrap();
+            assert_eq!(format(&once).unwrap(), once);
+            assert_eq!(assemble(&once).unwrap(), assemble(src).unwrap());
+        }
+        assert_eq!(
+            format("let x = ((1 + 2) * 3) - (4 - 5);").unwrap(),
+            "let x = (1 + 2) * 3 - (4 - 5);\n"
+        );
+
+        // hand written code that is already formatted is left as it is
+        let written = r#"// counts up
+let a = 1; // one
+let b = 2; /* two */ // and more
+if a > b {} elif a == b {
+    a = 0; // reset
+} else {}
+do {} while a > 3;
+
+fn void f() {} // does nothing
+
+let c = 3;
+"#;
+        assert_eq!(format(written).unwrap(), written);
+        assert_eq!(format("let a = 1;    // one\nif a {   }else{}").unwrap(), "let a = 1; // one\nif a {} else {}\n");
+        assert_eq!(format("if a { // opened\n}").unwrap(), "if a {\n    // opened\n}\n");
+        assert_eq!(format("let p=r\"a\\b\";").unwrap(), "let p = r\"a\\b\";\n");
+        assert_eq!(format("").unwrap(), "");
+        assert!(format("#define A 1\nlet a = A;").is_err());
+        assert!(format("macro twice(x) { x * 2 } let a = twice(1);").is_err());
+        assert!(format("let a = ;").is_err());
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        use crate::asm::{escape, unescape};
+        use crate::fmt::format;
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble(
+            r#"
+            let lines = "first\n\tsecond \"quoted\" \\ \u{1F600}";
+            std::str::escape(lines);
+            std::str::unescape("a\\nb\\u{41}");
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::String("a\nbA".to_string()));
+        assert_eq!(
+            vm.pop_stack().unwrap(),
+            Literal::String(r#"first\n\tsecond \"quoted\" \\ 😀"#.to_string())
+        );
+        assert_eq!(
+            vm.get_global("lines"),
+            Some(Literal::String("first\n\tsecond \"quoted\" \\ 😀".to_string()))
+        );
+
+        for value in ["", "plain", "tab\there", "\\\"\n\r\0", "\u{1b}[0m", "ünï😀"] {
+            assert_eq!(unescape(&escape(value)).unwrap(), value);
+        }
+        assert_eq!(escape("\u{7}"), "\\u{7}");
+        assert!(unescape("\\q").is_err());
+
+        // disassembled and formatted literals use the same escapes
+        let src = "let s = \"a\\nb\\t\\\"c\\\" \\u{1b}\";\nlet c = '\\'';\n";
+        assert_eq!(format(src).unwrap(), src);
+        let chain = assemble(src).unwrap();
+        assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let src = r####"
+            let path = r"C:\temp\new";
+            let json = r#"{"name": "gale", "tags": ["vm"]}"#;
+            let nested = r##"a "# b"##;
+            let query = """
+                SELECT *
+                  FROM users
+                WHERE id = 1
+                """;
+            let inline = """no "escapes" \n here""";
+        "####;
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(src).unwrap());
+        vm.process();
+        let string = |value: &str| Some(Literal::String(value.to_string()));
+        assert_eq!(vm.get_global("path"), string(r"C:\temp\new"));
+        assert_eq!(vm.get_global("json"), string(r#"{"name": "gale", "tags": ["vm"]}"#));
+        assert_eq!(vm.get_global("nested"), string(r##"a "# b"##));
+        assert_eq!(vm.get_global("query"), string("SELECT *\n  FROM users\nWHERE id = 1"));
+        assert_eq!(vm.get_global("inline"), string(r#"no "escapes" \n here"#));
+
+        // formatting keeps the form strings are written in
+        let formatted = crate::fmt::format(src).unwrap();
+        assert_eq!(
+            formatted,
+            r####"let path = r"C:\temp\new";
+let json = r#"{"name": "gale", "tags": ["vm"]}"#;
+let nested = r##"a "# b"##;
+let query = """
+    SELECT *
+      FROM users
+    WHERE id = 1
+    """;
+let inline = """
+    no "escapes" \n here
+    """;
+"####
+        );
+        assert_eq!(crate::fmt::format(&formatted).unwrap(), formatted);
+        assert_eq!(assemble(&formatted).unwrap(), assemble(src).unwrap());
+
+        // the same value keeps each of its forms, and text blocks follow the indentation
+        let nested = r#"fn void f() {
+    let a = "C:\\x";
+    let b = r"C:\x";
+    let c = """
+        {
+
+          "k": 1
+        }
+        """;
+}
+"#;
+        assert_eq!(crate::fmt::format(nested).unwrap(), nested);
+
+        // lines after a text block are still counted
+        let err = assemble("let a = \"\"\"\none\ntwo\n\"\"\";\nlet b = ;").unwrap_err();
+        assert!(err.to_string().contains("at line 5"), "{}", err);
+        assert!(assemble("let a = r\"open;").unwrap_err().to_string().contains("Unterminated raw string at line 1!"));
+        assert!(assemble("let a = \"\"\"open;").unwrap_err().to_string().contains("Unterminated text block at line 1!"));
+        assert!(assemble("let a = r#x;").is_err());
+    }
+
+    #[test]
+    fn test_files() {
+        let path = std::env::temp_dir().join(format!("gale-files-{}.txt", std::process::id()));
+        let path = path.to_string_lossy().replace('\\', "/");
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Files);
+        vm.load_chain(&mut assemble(&format!(
+            r#"
+            let out = std::io::file::open("{path}", "w");
+            out.write("first\r\nsecond\n");
+            out.write("third");
+            out.close();
+
+            let file = std::io::file::open("{path}", "r+");
+            let lines = [file.read_line(), file.read_line(), file.read_line(), file.read_line()];
+            let end = file.seek(-5);
+            file.write("THIRD");
+            let start = file.seek(0);
+            let first = file.read_line();
+            file.close();
+            "#
+        )).unwrap());
+        vm.process();
+        // lines are read without their line breaks, until void at the end of the file
+        assert_eq!(
+            vm.get_global("lines"),
+            Some(Literal::Array(vec![
+                Literal::String("first".to_string()),
+                Literal::String("second".to_string()),
+                Literal::String("third".to_string()),
+                Literal::Void,
+            ]))
+        );
+        assert_eq!(vm.get_global("end"), Some(Literal::Number(14)));
+        assert_eq!(vm.get_global("start"), Some(Literal::Number(0)));
+        assert_eq!(vm.get_global("first"), Some(Literal::String("first".to_string())));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\r\nsecond\nTHIRD");
+        match vm.get_global("file") {
+            Some(Literal::Struct(file)) => assert_eq!(file.type_name(), "File"),
+            other => panic!("Expected a file, got {:?}", other),
+        }
+
+        // closed files can not be used anymore
+        vm.load_chain(&mut assemble("file.read_line();").unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert!(crate::stdlib::test::_panic_message(err.as_ref()).contains("is not open!"));
+
+        // resetting the Vm closes the files left open
+        vm.reset();
+        vm.load_chain(&mut assemble(&format!(r#"let file = std::io::file::open("{path}", "r");"#)).unwrap());
+        vm.process();
+        assert_eq!(vm.handles().len(), 1);
+        vm.reset();
+        assert!(vm.handles().is_empty());
+        vm.load_chain(&mut assemble("file.read_line();").unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert!(crate::stdlib::test::_panic_message(err.as_ref()).contains("is not open!"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_path() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Path);
+        vm.load_chain(&mut assemble(
+            r#"
+            let joined = std::path::join("data", "report.csv");
+            let parent = std::path::parent(joined);
+            let name = std::path::file_name(joined);
+            let ext = std::path::extension(joined);
+            let no_ext = std::path::extension("Makefile");
+            let root_parent = std::path::parent("/");
+            let relative = std::path::is_absolute(joined);
+            let current = std::path::canonicalize(".");
+            let absolute = std::path::is_absolute(current);
+            "#,
+        ).unwrap());
+        vm.process();
+        let joined = std::path::Path::new("data").join("report.csv");
+        assert_eq!(vm.get_global("joined"), Some(Literal::String(joined.to_string_lossy().into_owned())));
+        assert_eq!(vm.get_global("parent"), Some(Literal::String("data".to_string())));
+        assert_eq!(vm.get_global("name"), Some(Literal::String("report.csv".to_string())));
+        assert_eq!(vm.get_global("ext"), Some(Literal::String("csv".to_string())));
+        assert_eq!(vm.get_global("no_ext"), Some(Literal::Void));
+        assert_eq!(vm.get_global("root_parent"), Some(Literal::Void));
+        assert_eq!(vm.get_global("relative"), Some(Literal::Bool(false)));
+        assert_eq!(vm.get_global("absolute"), Some(Literal::Bool(true)));
+        let current = std::env::current_dir().unwrap().canonicalize().unwrap();
+        assert_eq!(vm.get_global("current"), Some(Literal::String(current.to_string_lossy().into_owned())));
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Csv);
+        vm.load_chain(&mut assemble(
+            r#"
+            let text = "name,note\r\nada,\"likes \"\"maths\"\", logic\"\nbob,\"two\nlines\"\n";
+            let rows = std::csv::parse(text);
+            let people = std::csv::parse_with_headers(text);
+            let written = std::csv::stringify(rows);
+            let from_maps = std::csv::stringify(people);
+            let mixed = std::csv::stringify([[1, 2.5, true, *], ["a,b", ""]]);
+            let empty = std::csv::parse("");
+            "#,
+        ).unwrap());
+        vm.process();
+        let strings = |values: &[&str]| Literal::Array(values.iter().map(|it| Literal::String(it.to_string())).collect());
+        assert_eq!(
+            vm.get_global("rows"),
+            Some(Literal::Array(vec![
+                strings(&["name", "note"]),
+                strings(&["ada", "likes \"maths\", logic"]),
+                strings(&["bob", "two\nlines"]),
+            ]))
+        );
+        match vm.get_global("people") {
+            Some(Literal::Array(people)) => {
+                assert_eq!(people.len(), 2);
+                let ada = match &people[0] {
+                    Literal::Struct(ada) => ada,
+                    other => panic!("Expected a map, got {:?}", other),
+                };
+                assert_eq!(ada.type_name(), crate::marshal::MAP_TYPE);
+                assert_eq!(ada.field::<String>("name").unwrap(), "ada");
+                assert_eq!(ada.field::<String>("note").unwrap(), "likes \"maths\", logic");
+            }
+            other => panic!("Expected an array, got {:?}", other),
+        }
+        let text = "name,note\nada,\"likes \"\"maths\"\", logic\"\nbob,\"two\nlines\"\n";
+        assert_eq!(vm.get_global("written"), Some(Literal::String(text.to_string())));
+        assert_eq!(vm.get_global("from_maps"), Some(Literal::String(text.to_string())));
+        assert_eq!(vm.get_global("mixed"), Some(Literal::String("1,2.5,true,\n\"a,b\",\n".to_string())));
+        assert_eq!(vm.get_global("empty"), Some(Literal::Array(vec![])));
+
+        vm.load_chain(&mut assemble(r#"std::csv::parse_with_headers("a,b\n1");"#).unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert_eq!(
+            crate::stdlib::test::_panic_message(err.as_ref()),
+            "Row 2 has 1 fields, but there are 2 headers!"
+        );
+    }
+
+    #[test]
+    fn test_datetime() {
+        let mut vm = Vm::new();
+        vm.set_deterministic(7);
+        vm.deterministic().unwrap().set_now(Duration::from_millis(951_782_400_250));
+        vm.add_std_feature(StdFeature::DateTime);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::datetime::parse;
+            let now = std::datetime::now();
+            let leap = [now.year(), now.month(), now.day(), now.weekday(), now.millisecond()];
+            let later = now.add_secs(86400 + 3723);
+            let stamp = later.format("%a %d %b %Y %H:%M:%S.%f %%");
+            let parsed = parse("%Y-%m-%d %H:%M", "1969-12-31 23:59");
+            let before = [parsed.timestamp(), parsed.year(), parsed.hour(), parsed.is_before(now)];
+            let order = [now.compare(later), later.compare(now), now.compare(now.add_millis(0))];
+            let same = now.equals(std::datetime::from_millis(951782400250)) && later.is_after(now);
+            let epoch = std::datetime::from_secs(0).format("%Y-%m-%dT%H:%M:%S");
+            "#,
+        ).unwrap());
+        vm.process();
+        let nums = |values: &[i64]| Literal::Array(values.iter().map(|it| Literal::Number(*it)).collect());
+        assert_eq!(vm.get_global("leap"), Some(nums(&[2000, 2, 29, 2, 250])));
+        assert_eq!(vm.get_global("stamp"), Some(Literal::String("Wed 01 Mar 2000 01:02:03.250 %".to_string())));
+        assert_eq!(
+            vm.get_global("before"),
+            Some(Literal::Array(vec![Literal::Number(-60), Literal::Number(1969), Literal::Number(23), Literal::Bool(true)]))
+        );
+        assert_eq!(vm.get_global("order"), Some(nums(&[-1, 1, 0])));
+        assert_eq!(vm.get_global("same"), Some(Literal::Bool(true)));
+        assert_eq!(vm.get_global("epoch"), Some(Literal::String("1970-01-01T00:00:00".to_string())));
+        match vm.get_global("later") {
+            Some(Literal::Struct(later)) => assert_eq!(later.type_name(), "DateTime"),
+            other => panic!("Expected a DateTime, got {:?}", other),
+        }
+
+        vm.load_chain(&mut assemble(r#"std::datetime::parse("%Y-%m-%d", "2023-02-29");"#).unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert_eq!(
+            crate::stdlib::test::_panic_message(err.as_ref()),
+            "Could not parse \"2023-02-29\" as \"%Y-%m-%d\": 2023-2-29 is not a valid date!"
+        );
+    }
+
+    #[test]
+    fn test_ids() {
+        let run = |seed: u64| {
+            let mut vm = Vm::new();
+            vm.set_deterministic(seed);
+            vm.deterministic().unwrap().set_now(Duration::from_millis(1_577_836_805_000));
+            vm.add_std_feature(StdFeature::Id);
+            vm.load_chain(&mut assemble(
+                r#"
+                import std::id::snowflake;
+                let uuid = std::id::uuid_v4();
+                let short = std::id::nanoid(21);
+                let flakes = [snowflake(), snowflake()];
+                "#,
+            ).unwrap());
+            vm.process();
+            vm
+        };
+        let vm = run(3);
+        let uuid = vm.get_global("uuid").unwrap().to_string();
+        let groups: Vec<&str> = uuid.split('-').collect();
+        assert_eq!(groups.iter().map(|it| it.len()).collect::<Vec<_>>(), vec![8, 4, 4, 4, 12]);
+        assert!(uuid.chars().all(|it| it == '-' || it.is_ascii_hexdigit() && !it.is_ascii_uppercase()));
+        assert!(groups[2].starts_with('4'));
+        assert!(matches!(groups[3].chars().next(), Some('8' | '9' | 'a' | 'b')));
+        let short = vm.get_global("short").unwrap().to_string();
+        assert_eq!(short.len(), 21);
+        assert!(short.chars().all(|it| it.is_ascii_alphanumeric() || it == '_' || it == '-'));
+        assert_eq!(run(3).get_global("uuid").unwrap().to_string(), uuid);
+        assert_ne!(run(4).get_global("uuid").unwrap().to_string(), uuid);
+        match vm.get_global("flakes") {
+            Some(Literal::Array(flakes)) => match flakes.as_slice() {
+                [Literal::Number(first), Literal::Number(second)] => {
+                    assert!(second > first);
+                    assert!(first >> 22 >= 5000);
+                }
+                other => panic!("Expected two numbers, got {:?}", other),
+            },
+            other => panic!("Expected an array, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_arr_ordering() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Arrays);
+        vm.add_std_feature(StdFeature::Strings);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::arr::arr_sort;
+            import std::arr::arr_sort_desc;
+            import std::arr::arr_binary_search;
+            fn num by_len(a, b) {
+                return std::str::len_chars(a) - std::str::len_chars(b);
+            }
+            let sorted = arr_sort([3, 1.5, -2, 3, 1]);
+            let names = arr_sort_desc(["bob", "al", "carol"]);
+            let by_len = arr_sort(["carol", "al", "bob"], "by_len");
+            let unique = std::arr::arr_dedup(arr_sort([2, 1, 2, 3, 1]));
+            let found = [arr_binary_search(unique, 2), arr_binary_search(unique, 0), arr_binary_search(unique, 5)];
+            let by_len_found = arr_binary_search(by_len, "eve", "by_len");
+            let reversed = std::arr::arr_reverse([1, "two", '3']);
+            "#,
+        ).unwrap());
+        vm.process();
+        let nums = |values: &[i64]| Literal::Array(values.iter().copied().map(Literal::Number).collect());
+        let strings = |values: &[&str]| Literal::Array(values.iter().map(|it| Literal::String(it.to_string())).collect());
+        assert_eq!(
+            vm.get_global("sorted"),
+            Some(Literal::Array(vec![
+                Literal::Number(-2),
+                Literal::Number(1),
+                Literal::Float(1.5),
+                Literal::Number(3),
+                Literal::Number(3),
+            ]))
+        );
+        assert_eq!(vm.get_global("names"), Some(strings(&["carol", "bob", "al"])));
+        assert_eq!(vm.get_global("by_len"), Some(strings(&["al", "bob", "carol"])));
+        assert_eq!(vm.get_global("unique"), Some(nums(&[1, 2, 3])));
+        assert_eq!(vm.get_global("found"), Some(nums(&[1, -1, -4])));
+        assert_eq!(vm.get_global("by_len_found"), Some(Literal::Number(1)));
+        assert_eq!(
+            vm.get_global("reversed"),
+            Some(Literal::Array(vec![Literal::Char('3'), Literal::String("two".to_string()), Literal::Number(1)]))
+        );
+
+        vm.load_chain(&mut assemble(r#"std::arr::arr_sort([1, "one"]);"#).unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert_eq!(crate::stdlib::test::_panic_message(err.as_ref()), "Can not compare one with 1!");
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Sets);
+        vm.load_chain(&mut assemble(
+            r#"
+            Point {
+                x num
+                y num
+            }
+            let a = std::set::set_new([1, 2, 2, "two", 1.0, 1]);
+            let b = [2, 3, "two", 3];
+            let union = std::set::set_union(a, b);
+            let intersect = std::set::set_intersect(a, b);
+            let diff = std::set::set_diff(a, b);
+            let has = [std::set::set_contains(a, 1.0), std::set::set_contains(a, 3)];
+            let points = std::set::set_new([Point { x = 1, y = 2 }, Point { y = 2, x = 1 }, Point { x = 2, y = 1 }]);
+            "#,
+        ).unwrap());
+        vm.process();
+        let two = Literal::String("two".to_string());
+        let (one, float) = (Literal::Number(1), Literal::Float(1.0));
+        assert_eq!(vm.get_global("a"), Some(Literal::Array(vec![one.clone(), Literal::Number(2), two.clone(), float.clone()])));
+        assert_eq!(
+            vm.get_global("union"),
+            Some(Literal::Array(vec![one.clone(), Literal::Number(2), two.clone(), float.clone(), Literal::Number(3)]))
+        );
+        assert_eq!(vm.get_global("intersect"), Some(Literal::Array(vec![Literal::Number(2), two])));
+        assert_eq!(vm.get_global("diff"), Some(Literal::Array(vec![one, float])));
+        assert_eq!(vm.get_global("has"), Some(Literal::Array(vec![Literal::Bool(true), Literal::Bool(false)])));
+        match vm.get_global("points") {
+            Some(Literal::Array(points)) => assert_eq!(points.len(), 2),
+            other => panic!("Expected an array, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_collections() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Collections);
+        vm.load_chain(&mut assemble(
+            r#"
+            let queue = std::collections::deque();
+            queue.push_back(1);
+            queue.push_back("two");
+            queue.push_front(0);
+            let copy = queue;
+            let ends = [copy.peek_front(), copy.peek_back(), queue.len()];
+            let popped = [queue.pop_front(), queue.pop_back(), queue.pop_back(), queue.pop_back()];
+            let stack = std::collections::stack();
+            stack.push('a');
+            stack.push('b');
+            let top = stack.peek();
+            let values = stack.to_array();
+            let last = [stack.pop(), stack.len()];
+            "#,
+        ).unwrap());
+        vm.process();
+        let global = |name: &str| vm.get_global(name).unwrap();
+        assert_eq!(
+            global("ends"),
+            Literal::Array(vec![Literal::Number(0), Literal::String("two".to_string()), Literal::Number(3)])
+        );
+        assert_eq!(
+            global("popped"),
+            Literal::Array(vec![Literal::Number(0), Literal::String("two".to_string()), Literal::Number(1), Literal::Void])
+        );
+        assert_eq!(global("top"), Literal::Char('b'));
+        assert_eq!(global("values"), Literal::Array(vec![Literal::Char('a'), Literal::Char('b')]));
+        assert_eq!(global("last"), Literal::Array(vec![Literal::Char('b'), Literal::Number(1)]));
+        match global("stack") {
+            Literal::Struct(stack) => assert_eq!(stack.type_name(), "Stack"),
+            other => panic!("Expected a Stack, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_collection_handles() {
+        let collections = || {
+            let mut vm = Vm::new();
+            vm.add_std_feature(StdFeature::Collections);
+            vm
+        };
+        let run = |vm: &mut Vm, source: &str| {
+            vm.load_chain(&mut assemble(source).unwrap());
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process()))
+                .map_err(|err| crate::stdlib::test::_panic_message(err.as_ref()))
+        };
+        let mut first = collections();
+        run(&mut first, "let queue = std::collections::deque(); queue.push_back(1);").unwrap();
+        assert_eq!(first.handles().len(), 1);
+
+        // handles of one Vm do not reach the values of another
+        let mut second = collections();
+        second.set_global("queue", first.get_global("queue").unwrap()).unwrap();
+        assert!(run(&mut second, "let len = queue.len();").unwrap_err().contains("Collection 1 does not exist!"));
+
+        run(&mut first, "let len = queue.len(); queue.free();").unwrap();
+        assert_eq!(first.get_global("len"), Some(Literal::Number(1)));
+        assert!(first.handles().is_empty());
+        assert!(run(&mut first, "queue.len();").unwrap_err().contains("Collection 1 does not exist!"));
+        first.reset();
+
+        run(&mut first, "let stack = std::collections::stack(); let other = std::collections::stack(); other.free();").unwrap();
+        assert_eq!(first.handles().len(), 1);
+        first.reset();
+        assert!(first.handles().is_empty());
+    }
+
+    #[test]
+    fn test_iterators() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Iter);
+        vm.add_std_feature(StdFeature::Collections);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::iter::iter;
+            let total = 0;
+            let it = iter([1, 2, 3]);
+            while it.has_next() {
+                total = total + it.next();
+            }
+            let done = [it.has_next(), it.next()];
+            let chars = iter("hey").to_array();
+            let stack = std::collections::stack();
+            stack.push(1);
+            stack.push(2);
+            let from_stack = iter(stack).to_array();
+            let rest = iter([4, 5, 6]);
+            rest.next();
+            let passed = iter(rest).to_array();
+            "#,
+        ).unwrap());
+        vm.process();
+        let global = |name: &str| vm.get_global(name).unwrap();
+        assert_eq!(global("total"), Literal::Number(6));
+        assert_eq!(global("done"), Literal::Array(vec![Literal::Bool(false), Literal::Void]));
+        assert_eq!(global("chars"), Literal::Array("hey".chars().map(Literal::Char).collect()));
+        assert_eq!(global("from_stack"), Literal::Array(vec![Literal::Number(1), Literal::Number(2)]));
+        assert_eq!(global("passed"), Literal::Array(vec![Literal::Number(5), Literal::Number(6)]));
+        // `to_array` drops the iterators it exhausts, the others are kept until they are freed
+        assert_eq!(vm.handles().len(), 2);
+        vm.load_chain(&mut assemble("it.free(); stack.free();").unwrap());
+        vm.process();
+        assert!(vm.handles().is_empty());
+
+        let map = crate::structs::StructureInstance::builder(crate::marshal::MAP_TYPE)
+            .field("b", 2i64)
+            .field("a", 1i64)
+            .build();
+        vm.add_var("map".to_string(), Literal::Struct(Box::new(map)));
+        vm.load_chain(&mut assemble("let entries = iter(map).to_array();").unwrap());
+        vm.process();
+        let entry = |key: &str, value: i64| Literal::Array(vec![Literal::String(key.to_string()), Literal::Number(value)]);
+        assert_eq!(vm.get_global("entries"), Some(Literal::Array(vec![entry("a", 1), entry("b", 2)])));
+
+        vm.load_chain(&mut assemble("iter(3);").unwrap());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vm.process())).unwrap_err();
+        assert_eq!(crate::stdlib::test::_panic_message(err.as_ref()), "Value of type num is not iterable!");
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Range);
+        vm.add_std_feature(StdFeature::Iter);
+        vm.add_std_feature(StdFeature::Arrays);
+        vm.load_chain(&mut assemble(
+            r#"
+            let n = 3;
+            let digits = 0..n + 2;
+            let closed = 1..=n;
+            let values = [std::arr::arr_from_range(digits), closed.to_array(), (5..2).to_array()];
+            let lens = [digits.len(), closed.len(), (5..2).len()];
+            let has = [digits.contains(4), digits.contains(5), closed.contains(2.5), closed.contains(3)];
+            let total = 0;
+            let it = std::iter::iter(0..=1000000000);
+            while total < 10 {
+                total = total + it.next();
+            }
+            "#,
+        ).unwrap());
+        vm.process();
+        let nums = |values: &[i64]| Literal::Array(values.iter().copied().map(Literal::Number).collect());
+        let bools = |values: &[bool]| Literal::Array(values.iter().copied().map(Literal::Bool).collect());
+        assert_eq!(
+            vm.get_global("values"),
+            Some(Literal::Array(vec![nums(&[0, 1, 2, 3, 4]), nums(&[1, 2, 3]), nums(&[])]))
+        );
+        assert_eq!(vm.get_global("lens"), Some(nums(&[5, 3, 0])));
+        assert_eq!(vm.get_global("has"), Some(bools(&[true, false, true, true])));
+        assert_eq!(vm.get_global("total"), Some(Literal::Number(10)));
+        match vm.get_global("closed") {
+            Some(Literal::Struct(range)) => {
+                assert_eq!(range.type_name(), "Range");
+                assert_eq!(range.get_field("inclusive"), Some(Literal::Bool(true)));
+            }
+            other => panic!("Expected a range, got {:?}", other),
+        }
+
+        assert_eq!(
+            crate::fmt::format("let r=(a+1)..=b*2;\nlet [first, rest..] = values;\nlet s=(0..1).len();").unwrap(),
+            "let r = a + 1..=b * 2;\nlet [first, rest..] = values;\nlet s = (0..1).len();\n"
+        );
+    }
+
+    #[test]
+    fn test_slices() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Range);
+        vm.load_chain(&mut assemble(
+            r#"
+            let values = [10, 20, 30, 40, 50];
+            let text = "gale vm";
+            let items = [values[0], values[-1], text[1], values[1..3][1]];
+            let slices = [values[1..3], values[3..], values[..=-2], values[-2..], values[3..100], values[4..1]];
+            let words = [text[2..], text[..4], text[-2..], text[..]];
+            let nested = [[1, 2], [3, 4]][1][0];
+            "#,
+        ).unwrap());
+        vm.process();
+        let nums = |values: &[i64]| Literal::Array(values.iter().copied().map(Literal::Number).collect());
+        let strs = |values: &[&str]| Literal::Array(values.iter().map(|it| Literal::String(it.to_string())).collect());
+        assert_eq!(
+            vm.get_global("items"),
+            Some(Literal::Array(vec![Literal::Number(10), Literal::Number(50), Literal::Char('a'), Literal::Number(30)]))
+        );
+        assert_eq!(
+            vm.get_global("slices"),
+            Some(Literal::Array(vec![
+                nums(&[20, 30]),
+                nums(&[40, 50]),
+                nums(&[10, 20, 30, 40]),
+                nums(&[40, 50]),
+                nums(&[40, 50]),
+                nums(&[]),
+            ]))
+        );
+        assert_eq!(vm.get_global("words"), Some(strs(&["le vm", "gale", "vm", "gale vm"])));
+        assert_eq!(vm.get_global("nested"), Some(Literal::Number(3)));
+
+        for (source, message) in [
+            ("let wrong = values[5];", "Index 5 is out of bounds for length 5!"),
+            ("let wrong = values[-6];", "Index -6 is out of bounds for length 5!"),
+            ("let wrong = 1[0];", "Can not index a value of type num!"),
+        ] {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            let err = crate::stdlib::test::_panic_message(err.as_ref());
+            assert!(err.contains(message), "{}", err);
+        }
+
+        assert_eq!(
+            crate::fmt::format("let s=values[1..];\nlet c=(a+b)[0].len();\nlet d=text[..=n-1];").unwrap(),
+            "let s = values[1..];\nlet c = (a + b)[0].len();\nlet d = text[0..=n - 1];\n"
+        );
+    }
+
+    #[test]
+    fn test_matrices() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Matrix);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::math::matrix::identity;
+            import std::math::matrix::transpose;
+            import std::math::matrix::mat_mul;
+            import std::math::matrix::row;
+            import std::math::matrix::column;
+            let m = [[1, 2, 3], [4, 5, 6]];
+            let product = mat_mul(m, transpose(m));
+            let same = mat_mul(identity(2), m);
+            let scaled = mat_mul([[0.5, 0]], m);
+            let flipped = transpose(m);
+            let lines = [row(m, 1), column(m, -1), m[0][1]];
+            let empty = [identity(0), transpose([])];
+            "#,
+        ).unwrap());
+        vm.process();
+        let nums = |values: &[i64]| Literal::Array(values.iter().copied().map(Literal::Number).collect());
+        let floats = |values: &[f64]| Literal::Array(values.iter().copied().map(Literal::Float).collect());
+        assert_eq!(vm.get_global("product"), Some(Literal::Array(vec![nums(&[14, 32]), nums(&[32, 77])])));
+        assert_eq!(vm.get_global("same"), vm.get_global("m"));
+        assert_eq!(vm.get_global("scaled"), Some(Literal::Array(vec![floats(&[0.5, 1.0, 1.5])])));
+        assert_eq!(
+            vm.get_global("flipped"),
+            Some(Literal::Array(vec![nums(&[1, 4]), nums(&[2, 5]), nums(&[3, 6])]))
+        );
+        assert_eq!(
+            vm.get_global("lines"),
+            Some(Literal::Array(vec![nums(&[4, 5, 6]), nums(&[3, 6]), Literal::Number(2)]))
+        );
+        assert_eq!(vm.get_global("empty"), Some(Literal::Array(vec![nums(&[]), nums(&[])])));
+
+        for (source, message) in [
+            ("let wrong = mat_mul(m, m);", "Can not multiply a 2x3 matrix by a 2x3 matrix!"),
+            ("let wrong = transpose([[1, 2], [3]]);", "Rows of a matrix must have the same length, got 2 and 1!"),
+            ("let wrong = column(m, 3);", "Column 3 is out of bounds for a matrix with 3 columns!"),
+        ] {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            assert_eq!(crate::stdlib::test::_panic_message(err.as_ref()), message);
+        }
+    }
+
+    #[test]
+    fn test_vector_math() {
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Math);
+        vm.add_var("big".to_string(), Literal::Array((0..1001).map(|it| Literal::Float(it as f64)).collect()));
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::math::vec_add;
+            import std::math::vec_mul;
+            import std::math::vec_sum;
+            import std::math::vec_dot;
+            let a = [1, 2, 3];
+            let b = [4, 5, 6];
+            let nums = [vec_add(a, b), vec_mul(a, b), vec_sum(a), vec_dot(a, b), vec_sum([])];
+            let floats = [vec_add(a, [0.5, 0.5, 0.5]), vec_sum([0.5, 1, 1.5]), vec_dot([0.5, 2], [2, 0.25])];
+            let totals = [vec_sum(big), vec_dot(big, big), vec_sum(vec_add(big, big))];
+            "#,
+        ).unwrap());
+        vm.process();
+        let nums = |values: &[i64]| Literal::Array(values.iter().copied().map(Literal::Number).collect());
+        assert_eq!(
+            vm.get_global("nums"),
+            Some(Literal::Array(vec![
+                nums(&[5, 7, 9]),
+                nums(&[4, 10, 18]),
+                Literal::Number(6),
+                Literal::Number(32),
+                Literal::Number(0),
+            ]))
+        );
+        assert_eq!(
+            vm.get_global("floats"),
+            Some(Literal::Array(vec![
+                Literal::Array(vec![Literal::Float(1.5), Literal::Float(2.5), Literal::Float(3.5)]),
+                Literal::Float(3.0),
+                Literal::Float(1.5),
+            ]))
+        );
+        assert_eq!(
+            vm.get_global("totals"),
+            Some(Literal::Array(vec![Literal::Float(500500.0), Literal::Float(333833500.0), Literal::Float(1001000.0)]))
+        );
+
+        for (source, message) in [
+            ("let wrong = vec_add(a, [1]);", "Can not add arrays of length 3 and 1!"),
+            ("let wrong = vec_dot(a, []);", "Can not multiply arrays of length 3 and 0!"),
+            ("let wrong = vec_sum([1, \"2\"]);", "Expected an array of numbers, got 2!"),
+        ] {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            assert_eq!(crate::stdlib::test::_panic_message(err.as_ref()), message);
+        }
+    }
+
+    #[test]
+    fn test_decimals() {
+        use crate::tks::Decimal;
+        let dec = |text: &str| Literal::Decimal(text.parse::<Decimal>().unwrap());
+        let mut vm = Vm::new();
+        vm.add_std_feature(StdFeature::Decimal);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::decimal::decimal;
+            import std::decimal::round;
+            import std::decimal::round_even;
+            import std::decimal::format;
+            let price: decimal = 19.99d;
+            let total = price * 3 + 0.1d + 0.2d;
+            let sums = [0.1d + 0.2d, 10.00d - 0.01d, -1.5d * 2, 1d / 3d, 10.50d / 4, 7.5d % 2];
+            let checks = [0.1d + 0.2d == 0.3d, 1.5d == 1.50d, 2 < 2.01d, -0.5d > -1d, 1 == 1d, 1d == 1, 2 != 2.5d, 2.5d != 2];
+            let parsed = [decimal("1_234.5600"), decimal(0.1), decimal(7)];
+            let rounded = [round(2.345d, 2), round(-2.5d, 0), round_even(2.345d, 2), round_even(-2.5d, 0), round(1.5d, 3)];
+            let texts = [format(1234567.891d, 2, ","), format(-999.995d, 2, " "), format(5, 1, ",")];
+            let fee: decimal = 2;
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.get_global("total"), Some(dec("60.27")));
+        assert_eq!(
+            vm.get_global("sums"),
+            Some(Literal::Array(vec![
+                dec("0.3"),
+                dec("9.99"),
+                dec("-3.0"),
+                dec("0.3333333333333333"),
+                dec("2.625"),
+                dec("1.5"),
+            ]))
+        );
+        assert_eq!(vm.get_global("checks"), Some(Literal::Array(vec![Literal::Bool(true); 8])));
+        assert_eq!(vm.get_global("parsed"), Some(Literal::Array(vec![dec("1234.56"), dec("0.1"), dec("7")])));
+        assert_eq!(
+            vm.get_global("rounded").map(|it| it.to_string()),
+            Some("[2.35, -3, 2.34, -2, 1.500]".to_string())
+        );
+        assert_eq!(
+            vm.get_global("texts"),
+            Some(Literal::Array(vec![
+                Literal::String("1,234,567.89".to_string()),
+                Literal::String("-1 000.00".to_string()),
+                Literal::String("5.0".to_string()),
+            ]))
+        );
+        assert_eq!(vm.get_global("fee"), Some(dec("2")));
+        assert_eq!(vm.get_global("price").unwrap().this_type(), "decimal");
+
+        for (source, message) in [
+            ("let wrong = 1d / 0;", "Can not divide 1 by zero!"),
+            ("let wrong = 0.1d + 0.1;", "Invalid operand provided!"),
+            ("let wrong = 100000000000000000000d * 100000000000000000000d;", "overflows!"),
+            ("let wrong = decimal(\"1.2.3\");", "Could not parse \"1.2.3\" as a decimal"),
+        ] {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            let err = crate::stdlib::test::_panic_message(err.as_ref());
+            assert!(err.contains(message), "{}", err);
+        }
+        assert!(assemble("let wrong = 1e5d;").unwrap_err().to_string().contains("Invalid decimal literal 1e5d"));
+
+        let mut chain = assemble("let amount = -12.50d;").unwrap();
+        assert_eq!(crate::dasm::disassemble(&chain), "let amount = -12.50d;\n");
+        let mut bytes = vec![];
+        chain.write_to(&mut bytes).unwrap();
+        assert_eq!(TokenChain::read_from(&mut bytes.as_slice()).unwrap(), chain);
+        assert_eq!(crate::fmt::format("let a=1.50d*2;").unwrap(), "let a = 1.50d * 2;\n");
+    }
+
+
+    #[test]
+    fn test_durations() {
+        let mut vm = Vm::new();
+        vm.set_deterministic(7);
+        vm.add_std_feature(StdFeature::Prelude);
+        vm.add_std_feature(StdFeature::DateTime);
+        vm.load_chain(&mut assemble(
+            r#"
+            import std::time::now_millis;
+            import std::datetime::from_secs;
+            let wait = mins(1).plus(secs(30)).plus(millis(250));
+            let parts = [wait.as_millis(), wait.as_secs(), wait.as_mins()];
+            sleep(wait);
+            sleep(millis(0));
+            let after = now_millis();
+            let date = from_secs(0).add(secs(90)).format("%H:%M:%S");
+            "#,
+        ).unwrap());
+        vm.process();
+        let nums = |values: &[i64]| Literal::Array(values.iter().copied().map(Literal::Number).collect());
+        assert_eq!(vm.get_global("parts"), Some(nums(&[90250, 90, 1])));
+        assert_eq!(vm.get_global("after"), Some(Literal::Number(90250)));
+        assert_eq!(vm.get_global("date"), Some(Literal::String("00:01:30".to_string())));
+        match vm.get_global("wait") {
+            Some(Literal::Struct(wait)) => assert_eq!(wait.type_name(), "Duration"),
+            other => panic!("Expected a duration, got {:?}", other),
+        }
+
+        for (source, message) in [
+            ("sleep(5);", "Expected a duration to sleep for, like std::time::secs(5), got num 5!"),
+            ("let wrong = secs(-1);", "Invalid duration of -1 secs, durations can not be negative!"),
+            ("let wrong = mins(9223372036854775807);", "Duration of 9223372036854775807 mins overflows!"),
+            ("sleep(Duration { millis = -5 });", "Invalid duration of -5 millis, durations can not be negative!"),
+        ] {
+            let mut bad = vm.clone();
+            bad.load_chain(&mut assemble(source).unwrap());
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| bad.process())).unwrap_err();
+            assert_eq!(crate::stdlib::test::_panic_message(err.as_ref()), message);
+        }
+    }
+
+    /// Renders a parsed expression with every operation explicitly grouped
+    fn grouped(tk: &Token) -> String {
+        match tk.as_expr() {
+            Some(Expression::BinaryOp(op, lh, rh)) => {
+                format!("({} {} {})", grouped(lh), op, grouped(rh))
+            }
+            Some(Expression::UnaryOp(op, value)) => format!("({}{})", op, grouped(value)),
+            Some(Expression::Ternary(condition, then, otherwise)) => {
+                format!("({} ? {} : {})", grouped(condition), grouped(then), grouped(otherwise))
+            }
+            Some(Expression::InvokeStatic(name, params)) => {
+                format!("{}({})", name, params.iter().map(grouped).collect::<Vec<_>>().join(", "))
+            }
+            _ => render_token(tk),
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let cases = [
+            ("a + b * c", "(a + (b * c))"),
+            ("a * b + c", "((a * b) + c)"),
+            ("a - b - c", "((a - b) - c)"),
+            ("a / b / c", "((a / b) / c)"),
+            ("a * b % c", "((a * b) % c)"),
+            ("a + b - c + d", "(((a + b) - c) + d)"),
+            ("(a + b) * c", "((a + b) * c)"),
+            ("a * (b + c)", "(a * (b + c))"),
+            ("((a))", "a"),
+            ("a << b + c", "(a << (b + c))"),
+            ("a + b >> c", "((a + b) >> c)"),
+            ("a & b << c", "(a & (b << c))"),
+            ("a | b ^ c & d", "(a | (b ^ (c & d)))"),
+            ("a ^ b | c", "((a ^ b) | c)"),
+            ("a | b < c", "((a | b) < c)"),
+            ("a + b < c * d", "((a + b) < (c * d))"),
+            ("a < b == c > d", "((a < b) == (c > d))"),
+            ("a == b != c", "((a == b) != c)"),
+            ("a == b && c != d", "((a == b) && (c != d))"),
+            ("a && b || c && d", "((a && b) || (c && d))"),
+            ("a || b && c", "(a || (b && c))"),
+            ("a || b || c", "((a || b) || c)"),
+            ("!a && b", "((!a) && b)"),
+            ("!(a && b)", "(!(a && b))"),
+            ("-a * b", "((~a) * b)"),
+            ("-(a + b) * c", "((~(a + b)) * c)"),
+            ("a - -1", "(a - -1)"),
+            ("a = b = c + d", "(a = (b = (c + d)))"),
+            ("a = b || c", "(a = (b || c))"),
+            ("a || b ? c : d", "((a || b) ? c : d)"),
+            ("a ? b : c ? d : e", "(a ? b : (c ? d : e))"),
+            ("a = b ? c + d : e", "(a = (b ? (c + d) : e))"),
+            ("f(a + b * c, d) * e", "(f((a + (b * c)), d) * e)"),
+        ];
+        for (source, expected) in cases {
+            let chain = assemble(&format!("{};", source)).unwrap();
+            assert_eq!(chain.len(), 1, "{} should be a single expression", source);
+            assert_eq!(grouped(&chain[0]), expected, "{} was grouped incorrectly", source);
+            assert_eq!(assemble(&disassemble(&chain)).unwrap(), chain);
+        }
+
+        let mut vm = Vm::new();
+        vm.load_chain(&mut assemble(
+            r#"
+            let a = 2;
+            1 + 2 * 3;
+            (1 + 2) * 3;
+            10 - 4 - 3;
+            100 / 10 / 5;
+            2 + 3 * 4 % 5;
+            1 << 2 + 1;
+            -a * 3 + 10;
+            !(a > 1) || a * a > 3 && a < 3;
+            "#,
+        ).unwrap());
+        vm.process();
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Bool(true));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(4));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(8));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(4));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(2));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(3));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(9));
+        assert_eq!(vm.pop_stack().unwrap(), Literal::Number(7));
+    }
+
     fn example_print(params: Parameters) -> Literal {
         println!("{}", params.get(0).unwrap());
         Literal::Void
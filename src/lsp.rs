@@ -0,0 +1,439 @@
+//! Language server for gale sources, spoken over the standard streams by the `gale-lsp`
+//! binary. Sources are checked with [`check`](crate::check::check) against a [`Vm`] with
+//! every std feature added, whose scopes also provide the completed std functions.
+//!
+//! The server supports full document sync, diagnostics, go to definition of functions,
+//! structures and enums, and completion. Positions are sent to the client as characters,
+//! which only match the UTF-16 offsets of the protocol on lines without astral characters.
+
+use crate::asm::assemble_spanned;
+use crate::diagnostics::{Report, Severity};
+use crate::features::StdFeature;
+use crate::snapshot::VmStateSnapshot;
+use crate::span::{SourceMap, Span};
+use crate::tks::{Expression, Keyword, Literal, Token, TokenChain};
+use crate::visit::{ScopeProvider, Vm};
+use anyhow::{anyhow, bail};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum SymbolKind {
+    Function,
+    Structure,
+    Enum,
+    Variable,
+    Constant,
+}
+
+impl SymbolKind {
+    /// Kind of a completion item with this symbol, as numbered by the protocol
+    pub fn completion_kind(&self) -> u32 {
+        match self {
+            SymbolKind::Function => 3,
+            SymbolKind::Variable => 6,
+            SymbolKind::Enum => 13,
+            SymbolKind::Constant => 21,
+            SymbolKind::Structure => 22,
+        }
+    }
+}
+
+/// Name declared in a source, with the position of the name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Option<Span>,
+}
+
+/// Functions, structures, enums, variables and constants declared anywhere in the chain
+pub fn symbols(chain: &TokenChain, source_map: &SourceMap) -> Vec<Symbol> {
+    let mut symbols = vec![];
+    let mut declare = |name: &str, kind: SymbolKind, index: usize| {
+        symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            span: source_map.get(index),
+        })
+    };
+    for (i, tk) in chain.iter().enumerate() {
+        match (tk, chain.get(i + 1)) {
+            (Token::Keyword(Keyword::Function), _) => {
+                // the output type follows an optional modifier
+                let at = match chain.get(i + 1) {
+                    Some(Token::Keyword(_)) => i + 3,
+                    _ => i + 2,
+                };
+                if let Some(Token::Literal(Literal::Ident(name) | Literal::String(name))) =
+                    chain.get(at)
+                {
+                    declare(name, SymbolKind::Function, at)
+                }
+            }
+            (Token::Keyword(Keyword::Enum), Some(Token::Literal(Literal::Ident(name)))) => {
+                declare(name, SymbolKind::Enum, i + 1)
+            }
+            (Token::Keyword(kw), Some(Token::Literal(Literal::Ident(name))))
+                if matches!(kw, Keyword::Let | Keyword::Const | Keyword::Static) =>
+            {
+                let kind = match kw {
+                    Keyword::Let => SymbolKind::Variable,
+                    _ => SymbolKind::Constant,
+                };
+                declare(name, kind, i + 1)
+            }
+            (Token::Literal(Literal::Ident(name)), Some(Token::LBracket))
+                if !_takes_block(i.checked_sub(1).and_then(|it| chain.get(it))) =>
+            {
+                declare(name, SymbolKind::Structure, i)
+            }
+            _ => {}
+        }
+    }
+    symbols
+}
+
+/// Whether the token before a name followed by a block makes the block its own,
+/// like `while running { ... }`, instead of the name declaring a structure
+fn _takes_block(before: Option<&Token>) -> bool {
+    match before {
+        Some(Token::Keyword(_)) => true,
+        Some(Token::Expression(expr)) => matches!(
+            expr.as_ref(),
+            Expression::IfStmt
+                | Expression::ElifStmt
+                | Expression::WhileStmt
+                | Expression::DoWhileStmt
+                | Expression::MatchStmt
+        ),
+        _ => false,
+    }
+}
+
+/// Problems in a source, either the error of the assembler or diagnostics of the checker
+pub fn diagnostics(vm: &Vm, text: &str) -> Vec<Report> {
+    _reports(vm, &assemble_spanned(text))
+}
+
+fn _reports(vm: &Vm, assembled: &anyhow::Result<(TokenChain, SourceMap)>) -> Vec<Report> {
+    match assembled {
+        Ok((chain, source_map)) => vm
+            .check(chain)
+            .into_iter()
+            .map(|diagnostic| Report::from(&diagnostic.locate(source_map)))
+            .collect(),
+        Err(err) => vec![Report::from(err)],
+    }
+}
+
+/// Symbol the name refers to, which can be a path like `shapes::area`.
+/// Symbols declared with `let`, `const` or `static` are not definitions
+pub fn definition<'a>(symbols: &'a [Symbol], name: &str) -> Option<&'a Symbol> {
+    let name = name.rsplit("::").next().unwrap_or(name);
+    symbols.iter().find(|symbol| {
+        symbol.name == name
+            && matches!(
+                symbol.kind,
+                SymbolKind::Function | SymbolKind::Structure | SymbolKind::Enum
+            )
+    })
+}
+
+/// Names that can be completed after `prefix`, which is the path typed before the cursor.
+/// After a path like `std::io::` the functions of that scope are offered, otherwise the
+/// symbols of the source, the names of the global scope and full paths of std functions
+pub fn completions(
+    env: &VmStateSnapshot,
+    symbols: &[Symbol],
+    prefix: &str,
+) -> BTreeMap<String, SymbolKind> {
+    let mut items = BTreeMap::new();
+    if let Some((scope, _)) = prefix.rsplit_once("::") {
+        if let Some(scope) = env.scopes.get(scope) {
+            items.extend(
+                scope
+                    .functions
+                    .iter()
+                    .map(|name| (name.to_owned(), SymbolKind::Function)),
+            );
+        }
+        return items;
+    }
+    for (name, scope) in &env.scopes {
+        let kinds = [
+            (
+                scope.variables.keys().collect::<Vec<_>>(),
+                SymbolKind::Variable,
+            ),
+            (scope.constants.keys().collect(), SymbolKind::Constant),
+            (scope.functions.iter().collect(), SymbolKind::Function),
+        ];
+        for (names, kind) in kinds {
+            for item in names {
+                let label = match name.as_str() {
+                    "global" => item.to_owned(),
+                    _ => format!("{}::{}", name, item),
+                };
+                items.insert(label, kind);
+            }
+        }
+    }
+    items.extend(
+        env.structs
+            .iter()
+            .map(|name| (name.to_owned(), SymbolKind::Structure)),
+    );
+    items.extend(symbols.iter().map(|it| (it.name.to_owned(), it.kind)));
+    items
+}
+
+/// Path under the cursor, or the part of it before the cursor if `before_cursor` is set
+fn _word_at(text: &str, line: usize, character: usize, before_cursor: bool) -> String {
+    let chars: Vec<char> = text.lines().nth(line).unwrap_or("").chars().collect();
+    let is_word = |it: &char| it.is_alphanumeric() || *it == '_' || *it == ':';
+    let at = character.min(chars.len());
+    let start = chars[..at]
+        .iter()
+        .rposition(|it| !is_word(it))
+        .map_or(0, |it| it + 1);
+    let end = match before_cursor {
+        true => at,
+        false => chars[at..]
+            .iter()
+            .position(|it| !is_word(it))
+            .map_or(chars.len(), |it| at + it),
+    };
+    chars[start..end].iter().collect()
+}
+
+/// Range of the protocol covering a span. Spans of whole lines have no column, and cover
+/// the line without its indentation
+fn _range(text: &str, span: Span) -> Value {
+    let line = span.line.saturating_sub(1) as usize;
+    let (start, len) = match span.col {
+        0 => {
+            let content = text.lines().nth(line).unwrap_or("");
+            let trimmed = content.trim_start();
+            let indent = content.chars().count() - trimmed.chars().count();
+            (indent, trimmed.trim_end().chars().count())
+        }
+        col => (col as usize - 1, span.len as usize),
+    };
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": start + len.max(1) },
+    })
+}
+
+/// Open document, with the symbols of its last version that assembled
+#[derive(Debug, Clone, Default)]
+struct Document {
+    text: String,
+    symbols: Vec<Symbol>,
+}
+
+/// State of a running server
+pub struct Server<W: Write> {
+    out: W,
+    vm: Vm,
+    env: VmStateSnapshot,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(out: W) -> Self {
+        let mut vm = Vm::new();
+        for feature in StdFeature::ALL {
+            vm.add_std_feature(feature);
+        }
+        let env = vm.dump_state();
+        Self {
+            out,
+            vm,
+            env,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Handles messages from `input` until the client asks the server to exit
+    pub fn run<R: BufRead>(&mut self, mut input: R) -> anyhow::Result<()> {
+        while let Some(message) = read_message(&mut input)? {
+            if !self.handle(message)? {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request or a notification, returning false once the server should exit
+    pub fn handle(&mut self, message: Value) -> anyhow::Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "completionProvider": { "triggerCharacters": [":"] },
+                },
+                "serverInfo": { "name": "gale-lsp", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                let text = document["text"].as_str().unwrap_or_default();
+                self.update(document["uri"].as_str().unwrap_or_default(), text)?;
+                return Ok(true);
+            }
+            "textDocument/didChange" => {
+                // the whole text is sent on every change, as requested in `initialize`
+                let changes = params["contentChanges"].as_array();
+                if let Some(change) = changes.and_then(|it| it.last()) {
+                    let text = change["text"].as_str().unwrap_or_default();
+                    self.update(
+                        params["textDocument"]["uri"].as_str().unwrap_or_default(),
+                        text,
+                    )?;
+                }
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                self.documents.remove(uri);
+                self.publish(uri, vec![])?;
+                return Ok(true);
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/completion" => self.completion(params),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "exit" => return Ok(false),
+            _ if message.get("id").is_none() => return Ok(true),
+            _ => {
+                let error =
+                    json!({ "code": -32601, "message": format!("Unknown method {}!", method) });
+                self.send(json!({ "jsonrpc": "2.0", "id": message["id"], "error": error }))?;
+                return Ok(true);
+            }
+        };
+        self.send(json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))?;
+        Ok(true)
+    }
+
+    /// Whether the client asked the server to shut down
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
+    fn update(&mut self, uri: &str, text: &str) -> anyhow::Result<()> {
+        let assembled = assemble_spanned(text);
+        let document = self.documents.entry(uri.to_string()).or_default();
+        document.text = text.to_string();
+        if let Ok((chain, source_map)) = &assembled {
+            document.symbols = symbols(chain, source_map);
+        }
+        let diagnostics = _reports(&self.vm, &assembled)
+            .into_iter()
+            .map(|report| {
+                let span = report.span.unwrap_or(Span::new(1, 0, 0));
+                json!({
+                    "range": _range(text, span),
+                    "severity": match report.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    },
+                    "code": report.code,
+                    "source": "gale",
+                    "message": report.message,
+                })
+            })
+            .collect();
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Value>) -> anyhow::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    /// Document, line and character of a position in the params of a request
+    fn position<'a>(&'a self, params: &'a Value) -> (&'a str, &'a Document, usize, usize) {
+        static CLOSED: Document = Document {
+            text: String::new(),
+            symbols: Vec::new(),
+        };
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self.documents.get(uri).unwrap_or(&CLOSED);
+        let position = &params["position"];
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let character = position["character"].as_u64().unwrap_or(0) as usize;
+        (uri, document, line, character)
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let (uri, document, line, character) = self.position(params);
+        let word = _word_at(&document.text, line, character, false);
+        match definition(&document.symbols, &word).and_then(|it| it.span) {
+            Some(span) => json!({ "uri": uri, "range": _range(&document.text, span) }),
+            None => Value::Null,
+        }
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let (_, document, line, character) = self.position(params);
+        let prefix = _word_at(&document.text, line, character, true);
+        let items: Vec<Value> = completions(&self.env, &document.symbols, &prefix)
+            .into_iter()
+            .map(|(label, kind)| json!({ "label": label, "kind": kind.completion_kind() }))
+            .collect();
+        Value::Array(items)
+    }
+
+    fn send(&mut self, message: Value) -> anyhow::Result<()> {
+        write_message(&mut self.out, &message)
+    }
+}
+
+/// Reads a message framed by a `Content-Length` header, or nothing at the end of the input
+pub fn read_message<R: BufRead>(input: &mut R) -> anyhow::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => bail!("Expected a Content-Length header!"),
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| anyhow!("Invalid message: {}!", err))
+}
+
+pub fn write_message<W: Write>(out: &mut W, message: &Value) -> anyhow::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,376 @@
+use anyhow::{anyhow, bail};
+use galevm::asm::{assemble_spanned_with, AssembleOptions};
+use galevm::cache::{CompileCache, DEFAULT_CACHE_DIR};
+use galevm::dasm::disassemble;
+use galevm::diagnostics::{render, Report};
+use galevm::features::StdFeature;
+use galevm::fmt::format;
+use galevm::manifest::{HostCapabilities, Manifest};
+use galevm::program::{
+    read_program_with, write_program_with, Compression, Program, ProgramKey, ProgramOptions,
+};
+use galevm::spec::validate_program;
+use galevm::visit::{ScopeProvider, VisitorCore, Vm, DEFAULT_ENTRYPOINT};
+use galevm::warn::WarningLevel;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs, panic, process};
+
+const USAGE: &str = "Usage: gale <command> <file> [options] [-- args...]
+
+Commands:
+    run <file>                  Runs a source (.gale) or compiled (.galb) file, then calls
+                                its `main(args)` function with the arguments after `--`,
+                                exiting with the number it returns
+    build <file.gale> [-o out]  Compiles source file into a token chain
+    check <file.gale>           Statically checks source file without running it
+    test <file>                 Runs all `test \"name\" { ... }` blocks of a source or compiled
+                                file with `std::test` included, and reports their results
+    dasm <file.galb>            Prints compiled token chain as pseudocode
+    fmt <file.gale>             Rewrites source file with canonical indentation and spacing
+    validate <file.galb>        Checks the structure of a compiled file without running it
+
+Options:
+    -o, --output <file>         Output path for `build`, defaults to <file>.galb
+    -f, --feature <a,b,...>     Std features to include for `run` and `check`
+{features}
+    -c, --compress <kind>       Compression used by `build` (none, deflate, zstd)
+    --key <file>                File with a 32 byte key, used to encrypt programs in `build`
+                                and to decrypt them in `run` and `dasm`, which then reject
+                                unencrypted programs
+    -D, --define <name[=value]> Defines a name for `#if` and `#define` in source files,
+                                may be repeated
+    -e, --entry <name>          Entrypoint called by `run` instead of `main`, `build` stores it
+                                in the program
+    --deny-warnings             Treats warnings as errors for `run`
+    --typecheck                 Also checks types of arguments, returned values and operands
+                                in `check`
+    --check                     Only reports whether `fmt` would change the file, without
+                                rewriting it
+    --cache-dir <dir>           Directory for compiled sources, defaults to .gale-cache next
+                                to the source file
+    --no-cache                  Always compiles source files, without reading or writing
+                                the cache";
+
+/// [`USAGE`] with the names of all std features in place of `{features}`
+fn usage() -> String {
+    let indent = " ".repeat(32);
+    let mut lines = vec![];
+    let mut line = String::new();
+    for (i, feature) in StdFeature::ALL.iter().enumerate() {
+        let last = i + 1 == StdFeature::ALL.len();
+        let word = format!(
+            "{}{}{}",
+            if i == 0 { "(" } else { "" },
+            feature,
+            if last { ")" } else { "," }
+        );
+        if !line.is_empty() && indent.len() + line.len() + 1 + word.len() > 92 {
+            lines.push(format!("{}{}", indent, line));
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    lines.push(format!("{}{}", indent, line));
+    USAGE.replace("{features}", &lines.join("\n"))
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    command: String,
+    file: Option<PathBuf>,
+    output: Option<PathBuf>,
+    features: Vec<StdFeature>,
+    compression: Compression,
+    key: Option<PathBuf>,
+    entry: Option<String>,
+    /// Names from `--define`, for the preprocessor
+    assemble: AssembleOptions,
+    deny_warnings: bool,
+    typecheck: bool,
+    /// Whether `fmt` only checks the formatting
+    check_format: bool,
+    cache_dir: Option<PathBuf>,
+    no_cache: bool,
+    /// Arguments after `--`, passed to the entrypoint
+    program_args: Vec<String>,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut parsed = Args::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(out) => parsed.output = Some(PathBuf::from(out)),
+                None => bail!("Expected an output path after {}!", arg),
+            },
+            "-f" | "--feature" => match args.next() {
+                Some(features) => {
+                    for feature in features.split(',').filter(|it| !it.is_empty()) {
+                        parsed.features.push(feature.trim().parse()?);
+                    }
+                }
+                None => bail!("Expected a list of features after {}!", arg),
+            },
+            "-c" | "--compress" => match args.next() {
+                Some(compression) => parsed.compression = compression.parse()?,
+                None => bail!("Expected a compression after {}!", arg),
+            },
+            "--key" => match args.next() {
+                Some(key) => parsed.key = Some(PathBuf::from(key)),
+                None => bail!("Expected a key file after {}!", arg),
+            },
+            "-e" | "--entry" => match args.next() {
+                Some(entry) => parsed.entry = Some(entry),
+                None => bail!("Expected an entrypoint name after {}!", arg),
+            },
+            "--cache-dir" => match args.next() {
+                Some(dir) => parsed.cache_dir = Some(PathBuf::from(dir)),
+                None => bail!("Expected a cache directory after {}!", arg),
+            },
+            "--no-cache" => parsed.no_cache = true,
+            "-D" | "--define" => match args.next() {
+                Some(define) => {
+                    let (name, value) = define.split_once('=').unwrap_or((&define, ""));
+                    parsed
+                        .assemble
+                        .defines
+                        .insert(name.to_string(), value.to_string());
+                }
+                None => bail!("Expected a name to define after {}!", arg),
+            },
+            "--deny-warnings" => parsed.deny_warnings = true,
+            "--typecheck" => parsed.typecheck = true,
+            "--check" => parsed.check_format = true,
+            "--" => parsed.program_args.extend(args.by_ref()),
+            _ if parsed.command.is_empty() => parsed.command = arg,
+            _ if parsed.file.is_none() => parsed.file = Some(PathBuf::from(arg)),
+            _ => bail!("Unexpected argument {}!\n\n{}", arg, usage()),
+        }
+    }
+    Ok(parsed)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    match args.command.as_str() {
+        "run" => {
+            let mut vm = vm(&args);
+            let mut program = load(file(&args)?, &args, Some(vm.capabilities()))?;
+            vm.load_spanned(&mut program.chain, &program.source_map);
+            vm.process();
+            let entrypoint = args.entry.clone().or(program.manifest.entrypoint);
+            let code = match entrypoint {
+                Some(name) => vm.run_entrypoint(&name, args.program_args.clone())?,
+                None if vm.resolve_fn(DEFAULT_ENTRYPOINT).is_ok() => {
+                    vm.run_main(args.program_args.clone())?
+                }
+                None => 0,
+            };
+            let source = source(file(&args)?);
+            for warning in vm.take_warnings() {
+                let report = Report::from(&warning).with_origin(file(&args)?.display());
+                eprintln!("{}", render(&source, report));
+            }
+            if code != 0 {
+                process::exit(code)
+            }
+        }
+        "test" => {
+            let mut vm = vm(&args);
+            vm.add_std_feature(StdFeature::Test);
+            let mut program = load(file(&args)?, &args, Some(vm.capabilities()))?;
+            vm.load_spanned(&mut program.chain, &program.source_map);
+            vm.process();
+            // failures are collected into the report, so the default panic output is noise
+            panic::set_hook(Box::new(|_| {}));
+            let report = vm.run_tests();
+            let _ = panic::take_hook();
+            println!("{}", report);
+            if !report.is_success() {
+                process::exit(1)
+            }
+        }
+        "build" => {
+            let path = file(&args)?;
+            let program = compile(path, &args)?;
+            let output = args
+                .output
+                .clone()
+                .unwrap_or_else(|| path.with_extension("galb"));
+            let mut manifest = Manifest::infer(&program.chain, &vm(&args).capabilities());
+            manifest.entrypoint = args.entry.clone();
+            let mut writer = BufWriter::new(File::create(&output)?);
+            let mut program = program.with_manifest(manifest);
+            write_program_with(&mut writer, &mut program, &program_options(&args, None)?)?;
+            writer.flush()?;
+            println!("Compiled {} into {}", path.display(), output.display());
+        }
+        "check" => {
+            let path = file(&args)?;
+            let Program {
+                chain, source_map, ..
+            } = compile(path, &args)?;
+            let vm = vm(&args);
+            let mut diagnostics = vm.check(&chain);
+            if args.typecheck {
+                diagnostics.extend(vm.typecheck(&chain));
+            }
+            let source = source(path);
+            for diagnostic in &diagnostics {
+                let report = Report::from(&diagnostic.clone().locate(&source_map));
+                eprintln!("{}", render(&source, report.with_origin(path.display())));
+            }
+            if !diagnostics.is_empty() {
+                bail!(
+                    "Found {} problem(s) in {}!",
+                    diagnostics.len(),
+                    path.display()
+                )
+            }
+            println!("{}: OK ({} tokens)", path.display(), chain.len());
+        }
+        "dasm" => {
+            let program = read_compiled(file(&args)?, &args, None)?;
+            print!("{}", disassemble(&program.chain));
+        }
+        "fmt" => {
+            let path = file(&args)?;
+            let source = fs::read_to_string(path)?;
+            let formatted = format(&source)?;
+            if formatted == source {
+                println!("{}: OK", path.display());
+            } else if args.check_format {
+                bail!("{} is not formatted!", path.display())
+            } else {
+                fs::write(path, formatted)?;
+                println!("Formatted {}", path.display());
+            }
+        }
+        "validate" => {
+            let path = file(&args)?;
+            let info = validate_program(&fs::read(path)?)?;
+            let payload = match info.payload {
+                Some(payload) => format!(
+                    "{} tokens, {} bytes, nested {} levels",
+                    payload.tokens, payload.size, payload.depth
+                ),
+                None => "encrypted payload".to_string(),
+            };
+            println!(
+                "{}: OK (format {}, {} compression, {})",
+                path.display(),
+                info.format_version,
+                info.compression,
+                payload
+            );
+        }
+        "" | "help" => println!("{}", usage()),
+        other => bail!("Unknown command {}!\n\n{}", other, usage()),
+    }
+    Ok(())
+}
+
+fn vm(args: &Args) -> Vm {
+    let mut vm = Vm::new();
+    for feature in &args.features {
+        vm.add_std_feature(*feature);
+    }
+    for (name, value) in &args.assemble.defines {
+        vm.define(name.clone(), value.clone());
+    }
+    if args.deny_warnings {
+        vm.set_warning_level(WarningLevel::Deny);
+    }
+    vm
+}
+
+fn file(args: &Args) -> anyhow::Result<&Path> {
+    match &args.file {
+        Some(path) => Ok(path),
+        None => bail!("Expected a file path!\n\n{}", usage()),
+    }
+}
+
+/// Compiles a source file, reusing the program compiled from it earlier if it did not change.
+/// Errors in the source are printed with an excerpt of it
+fn compile(path: &Path, args: &Args) -> anyhow::Result<Program> {
+    let compiled = if args.no_cache {
+        fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|src| assemble_spanned_with(&src, &args.assemble))
+            .map(|(chain, source_map)| Program::new(chain, source_map))
+    } else {
+        let dir = match &args.cache_dir {
+            Some(dir) => dir.clone(),
+            None => path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(DEFAULT_CACHE_DIR),
+        };
+        CompileCache::new(dir)
+            .with_options(args.assemble.clone())
+            .compile(path)
+    };
+    compiled.map_err(|err| match fs::read_to_string(path) {
+        Ok(source) => {
+            eprintln!(
+                "{}",
+                render(&source, Report::from(&err).with_origin(path.display()))
+            );
+            anyhow!("Could not compile {}!", path.display())
+        }
+        Err(_) => err,
+    })
+}
+
+/// Text of a source file, or nothing for compiled files
+fn source(path: &Path) -> String {
+    match path.extension().and_then(|it| it.to_str()) {
+        Some("galb") => String::new(),
+        _ => fs::read_to_string(path).unwrap_or_default(),
+    }
+}
+
+fn program_options(args: &Args, host: Option<HostCapabilities>) -> anyhow::Result<ProgramOptions> {
+    let key = match &args.key {
+        Some(path) => match ProgramKey::try_from(fs::read(path)?) {
+            Ok(key) => Some(key),
+            Err(bytes) => bail!(
+                "Expected a key of 32 bytes in {}, got {}!",
+                path.display(),
+                bytes.len()
+            ),
+        },
+        None => None,
+    };
+    Ok(ProgramOptions {
+        compression: args.compression,
+        key,
+        host,
+    })
+}
+
+/// Reads a compiled program, decrypting it with the key from arguments if needed,
+/// and making sure that `host` provides everything it needs
+fn read_compiled(
+    path: &Path,
+    args: &Args,
+    host: Option<HostCapabilities>,
+) -> anyhow::Result<Program> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_program_with(&mut reader, &program_options(args, host)?)
+}
+
+/// Loads either a compiled or a source file, based on its extension
+fn load(path: &Path, args: &Args, host: Option<HostCapabilities>) -> anyhow::Result<Program> {
+    match path.extension().and_then(|it| it.to_str()) {
+        Some("galb") => read_compiled(path, args, host),
+        _ => compile(path, args),
+    }
+}
@@ -0,0 +1,334 @@
+//! Requirements of a compiled program, checked against the host before the program is read.
+
+use crate::features::StdFeature;
+use crate::tks::{Expression, Keyword, Literal, Token, TokenChain};
+use crate::vm::Transmute;
+use anyhow::bail;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Version of this crate, which is the version of the VM running programs
+pub const VM_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl Version {
+    pub fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Version of the running VM
+    pub fn current() -> Self {
+        VM_VERSION.parse().unwrap()
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // pre-release and build suffixes are ignored
+        let core = s.split(['-', '+']).next().unwrap_or_default();
+        let parts = core
+            .split('.')
+            .map(|it| it.parse::<u16>())
+            .collect::<Result<Vec<u16>, _>>();
+        match parts.as_deref() {
+            Ok([major, minor, patch]) => Ok(Version::new(*major, *minor, *patch)),
+            _ => bail!("Invalid version {} provided!", s),
+        }
+    }
+}
+
+impl Transmute for Version {
+    fn size(&mut self) -> usize {
+        6
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.major.write_to(buf)?;
+        self.minor.write_to(buf)?;
+        self.patch.write_to(buf)
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Version::new(
+            u16::read_from(buf)?,
+            u16::read_from(buf)?,
+            u16::read_from(buf)?,
+        ))
+    }
+}
+
+/// Signature of a host function, `name` is the full path it is called by
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ExternSignature {
+    pub name: String,
+    pub params: Vec<String>,
+    pub out_ty: String,
+}
+
+impl ExternSignature {
+    pub fn new(name: String, params: Vec<String>, out_ty: String) -> Self {
+        Self {
+            name,
+            params,
+            out_ty,
+        }
+    }
+
+    /// Whether a host function with this signature can be called as `required`
+    pub fn accepts(&self, required: &ExternSignature) -> bool {
+        let params = self.params.iter().any(|it| it == "varargs")
+            || self.params.len() == required.params.len();
+        let out_ty = self.out_ty == required.out_ty
+            || self.out_ty == "unknown"
+            || required.out_ty == "unknown";
+        self.name == required.name && params && out_ty
+    }
+}
+
+impl Display for ExternSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}({}) -> {}",
+            self.name,
+            self.params.join(", "),
+            self.out_ty
+        )
+    }
+}
+
+impl Transmute for ExternSignature {
+    fn size(&mut self) -> usize {
+        self.name.size() + self.params.size() + self.out_ty.size()
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.name.write_to(buf)?;
+        self.params.write_to(buf)?;
+        self.out_ty.write_to(buf)
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(ExternSignature::new(
+            String::read_from(buf)?,
+            Vec::read_from(buf)?,
+            String::read_from(buf)?,
+        ))
+    }
+}
+
+/// What a host provides to programs, see [`Vm::capabilities`](crate::visit::Vm::capabilities)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostCapabilities {
+    pub vm_version: Version,
+    pub features: Vec<StdFeature>,
+    pub extern_fns: Vec<ExternSignature>,
+}
+
+impl Default for HostCapabilities {
+    fn default() -> Self {
+        Self {
+            vm_version: Version::current(),
+            features: vec![],
+            extern_fns: vec![],
+        }
+    }
+}
+
+/// Requirements of a program, stored in its container ahead of the token chain
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub features: Vec<StdFeature>,
+    pub extern_fns: Vec<ExternSignature>,
+    pub min_vm_version: Version,
+    /// Name of the function the program should be started with
+    pub entrypoint: Option<String>,
+}
+
+impl Manifest {
+    /// Collects the std features and host functions the chain refers to. Only functions
+    /// that `host` provides outside of std scopes are recorded, as those can not be told
+    /// apart from functions the program declares by itself.
+    pub fn infer(chain: &TokenChain, host: &HostCapabilities) -> Self {
+        let mut paths = BTreeSet::new();
+        for (index, tk) in chain.iter().enumerate() {
+            if let (Token::Keyword(Keyword::Import), Some(Token::Literal(Literal::Ident(path)))) =
+                (tk, chain.get(index + 1))
+            {
+                paths.insert(path.to_owned());
+            }
+            _collect_paths(tk, &mut paths);
+        }
+
+        let mut features = BTreeSet::new();
+        let mut extern_fns = BTreeSet::new();
+        for path in &paths {
+            let feature = path
+                .rsplit_once("::")
+                .and_then(|(scope, _)| StdFeature::from_scope(scope));
+            if let Some(feature) = feature {
+                features.insert(feature);
+            } else if let Some(signature) = host.extern_fns.iter().find(|it| &it.name == path) {
+                extern_fns.insert(signature.to_owned());
+            }
+        }
+        Self {
+            features: features.into_iter().collect(),
+            extern_fns: extern_fns.into_iter().collect(),
+            min_vm_version: Version::current(),
+            entrypoint: None,
+        }
+    }
+
+    /// Makes sure the host provides everything the program needs
+    pub fn verify(&self, host: &HostCapabilities) -> anyhow::Result<()> {
+        if host.vm_version < self.min_vm_version {
+            bail!(
+                "Program needs VM version {} or newer, but the host runs {}!",
+                self.min_vm_version,
+                host.vm_version
+            )
+        }
+        for feature in &self.features {
+            if !host.features.contains(feature) {
+                bail!(
+                    "Program needs {}, but the host disabled the {} feature!",
+                    feature.scope().unwrap_or("std"),
+                    feature
+                )
+            }
+        }
+        for required in &self.extern_fns {
+            match host.extern_fns.iter().find(|it| it.name == required.name) {
+                Some(provided) if provided.accepts(required) => {}
+                Some(provided) => bail!(
+                    "Program needs extern fn {}, but the host provides {}!",
+                    required,
+                    provided
+                ),
+                None => bail!(
+                    "Program needs extern fn {}, but the host does not provide it!",
+                    required
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transmute for Manifest {
+    fn size(&mut self) -> usize {
+        self.features
+            .iter()
+            .map(|it| it.to_string())
+            .collect::<Vec<String>>()
+            .size()
+            + self.extern_fns.size()
+            + self.min_vm_version.size()
+            + self.entrypoint.size()
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        // features are stored by name, so hosts can tell which unknown feature is missing
+        self.features
+            .iter()
+            .map(|it| it.to_string())
+            .collect::<Vec<String>>()
+            .write_to(buf)?;
+        self.extern_fns.write_to(buf)?;
+        self.min_vm_version.write_to(buf)?;
+        self.entrypoint.write_to(buf)
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let features = Vec::<String>::read_from(buf)?
+            .iter()
+            .map(|name| match name.parse() {
+                Ok(feature) => Ok(feature),
+                Err(_) => bail!(
+                    "Program needs std feature {}, which this VM does not know!",
+                    name
+                ),
+            })
+            .collect::<anyhow::Result<Vec<StdFeature>>>()?;
+        Ok(Self {
+            features,
+            extern_fns: Vec::read_from(buf)?,
+            min_vm_version: Version::read_from(buf)?,
+            entrypoint: Option::read_from(buf)?,
+        })
+    }
+}
+
+fn _collect_paths(tk: &Token, paths: &mut BTreeSet<String>) {
+    let expr = match tk {
+        Token::Expression(expr) => expr,
+        _ => return,
+    };
+    match expr.as_ref() {
+        Expression::BinaryOp(_, l, r) => {
+            _collect_paths(l, paths);
+            _collect_paths(r, paths);
+        }
+        Expression::UnaryOp(_, v) => _collect_paths(v, paths),
+        Expression::StaticAccess(path) => {
+            paths.insert(path.join("::"));
+        }
+        Expression::InvokeStatic(path, params) => {
+            paths.insert(path.to_owned());
+            params.iter().for_each(|it| _collect_paths(it, paths));
+        }
+        Expression::Instantiate(_, params)
+        | Expression::InvokeInstance(_, _, params)
+        | Expression::Array(params) => params.iter().for_each(|it| _collect_paths(it, paths)),
+        Expression::InvokeChained(receiver, _, params) => {
+            _collect_paths(receiver, paths);
+            params.iter().for_each(|it| _collect_paths(it, paths));
+        }
+        Expression::Ternary(c, t, e) => {
+            _collect_paths(c, paths);
+            _collect_paths(t, paths);
+            _collect_paths(e, paths);
+        }
+        Expression::Index(value, index) => {
+            _collect_paths(value, paths);
+            _collect_paths(index, paths);
+        }
+        Expression::InstanceAccess(..)
+        | Expression::IfStmt
+        | Expression::ElseStmt
+        | Expression::ElifStmt
+        | Expression::WhileStmt
+        | Expression::DoWhileStmt
+        | Expression::MatchStmt => {}
+    }
+}
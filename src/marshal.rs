@@ -0,0 +1,318 @@
+//! Conversions between Rust values and [`Literal`]s, used by host functions
+//! and by hosts calling into programs, see [`Vm::run_function_as`](crate::visit::Vm::run_function_as).
+
+use crate::fns::Parameters;
+use crate::structs::StructureInstance;
+use crate::tks::{Decimal, Literal};
+use anyhow::bail;
+use std::collections::HashMap;
+
+/// Type name of the structures maps are converted into
+pub const MAP_TYPE: &str = "map";
+
+/// Converts a Rust value into a literal
+pub trait IntoLiteral {
+    fn into_literal(self) -> Literal;
+}
+
+/// Converts a literal back into a Rust value, failing if it holds a different type
+pub trait FromLiteral: Sized {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self>;
+
+    /// Type name of the literals this type is converted from, as used in declarations
+    fn type_name() -> String {
+        "unknown".to_string()
+    }
+}
+
+/// Converts parameter at `index` of a host function call
+pub fn param<T: FromLiteral>(params: &Parameters, index: usize) -> anyhow::Result<T> {
+    match params.get(index) {
+        Some(lit) => T::from_literal(lit.to_owned()),
+        None => bail!(
+            "Expected a parameter at index {}, got {} parameter(s)!",
+            index,
+            params.len()
+        ),
+    }
+}
+
+/// Takes the next parameter of a native method call, panicking if it is missing or mistyped
+#[doc(hidden)]
+pub fn _native_param<T: FromLiteral>(params: &mut impl Iterator<Item = Literal>, name: &str) -> T {
+    let lit = params
+        .next()
+        .unwrap_or_else(|| panic!("Missing parameter {}!", name));
+    T::from_literal(lit).unwrap_or_else(|err| panic!("Invalid parameter {}: {}", name, err))
+}
+
+fn _mismatch<T>(expected: &str, lit: &Literal) -> anyhow::Result<T> {
+    bail!("Expected {}, got {} {}!", expected, lit.this_type(), lit)
+}
+
+macro_rules! _int_marshal_impl {
+    ($($typ:ident),* $(,)*) => {
+        $(
+            /// Values that do not fit into a `num` wrap around
+            impl IntoLiteral for $typ {
+                fn into_literal(self) -> Literal {
+                    Literal::Number(self as i64)
+                }
+            }
+
+            impl FromLiteral for $typ {
+                fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+                    match lit {
+                        Literal::Number(v) => match $typ::try_from(v) {
+                            Ok(v) => Ok(v),
+                            Err(_) => bail!("Number {} does not fit into {}!", v, stringify!($typ)),
+                        },
+                        other => _mismatch("num", &other),
+                    }
+                }
+
+                fn type_name() -> String {
+                    "num".to_string()
+                }
+            }
+        )*
+    };
+}
+
+_int_marshal_impl! {
+    u8, u16, u32, u64, u128, usize,
+    i8, i16, i32, i64, i128, isize
+}
+
+macro_rules! _float_marshal_impl {
+    ($($typ:ident),* $(,)*) => {
+        $(
+            impl IntoLiteral for $typ {
+                fn into_literal(self) -> Literal {
+                    Literal::Float(self as f64)
+                }
+            }
+
+            /// Numbers are accepted as well
+            impl FromLiteral for $typ {
+                fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+                    match lit {
+                        Literal::Float(v) => Ok(v as $typ),
+                        Literal::Number(v) => Ok(v as $typ),
+                        other => _mismatch("float", &other),
+                    }
+                }
+
+                fn type_name() -> String {
+                    "float".to_string()
+                }
+            }
+        )*
+    };
+}
+
+_float_marshal_impl!(f32, f64);
+
+impl IntoLiteral for Decimal {
+    fn into_literal(self) -> Literal {
+        Literal::Decimal(self)
+    }
+}
+
+/// Numbers are accepted as well
+impl FromLiteral for Decimal {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::Decimal(v) => Ok(v),
+            Literal::Number(v) => Ok(Decimal::from(v)),
+            other => _mismatch("decimal", &other),
+        }
+    }
+
+    fn type_name() -> String {
+        "decimal".to_string()
+    }
+}
+
+impl IntoLiteral for String {
+    fn into_literal(self) -> Literal {
+        Literal::String(self)
+    }
+}
+
+impl FromLiteral for String {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::String(v) => Ok(v),
+            other => _mismatch("str", &other),
+        }
+    }
+
+    fn type_name() -> String {
+        "str".to_string()
+    }
+}
+
+impl IntoLiteral for &str {
+    fn into_literal(self) -> Literal {
+        Literal::String(self.to_string())
+    }
+}
+
+impl IntoLiteral for char {
+    fn into_literal(self) -> Literal {
+        Literal::Char(self)
+    }
+}
+
+impl FromLiteral for char {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::Char(v) => Ok(v),
+            other => _mismatch("char", &other),
+        }
+    }
+
+    fn type_name() -> String {
+        "char".to_string()
+    }
+}
+
+impl IntoLiteral for bool {
+    fn into_literal(self) -> Literal {
+        Literal::Bool(self)
+    }
+}
+
+impl FromLiteral for bool {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::Bool(v) => Ok(v),
+            other => _mismatch("bool", &other),
+        }
+    }
+
+    fn type_name() -> String {
+        "bool".to_string()
+    }
+}
+
+impl IntoLiteral for () {
+    fn into_literal(self) -> Literal {
+        Literal::Void
+    }
+}
+
+impl FromLiteral for () {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::Void => Ok(()),
+            other => _mismatch("void", &other),
+        }
+    }
+
+    fn type_name() -> String {
+        "void".to_string()
+    }
+}
+
+impl IntoLiteral for Literal {
+    fn into_literal(self) -> Literal {
+        self
+    }
+}
+
+impl FromLiteral for Literal {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        Ok(lit)
+    }
+}
+
+impl IntoLiteral for StructureInstance {
+    fn into_literal(self) -> Literal {
+        Literal::Struct(Box::new(self))
+    }
+}
+
+impl FromLiteral for StructureInstance {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::Struct(v) => Ok(*v),
+            other => _mismatch("a structure", &other),
+        }
+    }
+}
+
+impl<T: IntoLiteral> IntoLiteral for Vec<T> {
+    fn into_literal(self) -> Literal {
+        Literal::Array(self.into_iter().map(T::into_literal).collect())
+    }
+}
+
+/// Byte buffers are accepted as well, as arrays of numbers
+impl<T: FromLiteral> FromLiteral for Vec<T> {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::Array(v) => v.into_iter().map(T::from_literal).collect(),
+            Literal::Bytes(v) => v
+                .into_iter()
+                .map(|it| T::from_literal(Literal::Number(it as i64)))
+                .collect(),
+            other => _mismatch("array", &other),
+        }
+    }
+
+    fn type_name() -> String {
+        "array".to_string()
+    }
+}
+
+/// `None` is converted into void
+impl<T: IntoLiteral> IntoLiteral for Option<T> {
+    fn into_literal(self) -> Literal {
+        match self {
+            Some(v) => v.into_literal(),
+            None => Literal::Void,
+        }
+    }
+}
+
+impl<T: FromLiteral> FromLiteral for Option<T> {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        match lit {
+            Literal::Void => Ok(None),
+            other => T::from_literal(other).map(Some),
+        }
+    }
+}
+
+/// Maps are converted into [`MAP_TYPE`] structures with a field per entry
+impl<T: IntoLiteral> IntoLiteral for HashMap<String, T> {
+    fn into_literal(self) -> Literal {
+        self.into_iter()
+            .fold(StructureInstance::builder(MAP_TYPE), |builder, (k, v)| {
+                builder.field(&k, v)
+            })
+            .build()
+            .into_literal()
+    }
+}
+
+/// Fields of any structure are accepted
+impl<T: FromLiteral> FromLiteral for HashMap<String, T> {
+    fn from_literal(lit: Literal) -> anyhow::Result<Self> {
+        let instance = StructureInstance::from_literal(lit)?;
+        instance
+            .field_names()
+            .into_iter()
+            .map(|name| {
+                let value = instance.field(&name)?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+
+    fn type_name() -> String {
+        MAP_TYPE.to_string()
+    }
+}
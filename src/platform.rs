@@ -0,0 +1,118 @@
+//! Services of the platform a Vm runs on: the clock, blocking, randomness, the host stack
+//! and exiting.
+//!
+//! `wasm32` targets have no system clock, entropy source, threads or processes, so there
+//! the host supplies the clock and randomness, in a browser from `js_sys`:
+//!
+//! ```ignore
+//! galevm::platform::set_clock(|| Duration::from_secs_f64(js_sys::Date::now() / 1000.0));
+//! galevm::platform::set_random(|| (js_sys::Math::random() * u64::MAX as f64) as u64);
+//! ```
+//!
+//! Without them the clock stays at the unix epoch and randomness is seeded from a counter.
+//! Sleeping does not block on `wasm32`, and exiting panics with the exit code, as there is
+//! no process to end. Other targets use the standard library and ignore the host functions.
+
+#[cfg(target_arch = "wasm32")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Time since the unix epoch, as told by the host
+pub type ClockFn = fn() -> Duration;
+/// Uniformly distributed random number, provided by the host
+pub type RandomFn = fn() -> u64;
+
+static CLOCK: RwLock<Option<ClockFn>> = RwLock::new(None);
+static RANDOM: RwLock<Option<RandomFn>> = RwLock::new(None);
+#[cfg(target_arch = "wasm32")]
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the clock used on targets without a system clock
+pub fn set_clock(clock: ClockFn) {
+    *CLOCK.write().unwrap() = Some(clock);
+}
+
+/// Sets the source of randomness used on targets without an entropy source
+pub fn set_random(random: RandomFn) {
+    *RANDOM.write().unwrap() = Some(random);
+}
+
+/// Time since the unix epoch
+pub fn now() -> Duration {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        CLOCK
+            .read()
+            .unwrap()
+            .map(|clock| clock())
+            .unwrap_or_default()
+    }
+}
+
+/// Blocks the current thread, which is not possible on `wasm32`
+pub fn sleep(by: Duration) {
+    #[cfg(not(target_arch = "wasm32"))]
+    std::thread::sleep(by);
+    #[cfg(target_arch = "wasm32")]
+    let _ = by;
+}
+
+/// Random number, from the host on `wasm32` or a mixed counter if it does not provide one
+pub fn random_u64() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        rand::RngCore::next_u64(&mut rand::thread_rng())
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        match *RANDOM.read().unwrap() {
+            Some(random) => random(),
+            None => _splitmix(COUNTER.fetch_add(1, Ordering::Relaxed) ^ now().as_nanos() as u64),
+        }
+    }
+}
+
+/// Runs `call` with at least [`STACK_RED_ZONE`] bytes of stack left, moving it to a new
+/// [`STACK_SEGMENT`] sized segment when the current one runs low. Without it deep recursion
+/// overflows the host thread long before the call depth limit is reached. `wasm32` runs
+/// `call` directly
+pub fn grow_stack<R>(call: impl FnOnce() -> R) -> R {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, call)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        call()
+    }
+}
+
+/// Stack a gale call may use before [`grow_stack`] is reached again, with a wide margin
+/// over the roughly 50 KiB a call takes in debug builds and 8 KiB in release builds
+pub const STACK_RED_ZONE: usize = 256 * 1024;
+/// Size of the stack segments allocated by [`grow_stack`]
+pub const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Ends the process, or panics with the exit code where there is no process to end
+pub fn exit(code: i32) -> ! {
+    #[cfg(not(target_arch = "wasm32"))]
+    std::process::exit(code);
+    #[cfg(target_arch = "wasm32")]
+    panic!("Process exited with code {}", code)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn _splitmix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
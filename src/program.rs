@@ -0,0 +1,408 @@
+//! Container of a compiled program.
+//!
+//! After the header written by [`write_header`](crate::vm::write_header) comes a single flags byte. Its lowest
+//! two bits select the [`Compression`] of the payload, and [`FLAG_ENCRYPTED`] marks
+//! payloads sealed with ChaCha20-Poly1305. The flags are followed by the [`Manifest`],
+//! which is never compressed or encrypted, so it can be checked before the payload is read.
+//! The payload itself is the token chain followed by its source map, compressed first
+//! and encrypted afterwards. Encrypted payloads are prefixed with their nonce,
+//! and the header, flags and manifest are authenticated along with them.
+
+use crate::manifest::{HostCapabilities, Manifest};
+use crate::span::SourceMap;
+use crate::spec::_check_payload;
+use crate::tks::TokenChain;
+use crate::vm::{_write_header_of, read_header, Transmute, FORMAT_VERSION};
+use anyhow::{anyhow, bail};
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Key used to encrypt and decrypt programs
+pub type ProgramKey = [u8; 32];
+
+/// Flag set for encrypted payloads
+pub const FLAG_ENCRYPTED: u8 = 0x04;
+const COMPRESSION_MASK: u8 = 0x03;
+#[cfg(feature = "encrypt")]
+const NONCE_SIZE: usize = 12;
+
+/// Compression applied to the program payload
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Requires the `deflate` feature
+    Deflate,
+    /// Requires the `zstd` feature
+    Zstd,
+}
+
+impl Compression {
+    fn tag(self) -> u8 {
+        match self {
+            Compression::None => 0x00,
+            Compression::Deflate => 0x01,
+            Compression::Zstd => 0x02,
+        }
+    }
+
+    fn from_tag(tag: u8) -> anyhow::Result<Self> {
+        Ok(match tag {
+            0x00 => Compression::None,
+            0x01 => Compression::Deflate,
+            0x02 => Compression::Zstd,
+            _ => bail!("Invalid compression {:#04x} provided!", tag),
+        })
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "none" => Compression::None,
+            "deflate" | "flate" => Compression::Deflate,
+            "zstd" => Compression::Zstd,
+            _ => bail!("Unknown compression {}!", s),
+        })
+    }
+}
+
+/// How a program is written or read. Compression is detected when reading,
+/// and host capabilities are only used there
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProgramOptions {
+    pub compression: Compression,
+    /// Encrypts the program with this key, requires the `encrypt` feature
+    pub key: Option<ProgramKey>,
+    /// Verifies the manifest against these capabilities before reading the payload
+    pub host: Option<HostCapabilities>,
+}
+
+/// A compiled token chain along with its source map and requirements
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    pub chain: TokenChain,
+    pub source_map: SourceMap,
+    pub manifest: Manifest,
+}
+
+impl Program {
+    pub fn new(chain: TokenChain, source_map: SourceMap) -> Self {
+        Self {
+            chain,
+            source_map,
+            manifest: Manifest::default(),
+        }
+    }
+
+    pub fn with_manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = manifest;
+        self
+    }
+}
+
+/// Writes an uncompressed and unencrypted program
+pub fn write_program<W: Write + ?Sized>(
+    writer: &mut W,
+    program: &mut Program,
+) -> anyhow::Result<()> {
+    write_program_with(writer, program, &ProgramOptions::default())
+}
+
+/// Writes a program, compressing and encrypting it as `options` say.
+/// Unencrypted programs are streamed, encrypted ones are sealed in memory first
+pub fn write_program_with<W: Write + ?Sized>(
+    writer: &mut W,
+    program: &mut Program,
+    options: &ProgramOptions,
+) -> anyhow::Result<()> {
+    let mut flags = options.compression.tag();
+    if options.key.is_some() {
+        flags |= FLAG_ENCRYPTED;
+    }
+    let head = _head(FORMAT_VERSION, flags, &mut program.manifest)?;
+    writer.write_all(&head)?;
+
+    match &options.key {
+        None => _write_payload(writer, program, options.compression),
+        Some(key) => {
+            let mut payload = vec![];
+            _write_payload(&mut payload, program, options.compression)?;
+            writer.write_all(&_seal(key, &head, &payload)?)?;
+            Ok(())
+        }
+    }
+}
+
+/// Reads a program which is not encrypted
+pub fn read_program<R: Read + ?Sized>(reader: &mut R) -> anyhow::Result<Program> {
+    read_program_with(reader, &ProgramOptions::default())
+}
+
+/// Reads a program, decrypting it with the key from `options` if it is encrypted.
+/// Once a key is given, unencrypted programs are rejected, as they could replace an encrypted
+/// one without the key. Fails before reading the payload if the host from `options` does not
+/// fit the manifest.
+///
+/// Programs of older format versions are read as well. Their payload is buffered and checked
+/// against the tags of their version before it is decoded, see [`crate::spec`]
+pub fn read_program_with<R: Read + ?Sized>(
+    reader: &mut R,
+    options: &ProgramOptions,
+) -> anyhow::Result<Program> {
+    let mut head = _read_head(reader)?;
+    if let Some(host) = &options.host {
+        head.manifest.verify(host)?;
+    }
+
+    if !head.is_encrypted() {
+        if options.key.is_some() {
+            bail!("Program is not encrypted, but a key was provided!")
+        }
+        let program = _read_payload(reader, head.compression, head.version)?;
+        return Ok(program.with_manifest(head.manifest));
+    }
+    let key = match &options.key {
+        Some(key) => key,
+        None => bail!("Program is encrypted, but no key was provided!"),
+    };
+    let mut sealed = vec![];
+    reader.read_to_end(&mut sealed)?;
+    let payload = _open(
+        key,
+        &_head(head.version, head.flags, &mut head.manifest)?,
+        &sealed,
+    )?;
+    let program = _read_payload(&mut payload.as_slice(), head.compression, head.version)?;
+    Ok(program.with_manifest(head.manifest))
+}
+
+/// Everything that precedes the payload of a program
+pub(crate) struct Head {
+    pub(crate) version: u16,
+    pub(crate) flags: u8,
+    pub(crate) compression: Compression,
+    pub(crate) manifest: Manifest,
+}
+
+impl Head {
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+}
+
+/// Reads the header, flags and manifest. Programs before format version 2 have no flags,
+/// and programs before version 3 have no manifest
+pub(crate) fn _read_head<R: Read + ?Sized>(reader: &mut R) -> anyhow::Result<Head> {
+    let version = read_header(reader)?;
+    let (flags, compression) = if version >= 2 {
+        _read_flags(reader)?
+    } else {
+        (0, Compression::None)
+    };
+    let manifest = if version >= 3 {
+        Manifest::read_from(reader)?
+    } else {
+        Manifest::default()
+    };
+    Ok(Head {
+        version,
+        flags,
+        compression,
+        manifest,
+    })
+}
+
+/// Reads the flags byte, returning it along with the compression it selects
+fn _read_flags<R: Read + ?Sized>(reader: &mut R) -> anyhow::Result<(u8, Compression)> {
+    let flags = u8::read_from(reader)?;
+    if flags & !(COMPRESSION_MASK | FLAG_ENCRYPTED) != 0 {
+        bail!("Invalid program flags {:#04x} provided!", flags)
+    }
+    Ok((flags, Compression::from_tag(flags & COMPRESSION_MASK)?))
+}
+
+/// Header, flags and manifest, which are authenticated for encrypted programs
+fn _head(version: u16, flags: u8, manifest: &mut Manifest) -> anyhow::Result<Vec<u8>> {
+    let mut head = vec![];
+    _write_header_of(version, &mut head)?;
+    head.push(flags);
+    if version >= 3 {
+        manifest.write_to(&mut head)?;
+    }
+    Ok(head)
+}
+
+fn _write_payload<W: Write + ?Sized>(
+    writer: &mut W,
+    program: &mut Program,
+    compression: Compression,
+) -> anyhow::Result<()> {
+    let mut body = |writer: &mut dyn Write| -> anyhow::Result<()> {
+        program.chain.write_to(writer)?;
+        program.source_map.write_to(writer)
+    };
+    let mut writer = writer;
+    match compression {
+        Compression::None => body(&mut writer),
+        Compression::Deflate => _deflate(writer, &mut body),
+        Compression::Zstd => _zstd(writer, &mut body),
+    }
+}
+
+fn _read_payload<R: Read + ?Sized>(
+    reader: &mut R,
+    compression: Compression,
+    version: u16,
+) -> anyhow::Result<Program> {
+    let mut reader = _decompressed(reader, compression)?;
+    if version == FORMAT_VERSION {
+        return Ok(Program::new(
+            TokenChain::read_from(&mut reader)?,
+            SourceMap::read_from(&mut reader)?,
+        ));
+    }
+    let mut payload = vec![];
+    reader.read_to_end(&mut payload)?;
+    _check_payload(&payload, version)?;
+    let mut payload = payload.as_slice();
+    let chain = TokenChain::read_from(&mut payload)?;
+    // version 1 stored the source map only if there was one
+    let source_map = if payload.is_empty() {
+        SourceMap::new()
+    } else {
+        SourceMap::read_from(&mut payload)?
+    };
+    Ok(Program::new(chain, source_map))
+}
+
+/// Reads the payload through the decompressor it was written with
+pub(crate) fn _decompressed<'a, R: Read + ?Sized>(
+    reader: &'a mut R,
+    compression: Compression,
+) -> anyhow::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Deflate => _inflate(reader)?,
+        Compression::Zstd => _unzstd(reader)?,
+    })
+}
+
+type PayloadBody<'a> = dyn FnMut(&mut dyn Write) -> anyhow::Result<()> + 'a;
+
+fn _missing_feature(what: &str, feature: &str) -> anyhow::Error {
+    anyhow!(
+        "Program uses {}, which requires the `{}` feature of galevm!",
+        what,
+        feature
+    )
+}
+
+#[cfg(feature = "deflate")]
+fn _deflate<W: Write + ?Sized>(writer: &mut W, body: &mut PayloadBody) -> anyhow::Result<()> {
+    let mut encoder = flate2::write::DeflateEncoder::new(writer, flate2::Compression::default());
+    body(&mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "deflate"))]
+fn _deflate<W: Write + ?Sized>(_: &mut W, _: &mut PayloadBody) -> anyhow::Result<()> {
+    Err(_missing_feature("deflate compression", "deflate"))
+}
+
+#[cfg(feature = "deflate")]
+fn _inflate<'a, R: Read + ?Sized>(reader: &'a mut R) -> anyhow::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(flate2::read::DeflateDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "deflate"))]
+fn _inflate<'a, R: Read + ?Sized>(_: &'a mut R) -> anyhow::Result<Box<dyn Read + 'a>> {
+    Err(_missing_feature("deflate compression", "deflate"))
+}
+
+#[cfg(feature = "zstd")]
+fn _zstd<W: Write + ?Sized>(writer: &mut W, body: &mut PayloadBody) -> anyhow::Result<()> {
+    let mut encoder = zstd::stream::write::Encoder::new(writer, 0)?;
+    body(&mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "zstd"))]
+fn _zstd<W: Write + ?Sized>(_: &mut W, _: &mut PayloadBody) -> anyhow::Result<()> {
+    Err(_missing_feature("zstd compression", "zstd"))
+}
+
+#[cfg(feature = "zstd")]
+fn _unzstd<'a, R: Read + ?Sized>(reader: &'a mut R) -> anyhow::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(zstd::stream::read::Decoder::new(reader)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn _unzstd<'a, R: Read + ?Sized>(_: &'a mut R) -> anyhow::Result<Box<dyn Read + 'a>> {
+    Err(_missing_feature("zstd compression", "zstd"))
+}
+
+#[cfg(feature = "encrypt")]
+fn _seal(key: &ProgramKey, head: &[u8], payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+    use rand::RngCore;
+
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let payload = Payload {
+        msg: payload,
+        aad: head,
+    };
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|_| anyhow!("Could not encrypt program!"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn _seal(_: &ProgramKey, _: &[u8], _: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Err(_missing_feature("encryption", "encrypt"))
+}
+
+#[cfg(feature = "encrypt")]
+fn _open(key: &ProgramKey, head: &[u8], sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+    if sealed.len() < NONCE_SIZE {
+        bail!("Encrypted program is truncated!")
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let payload = Payload {
+        msg: ciphertext,
+        aad: head,
+    };
+    cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| anyhow!("Could not decrypt program, either the key is wrong or the program was tampered with!"))
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn _open(_: &ProgramKey, _: &[u8], _: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Err(_missing_feature("encryption", "encrypt"))
+}
@@ -0,0 +1,54 @@
+use crate::features::StdFeature;
+use crate::tks::TokenChain;
+use crate::visit::{ScopeProvider, Vm};
+use std::fmt::{Debug, Formatter};
+
+/// Std features and libraries that are built once and shared by many Vms.
+///
+/// Registering a feature pushes its extern functions into the global registry, so building
+/// each Vm through a runtime avoids registering them again for every program. The scopes are
+/// shared by reference, while each Vm still gets its own global scope, stack and tokens.
+/// Programs can not reopen shared scopes with `namespace`, so one Vm can not replace the
+/// functions another one calls.
+#[derive(Clone)]
+pub struct SharedRuntime {
+    template: Vm,
+}
+
+impl SharedRuntime {
+    pub fn new(features: &[StdFeature]) -> Self {
+        let mut template = Vm::new();
+        for feature in features {
+            template.add_std_feature(*feature);
+        }
+        Self { template }
+    }
+
+    /// Executes a library once, so it is available in all Vms of this runtime
+    pub fn add_library(&mut self, name: &str, chain: TokenChain) -> anyhow::Result<()> {
+        self.template.add_library(name, chain)
+    }
+
+    /// Names of all shared scopes, sorted
+    pub fn scopes(&self) -> Vec<String> {
+        self.template
+            .dump_state()
+            .scopes
+            .into_keys()
+            .filter(|it| it != "global")
+            .collect()
+    }
+
+    /// Constructs a new Vm that references scopes of this runtime
+    pub fn new_vm(&self) -> Vm {
+        Vm::from_template(&self.template)
+    }
+}
+
+impl Debug for SharedRuntime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedRuntime")
+            .field("scopes", &self.scopes())
+            .finish()
+    }
+}
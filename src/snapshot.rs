@@ -0,0 +1,38 @@
+use crate::tks::Literal;
+use crate::visit::Scope;
+use std::collections::BTreeMap;
+
+/// Deterministic view of a single scope, with everything sorted by name
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ScopeSnapshot {
+    pub variables: BTreeMap<String, Literal>,
+    pub constants: BTreeMap<String, Literal>,
+    pub functions: Vec<String>,
+    pub exports: Vec<String>,
+    pub imports: BTreeMap<String, Vec<String>>,
+}
+
+/// Deterministic view of the whole [`Vm`](crate::visit::Vm) state.
+///
+/// Temporary function scopes have random names, so they are not listed in `scopes`.
+/// When a function is running, its locals are available through `current`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VmStateSnapshot {
+    pub scopes: BTreeMap<String, ScopeSnapshot>,
+    pub current: ScopeSnapshot,
+    pub scope_levels: Vec<Scope>,
+    pub structs: Vec<String>,
+    /// Declared enums with the names of their variants
+    pub enums: BTreeMap<String, Vec<String>>,
+    /// Values declared with `static`
+    pub statics: BTreeMap<String, Literal>,
+    pub stack: Vec<Literal>,
+    pub pending_tokens: usize,
+}
+
+#[inline]
+pub(crate) fn is_temporary_scope(name: &str) -> bool {
+    name.starts_with("static_fn_0x") || name.starts_with("inst_fn_0x")
+}
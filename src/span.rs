@@ -0,0 +1,98 @@
+use crate::vm::Transmute;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+/// Position of a token in its source, lines and columns start at 1
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: u32,
+    pub col: u32,
+    pub len: u32,
+}
+
+impl Span {
+    pub fn new(line: u32, col: u32, len: u32) -> Self {
+        Self { line, col, len }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+impl Transmute for Span {
+    fn size(&mut self) -> usize {
+        12
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.line.write_to(buf)?;
+        self.col.write_to(buf)?;
+        self.len.write_to(buf)
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            line: u32::read_from(buf)?,
+            col: u32::read_from(buf)?,
+            len: u32::read_from(buf)?,
+        })
+    }
+}
+
+/// Side table of spans, keyed by the index of token in its top level chain
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceMap {
+    spans: Vec<Option<Span>>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, index: usize, span: Span) {
+        if self.spans.len() <= index {
+            self.spans.resize(index + 1, None);
+        }
+        self.spans[index] = Some(span);
+    }
+
+    pub fn get(&self, index: usize) -> Option<Span> {
+        self.spans.get(index).copied().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+impl Transmute for SourceMap {
+    fn size(&mut self) -> usize {
+        self.spans.size()
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.spans.write_to(buf)
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            spans: Vec::read_from(buf)?,
+        })
+    }
+}
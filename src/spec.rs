@@ -0,0 +1,480 @@
+//! Byte-level specification of compiled gale programs, and a validator for it.
+//!
+//! Primitive values are encoded as described in [`crate::vm`]: big-endian integers and floats,
+//! `u32` length prefixes for strings and collections, a `bool` flag for options and a `u8`
+//! tag for enums. A program is laid out as follows, see [`crate::program`] for the container:
+//!
+//! ```text
+//! program    = header flags manifest payload
+//! header     = "GALB" u16                        ; magic and format version
+//! flags      = u8                                ; bits 0-1: compression, 0 none, 1 deflate,
+//!                                                ; 2 zstd; bit 2: encrypted; other bits are 0
+//! manifest   = vec<string>                       ; std features by name
+//!              vec<signature>                    ; host functions
+//!              u16 u16 u16                       ; lowest VM version, major minor patch
+//!              option<string>                    ; entrypoint
+//! signature  = string vec<string> string         ; name, parameters, output type
+//! payload    = chain source-map                  ; compressed as the flags say, then
+//!                                                ; for encrypted programs sealed as
+//!            | nonce[12] ciphertext              ; ChaCha20-Poly1305, authenticating
+//!                                                ; header, flags and manifest
+//! source-map = vec<option<span>>                 ; span of each top level token
+//! span       = u32 u32 u32                       ; line, column, length
+//! chain      = vec<token>
+//! map<T>     = u32 (string T)*                   ; keys are unique, writers sort them
+//! ```
+//!
+//! Tags of the enums, followed by the fields of the variant:
+//!
+//! | tag    | token               | literal                  | expression                       |
+//! |--------|---------------------|--------------------------|----------------------------------|
+//! | `0x00` | whitespace | void | binary op: `op token token` |
+//! | `0x01` | `{` | number: `i64` | unary op: `op token` |
+//! | `0x02` | `}` | float: `f64` | static access: `vec<string>` |
+//! | `0x03` | `(` | string: `string` | invoke static: `string chain` |
+//! | `0x04` | `)` | char: `u32 code point` | `if` |
+//! | `0x05` | `[` | ident: `string` | `else` |
+//! | `0x06` | `]` | bool: `bool` | `while` |
+//! | `0x07` | literal | type name: `string` | `elif` |
+//! | `0x08` | keyword: `u8` | struct: `string map<literal>` | *reserved* |
+//! | `0x09` | expression | array: `vec<literal>` | instantiate: `string chain` |
+//! | `0x0A` | end of statement | bytes: `vec<u8>` | instance access: `string string` |
+//! | `0x0B` | attribute: `string literal` | enum: `string literal` | invoke instance: `string string chain` |
+//! | `0x0C` | comment: `string` | decimal: `i128 u32 scale` | array: `chain` |
+//! | `0x0D` |  |  | ternary: `token token token` |
+//! | `0x0E` |  |  | `do`-`while` |
+//! | `0x0F` |  |  | `match` |
+//! | `0x10` |  |  | invoke chained: `token string chain` |
+//! | `0x11` |  |  | index: `token token` |
+//!
+//! Keywords are `0x01` `export`, `0x02` `import`, `0x03` `let`, `0x04` `const`, `0x05` `fn`,
+//! `0x06` `return`, `0x07` `namespace`, `0x08` `test`, `0x09` `enum`, `0x0A` `static`,
+//! `0x0B` `pub`, `0x0C` `priv` and `0x0D` `init`. Binary operators are `0x00` `=`, `0x01` `+`,
+//! `0x02` `-`, `0x03` `/`, `0x04` `*`, `0x05` `%`, `0x06` `&&`, `0x07` `||`, `0x08` `==`,
+//! `0x09` `!=`, `0x0A` `&`, `0x0B` `|`, `0x0C` `^`, `0x0D` `>>`, `0x0E` `<<`, `0x0F` `<`
+//! and `0x10` `>`, unary operators are `0x00` `!` and `0x01` `-` or `~`.
+//!
+//! Programs are written with [`FORMAT_VERSION`], the last version
+//! below. Older versions are still read, tags their version does not have are rejected:
+//!
+//! | version | changes                                                                     |
+//! |---------|-----------------------------------------------------------------------------|
+//! | 1       | header directly followed by the chain, and the source map if there is one   |
+//! | 2       | flags byte, compressed and encrypted payloads                               |
+//! | 3       | manifest                                                                    |
+//! | 4       | no changes to programs                                                      |
+//! | 5       | keywords `0x08`-`0x0D`, enum literals and `match`, chained invocations,     |
+//! |         | comments                                                                    |
+//! | 6       | index expressions                                                           |
+//! | 7       | decimal literals                                                            |
+
+use crate::manifest::Manifest;
+use crate::program::{_decompressed, _read_head, Compression};
+use crate::tks::MAX_SCALE;
+use crate::vm::FORMAT_VERSION;
+use anyhow::bail;
+use std::collections::HashSet;
+use std::io::Read;
+
+/// Deepest nesting of tokens and literals [`validate_program`] accepts. Readers decode
+/// nested values recursively, so deeper programs could overflow their stack
+pub const MAX_DEPTH: usize = 1024;
+
+/// Highest tag of each enum in a version of the format, operators never changed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FormatTags {
+    pub token: u8,
+    pub literal: u8,
+    pub expression: u8,
+    pub keyword: u8,
+}
+
+impl FormatTags {
+    /// Tags of a readable format version, see [`read_header`](crate::vm::read_header)
+    pub fn of(version: u16) -> Self {
+        match version {
+            ..=4 => Self {
+                token: 0x0B,
+                literal: 0x0A,
+                expression: 0x0E,
+                keyword: 0x07,
+            },
+            5 => Self {
+                token: 0x0C,
+                literal: 0x0B,
+                expression: 0x10,
+                keyword: 0x0D,
+            },
+            6 => Self {
+                token: 0x0C,
+                literal: 0x0B,
+                expression: 0x11,
+                keyword: 0x0D,
+            },
+            _ => Self {
+                token: 0x0C,
+                literal: 0x0C,
+                expression: 0x11,
+                keyword: 0x0D,
+            },
+        }
+    }
+}
+
+/// What [`validate_program`] found out about a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramInfo {
+    pub format_version: u16,
+    pub compression: Compression,
+    pub encrypted: bool,
+    pub manifest: Manifest,
+    /// Contents of the payload, which is not checked for encrypted programs
+    pub payload: Option<PayloadInfo>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PayloadInfo {
+    /// Size of the payload after decompression
+    pub size: usize,
+    /// Top level tokens of the chain
+    pub tokens: usize,
+    /// Entries of the source map
+    pub spans: usize,
+    /// Deepest nesting of tokens and literals
+    pub depth: usize,
+}
+
+/// Checks that `bytes` are a well formed program without reading it into a [`Program`](crate::program::Program)
+/// or executing it: header and flags, tags of every value, lengths against the remaining
+/// bytes, UTF-8 of strings, code points of chars, uniqueness of map keys, nesting depth,
+/// and that nothing follows the payload.
+///
+/// Encrypted payloads can not be checked without the key, compressed ones are decompressed
+/// first, which requires the feature of their compression
+pub fn validate_program(bytes: &[u8]) -> anyhow::Result<ProgramInfo> {
+    let mut reader = bytes;
+    let head = _read_head(&mut reader)?;
+    let payload = if head.is_encrypted() {
+        None
+    } else if head.compression == Compression::None {
+        Some(_check_payload(reader, head.version)?)
+    } else {
+        let mut payload = vec![];
+        _decompressed(&mut reader, head.compression)?.read_to_end(&mut payload)?;
+        Some(_check_payload(&payload, head.version)?)
+    };
+    Ok(ProgramInfo {
+        format_version: head.version,
+        compression: head.compression,
+        encrypted: head.is_encrypted(),
+        manifest: head.manifest,
+        payload,
+    })
+}
+
+/// Checks a decompressed and decrypted payload of a program in `version`
+pub(crate) fn _check_payload(bytes: &[u8], version: u16) -> anyhow::Result<PayloadInfo> {
+    let mut walker = Walker {
+        bytes,
+        pos: 0,
+        depth: 0,
+        max_depth: 0,
+        version,
+        tags: FormatTags::of(version),
+        known: FormatTags::of(FORMAT_VERSION),
+    };
+    let tokens = walker.chain()?;
+    // version 1 stored the source map only if there was one
+    let spans = if version == 1 && walker.pos == bytes.len() {
+        0
+    } else {
+        walker.len(1)?
+    };
+    for _ in 0..spans {
+        if walker.bool()? {
+            walker.take(12)?;
+        }
+    }
+    if walker.pos != bytes.len() {
+        bail!(
+            "Found {} trailing bytes after the payload!",
+            bytes.len() - walker.pos
+        )
+    }
+    Ok(PayloadInfo {
+        size: bytes.len(),
+        tokens,
+        spans,
+        depth: walker.max_depth,
+    })
+}
+
+/// Walks the encoded payload without decoding it into values
+struct Walker<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+    max_depth: usize,
+    version: u16,
+    /// Tags of the version the payload is in
+    tags: FormatTags,
+    /// Tags of the current version
+    known: FormatTags,
+}
+
+impl<'a> Walker<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            bail!(
+                "Expected {} more bytes at byte {} of the payload, got {}!",
+                len,
+                self.pos,
+                self.bytes.len() - self.pos
+            )
+        }
+        let taken = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let mut exact = [0u8; 4];
+        exact.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(exact))
+    }
+
+    /// Length prefix of a collection, whose elements take at least `min_size` bytes each
+    fn len(&mut self, min_size: usize) -> anyhow::Result<usize> {
+        let at = self.pos;
+        let len = self.u32()? as usize;
+        if len.saturating_mul(min_size) > self.bytes.len() - self.pos {
+            bail!(
+                "Length {} at byte {} of the payload exceeds the remaining {} bytes!",
+                len,
+                at,
+                self.bytes.len() - self.pos
+            )
+        }
+        Ok(len)
+    }
+
+    fn bool(&mut self) -> anyhow::Result<bool> {
+        let at = self.pos;
+        match self.u8()? {
+            0x00 => Ok(false),
+            0x01 => Ok(true),
+            other => bail!("Invalid bool {:#04x} at byte {} of the payload!", other, at),
+        }
+    }
+
+    fn string(&mut self) -> anyhow::Result<&'a str> {
+        let len = self.len(1)?;
+        let at = self.pos;
+        match std::str::from_utf8(self.take(len)?) {
+            Ok(str) => Ok(str),
+            Err(_) => bail!("Invalid UTF-8 in a string at byte {} of the payload!", at),
+        }
+    }
+
+    /// Tag of an enum, from `first` up to `last` of the current version, while
+    /// the version of the payload only has the tags up to `last_in_version`
+    fn tag(&mut self, what: &str, first: u8, last: u8, last_in_version: u8) -> anyhow::Result<u8> {
+        let at = self.pos;
+        let tag = self.u8()?;
+        if tag < first || tag > last {
+            bail!(
+                "Unknown {} tag {:#04x} at byte {} of the payload!",
+                what,
+                tag,
+                at
+            )
+        }
+        if tag > last_in_version {
+            bail!(
+                "The {} tag {:#04x} at byte {} of the payload is not a part of format version {}!",
+                what,
+                tag,
+                at,
+                self.version
+            )
+        }
+        Ok(tag)
+    }
+
+    /// Enters a nested value, failing past [`MAX_DEPTH`]
+    fn nested<T>(
+        &mut self,
+        walk: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        if self.depth == MAX_DEPTH {
+            bail!(
+                "Values at byte {} of the payload are nested deeper than {} levels!",
+                self.pos,
+                MAX_DEPTH
+            )
+        }
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+        let output = walk(self);
+        self.depth -= 1;
+        output
+    }
+
+    /// Chain of tokens, returning its length
+    fn chain(&mut self) -> anyhow::Result<usize> {
+        let len = self.len(1)?;
+        for _ in 0..len {
+            self.token()?;
+        }
+        Ok(len)
+    }
+
+    fn token(&mut self) -> anyhow::Result<()> {
+        self.nested(
+            |it| match it.tag("token", 0x00, it.known.token, it.tags.token)? {
+                0x07 => it.literal(),
+                0x08 => it
+                    .tag("keyword", 0x01, it.known.keyword, it.tags.keyword)
+                    .map(|_| ()),
+                0x09 => it.expression(),
+                0x0B => {
+                    it.string()?;
+                    it.literal()
+                }
+                0x0C => it.string().map(|_| ()),
+                _ => Ok(()),
+            },
+        )
+    }
+
+    fn literal(&mut self) -> anyhow::Result<()> {
+        self.nested(|it| {
+            match it.tag("literal", 0x00, it.known.literal, it.tags.literal)? {
+                0x01 | 0x02 => {
+                    it.take(8)?;
+                }
+                0x03 | 0x05 | 0x07 => {
+                    it.string()?;
+                }
+                0x04 => {
+                    let at = it.pos;
+                    let code = it.u32()?;
+                    if char::from_u32(code).is_none() {
+                        bail!(
+                            "Invalid char code {:#x} at byte {} of the payload!",
+                            code,
+                            at
+                        )
+                    }
+                }
+                0x06 => {
+                    it.bool()?;
+                }
+                0x08 => {
+                    it.string()?;
+                    let len = it.len(5)?;
+                    let mut keys = HashSet::new();
+                    for _ in 0..len {
+                        let at = it.pos;
+                        let key = it.string()?;
+                        if !keys.insert(key) {
+                            bail!("Duplicate key {} at byte {} of the payload!", key, at)
+                        }
+                        it.literal()?;
+                    }
+                }
+                0x09 => {
+                    let len = it.len(1)?;
+                    for _ in 0..len {
+                        it.literal()?;
+                    }
+                }
+                0x0A => {
+                    let len = it.len(1)?;
+                    it.take(len)?;
+                }
+                0x0B => {
+                    it.string()?;
+                    it.literal()?;
+                }
+                0x0C => {
+                    it.take(16)?;
+                    let at = it.pos;
+                    let scale = it.u32()?;
+                    if scale > MAX_SCALE {
+                        bail!(
+                            "Invalid decimal scale {} at byte {} of the payload!",
+                            scale,
+                            at
+                        )
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    fn expression(&mut self) -> anyhow::Result<()> {
+        let at = self.pos;
+        match self.tag(
+            "expression",
+            0x00,
+            self.known.expression,
+            self.tags.expression,
+        )? {
+            0x00 => {
+                self.tag("binary operator", 0x00, 0x10, 0x10)?;
+                self.token()?;
+                self.token()?;
+            }
+            0x01 => {
+                self.tag("unary operator", 0x00, 0x01, 0x01)?;
+                self.token()?;
+            }
+            0x02 => {
+                let len = self.len(4)?;
+                for _ in 0..len {
+                    self.string()?;
+                }
+            }
+            0x03 | 0x09 => {
+                self.string()?;
+                self.chain()?;
+            }
+            0x08 => bail!("Invalid expression tag 0x08 at byte {} of the payload!", at),
+            0x0A => {
+                self.string()?;
+                self.string()?;
+            }
+            0x0B => {
+                self.string()?;
+                self.string()?;
+                self.chain()?;
+            }
+            0x0C => {
+                self.chain()?;
+            }
+            0x0D => {
+                self.token()?;
+                self.token()?;
+                self.token()?;
+            }
+            0x10 => {
+                self.token()?;
+                self.string()?;
+                self.chain()?;
+            }
+            0x11 => {
+                self.token()?;
+                self.token()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! Values declared with `static`, which are visible from every scope of a Vm without imports.
+
+use crate::tks::Literal;
+use crate::var::_typed_value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Declared value of a static, locked to its declared type if there is one
+#[derive(Debug, Clone, PartialEq)]
+struct StaticValue {
+    value: Literal,
+    ty: Option<String>,
+}
+
+/// Table of statics, shared by every clone of a Vm. Access is synchronized,
+/// so clones running on other threads see the same values
+#[derive(Debug, Clone, Default)]
+pub struct Statics(Arc<Mutex<HashMap<String, StaticValue>>>);
+
+impl Statics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a static, unless it already exists. Statics are initialized only once,
+    /// so declarations in functions keep their value between calls.
+    /// Returns whether the static was declared
+    pub fn declare(&self, name: &str, value: Literal, ty: Option<String>) -> bool {
+        let value = match &ty {
+            Some(ty) => _typed_value(name, ty, value),
+            None => value,
+        };
+        let mut statics = self.0.lock().unwrap();
+        if statics.contains_key(name) {
+            return false;
+        }
+        statics.insert(name.to_string(), StaticValue { value, ty });
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<Literal> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|it| it.value.to_owned())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.lock().unwrap().contains_key(name)
+    }
+
+    /// Declared type of a static, if it has one
+    pub fn type_of(&self, name: &str) -> Option<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|it| it.ty.to_owned())
+    }
+
+    /// Changes the value of an existing static, returning whether it exists
+    pub fn set(&self, name: &str, value: Literal) -> bool {
+        // checked before locking the table, so a type error does not poison it
+        let value = match self.type_of(name) {
+            Some(ty) => _typed_value(name, &ty, value),
+            None => value,
+        };
+        match self.0.lock().unwrap().get_mut(name) {
+            Some(it) => {
+                it.value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every static with its value, sorted by name
+    pub fn values(&self) -> BTreeMap<String, Literal> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, it)| (name.to_owned(), it.value.to_owned()))
+            .collect()
+    }
+}
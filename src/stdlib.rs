@@ -1,35 +1,54 @@
-use std::thread;
-use std::time::Duration;
-use crate::{extern_fns, Parameters, unwrap_args};
+use crate::{extern_fns, Parameters, platform, unwrap_args};
+use crate::marshal::FromLiteral;
+use crate::stdlib::time::Duration;
 use crate::tks::Literal;
-use crate::visit::Visitor;
+use crate::visit::{ScopeProvider, Visitor};
 
 pub mod io;
 pub mod math;
 pub mod strs;
 pub mod mem;
 pub mod prelude;
+pub mod reflect;
+pub mod arr;
+pub mod bytes;
+pub mod hash;
+pub mod chars;
+pub mod log;
+pub mod test;
+pub mod random;
+pub mod time;
+pub mod dbg;
+pub mod files;
+pub mod path;
+pub mod csv;
+pub mod datetime;
+pub mod id;
+pub mod set;
+pub mod collections;
+pub mod iter;
+pub mod range;
+pub mod matrix;
+pub mod decimal;
 
 fn panic(params: Parameters) -> Literal {
     let msg = unwrap_args!(params => (String));
     eprintln!("Process panicked: {}", msg);
-    std::process::exit(-1);
+    platform::exit(-1);
 }
 
 fn exit(params: Parameters) -> Literal {
     let exit_code = unwrap_args!(params => (Number));
-    std::process::exit(exit_code as i32);
+    platform::exit(exit_code as i32);
 }
 
-fn sleep(params: Parameters) -> Literal {
-    let time = unwrap_args!(params => (Number));
-    thread::sleep(Duration::from_secs(time as u64));
-    Literal::Void
-}
-
-fn sleep_millis(params: Parameters) -> Literal {
-    let time = unwrap_args!(params => (Number));
-    thread::sleep(Duration::from_millis(time as u64));
+/// Waits for a `Duration` of `std::time`, like `sleep(secs(5))`
+fn sleep(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let duration = match params.into_iter().next().unwrap_or(Literal::Void) {
+        lit @ Literal::Struct(_) => Duration::from_literal(lit).unwrap_or_else(|err| panic!("{}", err)),
+        other => panic!("Expected a duration to sleep for, like std::time::secs(5), got {} {}!", other.this_type(), other),
+    };
+    vm.entropy().sleep(duration.to_std());
     Literal::Void
 }
 
@@ -39,8 +58,7 @@ pub fn __core_feature<V>(visitor: &mut V) where V: Visitor {
         scope "std" {
             extern fn panic(message) -> void;
             extern fn exit(code) -> void;
-            extern fn sleep(time) -> void;
-            extern fn sleep_millis(time) -> void;
+            native fn sleep(duration) -> void;
         }
     })
 }
\ No newline at end of file
@@ -0,0 +1,164 @@
+use std::cmp::Ordering;
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::marshal::FromLiteral;
+use crate::stdlib::range::Range;
+use crate::tks::{Literal, Token};
+use crate::visit::{ScopeProvider, Visitor};
+
+// Function values are passed by their names, same as in `std::reflect::call_dynamic`
+fn _call(vm: &mut dyn ScopeProvider, fnc: &str, args: Vec<Literal>) -> Literal {
+    vm.call_static_fn(fnc.to_string(), args.into_iter().map(Token::Literal).collect())
+}
+
+fn arr_map(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (arr, fnc) = unwrap_args!(params => (Array, String));
+    Literal::Array(arr.into_iter().map(|it| _call(vm, &fnc, vec![it])).collect())
+}
+
+fn arr_filter(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (arr, fnc) = unwrap_args!(params => (Array, String));
+    let mut out = vec![];
+    for value in arr {
+        match _call(vm, &fnc, vec![value.clone()]) {
+            Literal::Bool(true) => out.push(value),
+            Literal::Bool(false) => {}
+            other => panic!("Expected filter function {} to return a bool, got {}!", fnc, other),
+        }
+    }
+    Literal::Array(out)
+}
+
+fn arr_reduce(vm: &mut dyn ScopeProvider, mut params: Parameters) -> Literal {
+    let init = params.remove(1);
+    let (arr, fnc) = unwrap_args!(params => (Array, String));
+    arr.into_iter().fold(init, |acc, it| _call(vm, &fnc, vec![acc, it]))
+}
+
+fn arr_foreach(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (arr, fnc) = unwrap_args!(params => (Array, String));
+    for value in arr {
+        _call(vm, &fnc, vec![value]);
+    }
+    Literal::Void
+}
+
+// Order of values without a comparator, numbers and floats can be compared with each other
+fn _natural_order(a: &Literal, b: &Literal) -> Ordering {
+    match (a, b) {
+        (Literal::Number(a), Literal::Number(b)) => a.cmp(b),
+        (Literal::Float(a), Literal::Float(b)) => a.total_cmp(b),
+        (Literal::Number(a), Literal::Float(b)) => (*a as f64).total_cmp(b),
+        (Literal::Float(a), Literal::Number(b)) => a.total_cmp(&(*b as f64)),
+        (Literal::String(a), Literal::String(b)) => a.cmp(b),
+        (Literal::Char(a), Literal::Char(b)) => a.cmp(b),
+        (Literal::Bool(a), Literal::Bool(b)) => a.cmp(b),
+        _ => panic!("Can not compare {} with {}!", a, b),
+    }
+}
+
+// Comparators return a negative num, zero or a positive num, like `std::math::cmp`
+fn _compare(vm: &mut dyn ScopeProvider, fnc: Option<&str>, a: &Literal, b: &Literal) -> Ordering {
+    match fnc {
+        None => _natural_order(a, b),
+        Some(fnc) => match _call(vm, fnc, vec![a.clone(), b.clone()]) {
+            Literal::Number(ord) => ord.cmp(&0),
+            other => panic!("Expected comparator function {} to return a num, got {}!", fnc, other),
+        },
+    }
+}
+
+// Optional comparator after the other arguments
+fn _comparator(rest: &[Literal]) -> Option<String> {
+    match rest {
+        [] => None,
+        [Literal::String(fnc)] => Some(fnc.to_owned()),
+        [other] => panic!("Expected the name of a comparator function, got {}!", other),
+        _ => panic!("Expected at most one comparator function, got {}!", rest.len()),
+    }
+}
+
+fn _sorted(vm: &mut dyn ScopeProvider, mut params: Parameters, descending: bool) -> Literal {
+    let rest = params.split_off(1.min(params.len()));
+    let mut arr = unwrap_args!(params => (Array));
+    let fnc = _comparator(&rest);
+    // stable, so equal values keep their order in both directions
+    arr.sort_by(|a, b| {
+        let ord = _compare(vm, fnc.as_deref(), a, b);
+        if descending { ord.reverse() } else { ord }
+    });
+    Literal::Array(arr)
+}
+
+fn arr_sort(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _sorted(vm, params, false)
+}
+
+fn arr_sort_desc(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _sorted(vm, params, true)
+}
+
+fn arr_sort_by(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (mut arr, fnc) = unwrap_args!(params => (Array, String));
+    arr.sort_by(|a, b| _compare(vm, Some(&fnc), a, b));
+    Literal::Array(arr)
+}
+
+/// Index of the value in an array sorted in the same order, or `-(index) - 1` with the index it
+/// would have to be inserted at if the array does not contain it
+fn arr_binary_search(vm: &mut dyn ScopeProvider, mut params: Parameters) -> Literal {
+    let rest = params.split_off(2.min(params.len()));
+    let fnc = _comparator(&rest);
+    let value = params.remove(1);
+    let arr = unwrap_args!(params => (Array));
+    match arr.binary_search_by(|it| _compare(vm, fnc.as_deref(), it, &value)) {
+        Ok(index) => Literal::Number(index as i64),
+        Err(index) => Literal::Number(-(index as i64) - 1),
+    }
+}
+
+// Removes consecutive duplicates, so sorted arrays end up with unique values
+fn arr_dedup(params: Parameters) -> Literal {
+    let mut arr = unwrap_args!(params => (Array));
+    arr.dedup();
+    Literal::Array(arr)
+}
+
+fn arr_reverse(params: Parameters) -> Literal {
+    let mut arr = unwrap_args!(params => (Array));
+    arr.reverse();
+    Literal::Array(arr)
+}
+
+fn arr_from_range(params: Parameters) -> Literal {
+    let range = params.into_iter().next().unwrap_or(Literal::Void);
+    match Range::from_literal(range) {
+        Ok(range) => Literal::Array(range.values().map(Literal::Number).collect()),
+        Err(err) => panic!("Expected a range: {}!", err),
+    }
+}
+
+fn arr_len(params: Parameters) -> Literal {
+    let arr = unwrap_args!(params => (Array));
+    Literal::Number(arr.len() as i64)
+}
+
+#[doc(hidden)]
+pub fn __arr_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::arr" {
+            extern fn arr_len(arr) -> num;
+            extern fn arr_dedup(arr) -> array;
+            extern fn arr_reverse(arr) -> array;
+            extern fn arr_from_range(range) -> array;
+
+            native fn arr_map(arr, fnc) -> array;
+            native fn arr_filter(arr, fnc) -> array;
+            native fn arr_reduce(arr, init, fnc) -> unknown;
+            native fn arr_foreach(arr, fnc) -> void;
+            native fn arr_sort_by(arr, fnc) -> array;
+            native fn arr_sort(arr, varargs) -> array;
+            native fn arr_sort_desc(arr, varargs) -> array;
+            native fn arr_binary_search(arr, value, varargs) -> num;
+        }
+    })
+}
@@ -0,0 +1,95 @@
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::Visitor;
+
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn _range(len: usize, from: i64, to: i64) -> (usize, usize) {
+    if from < 0 || to < from || to as usize > len {
+        panic!("Invalid byte range {}..{} for buffer of length {}!", from, to, len)
+    }
+    (from as usize, to as usize)
+}
+
+fn from_str(params: Parameters) -> Literal {
+    let str = unwrap_args!(params => (String));
+    Literal::Bytes(str.into_bytes())
+}
+
+fn to_str(params: Parameters) -> Literal {
+    let bytes = unwrap_args!(params => (Bytes));
+    match String::from_utf8(bytes) {
+        Ok(str) => Literal::String(str),
+        Err(err) => panic!("Bytes are not a valid UTF-8 string: {}", err),
+    }
+}
+
+fn len(params: Parameters) -> Literal {
+    let bytes = unwrap_args!(params => (Bytes));
+    Literal::Number(bytes.len() as i64)
+}
+
+fn slice(params: Parameters) -> Literal {
+    let (bytes, from, to) = unwrap_args!(params => (Bytes, Number, Number));
+    let (from, to) = _range(bytes.len(), from, to);
+    Literal::Bytes(bytes[from..to].to_vec())
+}
+
+fn read_u32(params: Parameters) -> Literal {
+    let (bytes, offset) = unwrap_args!(params => (Bytes, Number));
+    let (from, to) = _range(bytes.len(), offset, offset + 4);
+    Literal::Number(u32::from_be_bytes(bytes[from..to].try_into().unwrap()) as i64)
+}
+
+fn write_u32(params: Parameters) -> Literal {
+    let (mut bytes, offset, value) = unwrap_args!(params => (Bytes, Number, Number));
+    let value = u32::try_from(value)
+        .unwrap_or_else(|_| panic!("Value {} does not fit into an u32!", value));
+    let (from, _) = _range(bytes.len(), offset, offset);
+    // writing past the end extends the buffer
+    if from + 4 > bytes.len() {
+        bytes.resize(from + 4, 0);
+    }
+    bytes[from..from + 4].copy_from_slice(&value.to_be_bytes());
+    Literal::Bytes(bytes)
+}
+
+fn hex_encode(params: Parameters) -> Literal {
+    let bytes = unwrap_args!(params => (Bytes));
+    Literal::String(bytes.iter().map(|it| format!("{:02x}", it)).collect())
+}
+
+fn base64_encode(params: Parameters) -> Literal {
+    let bytes = unwrap_args!(params => (Bytes));
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, byte)| acc | (*byte as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(group >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    Literal::String(out)
+}
+
+#[doc(hidden)]
+pub fn __bytes_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::bytes" {
+            extern fn from_str(value: str) -> bytes;
+            extern fn to_str(bytes: bytes) -> str;
+            extern fn len(bytes: bytes) -> num;
+            extern fn slice(bytes: bytes, from: num, to: num) -> bytes;
+            extern fn read_u32(bytes: bytes, offset: num) -> num;
+            extern fn write_u32(bytes: bytes, offset: num, value: num) -> bytes;
+            extern fn hex_encode(bytes: bytes) -> str;
+            extern fn base64_encode(bytes: bytes) -> str;
+        }
+    })
+}
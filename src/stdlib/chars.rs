@@ -0,0 +1,81 @@
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::Visitor;
+
+fn is_digit(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    Literal::Bool(ch.is_ascii_digit())
+}
+
+fn is_alpha(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    Literal::Bool(ch.is_alphabetic())
+}
+
+fn is_whitespace(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    Literal::Bool(ch.is_whitespace())
+}
+
+fn is_upper(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    Literal::Bool(ch.is_uppercase())
+}
+
+// Some chars map to several ones, e.g. `ß` to `SS`, these are returned as strings
+fn _single_or_str(mapped: impl Iterator<Item = char>) -> Literal {
+    let mapped: String = mapped.collect();
+    let mut chars = mapped.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Literal::Char(ch),
+        _ => Literal::String(mapped),
+    }
+}
+
+fn to_upper(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    _single_or_str(ch.to_uppercase())
+}
+
+fn to_lower(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    _single_or_str(ch.to_lowercase())
+}
+
+fn to_num(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    match ch.to_digit(10) {
+        Some(digit) => Literal::Number(digit as i64),
+        None => panic!("Char {:?} is not a digit!", ch),
+    }
+}
+
+fn code(params: Parameters) -> Literal {
+    let ch = unwrap_args!(params => (Char));
+    Literal::Number(ch as i64)
+}
+
+fn from_code(params: Parameters) -> Literal {
+    let code = unwrap_args!(params => (Number));
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(ch) => Literal::Char(ch),
+        None => panic!("{} is not a valid char code!", code),
+    }
+}
+
+#[doc(hidden)]
+pub fn __char_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::char" {
+            extern fn is_digit(ch) -> bool;
+            extern fn is_alpha(ch) -> bool;
+            extern fn is_whitespace(ch) -> bool;
+            extern fn is_upper(ch) -> bool;
+            extern fn to_upper(ch) -> unknown;
+            extern fn to_lower(ch) -> unknown;
+            extern fn to_num(ch) -> num;
+            extern fn code(ch) -> num;
+            extern fn from_code(code) -> char;
+        }
+    })
+}
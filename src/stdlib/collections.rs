@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use crate::{extern_fns, native_struct, Parameters};
+use crate::marshal::IntoLiteral;
+use crate::structs::NativeStruct;
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+// Collections are referenced by handles, so copies of a deque or stack share their values
+type Values = VecDeque<Literal>;
+
+fn _with<T>(vm: &dyn ScopeProvider, handle: i64, fun: impl FnOnce(&mut Values) -> T) -> T {
+    vm.handles()
+        .with(handle, fun)
+        .unwrap_or_else(|| panic!("Collection {} does not exist!", handle))
+}
+
+// Values of a deque from the front or of a stack from the bottom, used by `std::iter::iter`
+pub(crate) fn _values(vm: &dyn ScopeProvider, handle: i64) -> Vec<Literal> {
+    _with(vm, handle, |it| it.iter().cloned().collect())
+}
+
+// Drops the values, the collection can not be used afterwards
+fn _free(vm: &dyn ScopeProvider, handle: i64) {
+    vm.handles().remove::<Values>(handle);
+}
+
+/// Double ended queue created with `std::collections::deque`, whose values are kept by the Vm
+/// until it is freed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deque {
+    pub handle: i64,
+}
+
+native_struct! {
+    Deque {
+        handle: i64,
+    }
+    fn push_front(&self, vm, value: Literal) -> ();
+    fn push_back(&self, vm, value: Literal) -> ();
+    fn pop_front(&self, vm) -> Option<Literal>;
+    fn pop_back(&self, vm) -> Option<Literal>;
+    fn peek_front(&self, vm) -> Option<Literal>;
+    fn peek_back(&self, vm) -> Option<Literal>;
+    fn len(&self, vm) -> i64;
+    fn to_array(&self, vm) -> Vec<Literal>;
+    fn free(&self, vm) -> ();
+}
+
+// Popping or peeking an empty collection yields void
+impl Deque {
+    fn push_front(&self, vm: &dyn ScopeProvider, value: Literal) {
+        _with(vm, self.handle, |it| it.push_front(value))
+    }
+
+    fn push_back(&self, vm: &dyn ScopeProvider, value: Literal) {
+        _with(vm, self.handle, |it| it.push_back(value))
+    }
+
+    fn pop_front(&self, vm: &dyn ScopeProvider) -> Option<Literal> {
+        _with(vm, self.handle, |it| it.pop_front())
+    }
+
+    fn pop_back(&self, vm: &dyn ScopeProvider) -> Option<Literal> {
+        _with(vm, self.handle, |it| it.pop_back())
+    }
+
+    fn peek_front(&self, vm: &dyn ScopeProvider) -> Option<Literal> {
+        _with(vm, self.handle, |it| it.front().cloned())
+    }
+
+    fn peek_back(&self, vm: &dyn ScopeProvider) -> Option<Literal> {
+        _with(vm, self.handle, |it| it.back().cloned())
+    }
+
+    fn len(&self, vm: &dyn ScopeProvider) -> i64 {
+        _with(vm, self.handle, |it| it.len() as i64)
+    }
+
+    /// Values from the front to the back
+    fn to_array(&self, vm: &dyn ScopeProvider) -> Vec<Literal> {
+        _values(vm, self.handle)
+    }
+
+    fn free(&self, vm: &dyn ScopeProvider) {
+        _free(vm, self.handle)
+    }
+}
+
+/// Last in, first out stack created with `std::collections::stack`, whose values are kept by
+/// the Vm until it is freed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stack {
+    pub handle: i64,
+}
+
+native_struct! {
+    Stack {
+        handle: i64,
+    }
+    fn push(&self, vm, value: Literal) -> ();
+    fn pop(&self, vm) -> Option<Literal>;
+    fn peek(&self, vm) -> Option<Literal>;
+    fn len(&self, vm) -> i64;
+    fn to_array(&self, vm) -> Vec<Literal>;
+    fn free(&self, vm) -> ();
+}
+
+impl Stack {
+    fn push(&self, vm: &dyn ScopeProvider, value: Literal) {
+        _with(vm, self.handle, |it| it.push_back(value))
+    }
+
+    fn pop(&self, vm: &dyn ScopeProvider) -> Option<Literal> {
+        _with(vm, self.handle, |it| it.pop_back())
+    }
+
+    fn peek(&self, vm: &dyn ScopeProvider) -> Option<Literal> {
+        _with(vm, self.handle, |it| it.back().cloned())
+    }
+
+    fn len(&self, vm: &dyn ScopeProvider) -> i64 {
+        _with(vm, self.handle, |it| it.len() as i64)
+    }
+
+    /// Values from the bottom to the top
+    fn to_array(&self, vm: &dyn ScopeProvider) -> Vec<Literal> {
+        _values(vm, self.handle)
+    }
+
+    fn free(&self, vm: &dyn ScopeProvider) {
+        _free(vm, self.handle)
+    }
+}
+
+fn deque(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Deque { handle: vm.handles().insert(Values::new()) }.into_literal()
+}
+
+fn stack(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Stack { handle: vm.handles().insert(Values::new()) }.into_literal()
+}
+
+#[doc(hidden)]
+pub fn __collections_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::collections" {
+            native fn deque() -> Deque;
+            native fn stack() -> Stack;
+        }
+    });
+    Deque::register(visitor);
+    Stack::register(visitor);
+}
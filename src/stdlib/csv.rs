@@ -0,0 +1,129 @@
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::marshal::{IntoLiteral, MAP_TYPE};
+use crate::structs::StructureInstance;
+use crate::tks::Literal;
+use crate::visit::Visitor;
+
+/// Splits the text into rows of fields as in RFC 4180: fields are separated by commas,
+/// and quoted fields can contain commas, line breaks and quotes written twice
+fn _parse_rows(text: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    // whether anything was read since the end of the last row
+    let mut started = false;
+    while let Some(c) = chars.next() {
+        started = true;
+        match c {
+            '"' if field.is_empty() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c)
+                        }
+                        None => panic!("Unterminated quoted field at line {}!", start),
+                    }
+                }
+            }
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                line += 1;
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                started = false;
+            }
+            c => field.push(c),
+        }
+    }
+    if started {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+fn _quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn _field(value: Literal) -> String {
+    match value {
+        Literal::String(str) => str,
+        Literal::Void => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn parse(params: Parameters) -> Literal {
+    let text = unwrap_args!(params => (String));
+    _parse_rows(&text).into_literal()
+}
+
+/// Rows after the first one as maps, with the fields of the first row as their keys
+fn parse_with_headers(params: Parameters) -> Literal {
+    let text = unwrap_args!(params => (String));
+    let mut rows = _parse_rows(&text).into_iter();
+    let headers = rows.next().unwrap_or_default();
+    Literal::Array(rows.enumerate().map(|(index, row)| {
+        if row.len() != headers.len() {
+            panic!("Row {} has {} fields, but there are {} headers!", index + 2, row.len(), headers.len())
+        }
+        headers
+            .iter()
+            .zip(row)
+            .fold(StructureInstance::builder(MAP_TYPE), |builder, (header, field)| builder.field(header, field))
+            .build()
+            .into_literal()
+    }).collect())
+}
+
+/// Writes rows, which are either arrays of values or maps. Maps are written with a header
+/// of the keys of the first one, in alphabetical order
+fn stringify(params: Parameters) -> Literal {
+    let rows = unwrap_args!(params => (Array));
+    let mut lines = vec![];
+    let mut headers: Option<Vec<String>> = None;
+    for row in rows {
+        let fields = match row {
+            Literal::Array(values) => values.into_iter().map(_field).collect::<Vec<_>>(),
+            Literal::Struct(map) => {
+                let headers = headers.get_or_insert_with(|| {
+                    let names = map.field_names();
+                    lines.push(names.iter().map(|it| _quote(it)).collect::<Vec<_>>().join(","));
+                    names
+                });
+                headers.iter().map(|name| _field(map.get_field(name).unwrap_or(Literal::Void))).collect()
+            }
+            other => panic!("Expected a row as an array or a map, got {}!", other),
+        };
+        lines.push(fields.iter().map(|it| _quote(it)).collect::<Vec<_>>().join(","));
+    }
+    Literal::String(lines.into_iter().map(|it| it + "\n").collect())
+}
+
+#[doc(hidden)]
+pub fn __csv_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::csv" {
+            extern fn parse(text: str) -> array;
+            extern fn parse_with_headers(text: str) -> array;
+            extern fn stringify(rows: array) -> str;
+        }
+    })
+}
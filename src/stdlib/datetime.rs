@@ -0,0 +1,281 @@
+use std::cmp::Ordering;
+use crate::{extern_fns, native_struct, Parameters, unwrap_args};
+use crate::marshal::IntoLiteral;
+use crate::stdlib::time::Duration;
+use crate::structs::NativeStruct;
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Point in time in UTC, stored as milliseconds since the unix epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateTime {
+    pub millis: i64,
+}
+
+native_struct! {
+    DateTime {
+        millis: i64,
+    }
+    fn year(&self) -> i64;
+    fn month(&self) -> i64;
+    fn day(&self) -> i64;
+    fn hour(&self) -> i64;
+    fn minute(&self) -> i64;
+    fn second(&self) -> i64;
+    fn millisecond(&self) -> i64;
+    fn weekday(&self) -> i64;
+    fn timestamp(&self) -> i64;
+    fn format(&self, fmt: String) -> String;
+    fn add_secs(&self, secs: i64) -> DateTime;
+    fn add_millis(&self, millis: i64) -> DateTime;
+    fn add(&self, duration: Duration) -> DateTime;
+    fn compare(&self, other: DateTime) -> i64;
+    fn is_before(&self, other: DateTime) -> bool;
+    fn is_after(&self, other: DateTime) -> bool;
+    fn equals(&self, other: DateTime) -> bool;
+}
+
+// Conversions between days since the epoch and dates of the proleptic gregorian calendar,
+// after the `days_from_civil` and `civil_from_days` algorithms of Howard Hinnant
+fn _days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn _civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if month <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, month, day)
+}
+
+fn _days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    fn date(&self) -> (i64, i64, i64) {
+        _civil_from_days(self.millis.div_euclid(MILLIS_PER_DAY))
+    }
+
+    /// Milliseconds since the start of the day
+    fn time(&self) -> i64 {
+        self.millis.rem_euclid(MILLIS_PER_DAY)
+    }
+
+    fn year(&self) -> i64 {
+        self.date().0
+    }
+
+    /// Month of the year, from 1 for january
+    fn month(&self) -> i64 {
+        self.date().1
+    }
+
+    fn day(&self) -> i64 {
+        self.date().2
+    }
+
+    fn hour(&self) -> i64 {
+        self.time() / 3_600_000
+    }
+
+    fn minute(&self) -> i64 {
+        self.time() / 60_000 % 60
+    }
+
+    fn second(&self) -> i64 {
+        self.time() / 1000 % 60
+    }
+
+    fn millisecond(&self) -> i64 {
+        self.time() % 1000
+    }
+
+    /// Day of the week, from 1 for monday to 7 for sunday
+    fn weekday(&self) -> i64 {
+        // the epoch was a thursday
+        (self.millis.div_euclid(MILLIS_PER_DAY) + 3).rem_euclid(7) + 1
+    }
+
+    /// Whole seconds since the epoch
+    fn timestamp(&self) -> i64 {
+        self.millis.div_euclid(1000)
+    }
+
+    /// Renders the date with `strftime` like specifiers, see the docs of `std::datetime::parse`.
+    /// `%a` and `%b` additionally print the short names of the weekday and month
+    fn format(&self, fmt: String) -> String {
+        let (year, month, day) = self.date();
+        let mut out = String::new();
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{:04}", year)),
+                Some('m') => out.push_str(&format!("{:02}", month)),
+                Some('d') => out.push_str(&format!("{:02}", day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour())),
+                Some('M') => out.push_str(&format!("{:02}", self.minute())),
+                Some('S') => out.push_str(&format!("{:02}", self.second())),
+                Some('f') => out.push_str(&format!("{:03}", self.millisecond())),
+                Some('a') => out.push_str(WEEKDAYS[self.weekday() as usize - 1]),
+                Some('b') => out.push_str(MONTHS[month as usize - 1]),
+                Some('%') => out.push('%'),
+                Some(other) => panic!("Unknown format specifier %{}!", other),
+                None => panic!("Format {:?} ends with a lone %!", fmt),
+            }
+        }
+        out
+    }
+
+    fn add_secs(&self, secs: i64) -> DateTime {
+        self.add_millis(secs.checked_mul(1000).expect("Date is out of range!"))
+    }
+
+    fn add_millis(&self, millis: i64) -> DateTime {
+        DateTime {
+            millis: self.millis.checked_add(millis).expect("Date is out of range!"),
+        }
+    }
+
+    fn add(&self, duration: Duration) -> DateTime {
+        self.add_millis(duration.millis)
+    }
+
+    /// -1, 0 or 1 if this date is before, at the same time as or after the other one
+    fn compare(&self, other: DateTime) -> i64 {
+        match self.millis.cmp(&other.millis) {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        }
+    }
+
+    fn is_before(&self, other: DateTime) -> bool {
+        self.millis < other.millis
+    }
+
+    fn is_after(&self, other: DateTime) -> bool {
+        self.millis > other.millis
+    }
+
+    fn equals(&self, other: DateTime) -> bool {
+        self.millis == other.millis
+    }
+}
+
+/// Reads up to `max` digits of a field, with an optional sign for years
+fn _parse_field(text: &mut std::iter::Peekable<std::str::Chars>, max: usize, signed: bool) -> Result<i64, String> {
+    let mut digits = String::new();
+    if signed && text.peek() == Some(&'-') {
+        digits.push('-');
+        text.next();
+    }
+    while digits.trim_start_matches('-').len() < max {
+        match text.peek() {
+            Some(c) if c.is_ascii_digit() => digits.push(text.next().unwrap()),
+            _ => break,
+        }
+    }
+    digits.parse::<i64>().map_err(|_| "expected a number".to_string())
+}
+
+fn _parse(fmt: &str, text: &str) -> Result<DateTime, String> {
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let (mut hour, mut minute, mut second, mut milli) = (0, 0, 0, 0);
+    let mut input = text.chars().peekable();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            match input.next() {
+                Some(it) if it == c => continue,
+                _ => return Err(format!("expected {:?}", c)),
+            }
+        }
+        match chars.next() {
+            Some('Y') => year = _parse_field(&mut input, 4, true)?,
+            Some('m') => month = _parse_field(&mut input, 2, false)?,
+            Some('d') => day = _parse_field(&mut input, 2, false)?,
+            Some('H') => hour = _parse_field(&mut input, 2, false)?,
+            Some('M') => minute = _parse_field(&mut input, 2, false)?,
+            Some('S') => second = _parse_field(&mut input, 2, false)?,
+            Some('f') => milli = _parse_field(&mut input, 3, false)?,
+            Some('%') if input.next() == Some('%') => {}
+            Some('%') => return Err("expected '%'".to_string()),
+            Some(other) => return Err(format!("unknown format specifier %{}", other)),
+            None => return Err("the format ends with a lone %".to_string()),
+        }
+    }
+    if input.next().is_some() {
+        return Err("unexpected text after the date".to_string());
+    }
+    if !(1..=12).contains(&month) || !(1..=_days_in_month(year, month)).contains(&day) {
+        return Err(format!("{}-{}-{} is not a valid date", year, month, day));
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(format!("{}:{}:{} is not a valid time", hour, minute, second));
+    }
+    let days = _days_from_civil(year, month, day);
+    Ok(DateTime {
+        millis: days * MILLIS_PER_DAY + ((hour * 60 + minute) * 60 + second) * 1000 + milli,
+    })
+}
+
+/// Current date, which follows the virtual clock of a deterministic VM
+fn now(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    DateTime { millis: vm.entropy().now().as_millis() as i64 }.into_literal()
+}
+
+/// Parses a date in UTC with the specifiers `%Y` (year), `%m` (month), `%d` (day), `%H` (hour),
+/// `%M` (minute), `%S` (second), `%f` (millisecond) and `%%`. Missing parts default to the epoch
+fn parse(params: Parameters) -> Literal {
+    let (fmt, text) = unwrap_args!(params => (String, String));
+    _parse(&fmt, &text)
+        .unwrap_or_else(|err| panic!("Could not parse {:?} as {:?}: {}!", text, fmt, err))
+        .into_literal()
+}
+
+fn from_millis(params: Parameters) -> Literal {
+    let millis = unwrap_args!(params => (Number));
+    DateTime { millis }.into_literal()
+}
+
+fn from_secs(params: Parameters) -> Literal {
+    let secs = unwrap_args!(params => (Number));
+    DateTime { millis: secs.checked_mul(1000).expect("Date is out of range!") }.into_literal()
+}
+
+#[doc(hidden)]
+pub fn __datetime_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::datetime" {
+            extern fn parse(fmt: str, text: str) -> DateTime;
+            extern fn from_millis(millis: num) -> DateTime;
+            extern fn from_secs(secs: num) -> DateTime;
+            native fn now() -> DateTime;
+        }
+    });
+    DateTime::register(visitor);
+}
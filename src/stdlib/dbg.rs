@@ -0,0 +1,44 @@
+use crate::extern_fns;
+use crate::fns::{register_native_fn, Parameters};
+use crate::marshal::{IntoLiteral, MAP_TYPE};
+use crate::structs::StructureInstance;
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+/// Map of every variable, constant and static visible from the current scope, along with
+/// the functions, whose values are their signatures like `fn(a, b) -> num`
+fn scope(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    let mut entries = vm.visible_values();
+    for (name, fnc) in vm.visible_fns() {
+        entries.entry(name).or_insert_with(|| {
+            Literal::String(format!("fn({}) -> {}", fnc.param_names().join(", "), fnc.out_ty()))
+        });
+    }
+    entries
+        .into_iter()
+        .fold(StructureInstance::builder(MAP_TYPE), |builder, (name, value)| builder.field(&name, value))
+        .build()
+        .into_literal()
+}
+
+fn stack(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Literal::Array(vm.stack_values())
+}
+
+fn current_scope(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Literal::String(vm.current_scope_name())
+}
+
+#[doc(hidden)]
+pub fn __dbg_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::dbg" {
+            native fn scope() -> map;
+            native fn stack() -> array;
+        }
+    });
+    // `where` is a keyword of Rust, so it can not be declared by `extern_fns!`
+    let mut dbg = visitor.get_scope("std::dbg".to_string());
+    dbg.export("where");
+    dbg.add_native_fn("where", "str".to_string(), vec![], register_native_fn(current_scope));
+}
@@ -0,0 +1,91 @@
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::{Decimal, Literal, Rounding, MAX_SCALE};
+use crate::visit::Visitor;
+
+fn _places(places: i64) -> u32 {
+    if places < 0 || places > MAX_SCALE as i64 {
+        panic!("Invalid number of decimal places {}, expected 0 to {}!", places, MAX_SCALE)
+    }
+    places as u32
+}
+
+fn _rounded(value: Decimal, places: i64, rounding: Rounding) -> Decimal {
+    value
+        .with_scale(_places(places), rounding)
+        .unwrap_or_else(|| panic!("Decimal {} overflows with {} places!", value, places))
+}
+
+/// Decimal of a str like `"12.50"` or of a num. Floats are converted by their shortest text,
+/// so `0.1` becomes `0.1d` rather than the binary value closest to it
+fn decimal(params: Parameters) -> Literal {
+    let value = params.into_iter().next().unwrap_or(Literal::Void);
+    let text = match value {
+        Literal::Decimal(value) => return Literal::Decimal(value),
+        Literal::Number(num) => return Literal::Decimal(num.into()),
+        Literal::String(str) => str,
+        Literal::Float(float) if float.is_finite() => float.to_string(),
+        other => panic!("Can not convert {} {} to a decimal!", other.this_type(), other),
+    };
+    match text.trim().parse() {
+        Ok(value) => Literal::Decimal(value),
+        Err(err) => panic!("Could not parse {:?} as a decimal: {}", text, err),
+    }
+}
+
+/// Rounds half away from zero, or pads with zeros, to exactly `places` places
+fn round(params: Parameters) -> Literal {
+    let (value, places) = unwrap_args!(params => (Decimal, Number));
+    Literal::Decimal(_rounded(value, places, Rounding::HalfUp))
+}
+
+/// Rounds half to even, which does not drift sums of many rounded values
+fn round_even(params: Parameters) -> Literal {
+    let (value, places) = unwrap_args!(params => (Decimal, Number));
+    Literal::Decimal(_rounded(value, places, Rounding::HalfEven))
+}
+
+fn scale(params: Parameters) -> Literal {
+    let value = unwrap_args!(params => (Decimal));
+    Literal::Number(value.scale() as i64)
+}
+
+fn to_float(params: Parameters) -> Literal {
+    let value = unwrap_args!(params => (Decimal));
+    Literal::Float(value.to_f64())
+}
+
+/// Text of the value rounded half away from zero to `places` places, with its integer
+/// digits grouped by threes with the separator, like `format(1234.5d, 2, ",")` is `1,234.50`
+fn format(params: Parameters) -> Literal {
+    let (value, places, separator) = unwrap_args!(params => (Decimal, Number, String));
+    let text = _rounded(value, places, Rounding::HalfUp).to_string();
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(text) => ("-", text),
+        None => ("", text.as_str()),
+    };
+    let (int, frac) = match text.split_once('.') {
+        Some((int, frac)) => (int, format!(".{}", frac)),
+        None => (text, String::new()),
+    };
+    let groups: Vec<&str> = int
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|it| std::str::from_utf8(it).unwrap())
+        .collect();
+    Literal::String(format!("{}{}{}", sign, groups.join(&separator), frac))
+}
+
+#[doc(hidden)]
+pub fn __decimal_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::decimal" {
+            extern fn decimal(value) -> decimal;
+            extern fn round(value: decimal, places: num) -> decimal;
+            extern fn round_even(value: decimal, places: num) -> decimal;
+            extern fn scale(value: decimal) -> num;
+            extern fn to_float(value: decimal) -> float;
+            extern fn format(value: decimal, places: num, separator: str) -> str;
+        }
+    });
+}
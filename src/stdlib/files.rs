@@ -0,0 +1,106 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use crate::{extern_fns, native_struct, Parameters, unwrap_args};
+use crate::marshal::IntoLiteral;
+use crate::structs::NativeStruct;
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+// Open files are referenced by handles, like string builders, since literals are always passed by value
+type OpenFile = BufReader<std::fs::File>;
+
+/// Handle of a file opened with `std::io::file::open`, which stays open until it is closed
+/// or the Vm is reset
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+    pub handle: i64,
+    pub path: String,
+    pub mode: String,
+}
+
+native_struct! {
+    File {
+        handle: i64,
+        path: String,
+        mode: String,
+    }
+    fn read_line(&self, vm) -> Option<String>;
+    fn write(&self, vm, text: String) -> ();
+    fn seek(&self, vm, pos: i64) -> i64;
+    fn close(&self, vm) -> ();
+}
+
+impl File {
+    fn with_file<T>(&self, vm: &dyn ScopeProvider, action: &str, fun: impl FnOnce(&mut OpenFile) -> std::io::Result<T>) -> T {
+        vm.handles()
+            .with(self.handle, fun)
+            .unwrap_or_else(|| panic!("File {} is not open!", self.path))
+            .unwrap_or_else(|err| panic!("Could not {} {}: {}!", action, self.path, err))
+    }
+
+    /// Next line without its line break, or void once the end of the file is reached
+    fn read_line(&self, vm: &dyn ScopeProvider) -> Option<String> {
+        self.with_file(vm, "read", |file| {
+            let mut line = String::new();
+            if file.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Some(line))
+        })
+    }
+
+    fn write(&self, vm: &dyn ScopeProvider, text: String) {
+        self.with_file(vm, "write", |file| {
+            // moves the file to the position of the reader, which may have read ahead
+            file.stream_position()?;
+            file.get_mut().write_all(text.as_bytes())
+        })
+    }
+
+    /// Moves to the byte `pos` counted from the start, or from the end if it is negative,
+    /// returning the new position
+    fn seek(&self, vm: &dyn ScopeProvider, pos: i64) -> i64 {
+        let pos = if pos < 0 { SeekFrom::End(pos) } else { SeekFrom::Start(pos as u64) };
+        self.with_file(vm, "seek", |file| file.seek(pos)) as i64
+    }
+
+    fn close(&self, vm: &dyn ScopeProvider) {
+        if let Some(mut file) = vm.handles().remove::<OpenFile>(self.handle) {
+            file.get_mut().flush().unwrap_or_else(|err| panic!("Could not close {}: {}!", self.path, err));
+        }
+    }
+}
+
+/// Opens the file at `path` in the `mode` of C's `fopen`: `r`, `w`, `a`, `r+`, `w+` or `a+`
+fn open(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (path, mode) = unwrap_args!(params => (String, String));
+    let mut options = OpenOptions::new();
+    match mode.as_str() {
+        "r" => options.read(true),
+        "w" => options.write(true).create(true).truncate(true),
+        "a" => options.append(true).create(true),
+        "r+" => options.read(true).write(true),
+        "w+" => options.read(true).write(true).create(true).truncate(true),
+        "a+" => options.read(true).append(true).create(true),
+        other => panic!("Unknown file mode {}!", other),
+    };
+    let file = options.open(&path).unwrap_or_else(|err| panic!("Could not open {}: {}!", path, err));
+    let handle = vm.handles().insert(BufReader::new(file));
+    File { handle, path, mode }.into_literal()
+}
+
+#[doc(hidden)]
+pub fn __files_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::io::file" {
+            native fn open(path, mode) -> File;
+        }
+    });
+    File::register(visitor);
+}
@@ -0,0 +1,131 @@
+use crate::{extern_fns, Parameters};
+use crate::fnv::{_fnv1a, FNV_OFFSET};
+use crate::tks::Literal;
+use crate::visit::Visitor;
+use crate::vm::Transmute;
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Raw bytes of strings and byte buffers, other values are hashed in their binary form
+fn _bytes_of(value: &Literal) -> Vec<u8> {
+    match value {
+        Literal::String(str) => str.as_bytes().to_vec(),
+        Literal::Bytes(bytes) => bytes.to_owned(),
+        other => {
+            let mut buf = vec![];
+            other.to_owned().write(&mut buf).unwrap();
+            buf
+        }
+    }
+}
+
+fn _crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+fn _sha256(bytes: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut out = [0u8; 32];
+    for (i, value) in state.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    out
+}
+
+fn fnv(params: Parameters) -> Literal {
+    Literal::Number(_fnv1a(FNV_OFFSET, &_bytes_of(&params[0])) as i64)
+}
+
+fn crc32(params: Parameters) -> Literal {
+    Literal::Number(_crc32(&_bytes_of(&params[0])) as i64)
+}
+
+fn sha256(params: Parameters) -> Literal {
+    let digest = _sha256(&_bytes_of(&params[0]));
+    Literal::String(digest.iter().map(|it| format!("{:02x}", it)).collect())
+}
+
+// Unlike `fnv`, values of different types never share the hashed bytes, e.g. `"1"` and `1`
+fn hash(params: Parameters) -> Literal {
+    let mut buf = vec![];
+    params[0].to_owned().write(&mut buf).unwrap();
+    Literal::Number(_fnv1a(FNV_OFFSET, &buf) as i64)
+}
+
+#[doc(hidden)]
+pub fn __hash_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::hash" {
+            extern fn fnv(value) -> num;
+            extern fn crc32(bytes) -> num;
+            extern fn sha256(bytes) -> str;
+            extern fn hash(value) -> num;
+        }
+    })
+}
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use rand::{Rng, RngCore};
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+const NANOID_ALPHABET: &[u8; 64] = b"_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+// 2020-01-01, which leaves room for 69 years of snowflakes
+const SNOWFLAKE_EPOCH: i64 = 1_577_836_800_000;
+
+// Last snowflake handed out in this process, so ids of the same millisecond still increase
+static LAST_SNOWFLAKE: AtomicI64 = AtomicI64::new(0);
+
+/// Random uuid of version 4, like `f47ac10b-58cc-4372-a567-0e02b2c3d479`
+fn uuid_v4(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    let mut bytes = [0u8; 16];
+    vm.entropy().rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0F) | 0x40;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+    let hex: String = bytes.iter().map(|it| format!("{:02x}", it)).collect();
+    Literal::String(format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..]))
+}
+
+/// Random url safe id of `len` characters, 21 of them are as unlikely to collide as a uuid
+fn nanoid(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let len = unwrap_args!(params => (Number));
+    if len < 0 {
+        panic!("Invalid id length {}!", len)
+    }
+    let mut rng = vm.entropy().rng();
+    Literal::String((0..len).map(|_| NANOID_ALPHABET[rng.gen_range(0..64)] as char).collect())
+}
+
+/// Time ordered id of the milliseconds since 2020 shifted left by 22 bits, where the lower bits
+/// count the ids created in the same millisecond, so they are unique within the process
+fn snowflake(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    let since = (vm.entropy().now().as_millis() as i64 - SNOWFLAKE_EPOCH).max(0);
+    let next = since << 22;
+    let previous = LAST_SNOWFLAKE
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next.max(last + 1)))
+        .unwrap();
+    Literal::Number(next.max(previous + 1))
+}
+
+#[doc(hidden)]
+pub fn __id_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::id" {
+            native fn uuid_v4() -> str;
+            native fn nanoid(len) -> str;
+            native fn snowflake() -> num;
+        }
+    });
+}
@@ -1,20 +1,34 @@
 use std::collections::VecDeque;
 use crate::{extern_fns, Parameters, unwrap_args};
 use crate::tks::Literal;
-use crate::visit::Visitor;
+use crate::visit::{ScopeProvider, Visitor};
 
-fn print(params: Parameters) -> Literal {
+fn print(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
     let val = unwrap_args!(params => (String));
-    print!("{}", val);
+    vm.io().write_out(&val);
     Literal::Void
 }
 
-fn println(params: Parameters) -> Literal {
+fn println(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
     let val = unwrap_args!(params => (String));
-    println!("{}", val);
+    vm.io().write_out(&format!("{}\n", val));
     Literal::Void
 }
 
+fn eprintln(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let val = unwrap_args!(params => (String));
+    vm.io().write_err(&format!("{}\n", val));
+    Literal::Void
+}
+
+// Yields void once the input is exhausted
+fn read_line(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    match vm.io().read_in() {
+        Some(line) => Literal::String(line),
+        None => Literal::Void
+    }
+}
+
 fn fmt(params: Parameters) -> Literal {
     let mut params = VecDeque::from(params);
     let mut pattern = match params.pop_front().unwrap() {
@@ -27,18 +41,24 @@ fn fmt(params: Parameters) -> Literal {
     Literal::String(pattern.to_string())
 }
 
-fn debug(params: Parameters) -> Literal {
+fn debug(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
     let value = params[0].to_owned();
-    match value {
-        Literal::Number(v) => println!("{}", v),
-        Literal::Float(v) => println!("{}", v),
-        Literal::String(v) => println!("{}", v),
-        Literal::Char(v) => println!("{}", v),
-        Literal::Ident(v) => println!("${}", v),
-        Literal::Bool(v) => println!("{}", v),
-        Literal::TypeName(v) => println!("type {}", v),
-        Literal::Void => println!("void")
+    let line = match value {
+        Literal::Number(v) => format!("{}", v),
+        Literal::Float(v) => format!("{}", v),
+        Literal::String(v) => v,
+        Literal::Char(v) => format!("{}", v),
+        Literal::Ident(v) => format!("${}", v),
+        Literal::Bool(v) => format!("{}", v),
+        Literal::TypeName(v) => format!("type {}", v),
+        Literal::Struct(v) => format!("{}", v),
+        Literal::Array(v) => format!("{}", Literal::Array(v)),
+        Literal::Bytes(v) => format!("{}", Literal::Bytes(v)),
+        Literal::Enum(variant, payload) => format!("{}", Literal::Enum(variant, payload)),
+        Literal::Decimal(v) => format!("{}d", v),
+        Literal::Void => "void".to_string()
     };
+    vm.io().write_out(&format!("{}\n", line));
     Literal::Void
 }
 
@@ -46,10 +66,12 @@ fn debug(params: Parameters) -> Literal {
 pub fn __io_feature<V>(visitor: &mut V) where V: Visitor {
     extern_fns!(visitor {
         scope "std::io" {
-            extern fn print(value) -> void;
-            extern fn println(value) -> void;
             extern fn fmt(pattern, varargs) -> str;
-            extern fn debug(value) -> void;
+            native fn print(value) -> void;
+            native fn println(value) -> void;
+            native fn eprintln(value) -> void;
+            native fn read_line() -> unknown;
+            native fn debug(value) -> void;
         }
     });
 }
\ No newline at end of file
@@ -0,0 +1,112 @@
+use std::iter::Peekable;
+use crate::{extern_fns, native_struct, Parameters};
+use crate::marshal::{FromLiteral, IntoLiteral, MAP_TYPE};
+use crate::stdlib::collections;
+use crate::stdlib::range::Range;
+use crate::structs::NativeStruct;
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+// Iterators are referenced by handles, so copies of an iterator advance together
+type Values = Peekable<Box<dyn std::iter::Iterator<Item = Literal> + Send>>;
+
+/// Iterator over the values of a collection, created with `std::iter::iter`. The Vm keeps
+/// the iterator until it is exhausted by `to_array` or freed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Iterator {
+    pub handle: i64,
+}
+
+native_struct! {
+    Iterator {
+        handle: i64,
+    }
+    fn has_next(&self, vm) -> bool;
+    fn next(&self, vm) -> Option<Literal>;
+    fn to_array(&self, vm) -> Vec<Literal>;
+    fn free(&self, vm) -> ();
+}
+
+impl Iterator {
+    /// Registers an iterator over the values, which are only produced once they are needed
+    pub fn new<I>(vm: &dyn ScopeProvider, values: I) -> Self
+    where
+        I: std::iter::Iterator<Item = Literal> + Send + 'static,
+    {
+        let values: Box<dyn std::iter::Iterator<Item = Literal> + Send> = Box::new(values);
+        Self { handle: vm.handles().insert(values.peekable()) }
+    }
+
+    fn with_values<T>(&self, vm: &dyn ScopeProvider, fun: impl FnOnce(&mut Values) -> T) -> T {
+        vm.handles()
+            .with(self.handle, fun)
+            .unwrap_or_else(|| panic!("Iterator {} does not exist!", self.handle))
+    }
+
+    fn has_next(&self, vm: &dyn ScopeProvider) -> bool {
+        self.with_values(vm, |it| it.peek().is_some())
+    }
+
+    /// Next value, or void once the iterator is exhausted
+    fn next(&self, vm: &dyn ScopeProvider) -> Option<Literal> {
+        self.with_values(vm, |it| it.next())
+    }
+
+    /// Remaining values, which exhausts the iterator
+    fn to_array(&self, vm: &dyn ScopeProvider) -> Vec<Literal> {
+        // the iterator is dropped, as nothing can be read from it anymore
+        vm.handles()
+            .remove::<Values>(self.handle)
+            .map(|it| it.collect())
+            .unwrap_or_default()
+    }
+
+    fn free(&self, vm: &dyn ScopeProvider) {
+        vm.handles().remove::<Values>(self.handle);
+    }
+}
+
+/// Values of arrays and sets, the chars of strings, `[key, value]` entries of maps sorted by
+/// their keys, values of deques from the front, values of stacks from the bottom and the
+/// numbers of ranges. Iterators are passed through, so they can be iterated from where they are
+fn iter(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let value = params.into_iter().next().unwrap_or(Literal::Void);
+    let values: Vec<Literal> = match value {
+        Literal::Array(values) => values,
+        Literal::String(str) => str.chars().map(Literal::Char).collect(),
+        Literal::Struct(instance) => match instance.type_name().as_str() {
+            "Iterator" => return Literal::Struct(instance),
+            // ranges are not collected, they may be long
+            "Range" => {
+                let range = Range::from_literal(Literal::Struct(instance))
+                    .unwrap_or_else(|err| panic!("Invalid range: {}!", err));
+                return Iterator::new(vm, range.values().map(Literal::Number)).into_literal();
+            }
+            MAP_TYPE => instance
+                .field_names()
+                .into_iter()
+                .map(|name| {
+                    let value = instance.get_field(&name).unwrap_or(Literal::Void);
+                    Literal::Array(vec![Literal::String(name), value])
+                })
+                .collect(),
+            "Deque" | "Stack" => match instance.get_field("handle") {
+                Some(Literal::Number(handle)) => collections::_values(vm, handle),
+                _ => panic!("Value of type {} is not iterable!", instance.type_name()),
+            },
+            other => panic!("Value of type {} is not iterable!", other),
+        },
+        other => panic!("Value of type {} is not iterable!", other.this_type()),
+    };
+    Iterator::new(vm, values.into_iter()).into_literal()
+}
+
+#[doc(hidden)]
+pub fn __iter_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::iter" {
+            native fn iter(value) -> Iterator;
+        }
+    });
+    Iterator::register(visitor);
+}
@@ -0,0 +1,68 @@
+use std::fmt::{Display, Formatter};
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+/// Severity of a message logged by a program through `std::log`
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        })
+    }
+}
+
+/// Receives messages logged by programs, see [`Vm::set_logger`](crate::visit::Vm::set_logger)
+pub type Logger = dyn Fn(Level, &str) + Send + Sync;
+
+fn _log(vm: &mut dyn ScopeProvider, level: Level, params: Parameters) -> Literal {
+    let msg = unwrap_args!(params => (String));
+    vm.log(level, &msg);
+    Literal::Void
+}
+
+fn trace(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _log(vm, Level::Trace, params)
+}
+
+fn debug(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _log(vm, Level::Debug, params)
+}
+
+fn info(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _log(vm, Level::Info, params)
+}
+
+fn warn(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _log(vm, Level::Warn, params)
+}
+
+fn error(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _log(vm, Level::Error, params)
+}
+
+#[doc(hidden)]
+pub fn __log_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::log" {
+            native fn trace(msg) -> void;
+            native fn debug(msg) -> void;
+            native fn info(msg) -> void;
+            native fn warn(msg) -> void;
+            native fn error(msg) -> void;
+        }
+    });
+}
@@ -57,6 +57,93 @@ fn tan(params: Parameters) -> Literal {
     Literal::Float(val.tan())
 }
 
+// Arrays of nums stay nums, as soon as one of their values is a float they are all floats
+enum Numbers {
+    Nums(Vec<i64>),
+    Floats(Vec<f64>),
+}
+
+// Floats are summed in independent lanes, so the compiler can vectorize the loop
+const LANES: usize = 8;
+
+fn _numbers(values: Vec<Literal>) -> Numbers {
+    let nums: Option<Vec<i64>> = values.iter().map(|it| match it {
+        Literal::Number(num) => Some(*num),
+        _ => None,
+    }).collect();
+    if let Some(nums) = nums {
+        return Numbers::Nums(nums);
+    }
+    Numbers::Floats(values.into_iter().map(|it| match it {
+        Literal::Number(num) => num as f64,
+        Literal::Float(float) => float,
+        other => panic!("Expected an array of numbers, got {}!", other),
+    }).collect())
+}
+
+fn _floats(numbers: Numbers) -> Vec<f64> {
+    match numbers {
+        Numbers::Nums(nums) => nums.into_iter().map(|it| it as f64).collect(),
+        Numbers::Floats(floats) => floats,
+    }
+}
+
+fn _lanes(lh: &[f64], rh: &[f64], fun: impl Fn(f64, f64) -> f64) -> f64 {
+    let mut lanes = [0.0; LANES];
+    let (lh_chunks, rh_chunks) = (lh.chunks_exact(LANES), rh.chunks_exact(LANES));
+    let rest: f64 = lh_chunks.remainder().iter().zip(rh_chunks.remainder()).map(|(l, r)| fun(*l, *r)).sum();
+    for (lh, rh) in lh_chunks.zip(rh_chunks) {
+        for ((lane, l), r) in lanes.iter_mut().zip(lh).zip(rh) {
+            *lane += fun(*l, *r);
+        }
+    }
+    lanes.iter().sum::<f64>() + rest
+}
+
+// Applies the operation to the values at the same positions of both arrays
+fn _zip(params: Parameters, op: &str, nums: fn(i64, i64) -> i64, floats: fn(f64, f64) -> f64) -> Literal {
+    let (lh, rh) = unwrap_args!(params => (Array, Array));
+    if lh.len() != rh.len() {
+        panic!("Can not {} arrays of length {} and {}!", op, lh.len(), rh.len())
+    }
+    match (_numbers(lh), _numbers(rh)) {
+        (Numbers::Nums(lh), Numbers::Nums(rh)) => {
+            Literal::Array(lh.iter().zip(&rh).map(|(l, r)| Literal::Number(nums(*l, *r))).collect())
+        }
+        (lh, rh) => {
+            let (lh, rh) = (_floats(lh), _floats(rh));
+            Literal::Array(lh.iter().zip(&rh).map(|(l, r)| Literal::Float(floats(*l, *r))).collect())
+        }
+    }
+}
+
+fn vec_add(params: Parameters) -> Literal {
+    _zip(params, "add", |l, r| l + r, |l, r| l + r)
+}
+
+fn vec_mul(params: Parameters) -> Literal {
+    _zip(params, "multiply", |l, r| l * r, |l, r| l * r)
+}
+
+fn vec_sum(params: Parameters) -> Literal {
+    let values = unwrap_args!(params => (Array));
+    match _numbers(values) {
+        Numbers::Nums(nums) => Literal::Number(nums.iter().sum()),
+        Numbers::Floats(floats) => Literal::Float(_lanes(&floats, &floats, |l, _| l)),
+    }
+}
+
+fn vec_dot(params: Parameters) -> Literal {
+    let (lh, rh) = unwrap_args!(params => (Array, Array));
+    if lh.len() != rh.len() {
+        panic!("Can not multiply arrays of length {} and {}!", lh.len(), rh.len())
+    }
+    match (_numbers(lh), _numbers(rh)) {
+        (Numbers::Nums(lh), Numbers::Nums(rh)) => Literal::Number(lh.iter().zip(&rh).map(|(l, r)| l * r).sum()),
+        (lh, rh) => Literal::Float(_lanes(&_floats(lh), &_floats(rh), |l, r| l * r)),
+    }
+}
+
 #[doc(hidden)]
 pub fn __math_feature<V>(visitor: &mut V) where V: Visitor {
     extern_fns!(visitor {
@@ -74,6 +161,11 @@ pub fn __math_feature<V>(visitor: &mut V) where V: Visitor {
             extern fn sin(value) -> float;
             extern fn cos(value) -> float;
             extern fn tan(value) -> float;
+
+            extern fn vec_add(first: array, second: array) -> array;
+            extern fn vec_mul(first: array, second: array) -> array;
+            extern fn vec_sum(values: array) -> unknown;
+            extern fn vec_dot(first: array, second: array) -> unknown;
         }
     })
 }
\ No newline at end of file
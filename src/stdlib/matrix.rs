@@ -0,0 +1,132 @@
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::Visitor;
+
+// Matrices are arrays of rows, which are arrays of the same length. Arithmetic keeps nums as
+// nums, and only turns into floats once one of the multiplied values is a float
+fn _rows(matrix: Vec<Literal>) -> Vec<Vec<Literal>> {
+    let rows: Vec<Vec<Literal>> = matrix
+        .into_iter()
+        .map(|row| match row {
+            Literal::Array(values) => values,
+            other => panic!("Expected a row of a matrix, got {}!", other),
+        })
+        .collect();
+    if let Some(first) = rows.first() {
+        if let Some(row) = rows.iter().find(|it| it.len() != first.len()) {
+            panic!("Rows of a matrix must have the same length, got {} and {}!", first.len(), row.len())
+        }
+    }
+    rows
+}
+
+fn _columns(rows: &[Vec<Literal>]) -> usize {
+    rows.first().map(Vec::len).unwrap_or(0)
+}
+
+fn _matrix(rows: Vec<Vec<Literal>>) -> Literal {
+    Literal::Array(rows.into_iter().map(Literal::Array).collect())
+}
+
+fn _float(value: &Literal) -> f64 {
+    match value {
+        Literal::Number(num) => *num as f64,
+        Literal::Float(float) => *float,
+        other => panic!("Expected a number in a matrix, got {}!", other),
+    }
+}
+
+fn _mul_add(sum: Literal, lh: &Literal, rh: &Literal) -> Literal {
+    match (sum, lh, rh) {
+        (Literal::Number(sum), Literal::Number(lh), Literal::Number(rh)) => Literal::Number(sum + lh * rh),
+        (sum, lh, rh) => Literal::Float(_float(&sum) + _float(lh) * _float(rh)),
+    }
+}
+
+// Negative indices count from the end, like when indexing an array
+fn _index(index: i64, len: usize, what: &str) -> usize {
+    let at = if index < 0 { index + len as i64 } else { index };
+    if at < 0 || at >= len as i64 {
+        panic!("{} {} is out of bounds for a matrix with {} {}s!", what, index, len, what.to_lowercase())
+    }
+    at as usize
+}
+
+/// Square matrix of nums with ones on its diagonal
+fn identity(params: Parameters) -> Literal {
+    let size = unwrap_args!(params => (Number));
+    if size < 0 {
+        panic!("Invalid matrix size {}!", size)
+    }
+    let size = size as usize;
+    _matrix(
+        (0..size)
+            .map(|row| (0..size).map(|col| Literal::Number((row == col) as i64)).collect())
+            .collect(),
+    )
+}
+
+fn transpose(params: Parameters) -> Literal {
+    let matrix = unwrap_args!(params => (Array));
+    let rows = _rows(matrix);
+    let mut columns = vec![Vec::with_capacity(rows.len()); _columns(&rows)];
+    for row in rows {
+        for (column, value) in columns.iter_mut().zip(row) {
+            column.push(value);
+        }
+    }
+    _matrix(columns)
+}
+
+fn mat_mul(params: Parameters) -> Literal {
+    let (first, second) = unwrap_args!(params => (Array, Array));
+    let (first, second) = (_rows(first), _rows(second));
+    let (shared, columns) = (_columns(&first), _columns(&second));
+    if shared != second.len() {
+        panic!(
+            "Can not multiply a {}x{} matrix by a {}x{} matrix!",
+            first.len(), shared, second.len(), columns
+        )
+    }
+    _matrix(
+        first
+            .iter()
+            .map(|row| {
+                (0..columns)
+                    .map(|col| {
+                        row.iter()
+                            .zip(&second)
+                            .fold(Literal::Number(0), |sum, (lh, rh)| _mul_add(sum, lh, &rh[col]))
+                    })
+                    .collect()
+            })
+            .collect(),
+    )
+}
+
+fn row(params: Parameters) -> Literal {
+    let (matrix, index) = unwrap_args!(params => (Array, Number));
+    let mut rows = _rows(matrix);
+    let at = _index(index, rows.len(), "Row");
+    Literal::Array(rows.swap_remove(at))
+}
+
+fn column(params: Parameters) -> Literal {
+    let (matrix, index) = unwrap_args!(params => (Array, Number));
+    let rows = _rows(matrix);
+    let at = _index(index, _columns(&rows), "Column");
+    Literal::Array(rows.into_iter().map(|mut row| row.swap_remove(at)).collect())
+}
+
+#[doc(hidden)]
+pub fn __matrix_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::math::matrix" {
+            extern fn identity(size: num) -> array;
+            extern fn transpose(matrix: array) -> array;
+            extern fn mat_mul(first: array, second: array) -> array;
+            extern fn row(matrix: array, index: num) -> array;
+            extern fn column(matrix: array, index: num) -> array;
+        }
+    });
+}
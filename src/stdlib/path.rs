@@ -0,0 +1,66 @@
+use std::path::Path;
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::Visitor;
+
+fn _path_str(path: &Path) -> Literal {
+    Literal::String(path.to_string_lossy().into_owned())
+}
+
+// Yields void if the path has no such part
+fn _path_part(part: Option<&std::ffi::OsStr>) -> Literal {
+    match part {
+        Some(part) => Literal::String(part.to_string_lossy().into_owned()),
+        None => Literal::Void,
+    }
+}
+
+fn join(params: Parameters) -> Literal {
+    let (base, path) = unwrap_args!(params => (String, String));
+    _path_str(&Path::new(&base).join(path))
+}
+
+fn parent(params: Parameters) -> Literal {
+    let path = unwrap_args!(params => (String));
+    match Path::new(&path).parent() {
+        Some(parent) => _path_str(parent),
+        None => Literal::Void,
+    }
+}
+
+fn file_name(params: Parameters) -> Literal {
+    let path = unwrap_args!(params => (String));
+    _path_part(Path::new(&path).file_name())
+}
+
+fn extension(params: Parameters) -> Literal {
+    let path = unwrap_args!(params => (String));
+    _path_part(Path::new(&path).extension())
+}
+
+fn canonicalize(params: Parameters) -> Literal {
+    let path = unwrap_args!(params => (String));
+    match Path::new(&path).canonicalize() {
+        Ok(path) => _path_str(&path),
+        Err(err) => panic!("Could not canonicalize {}: {}!", path, err),
+    }
+}
+
+fn is_absolute(params: Parameters) -> Literal {
+    let path = unwrap_args!(params => (String));
+    Literal::Bool(Path::new(&path).is_absolute())
+}
+
+#[doc(hidden)]
+pub fn __path_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::path" {
+            extern fn join(base: str, path: str) -> str;
+            extern fn parent(path: str) -> unknown;
+            extern fn file_name(path: str) -> unknown;
+            extern fn extension(path: str) -> unknown;
+            extern fn canonicalize(path: str) -> str;
+            extern fn is_absolute(path: str) -> bool;
+        }
+    })
+}
@@ -15,9 +15,11 @@ pub fn __prelude_features<V>(visitor: &mut V) where V: Visitor {
     visitor.add_std_feature(StdFeature::IO);
     visitor.add_std_feature(StdFeature::Strings);
     visitor.add_std_feature(StdFeature::Math);
+    visitor.add_std_feature(StdFeature::Time);
 
     exports!(visitor => "std::io"[print, println, debug, fmt]);
     exports!(visitor => "std::math"[min, max, pow, cmp]);
     exports!(visitor => "std::str"[stringify]);
     exports!(visitor => "std"[exit, panic, sleep]);
+    exports!(visitor => "std::time"[millis, secs, mins]);
 }
\ No newline at end of file
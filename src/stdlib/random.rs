@@ -0,0 +1,32 @@
+use rand::Rng;
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+fn random(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Literal::Float(vm.entropy().rng().gen())
+}
+
+// Upper bound is exclusive
+fn random_range(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (from, to) = unwrap_args!(params => (Number, Number));
+    if from >= to {
+        panic!("Invalid random range {}..{}!", from, to)
+    }
+    Literal::Number(vm.entropy().rng().gen_range(from..to))
+}
+
+fn random_bool(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Literal::Bool(vm.entropy().rng().gen())
+}
+
+#[doc(hidden)]
+pub fn __rand_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::rand" {
+            native fn random() -> float;
+            native fn random_range(from, to) -> num;
+            native fn random_bool() -> bool;
+        }
+    });
+}
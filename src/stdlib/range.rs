@@ -0,0 +1,80 @@
+use crate::{extern_fns, native_struct, Parameters, unwrap_args};
+use crate::marshal::IntoLiteral;
+use crate::stdlib::iter::Iterator;
+use crate::structs::NativeStruct;
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+/// Numbers from `start` up to `end`, which is only part of the range if it is `inclusive`.
+/// Written as `start..end` or `start..=end`, its numbers are only produced when iterated
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+native_struct! {
+    Range {
+        start: i64,
+        end: i64,
+        inclusive: bool,
+    }
+    fn contains(&self, value: Literal) -> bool;
+    fn len(&self) -> i64;
+    fn to_array(&self) -> Vec<i64>;
+    fn iter(&self, vm) -> Iterator;
+}
+
+impl Range {
+    /// Numbers of the range, ranges that end before they start are empty
+    pub fn values(&self) -> std::ops::Range<i64> {
+        let end = if self.inclusive { self.end.saturating_add(1) } else { self.end };
+        self.start..end.max(self.start)
+    }
+
+    /// Whether the number is in the range, floats between its bounds are too
+    fn contains(&self, value: Literal) -> bool {
+        let (start, end) = (self.start as f64, self.end as f64);
+        let value = match value {
+            Literal::Number(num) => num as f64,
+            Literal::Float(float) => float,
+            other => panic!("Expected a number to look for in a range, got {}!", other),
+        };
+        value >= start && if self.inclusive { value <= end } else { value < end }
+    }
+
+    fn len(&self) -> i64 {
+        let values = self.values();
+        values.end - values.start
+    }
+
+    fn to_array(&self) -> Vec<i64> {
+        self.values().collect()
+    }
+
+    fn iter(&self, vm: &dyn ScopeProvider) -> Iterator {
+        Iterator::new(vm, self.values().map(Literal::Number))
+    }
+}
+
+fn range(params: Parameters) -> Literal {
+    let (start, end) = unwrap_args!(params => (Number, Number));
+    Range { start, end, inclusive: false }.into_literal()
+}
+
+fn range_inclusive(params: Parameters) -> Literal {
+    let (start, end) = unwrap_args!(params => (Number, Number));
+    Range { start, end, inclusive: true }.into_literal()
+}
+
+#[doc(hidden)]
+pub fn __range_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::range" {
+            extern fn range(start: num, end: num) -> Range;
+            extern fn range_inclusive(start: num, end: num) -> Range;
+        }
+    });
+    Range::register(visitor);
+}
@@ -0,0 +1,74 @@
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::{Literal, Token};
+use crate::visit::{ScopeProvider, Visitor};
+
+fn type_of(params: Parameters) -> Literal {
+    Literal::TypeName(params.first().unwrap().this_type())
+}
+
+fn fields_of(params: Parameters) -> Literal {
+    let value = unwrap_args!(params => (Struct));
+    Literal::Array(value.field_names().into_iter().map(Literal::String).collect())
+}
+
+fn has_field(params: Parameters) -> Literal {
+    let (value, name) = unwrap_args!(params => (Struct, String));
+    Literal::Bool(value.get_field(&name).is_some())
+}
+
+fn get_field_dynamic(params: Parameters) -> Literal {
+    let (value, name) = unwrap_args!(params => (Struct, String));
+    value
+        .get_field(&name)
+        .unwrap_or_else(|| panic!("Structure {} has no field {}!", value.type_name(), name))
+}
+
+fn functions_of(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let name = unwrap_args!(params => (TypeName));
+    let template = vm.resolve_type(&name).unwrap();
+    Literal::Array(template.inst_fn_names().into_iter().map(Literal::String).collect())
+}
+
+fn call_dynamic(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (name, args) = unwrap_args!(params => (String, Array));
+    vm.call_static_fn(name, args.into_iter().map(Token::Literal).collect())
+}
+
+fn fn_meta(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (name, key) = unwrap_args!(params => (String, String));
+    let fnc = vm.resolve_fn(&name).unwrap();
+    fnc.meta().remove(&key).unwrap_or(Literal::Void)
+}
+
+fn type_meta(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (name, key) = unwrap_args!(params => (TypeName, String));
+    let template = vm.resolve_type(&name).unwrap();
+    template.meta().get(&key).cloned().unwrap_or(Literal::Void)
+}
+
+fn inst_fn_meta(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let (name, fnc, key) = unwrap_args!(params => (TypeName, String, String));
+    let template = vm.resolve_type(&name).unwrap();
+    let fnc = template
+        .get_inst_fn(&fnc)
+        .unwrap_or_else(|| panic!("Could not find instance function {} in structure {}!", fnc, name));
+    fnc.meta().get(&key).cloned().unwrap_or(Literal::Void)
+}
+
+#[doc(hidden)]
+pub fn __reflect_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::reflect" {
+            extern fn type_of(value) -> typename;
+            extern fn fields_of(value) -> array;
+            extern fn has_field(value, name) -> bool;
+            extern fn get_field_dynamic(value, name) -> unknown;
+
+            native fn functions_of(ty) -> array;
+            native fn call_dynamic(name, args) -> unknown;
+            native fn fn_meta(name, key) -> unknown;
+            native fn type_meta(ty, key) -> unknown;
+            native fn inst_fn_meta(ty, name, key) -> unknown;
+        }
+    });
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::tks::Literal;
+use crate::visit::Visitor;
+
+// Sets are arrays without duplicates, which keep the order their values were first seen in.
+// Literals can not be hashed because of floats, so values are hashed by a key of their contents
+fn _key(value: &Literal, out: &mut String) {
+    match value {
+        Literal::Array(values) => {
+            out.push('[');
+            for value in values {
+                _key(value, out);
+                out.push(',');
+            }
+            out.push(']');
+        }
+        // fields are not ordered, so they are keyed by their sorted names
+        Literal::Struct(instance) => {
+            out.push_str(&instance.type_name());
+            out.push('{');
+            for name in instance.field_names() {
+                out.push_str(&name);
+                out.push(':');
+                if let Some(field) = instance.get_field(&name) {
+                    _key(&field, out);
+                }
+                out.push(',');
+            }
+            out.push('}');
+        }
+        other => out.push_str(&format!("{:?}", other)),
+    }
+}
+
+fn _keyed(value: &Literal) -> String {
+    let mut key = String::new();
+    _key(value, &mut key);
+    key
+}
+
+fn _keys(values: &[Literal]) -> HashSet<String> {
+    values.iter().map(_keyed).collect()
+}
+
+// Values of the array that pass the filter, without duplicates
+fn _unique(values: Vec<Literal>, filter: impl Fn(&str) -> bool) -> Literal {
+    let mut seen = HashSet::new();
+    Literal::Array(
+        values
+            .into_iter()
+            .filter(|it| {
+                let key = _keyed(it);
+                filter(&key) && seen.insert(key)
+            })
+            .collect(),
+    )
+}
+
+fn set_new(params: Parameters) -> Literal {
+    let values = unwrap_args!(params => (Array));
+    _unique(values, |_| true)
+}
+
+fn set_union(params: Parameters) -> Literal {
+    let (mut first, second) = unwrap_args!(params => (Array, Array));
+    first.extend(second);
+    _unique(first, |_| true)
+}
+
+fn set_intersect(params: Parameters) -> Literal {
+    let (first, second) = unwrap_args!(params => (Array, Array));
+    let second = _keys(&second);
+    _unique(first, |it| second.contains(it))
+}
+
+// Values of the first set that are not in the second one
+fn set_diff(params: Parameters) -> Literal {
+    let (first, second) = unwrap_args!(params => (Array, Array));
+    let second = _keys(&second);
+    _unique(first, |it| !second.contains(it))
+}
+
+fn set_contains(mut params: Parameters) -> Literal {
+    let value = params.remove(1);
+    let set = unwrap_args!(params => (Array));
+    Literal::Bool(set.contains(&value))
+}
+
+#[doc(hidden)]
+pub fn __set_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::set" {
+            extern fn set_new(values: array) -> array;
+            extern fn set_union(first: array, second: array) -> array;
+            extern fn set_intersect(first: array, second: array) -> array;
+            extern fn set_diff(first: array, second: array) -> array;
+            extern fn set_contains(set: array, value) -> bool;
+        }
+    });
+}
@@ -1,16 +1,136 @@
-use crate::{extern_fns, Parameters};
+use crate::{asm, extern_fns, Parameters, unwrap_args};
 use crate::tks::Literal;
-use crate::visit::Visitor;
+use crate::visit::{ScopeProvider, Visitor};
+
+// Builders are referenced by handles, since literals are always passed by value
+struct Builder(String);
 
 fn stringify(params: Parameters) -> Literal {
     Literal::String(params.get(0).unwrap().to_string())
 }
 
+fn _char_range(str: &str, from: i64, to: i64) -> (usize, usize) {
+    let len = str.chars().count();
+    if from < 0 || to < from || to as usize > len {
+        panic!("Invalid char range {}..{} for string of {} chars!", from, to, len)
+    }
+    (from as usize, to as usize)
+}
+
+fn chars(params: Parameters) -> Literal {
+    let str = unwrap_args!(params => (String));
+    Literal::Array(str.chars().map(Literal::Char).collect())
+}
+
+fn char_at(params: Parameters) -> Literal {
+    let (str, index) = unwrap_args!(params => (String, Number));
+    let (from, _) = _char_range(&str, index, index);
+    match str.chars().nth(from) {
+        Some(ch) => Literal::Char(ch),
+        None => panic!("Char index {} is out of bounds for string {:?}!", index, str),
+    }
+}
+
+fn len_chars(params: Parameters) -> Literal {
+    let str = unwrap_args!(params => (String));
+    Literal::Number(str.chars().count() as i64)
+}
+
+fn byte_len(params: Parameters) -> Literal {
+    let str = unwrap_args!(params => (String));
+    Literal::Number(str.len() as i64)
+}
+
+fn substr(params: Parameters) -> Literal {
+    let (str, from, to) = unwrap_args!(params => (String, Number, Number));
+    let (from, to) = _char_range(&str, from, to);
+    Literal::String(str.chars().skip(from).take(to - from).collect())
+}
+
+fn byte_substr(params: Parameters) -> Literal {
+    let (str, from, to) = unwrap_args!(params => (String, Number, Number));
+    if from < 0 || to < from || to as usize > str.len() {
+        panic!("Invalid byte range {}..{} for string of {} bytes!", from, to, str.len())
+    }
+    let (from, to) = (from as usize, to as usize);
+    if !str.is_char_boundary(from) || !str.is_char_boundary(to) {
+        panic!("Byte range {}..{} does not lie on char boundaries of {:?}!", from, to, str)
+    }
+    Literal::String(str[from..to].to_string())
+}
+
+fn escape(params: Parameters) -> Literal {
+    let str = unwrap_args!(params => (String));
+    Literal::String(asm::escape(&str))
+}
+
+fn unescape(params: Parameters) -> Literal {
+    let str = unwrap_args!(params => (String));
+    match asm::unescape(&str) {
+        Ok(value) => Literal::String(value),
+        Err(err) => panic!("Invalid escapes in {:?}: {}", str, err),
+    }
+}
+
+fn _with_builder<T>(vm: &dyn ScopeProvider, params: &Parameters, fun: impl FnOnce(&mut String) -> T) -> T {
+    let handle = unwrap_args!(params => (Number));
+    vm.handles()
+        .with(handle, |builder: &mut Builder| fun(&mut builder.0))
+        .unwrap_or_else(|| panic!("Invalid string builder handle {}!", handle))
+}
+
+fn builder_new(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Literal::Number(vm.handles().insert(Builder(String::new())))
+}
+
+fn builder_push(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    _with_builder(vm, &params, |builder| match &params[1] {
+        Literal::String(str) => builder.push_str(str),
+        other => builder.push_str(&other.to_string()),
+    });
+    Literal::Void
+}
+
+fn builder_len(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    Literal::Number(_with_builder(vm, &params, |builder| builder.len() as i64))
+}
+
+/// Text of the builder, which is freed by building it
+fn builder_build(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let handle = unwrap_args!(params => (Number));
+    match vm.handles().remove::<Builder>(handle) {
+        Some(builder) => Literal::String(builder.0),
+        None => panic!("Invalid string builder handle {}!", handle),
+    }
+}
+
+/// Drops a builder without building it
+fn builder_free(vm: &mut dyn ScopeProvider, params: Parameters) -> Literal {
+    let handle = unwrap_args!(params => (Number));
+    vm.handles().remove::<Builder>(handle);
+    Literal::Void
+}
+
 #[doc(hidden)]
 pub fn __str_feature<V>(visitor: &mut V) where V: Visitor {
     extern_fns!(visitor {
         scope "std::str" {
             extern fn stringify(value) -> str;
+
+            extern fn chars(str: str) -> array;
+            extern fn char_at(str: str, index: num) -> char;
+            extern fn len_chars(str: str) -> num;
+            extern fn byte_len(str: str) -> num;
+            extern fn substr(str: str, from: num, to: num) -> str;
+            extern fn byte_substr(str: str, from: num, to: num) -> str;
+            extern fn escape(str: str) -> str;
+            extern fn unescape(str: str) -> str;
+
+            native fn builder_new() -> num;
+            native fn builder_push(builder, value) -> void;
+            native fn builder_len(builder) -> num;
+            native fn builder_build(builder) -> str;
+            native fn builder_free(builder) -> void;
         }
     })
-}
\ No newline at end of file
+}
@@ -0,0 +1,138 @@
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use crate::{extern_fns, Parameters, unwrap_args};
+use crate::fns::{_call_chain, import_globals};
+use crate::tks::{Literal, TokenChain};
+use crate::var::ContainingScope;
+use crate::visit::{Scope, Visitor};
+
+/// A `test "name" { ... }` block collected by the Vm, see [`Vm::run_tests`](crate::visit::Vm::run_tests)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    /// Scope the test was declared in
+    pub scope: String,
+    pub chain: TokenChain,
+}
+
+/// Outcome of a single test
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// Panic message of a failed test, `None` if it passed
+    pub failure: Option<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+impl Display for TestResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.failure {
+            None => write!(f, "test {} ... ok", self.name),
+            Some(msg) => write!(f, "test {} ... FAILED: {}", self.name, msg),
+        }
+    }
+}
+
+/// Outcomes of all tests in the order they were declared
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|it| it.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0
+    }
+}
+
+impl Display for TestReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for result in &self.results {
+            writeln!(f, "{}", result)?;
+        }
+        write!(f, "test result: {}. {} passed; {} failed",
+               if self.is_success() { "ok" } else { "FAILED" }, self.passed(), self.failed())
+    }
+}
+
+/// Runs the test body like a function without parameters, which sees everything
+/// declared in and imported into the scope of the test
+pub(crate) fn _run_test<V>(visitor: &mut V, test: &TestCase) where V: Visitor {
+    let mut scope = ContainingScope::new();
+    import_globals(&mut scope, visitor);
+    let mut declaring = visitor.get_scope(test.scope.clone());
+    if test.scope != "global" {
+        for (from, imports) in declaring.imports() {
+            for import in imports {
+                scope.import(&from, &import);
+            }
+        }
+    }
+    let declared = declaring.static_fns().map(|(name, _)| name.to_owned()).collect::<Vec<_>>();
+    for name in declared.into_iter().chain(declaring.declared_values().into_keys()) {
+        scope.import(&test.scope, &name);
+    }
+    drop(declaring);
+
+    let name = format!("test_0x{:2x}", visitor.entropy().next_id());
+    _call_chain(visitor, Scope::StaticFunction, name, scope, &test.chain);
+}
+
+/// Message of a caught panic, as raised by `panic!` or a failed assertion
+pub(crate) fn _panic_message(err: &(dyn Any + Send)) -> String {
+    match err.downcast_ref::<String>() {
+        Some(msg) => msg.to_owned(),
+        None => err.downcast_ref::<&str>().map(|it| it.to_string()).unwrap_or_else(|| "unknown panic".to_string())
+    }
+}
+
+fn assert(params: Parameters) -> Literal {
+    let (cond, msg) = unwrap_args!(params => (Bool, String));
+    if !cond {
+        panic!("Assertion failed: {}", msg)
+    }
+    Literal::Void
+}
+
+fn assert_eq(params: Parameters) -> Literal {
+    if params[0] != params[1] {
+        panic!("Assertion failed: {} != {}", params[0], params[1])
+    }
+    Literal::Void
+}
+
+// Type names are accepted both as strings and as type literals
+fn assert_type(params: Parameters) -> Literal {
+    let ty = match &params[1] {
+        Literal::String(ty) | Literal::TypeName(ty) => ty,
+        other => panic!("Expected a type name, got {}!", other)
+    };
+    if !params[0].type_str(ty) {
+        panic!("Assertion failed: expected value of type {}, got {} {}", ty, params[0].this_type(), params[0])
+    }
+    Literal::Void
+}
+
+#[doc(hidden)]
+pub fn __test_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::test" {
+            extern fn assert(cond, msg) -> void;
+            extern fn assert_eq(a, b) -> void;
+            extern fn assert_type(value, ty) -> void;
+        }
+    });
+}
@@ -0,0 +1,99 @@
+use crate::{extern_fns, native_struct, Parameters, unwrap_args};
+use crate::marshal::IntoLiteral;
+use crate::structs::NativeStruct;
+use crate::tks::Literal;
+use crate::visit::{ScopeProvider, Visitor};
+
+/// Span of time created with `std::time::millis`, `secs` or `mins`, which `std::sleep` waits for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duration {
+    pub millis: i64,
+}
+
+native_struct! {
+    Duration {
+        millis: i64,
+    }
+    fn as_millis(&self) -> i64;
+    fn as_secs(&self) -> i64;
+    fn as_mins(&self) -> i64;
+    fn plus(&self, other: Duration) -> Duration;
+}
+
+impl Duration {
+    /// Duration of `amount` times `unit` milliseconds, validated so it is neither negative nor overflows
+    fn of(amount: i64, unit: i64, name: &str) -> Self {
+        if amount < 0 {
+            panic!("Invalid duration of {} {}, durations can not be negative!", amount, name)
+        }
+        match amount.checked_mul(unit) {
+            Some(millis) => Self { millis },
+            None => panic!("Duration of {} {} overflows!", amount, name),
+        }
+    }
+
+    /// Same duration for the host, durations built by hand with negative millis are rejected
+    pub fn to_std(&self) -> std::time::Duration {
+        if self.millis < 0 {
+            panic!("Invalid duration of {} millis, durations can not be negative!", self.millis)
+        }
+        std::time::Duration::from_millis(self.millis as u64)
+    }
+
+    fn as_millis(&self) -> i64 {
+        self.millis
+    }
+
+    // whole seconds and minutes, the rest is cut off
+    fn as_secs(&self) -> i64 {
+        self.millis / 1000
+    }
+
+    fn as_mins(&self) -> i64 {
+        self.millis / 60_000
+    }
+
+    fn plus(&self, other: Duration) -> Duration {
+        match self.millis.checked_add(other.millis) {
+            Some(millis) => Duration { millis },
+            None => panic!("Duration of {} and {} millis overflows!", self.millis, other.millis),
+        }
+    }
+}
+
+fn now_millis(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Literal::Number(vm.entropy().now().as_millis() as i64)
+}
+
+fn now_secs(vm: &mut dyn ScopeProvider, _params: Parameters) -> Literal {
+    Literal::Number(vm.entropy().now().as_secs() as i64)
+}
+
+fn millis(params: Parameters) -> Literal {
+    let amount = unwrap_args!(params => (Number));
+    Duration::of(amount, 1, "millis").into_literal()
+}
+
+fn secs(params: Parameters) -> Literal {
+    let amount = unwrap_args!(params => (Number));
+    Duration::of(amount, 1000, "secs").into_literal()
+}
+
+fn mins(params: Parameters) -> Literal {
+    let amount = unwrap_args!(params => (Number));
+    Duration::of(amount, 60_000, "mins").into_literal()
+}
+
+#[doc(hidden)]
+pub fn __time_feature<V>(visitor: &mut V) where V: Visitor {
+    extern_fns!(visitor {
+        scope "std::time" {
+            extern fn millis(amount: num) -> Duration;
+            extern fn secs(amount: num) -> Duration;
+            extern fn mins(amount: num) -> Duration;
+            native fn now_millis() -> num;
+            native fn now_secs() -> num;
+        }
+    });
+    Duration::register(visitor);
+}
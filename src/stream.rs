@@ -0,0 +1,46 @@
+//! Tokens supplied to a Vm while it is processing, see [`Vm::set_source`](crate::visit::Vm::set_source).
+
+use crate::tks::Token;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Supplies tokens on demand. A Vm pulls the next token whenever its queue runs empty,
+/// so programs are executed while they are still being produced
+pub trait TokenSource: Send {
+    /// Next token of the stream, or `None` if there are no more tokens for now.
+    /// Processing stops once the source runs out, and continues with the next `process` call
+    fn next_token(&mut self) -> Option<Token>;
+}
+
+impl<I> TokenSource for I
+where
+    I: Iterator<Item = Token> + Send,
+{
+    fn next_token(&mut self) -> Option<Token> {
+        self.next()
+    }
+}
+
+/// Source of a Vm, shared by all of its clones
+#[derive(Clone, Default)]
+pub(crate) struct SourceSlot(Option<Arc<Mutex<dyn TokenSource>>>);
+
+impl SourceSlot {
+    pub(crate) fn new(source: impl TokenSource + 'static) -> Self {
+        Self(Some(Arc::new(Mutex::new(source))))
+    }
+
+    pub(crate) fn pull(&self) -> Option<Token> {
+        self.0.as_ref()?.lock().unwrap().next_token()
+    }
+}
+
+impl Debug for SourceSlot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SourceSlot({})",
+            if self.0.is_some() { "set" } else { "none" }
+        )
+    }
+}
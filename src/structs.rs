@@ -0,0 +1,576 @@
+use crate::fns::{register_native_fn, InstFn, Metadata, Parameters};
+use crate::marshal::{FromLiteral, IntoLiteral};
+use crate::tks::{BinaryOp, Expression, Keyword, Literal, Token, TokenChain};
+use crate::var::ContainingScope;
+use crate::visit::{GlobalScope, ScopeProvider};
+use crate::vm::Transmute;
+use anyhow::bail;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructureTemplate {
+    name: String,
+    inst_vars: HashMap<String, String>,
+    defaults: HashMap<String, Literal>,
+    inst_fns: HashMap<String, InstFn>,
+    meta: Metadata,
+    /// Members declared with `priv`, sorted. They can only be accessed by functions of the structure
+    private: Vec<String>,
+}
+
+impl Transmute for StructureTemplate {
+    fn size(&mut self) -> usize {
+        self.name.size()
+            + self.inst_vars.size()
+            + self.defaults.size()
+            + self.inst_fns.size()
+            + self.meta.size()
+            + self.private.size()
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.name.write_to(buf)?;
+        self.inst_vars.write_to(buf)?;
+        self.defaults.write_to(buf)?;
+        self.inst_fns.write_to(buf)?;
+        self.meta.write_to(buf)?;
+        self.private.write_to(buf)?;
+        Ok(())
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            name: String::read_from(buf)?,
+            inst_vars: HashMap::read_from(buf)?,
+            defaults: HashMap::read_from(buf)?,
+            inst_fns: HashMap::read_from(buf)?,
+            meta: HashMap::read_from(buf)?,
+            private: Vec::read_from(buf)?,
+        })
+    }
+}
+
+impl StructureTemplate {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            inst_vars: Default::default(),
+            defaults: Default::default(),
+            inst_fns: Default::default(),
+            meta: Default::default(),
+            private: vec![],
+        }
+    }
+
+    pub fn with_meta(mut self, meta: Metadata) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn meta(&self) -> &Metadata {
+        &self.meta
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn add_inst_var(&mut self, name: &str, ty: String, default: Option<Literal>) {
+        self.inst_vars.insert(name.to_string(), ty);
+        match default {
+            Some(default) => self.defaults.insert(name.to_string(), default),
+            None => self.defaults.remove(name),
+        };
+    }
+
+    pub fn add_inst_fn(
+        &mut self,
+        name: &str,
+        output_ty: String,
+        param_names: Vec<String>,
+        tks: TokenChain,
+        meta: Metadata,
+    ) {
+        self.inst_fns.insert(
+            name.to_string(),
+            InstFn::new(output_ty, param_names, tks).with_meta(meta),
+        );
+    }
+
+    pub fn get_inst_var_type(&self, name: &str) -> Option<String> {
+        self.inst_vars.get(name).map(|ty| ty.to_owned())
+    }
+
+    pub fn get_default(&self, name: &str) -> Option<Literal> {
+        self.defaults.get(name).map(|l| l.to_owned())
+    }
+
+    pub fn get_inst_fn(&self, name: &str) -> Option<InstFn> {
+        self.inst_fns.get(name).map(|f| f.to_owned())
+    }
+
+    /// Hides a field, function or static value of the structure from code outside of its functions
+    pub fn set_private(&mut self, member: &str) {
+        if let Err(index) = self.private.binary_search_by(|it| it.as_str().cmp(member)) {
+            self.private.insert(index, member.to_string());
+        }
+    }
+
+    pub fn is_private(&self, member: &str) -> bool {
+        self.private
+            .binary_search_by(|it| it.as_str().cmp(member))
+            .is_ok()
+    }
+
+    pub fn inst_fn_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.inst_fns.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+/// Tagged union declared with `enum Name { Variant(payload type), Unit }`. Values of
+/// the enum are [`Literal::Enum`], holding the full path of the variant and its payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumTemplate {
+    name: String,
+    /// Payload type of every variant, `void` for variants without a payload
+    variants: HashMap<String, String>,
+}
+
+impl Transmute for EnumTemplate {
+    fn size(&mut self) -> usize {
+        self.name.size() + self.variants.size()
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.name.write_to(buf)?;
+        self.variants.write_to(buf)?;
+        Ok(())
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            name: String::read_from(buf)?,
+            variants: HashMap::read_from(buf)?,
+        })
+    }
+}
+
+impl EnumTemplate {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            variants: Default::default(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn add_variant(&mut self, name: &str, payload_ty: String) {
+        self.variants.insert(name.to_string(), payload_ty);
+    }
+
+    pub fn get_variant_type(&self, name: &str) -> Option<String> {
+        self.variants.get(name).map(|ty| ty.to_owned())
+    }
+
+    pub fn variant_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.variants.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Creates a value of the variant, checking the payload against its declared type
+    pub fn instantiate(&self, variant: &str, payload: Literal) -> anyhow::Result<Literal> {
+        let ty = match self.variants.get(variant) {
+            Some(ty) => ty,
+            None => bail!("Enum {} has no variant {}!", self.name, variant),
+        };
+        if ty != "unknown" && !payload.type_str(ty) {
+            bail!(
+                "Invalid payload {} provided for variant {}::{}! Expected value of type {:?}",
+                payload,
+                self.name,
+                variant,
+                ty
+            )
+        }
+        Ok(Literal::Enum(
+            format!("{}::{}", self.name, variant),
+            Box::new(payload),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct StructureInstance {
+    type_name: String,
+    fields: HashMap<String, Literal>,
+}
+
+impl Transmute for StructureInstance {
+    fn size(&mut self) -> usize {
+        self.type_name.size() + self.fields.size()
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.type_name.write_to(buf)?;
+        self.fields.write_to(buf)?;
+        Ok(())
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            type_name: String::read_from(buf)?,
+            fields: HashMap::read_from(buf)?,
+        })
+    }
+}
+
+impl Display for StructureInstance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<&String> = self.fields.keys().collect();
+        names.sort();
+        let fields: Vec<String> = names
+            .iter()
+            .map(|name| format!("{}: {}", name, self.fields[*name]))
+            .collect();
+        write!(f, "{} {{ {} }}", self.type_name, fields.join(", "))
+    }
+}
+
+impl StructureInstance {
+    /// Creates a new instance with all the default field values applied
+    pub fn from_template(template: &StructureTemplate) -> Self {
+        Self {
+            type_name: template.name(),
+            fields: template.defaults.clone(),
+        }
+    }
+
+    /// Makes sure that every field declared in the template has a value
+    pub fn validate(&self, template: &StructureTemplate) -> anyhow::Result<()> {
+        let mut missing: Vec<&String> = template
+            .inst_vars
+            .keys()
+            .filter(|name| !self.fields.contains_key(*name))
+            .collect();
+        if !missing.is_empty() {
+            missing.sort();
+            bail!(
+                "Missing values for fields of structure {}: {:?}!",
+                self.type_name,
+                missing
+            )
+        }
+        Ok(())
+    }
+
+    pub fn type_name(&self) -> String {
+        self.type_name.clone()
+    }
+
+    pub fn field_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.fields.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<Literal> {
+        self.fields.get(name).map(|l| l.to_owned())
+    }
+
+    /// Converts value of a field into a Rust type
+    pub fn field<T: FromLiteral>(&self, name: &str) -> anyhow::Result<T> {
+        match self.fields.get(name) {
+            Some(value) => T::from_literal(value.to_owned()),
+            None => bail!("Structure {} has no field {}!", self.type_name, name),
+        }
+    }
+
+    /// Starts building an instance from Rust values, without a template
+    pub fn builder(type_name: &str) -> InstanceBuilder {
+        InstanceBuilder {
+            instance: Self {
+                type_name: type_name.to_string(),
+                fields: HashMap::new(),
+            },
+        }
+    }
+
+    pub fn set_field(
+        &mut self,
+        template: &StructureTemplate,
+        name: &str,
+        value: Literal,
+    ) -> anyhow::Result<()> {
+        let ty = match template.get_inst_var_type(name) {
+            Some(ty) => ty,
+            None => bail!("Structure {} has no field {}!", self.type_name, name),
+        };
+        if ty != "unknown" && !value.type_str(&ty) {
+            bail!(
+                "Invalid value provided for field {}.{}! Expected value of type {:?}",
+                self.type_name,
+                name,
+                ty
+            )
+        }
+        self.fields.insert(name.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Builds a [`StructureInstance`] field by field, see [`StructureInstance::builder`].
+/// Fields are not checked against a template, use [`StructureInstance::validate`] for that
+#[derive(Debug, Clone)]
+pub struct InstanceBuilder {
+    instance: StructureInstance,
+}
+
+impl InstanceBuilder {
+    pub fn field<T: IntoLiteral>(mut self, name: &str, value: T) -> Self {
+        self.instance
+            .fields
+            .insert(name.to_string(), value.into_literal());
+        self
+    }
+
+    pub fn build(self) -> StructureInstance {
+        self.instance
+    }
+}
+
+/// A Rust type exposed to programs as a structure, usually implemented with [`native_struct!`](crate::native_struct)
+pub trait NativeStruct: IntoLiteral + FromLiteral {
+    /// Name of the structure in programs
+    const NAME: &'static str;
+
+    /// Makes the structure available to programs run by `vm`. Its methods are bound to
+    /// extern functions, which are registered in the [`native_scope`] of the structure
+    fn register<V: ScopeProvider + GlobalScope>(vm: &mut V);
+}
+
+/// Name of the scope holding extern functions that back methods of the native structure `name`
+pub fn native_scope(name: &str) -> String {
+    format!("native::{}", name)
+}
+
+/// Binds a method of a native structure to a native function. The function receives
+/// `this` followed by the other parameters. Methods that mutate `this` return the new
+/// instance from the function, which is written back, other methods return their output.
+#[doc(hidden)]
+pub fn _bind_native_method(
+    template: &mut StructureTemplate,
+    scope: &mut ContainingScope,
+    name: &str,
+    params: Vec<String>,
+    out_ty: String,
+    mutates: bool,
+    fun: impl Fn(&mut dyn ScopeProvider, Parameters) -> Literal + Sync + Send + 'static,
+) {
+    let scope_name = native_scope(&template.name());
+    let handler = register_native_fn(fun);
+
+    let mut param_names = vec!["this".to_string()];
+    param_names.extend(params);
+    let call = Token::Expression(Box::new(Expression::InvokeStatic(
+        format!("{}::{}", scope_name, name),
+        param_names
+            .iter()
+            .map(|it| Token::Literal(Literal::Ident(it.to_owned())))
+            .collect(),
+    )));
+    let (fn_out_ty, chain) = if mutates {
+        // this = native::Type::name(this, ...); return *;
+        let assign = Expression::BinaryOp(
+            BinaryOp::Assign,
+            Token::Literal(Literal::Ident("this".to_string())),
+            call,
+        );
+        let chain = vec![
+            Token::Expression(Box::new(assign)),
+            Token::Keyword(Keyword::Return),
+            Token::Literal(Literal::Void),
+        ];
+        (template.name(), chain)
+    } else {
+        // return native::Type::name(this, ...);
+        (out_ty.clone(), vec![Token::Keyword(Keyword::Return), call])
+    };
+
+    scope.export(name);
+    scope.add_native_fn(name, fn_out_ty, param_names.clone(), handler);
+    template.add_inst_fn(name, out_ty, param_names, chain, Metadata::default());
+}
+
+/// Converts an instance of the native structure `name` into its fields
+#[doc(hidden)]
+pub fn _native_instance(name: &str, lit: Literal) -> anyhow::Result<StructureInstance> {
+    let instance = StructureInstance::from_literal(lit)?;
+    if instance.type_name != name {
+        bail!("Expected structure {}, got {}!", name, instance.type_name)
+    }
+    Ok(instance)
+}
+
+/// Exposes a Rust struct to programs as a structure of the same name. Every field
+/// has to be listed, and field types have to implement both [`IntoLiteral`] and [`FromLiteral`].
+/// Methods taking `&self` return their output, while methods taking `&mut self` can
+/// not return anything and write the mutated instance back instead. Methods declared
+/// with `vm` after `&self` also receive the visitor, as a `&mut dyn ScopeProvider`.
+///
+/// ```ignore
+/// native_struct! {
+///     Point {
+///         x: i64,
+///         y: i64,
+///     }
+///     fn length(&self) -> f64;
+///     fn describe(&self, vm, precision: i64) -> String;
+///     fn move_by(&mut self, dx: i64, dy: i64);
+/// }
+///
+/// register_native_struct!(vm, Point);
+/// ```
+#[macro_export]
+macro_rules! native_struct {
+    ($ty:ident {
+        $($field:ident : $fty:ty),* $(,)*
+    }
+    $($methods:tt)*) => {
+        impl $crate::marshal::IntoLiteral for $ty {
+            fn into_literal(self) -> $crate::tks::Literal {
+                let builder = $crate::structs::StructureInstance::builder(stringify!($ty));
+                $(let builder = builder.field(stringify!($field), self.$field);)*
+                $crate::marshal::IntoLiteral::into_literal(builder.build())
+            }
+        }
+
+        impl $crate::marshal::FromLiteral for $ty {
+            fn from_literal(lit: $crate::tks::Literal) -> anyhow::Result<Self> {
+                #[allow(unused_variables)]
+                let instance = $crate::structs::_native_instance(stringify!($ty), lit)?;
+                Ok(Self {
+                    $($field: instance.field(stringify!($field))?),*
+                })
+            }
+
+            fn type_name() -> String {
+                stringify!($ty).to_string()
+            }
+        }
+
+        impl $crate::structs::NativeStruct for $ty {
+            const NAME: &'static str = stringify!($ty);
+
+            fn register<V: $crate::visit::ScopeProvider + $crate::visit::GlobalScope>(vm: &mut V) {
+                let mut template = $crate::structs::StructureTemplate::new(stringify!($ty).to_string());
+                $(
+                    template.add_inst_var(
+                        stringify!($field),
+                        <$fty as $crate::marshal::FromLiteral>::type_name(),
+                        None,
+                    );
+                )*
+                #[allow(unused_mut)]
+                let mut scope = $crate::var::ContainingScope::new();
+                $crate::native_struct!(@methods $ty, template, scope; $($methods)*);
+                vm.push_scope($crate::structs::native_scope(stringify!($ty)), scope);
+                vm.add_struct(template);
+            }
+        }
+    };
+
+    (@methods $ty:ident, $template:ident, $scope:ident;
+        fn $name:ident(&self, vm $(, $param:ident : $pty:ty)* $(,)*) -> $out:ty;
+        $($rest:tt)*
+    ) => {
+        $crate::structs::_bind_native_method(
+            &mut $template,
+            &mut $scope,
+            stringify!($name),
+            vec![$(stringify!($param).to_string()),*],
+            <$out as $crate::marshal::FromLiteral>::type_name(),
+            false,
+            |vm: &mut dyn $crate::visit::ScopeProvider, params: $crate::fns::Parameters| {
+                let mut params = params.into_iter();
+                let this: $ty = $crate::marshal::_native_param(&mut params, "this");
+                $(let $param: $pty = $crate::marshal::_native_param(&mut params, stringify!($param));)*
+                $crate::marshal::IntoLiteral::into_literal(this.$name(vm, $($param),*))
+            },
+        );
+        $crate::native_struct!(@methods $ty, $template, $scope; $($rest)*);
+    };
+
+    (@methods $ty:ident, $template:ident, $scope:ident;
+        fn $name:ident(&self $(, $param:ident : $pty:ty)* $(,)*) -> $out:ty;
+        $($rest:tt)*
+    ) => {
+        $crate::structs::_bind_native_method(
+            &mut $template,
+            &mut $scope,
+            stringify!($name),
+            vec![$(stringify!($param).to_string()),*],
+            <$out as $crate::marshal::FromLiteral>::type_name(),
+            false,
+            |_: &mut dyn $crate::visit::ScopeProvider, params: $crate::fns::Parameters| {
+                let mut params = params.into_iter();
+                let this: $ty = $crate::marshal::_native_param(&mut params, "this");
+                $(let $param: $pty = $crate::marshal::_native_param(&mut params, stringify!($param));)*
+                $crate::marshal::IntoLiteral::into_literal(this.$name($($param),*))
+            },
+        );
+        $crate::native_struct!(@methods $ty, $template, $scope; $($rest)*);
+    };
+
+    (@methods $ty:ident, $template:ident, $scope:ident;
+        fn $name:ident(&mut self $(, $param:ident : $pty:ty)* $(,)*);
+        $($rest:tt)*
+    ) => {
+        $crate::structs::_bind_native_method(
+            &mut $template,
+            &mut $scope,
+            stringify!($name),
+            vec![$(stringify!($param).to_string()),*],
+            "void".to_string(),
+            true,
+            |_: &mut dyn $crate::visit::ScopeProvider, params: $crate::fns::Parameters| {
+                let mut params = params.into_iter();
+                let mut this: $ty = $crate::marshal::_native_param(&mut params, "this");
+                $(let $param: $pty = $crate::marshal::_native_param(&mut params, stringify!($param));)*
+                this.$name($($param),*);
+                $crate::marshal::IntoLiteral::into_literal(this)
+            },
+        );
+        $crate::native_struct!(@methods $ty, $template, $scope; $($rest)*);
+    };
+
+    (@methods $ty:ident, $template:ident, $scope:ident;) => {};
+}
+
+/// Registers structures declared with [`native_struct!`] in a VM
+#[macro_export]
+macro_rules! register_native_struct {
+    ($vm:ident, $($ty:ty),+ $(,)*) => {
+        $(
+            <$ty as $crate::structs::NativeStruct>::register(&mut $vm);
+        )+
+    };
+}
@@ -0,0 +1,56 @@
+//! Text documents with embedded `{{ expression }}` blocks, like emails or generated configs,
+//! rendered against the declarations of a Vm.
+//!
+//! A literal `{{` is written as `{{ "{{" }}`.
+
+use crate::span::Span;
+use crate::tks::Literal;
+use crate::visit::Vm;
+use anyhow::{bail, Context};
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+/// Replaces every `{{ expression }}` block of `src` with the value of its expression,
+/// evaluated with [`Vm::eval`]. Expressions producing nothing render as empty text.
+///
+/// Fails on unclosed blocks and on expressions that can not be evaluated,
+/// reporting the position of the block in `src`
+pub fn render(vm: &mut Vm, src: &str) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(src.len());
+    let mut rest = src;
+    while let Some(start) = rest.find(OPEN) {
+        out.push_str(&rest[..start]);
+        let offset = src.len() - rest.len() + start;
+        let body = &rest[start + OPEN.len()..];
+        let end = match body.find(CLOSE) {
+            Some(end) => end,
+            None => bail!("Unclosed template block at {}!", _span_at(src, offset)),
+        };
+        let value = vm.eval(&body[..end]).with_context(|| {
+            format!(
+                "Could not render template block at {}",
+                _span_at(src, offset)
+            )
+        })?;
+        out.push_str(&render_value(&value));
+        rest = &body[end + CLOSE.len()..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Text a value renders as, which is its display form except for void values
+pub fn render_value(value: &Literal) -> String {
+    match value {
+        Literal::Void => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn _span_at(src: &str, offset: usize) -> Span {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |it| it + 1) + 1;
+    Span::new(line as u32, col as u32, OPEN.len() as u32)
+}
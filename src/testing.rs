@@ -0,0 +1,313 @@
+//! Proptest strategies for the [`Transmute`] types of the VM, and helpers validating
+//! their binary format. Crates adding their own serializable types can reuse them with
+//! the `testing` feature.
+//!
+//! Generated floats are never NaN, so generated values always equal themselves after a round trip.
+
+use crate::features::StdFeature;
+use crate::manifest::{ExternSignature, Manifest, Version};
+use crate::span::{SourceMap, Span};
+use crate::structs::{EnumTemplate, StructureInstance, StructureTemplate};
+use crate::tks::{
+    BinaryOp, Decimal, Expression, Keyword, Literal, Token, TokenChain, UnaryOp, MAX_SCALE,
+};
+use crate::var::ContainingScope;
+use crate::vm::Transmute;
+use proptest::collection::{btree_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::select;
+use std::fmt::Debug;
+use std::io::Cursor;
+
+/// Writes `value` and reads it back, checking that the result is equal, that
+/// [`Transmute::size`] matches the written bytes, and that nothing is left unread
+pub fn assert_roundtrip<T>(mut value: T)
+where
+    T: Transmute + Clone + PartialEq + Debug,
+{
+    let original = value.clone();
+    let mut buf = vec![];
+    value.write(&mut buf).unwrap();
+    assert_eq!(buf.len(), value.size(), "size of {:?}", original);
+    let mut cursor = Cursor::new(buf);
+    assert_eq!(T::read(&mut cursor).unwrap(), original);
+    assert_eq!(
+        cursor.position() as usize,
+        cursor.get_ref().len(),
+        "trailing bytes of {:?}",
+        original
+    );
+}
+
+/// Most levels of nested literals and tokens generated
+const MAX_DEPTH: u32 = 3;
+
+/// Names of identifiers, types and fields
+pub fn ident() -> impl Strategy<Value = String> {
+    "[a-zA-Z_][a-zA-Z0-9_]{0,8}"
+}
+
+/// Floats of every class but NaN
+pub fn float() -> impl Strategy<Value = f64> {
+    use proptest::num::f64::*;
+    NORMAL | SUBNORMAL | ZERO | INFINITE | NEGATIVE | POSITIVE
+}
+
+pub fn binary_op() -> impl Strategy<Value = BinaryOp> {
+    select(vec![
+        BinaryOp::Assign,
+        BinaryOp::Add,
+        BinaryOp::Sub,
+        BinaryOp::Div,
+        BinaryOp::Mul,
+        BinaryOp::Mod,
+        BinaryOp::And,
+        BinaryOp::Or,
+        BinaryOp::Eq,
+        BinaryOp::Neq,
+        BinaryOp::Gt,
+        BinaryOp::Lt,
+        BinaryOp::BitAnd,
+        BinaryOp::BitOr,
+        BinaryOp::BitXor,
+        BinaryOp::BitLsh,
+        BinaryOp::BitRsh,
+    ])
+}
+
+pub fn unary_op() -> impl Strategy<Value = UnaryOp> {
+    select(vec![UnaryOp::Neg, UnaryOp::Rev])
+}
+
+pub fn keyword() -> impl Strategy<Value = Keyword> {
+    select(vec![
+        Keyword::Export,
+        Keyword::Import,
+        Keyword::Let,
+        Keyword::Const,
+        Keyword::Function,
+        Keyword::Return,
+        Keyword::Namespace,
+        Keyword::Test,
+        Keyword::Enum,
+        Keyword::Static,
+        Keyword::Pub,
+        Keyword::Priv,
+        Keyword::Init,
+    ])
+}
+
+fn _leaf_literal() -> impl Strategy<Value = Literal> {
+    prop_oneof![
+        any::<i64>().prop_map(Literal::Number),
+        float().prop_map(Literal::Float),
+        (any::<i64>(), 0..=MAX_SCALE).prop_map(|(mantissa, scale)| Literal::Decimal(
+            Decimal::new(mantissa as i128, scale).unwrap()
+        )),
+        any::<String>().prop_map(Literal::String),
+        any::<char>().prop_map(Literal::Char),
+        ident().prop_map(Literal::Ident),
+        any::<bool>().prop_map(Literal::Bool),
+        ident().prop_map(Literal::TypeName),
+        vec(any::<u8>(), 0..16).prop_map(Literal::Bytes),
+        Just(Literal::Void),
+    ]
+}
+
+fn _instance(fields: impl Strategy<Value = Literal>) -> impl Strategy<Value = StructureInstance> {
+    (ident(), vec((ident(), fields), 0..4)).prop_map(|(type_name, fields)| {
+        fields
+            .into_iter()
+            .fold(
+                StructureInstance::builder(&type_name),
+                |builder, (name, value)| builder.field(&name, value),
+            )
+            .build()
+    })
+}
+
+/// Literals of every kind, including nested arrays, structures and enum payloads
+pub fn literal() -> impl Strategy<Value = Literal> {
+    _leaf_literal().prop_recursive(MAX_DEPTH, 32, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..4).prop_map(Literal::Array),
+            _instance(inner.clone()).prop_map(|it| Literal::Struct(Box::new(it))),
+            (ident(), ident(), inner).prop_map(|(name, variant, payload)| Literal::Enum(
+                format!("{}::{}", name, variant),
+                Box::new(payload)
+            )),
+        ]
+    })
+}
+
+pub fn structure_instance() -> impl Strategy<Value = StructureInstance> {
+    _instance(literal())
+}
+
+fn _leaf_token() -> impl Strategy<Value = Token> {
+    prop_oneof![
+        select(vec![
+            Token::Whitespace,
+            Token::LBracket,
+            Token::RBracket,
+            Token::LParen,
+            Token::RParen,
+            Token::LSquare,
+            Token::RSquare,
+            Token::End,
+        ]),
+        literal().prop_map(Token::Literal),
+        keyword().prop_map(Token::Keyword),
+        (ident(), literal()).prop_map(|(name, value)| Token::Attribute(name, value)),
+        select(vec![
+            Expression::IfStmt,
+            Expression::ElseStmt,
+            Expression::ElifStmt,
+            Expression::WhileStmt,
+            Expression::DoWhileStmt,
+            Expression::MatchStmt,
+        ])
+        .prop_map(|it| Token::Expression(Box::new(it))),
+    ]
+}
+
+fn _expression(token: BoxedStrategy<Token>) -> impl Strategy<Value = Expression> {
+    let chain = vec(token.clone(), 0..4);
+    prop_oneof![
+        (binary_op(), token.clone(), token.clone())
+            .prop_map(|(op, lh, rh)| Expression::BinaryOp(op, lh, rh)),
+        (unary_op(), token.clone()).prop_map(|(op, value)| Expression::UnaryOp(op, value)),
+        vec(ident(), 1..4).prop_map(Expression::StaticAccess),
+        (ident(), chain.clone()).prop_map(|(name, params)| Expression::InvokeStatic(name, params)),
+        (ident(), chain.clone()).prop_map(|(name, fields)| Expression::Instantiate(name, fields)),
+        (ident(), ident()).prop_map(|(name, field)| Expression::InstanceAccess(name, field)),
+        (ident(), ident(), chain.clone())
+            .prop_map(|(name, fnc, params)| Expression::InvokeInstance(name, fnc, params)),
+        (token.clone(), ident(), chain.clone())
+            .prop_map(|(receiver, fnc, params)| Expression::InvokeChained(receiver, fnc, params)),
+        chain.prop_map(Expression::Array),
+        (token.clone(), token.clone(), token.clone())
+            .prop_map(|(cond, then, otherwise)| Expression::Ternary(cond, then, otherwise)),
+        (token.clone(), token).prop_map(|(value, index)| Expression::Index(value, index)),
+    ]
+}
+
+/// Tokens of every kind, including nested expressions
+pub fn token() -> impl Strategy<Value = Token> {
+    _leaf_token().prop_recursive(MAX_DEPTH, 32, 4, |inner| {
+        _expression(inner).prop_map(|it| Token::Expression(Box::new(it)))
+    })
+}
+
+pub fn expression() -> impl Strategy<Value = Expression> {
+    _expression(token().boxed())
+}
+
+pub fn token_chain() -> impl Strategy<Value = TokenChain> {
+    vec(token(), 0..16)
+}
+
+pub fn span() -> impl Strategy<Value = Span> {
+    (any::<u32>(), any::<u32>(), any::<u32>())
+        .prop_map(|(line, col, len)| Span::new(line, col, len))
+}
+
+pub fn source_map() -> impl Strategy<Value = SourceMap> {
+    vec((0..1024usize, span()), 0..16).prop_map(|spans| {
+        let mut map = SourceMap::new();
+        for (pos, span) in spans {
+            map.insert(pos, span);
+        }
+        map
+    })
+}
+
+pub fn version() -> impl Strategy<Value = Version> {
+    (any::<u16>(), any::<u16>(), any::<u16>())
+        .prop_map(|(major, minor, patch)| Version::new(major, minor, patch))
+}
+
+pub fn extern_signature() -> impl Strategy<Value = ExternSignature> {
+    (ident(), vec(ident(), 0..4), ident())
+        .prop_map(|(name, params, out_ty)| ExternSignature::new(name, params, out_ty))
+}
+
+pub fn manifest() -> impl Strategy<Value = Manifest> {
+    (
+        vec(select(StdFeature::ALL.to_vec()), 0..4),
+        vec(extern_signature(), 0..4),
+        version(),
+        option::of(ident()),
+    )
+        .prop_map(
+            |(features, extern_fns, min_vm_version, entrypoint)| Manifest {
+                features,
+                extern_fns,
+                min_vm_version,
+                entrypoint,
+            },
+        )
+}
+
+pub fn structure_template() -> impl Strategy<Value = StructureTemplate> {
+    (
+        ident(),
+        vec((ident(), ident(), option::of(literal())), 0..4),
+        vec((ident(), ident(), token_chain()), 0..2),
+    )
+        .prop_map(|(name, vars, fns)| {
+            let mut template = StructureTemplate::new(name);
+            for (name, ty, default) in vars {
+                template.add_inst_var(&name, ty, default);
+            }
+            for (name, out_ty, chain) in fns {
+                template.add_inst_fn(
+                    &name,
+                    out_ty,
+                    vec!["this".to_string()],
+                    chain,
+                    Default::default(),
+                );
+            }
+            template
+        })
+}
+
+pub fn enum_template() -> impl Strategy<Value = EnumTemplate> {
+    (ident(), btree_map(ident(), ident(), 0..4)).prop_map(|(name, variants)| {
+        let mut template = EnumTemplate::new(name);
+        for (variant, payload_ty) in variants {
+            template.add_variant(&variant, payload_ty);
+        }
+        template
+    })
+}
+
+/// Scopes with variables, constants, functions, exports and imports
+pub fn containing_scope() -> impl Strategy<Value = ContainingScope> {
+    (
+        // constants can not be declared twice, so every value gets its own name
+        btree_map(ident(), (literal(), any::<bool>()), 0..8),
+        vec((ident(), ident(), vec(ident(), 0..3), token_chain()), 0..2),
+        vec((ident(), ident()), 0..3),
+    )
+        .prop_map(|(values, fns, imports)| {
+            let mut scope = ContainingScope::new();
+            for (name, (value, constant)) in values {
+                if constant {
+                    scope.add_const(&name, value);
+                    scope.export(&name);
+                } else {
+                    scope.add_var(&name, value);
+                }
+            }
+            for (name, out_ty, params, chain) in fns {
+                scope.add_static_fn(&name, out_ty, params, chain);
+            }
+            for (from, name) in imports {
+                scope.import(&from, &name);
+            }
+            scope
+        })
+}
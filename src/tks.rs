@@ -1,9 +1,11 @@
+mod decimal;
 mod expr;
 pub(crate) mod expr_handlers;
 mod kw;
 mod lit;
 mod ops;
 
+pub use decimal::*;
 pub use expr::*;
 pub use kw::*;
 pub use lit::*;
@@ -12,12 +14,14 @@ pub use ops::*;
 use crate::visit::{Visitable, Visitor};
 use crate::vm::Transmute;
 use anyhow::bail;
-use std::io::Cursor;
+use std::io::{Read, Write};
 
 pub type Ident = String;
 pub type TokenChain = Vec<Token>;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Token {
     Whitespace,
     LBracket,
@@ -29,7 +33,12 @@ pub enum Token {
     Literal(Literal),
     Keyword(Keyword),
     Expression(Box<Expression>),
+    Attribute(Ident, Literal),
     End,
+    /// Comment of the source as written, like `// note` or `/* note */`, kept by the
+    /// assembler only when asked to, see [`AssembleOptions::comments`](crate::asm::AssembleOptions::comments).
+    /// The Vm skips it
+    Comment(String),
 }
 
 impl Transmute for Token {
@@ -45,43 +54,54 @@ impl Transmute for Token {
             Token::Literal(lit) => lit.size(),
             Token::Keyword(kw) => kw.size(),
             Token::Expression(expr) => expr.size(),
+            Token::Attribute(name, value) => name.size() + value.size(),
             Token::End => 0,
+            Token::Comment(text) => text.size(),
         }
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
         match self {
-            Token::Whitespace => 0u8.write(buf)?,
-            Token::LBracket => 0x01u8.write(buf)?,
-            Token::RBracket => 0x02u8.write(buf)?,
-            Token::LParen => 0x03u8.write(buf)?,
-            Token::RParen => 0x04u8.write(buf)?,
-            Token::LSquare => 0x05u8.write(buf)?,
-            Token::RSquare => 0x06u8.write(buf)?,
+            Token::Whitespace => 0u8.write_to(buf)?,
+            Token::LBracket => 0x01u8.write_to(buf)?,
+            Token::RBracket => 0x02u8.write_to(buf)?,
+            Token::LParen => 0x03u8.write_to(buf)?,
+            Token::RParen => 0x04u8.write_to(buf)?,
+            Token::LSquare => 0x05u8.write_to(buf)?,
+            Token::RSquare => 0x06u8.write_to(buf)?,
             Token::Literal(l) => {
-                0x07u8.write(buf)?;
-                l.write(buf)?;
+                0x07u8.write_to(buf)?;
+                l.write_to(buf)?;
             }
             Token::Keyword(kw) => {
-                0x08u8.write(buf)?;
-                kw.write(buf)?;
+                0x08u8.write_to(buf)?;
+                kw.write_to(buf)?;
             }
             Token::Expression(expr) => {
-                0x09u8.write(buf)?;
-                expr.write(buf)?;
+                0x09u8.write_to(buf)?;
+                expr.write_to(buf)?;
             }
             Token::End => {
-                0x0Au8.write(buf)?;
+                0x0Au8.write_to(buf)?;
+            }
+            Token::Attribute(name, value) => {
+                0x0Bu8.write_to(buf)?;
+                name.write_to(buf)?;
+                value.write_to(buf)?;
+            }
+            Token::Comment(text) => {
+                0x0Cu8.write_to(buf)?;
+                text.write_to(buf)?;
             }
         };
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(match u8::read(buf)? {
+        Ok(match u8::read_from(buf)? {
             0x00 => Token::Whitespace,
             0x01 => Token::LBracket,
             0x02 => Token::RBracket,
@@ -89,11 +109,13 @@ impl Transmute for Token {
             0x04 => Token::RParen,
             0x05 => Token::LSquare,
             0x06 => Token::RSquare,
-            0x07 => Token::Literal(Literal::read(buf)?),
-            0x08 => Token::Keyword(Keyword::read(buf)?),
-            0x09 => Token::Expression(Box::new(Expression::read(buf)?)),
+            0x07 => Token::Literal(Literal::read_from(buf)?),
+            0x08 => Token::Keyword(Keyword::read_from(buf)?),
+            0x09 => Token::Expression(Box::new(Expression::read_from(buf)?)),
             0x0A => Token::End,
-            _ => bail!("Invalid token provided!"),
+            0x0B => Token::Attribute(Ident::read_from(buf)?, Literal::read_from(buf)?),
+            0x0C => Token::Comment(String::read_from(buf)?),
+            tag => bail!("Unknown token tag {:#04x}!", tag),
         })
     }
 }
@@ -107,6 +129,10 @@ impl Visitable for Token {
             Token::Literal(literal) => literal.visit(visitor),
             Token::Keyword(kw) => kw.visit(visitor),
             Token::Expression(expr) => expr.visit(visitor),
+            Token::Attribute(name, value) => {
+                visitor.add_attr(name.to_owned(), value.to_owned());
+                Ok(())
+            }
             Token::End => {
                 // We should not reach this!
                 panic!("Tried to move to END scope!")
@@ -115,41 +141,41 @@ impl Visitable for Token {
                 visitor.pop_scope_level();
                 Ok(())
             }
-            _ => Ok(()), // ignoring because it is either scopes or whitespaces
+            _ => Ok(()), // ignoring because it is either scopes, whitespaces or comments
         }
     }
 }
 
 impl Token {
-    pub fn as_lit_advanced<V>(&mut self, visitor: &mut V, panic_msg: &str) -> Literal
+    pub fn as_lit_advanced<V>(&mut self, visitor: &mut V, err_msg: &str) -> anyhow::Result<Literal>
     where
         V: Visitor,
     {
-        match self {
+        Ok(match self {
             Token::Literal(lit) => match lit {
                 Literal::Ident(id) => visitor.resolve_any_var(id.as_str()),
                 _ => lit.to_owned(),
             },
             Token::Expression(expr) => {
-                expr.visit(visitor).unwrap();
-                visitor.pop_stack()
+                expr.visit(visitor)?;
+                visitor.pop_stack()?
             }
-            _ => panic!("{}", panic_msg),
-        }
+            _ => bail!("{}", err_msg),
+        })
     }
 
-    pub fn as_lit_no_ident<V>(&mut self, visitor: &mut V, panic_msg: &str) -> Literal
+    pub fn as_lit_no_ident<V>(&mut self, visitor: &mut V, err_msg: &str) -> anyhow::Result<Literal>
     where
         V: Visitor,
     {
-        match self {
+        Ok(match self {
             Token::Literal(lit) => lit.to_owned(),
             Token::Expression(expr) => {
-                expr.visit(visitor).unwrap();
-                visitor.pop_stack()
+                expr.visit(visitor)?;
+                visitor.pop_stack()?
             }
-            _ => panic!("{}", panic_msg),
-        }
+            _ => bail!("{}", err_msg),
+        })
     }
 
     pub fn as_lit(&self, panic_msg: &str) -> Literal {
@@ -158,4 +184,36 @@ impl Token {
             _ => panic!("{}", panic_msg),
         }
     }
+
+    /// Expression of this token, if it is one
+    pub fn as_expr(&self) -> Option<&Expression> {
+        match self {
+            Token::Expression(expr) => Some(expr),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a `{ ... }` block from the visitor, brackets of the block itself are not included
+pub(crate) fn read_block<V>(visitor: &mut V) -> anyhow::Result<TokenChain>
+where
+    V: Visitor,
+{
+    match visitor.next_token()? {
+        Token::LBracket => {}
+        other => bail!("Expected a block, got {:?}!", other),
+    }
+    let mut chain = TokenChain::new();
+    let mut depth = 0;
+    loop {
+        let tk = visitor.next_token()?;
+        match tk {
+            Token::LBracket => depth += 1,
+            Token::RBracket if depth == 0 => break,
+            Token::RBracket => depth -= 1,
+            _ => {}
+        }
+        chain.push(tk);
+    }
+    Ok(chain)
 }
@@ -0,0 +1,281 @@
+use anyhow::bail;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::str::FromStr;
+
+/// Most decimal places a [`Decimal`] can have
+pub const MAX_SCALE: u32 = 28;
+/// Least decimal places of a quotient, so `1d / 3d` is `0.3333333333333333`
+pub const DIV_SCALE: u32 = 16;
+
+/// Exact base-10 number, written as `12.50d`. It is the `mantissa` scaled down by `scale`
+/// decimal places, so `12.50d` has a mantissa of `1250` and a scale of `2`.
+///
+/// Sums and differences keep the larger scale of their operands and products add them up,
+/// so amounts keep the places they were written with. Quotients have at least
+/// [`DIV_SCALE`] places, without the zeros that trail past the places of their operands.
+/// Results round half away from zero once they need more than [`MAX_SCALE`] places,
+/// and arithmetic panics when the mantissa overflows.
+///
+/// Decimals are compared by their value, so `1.5d` equals `1.50d`
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+/// How a [`Decimal`] is rounded when it loses places
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rounding {
+    /// `2.5` becomes `3` and `-2.5` becomes `-3`
+    HalfUp,
+    /// `2.5` becomes `2` and `3.5` becomes `4`, also known as banker's rounding
+    HalfEven,
+}
+
+fn _pow10(exp: u32) -> Option<i128> {
+    10i128.checked_pow(exp)
+}
+
+impl Decimal {
+    pub fn new(mantissa: i128, scale: u32) -> anyhow::Result<Self> {
+        if scale > MAX_SCALE {
+            bail!("Decimal scale {} is larger than {}!", scale, MAX_SCALE)
+        }
+        Ok(Self { mantissa, scale })
+    }
+
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Rounds or pads the decimal to exactly `scale` places, `None` if the mantissa overflows
+    pub fn with_scale(&self, scale: u32, rounding: Rounding) -> Option<Self> {
+        let scale = scale.min(MAX_SCALE);
+        let mantissa = if scale >= self.scale {
+            self.mantissa.checked_mul(_pow10(scale - self.scale)?)?
+        } else {
+            _round_div(self.mantissa, _pow10(self.scale - scale)?, rounding)
+        };
+        Some(Self { mantissa, scale })
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // parsing the text keeps the closest float, dividing the mantissa would not
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        let (lh, rh, scale) = _aligned(self, rhs)?;
+        Self::_fit(lh.checked_add(rh)?, scale)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        let (lh, rh, scale) = _aligned(self, rhs)?;
+        Self::_fit(lh.checked_sub(rh)?, scale)
+    }
+
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        Self::_fit(self.mantissa.checked_mul(rhs.mantissa)?, self.scale + rhs.scale)
+    }
+
+    /// Quotient of the decimals, `None` if it overflows or `rhs` is zero
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        if rhs.mantissa == 0 {
+            return None;
+        }
+        let kept = self.scale.max(rhs.scale);
+        let scale = kept.max(DIV_SCALE);
+        let lh = self.mantissa.checked_mul(_pow10(scale + rhs.scale - self.scale)?)?;
+        let mut quotient = Self {
+            mantissa: _round_div(lh, rhs.mantissa, Rounding::HalfUp),
+            scale,
+        };
+        while quotient.scale > kept && quotient.mantissa % 10 == 0 {
+            quotient.mantissa /= 10;
+            quotient.scale -= 1;
+        }
+        Some(quotient)
+    }
+
+    pub fn checked_rem(self, rhs: Self) -> Option<Self> {
+        let (lh, rh, scale) = _aligned(self, rhs)?;
+        Some(Self { mantissa: lh.checked_rem(rh)?, scale })
+    }
+
+    fn _fit(mantissa: i128, scale: u32) -> Option<Self> {
+        let value = Self { mantissa, scale };
+        if scale > MAX_SCALE {
+            value.with_scale(MAX_SCALE, Rounding::HalfUp)
+        } else {
+            Some(value)
+        }
+    }
+}
+
+// Mantissas of both decimals at the larger of their scales
+fn _aligned(lh: Decimal, rh: Decimal) -> Option<(i128, i128, u32)> {
+    let scale = lh.scale.max(rh.scale);
+    let lhm = lh.mantissa.checked_mul(_pow10(scale - lh.scale)?)?;
+    let rhm = rh.mantissa.checked_mul(_pow10(scale - rh.scale)?)?;
+    Some((lhm, rhm, scale))
+}
+
+fn _round_div(value: i128, by: i128, rounding: Rounding) -> i128 {
+    let (quotient, rem) = (value / by, value % by);
+    let twice = rem.unsigned_abs() * 2;
+    let up = match twice.cmp(&by.unsigned_abs()) {
+        Ordering::Greater => true,
+        Ordering::Equal => rounding == Rounding::HalfUp || quotient % 2 != 0,
+        Ordering::Less => false,
+    };
+    if !up {
+        quotient
+    } else if (value < 0) != (by < 0) {
+        quotient - 1
+    } else {
+        quotient + 1
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Self {
+            mantissa: value as i128,
+            scale: 0,
+        }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = anyhow::Error;
+
+    /// Parses decimals like `12.50`, `-3` or `1_000.25`, without exponents
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.replace('_', "");
+        let (negative, digits) = match digits.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if int.is_empty()
+            || !int.chars().chain(frac.chars()).all(|it| it.is_ascii_digit())
+            || (digits.contains('.') && frac.is_empty())
+        {
+            bail!("Invalid decimal {}!", s)
+        }
+        let scale = frac.len() as u32;
+        if scale > MAX_SCALE {
+            bail!("Decimal {} has more than {} places!", s, MAX_SCALE)
+        }
+        let mantissa: i128 = match format!("{}{}", int, frac).parse() {
+            Ok(mantissa) => mantissa,
+            Err(_) => bail!("Decimal {} is too large!", s),
+        };
+        Ok(Self {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale,
+        })
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        if self.mantissa < 0 {
+            f.write_str("-")?;
+        }
+        f.write_str(int)?;
+        if scale > 0 {
+            write!(f, ".{}", frac)?;
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match _aligned(*self, *other) {
+            Some((lh, rh, _)) => lh.cmp(&rh),
+            // only the decimal of the smaller scale is scaled up, which overflows when its
+            // magnitude is larger than the other one could ever be
+            None if self.scale < other.scale => self.mantissa.cmp(&0),
+            None => 0.cmp(&other.mantissa),
+        }
+    }
+}
+
+macro_rules! _decimal_op {
+    ($($tr:ident $fun:ident $checked:ident $op:literal),*) => {
+        $(
+            impl $tr for Decimal {
+                type Output = Decimal;
+
+                fn $fun(self, rhs: Self) -> Self::Output {
+                    match self.$checked(rhs) {
+                        Some(value) => value,
+                        None if rhs.mantissa == 0 && matches!($op, "/" | "%") => {
+                            panic!("Can not divide {} by zero!", self)
+                        }
+                        None => panic!("Decimal {} {} {} overflows!", self, $op, rhs),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+_decimal_op!(
+    Add add checked_add "+",
+    Sub sub checked_sub "-",
+    Mul mul checked_mul "*",
+    Div div checked_div "/",
+    Rem rem checked_rem "%"
+);
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Self::Output {
+        match self.mantissa.checked_neg() {
+            Some(mantissa) => Self {
+                mantissa,
+                scale: self.scale,
+            },
+            None => panic!("Decimal -{} overflows!", self),
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Decimal {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            mantissa: u.arbitrary()?,
+            scale: u.int_in_range(0..=MAX_SCALE)?,
+        })
+    }
+}
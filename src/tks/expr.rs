@@ -1,21 +1,40 @@
-use std::collections::VecDeque;
-use crate::tks::expr_handlers::_binary_op_handler;
-use crate::tks::{BinaryOp, Ident, Literal, Token, TokenChain, UnaryOp};
+use crate::structs::StructureInstance;
+use crate::tks::expr_handlers::{_binary_op_handler, _index_handler};
+use crate::tks::{read_block, BinaryOp, Ident, Literal, Token, TokenChain, UnaryOp};
 use crate::visit::{Visitable, Visitor};
 use crate::vm::Transmute;
 use anyhow::bail;
-use std::io::Cursor;
+use std::io::{Read, Write};
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Expression {
     BinaryOp(BinaryOp, Token, Token),
     UnaryOp(UnaryOp, Token),
     StaticAccess(Vec<Ident>),
     InvokeStatic(Ident, TokenChain),
+    Instantiate(Ident, TokenChain),
+    InstanceAccess(Ident, Ident),
+    InvokeInstance(Ident, Ident, TokenChain),
+    /// `receiver.name(params)` on the result of another expression, like `a.b().c()`.
+    /// The receiver is a temporary value, so changes to `this` are not written back
+    InvokeChained(Token, Ident, TokenChain),
+    Array(TokenChain),
+    /// `cond ? then : else`, only the selected branch is evaluated
+    Ternary(Token, Token, Token),
+    /// `value[index]` of an array or string, or a slice of it like `value[1..3]`
+    Index(Token, Token),
     IfStmt,
     ElseStmt,
     ElifStmt,
     WhileStmt,
+    /// `do { ... } while cond`, body of which is executed at least once
+    DoWhileStmt,
+    /// `match value { Variant(binding) => { ... } _ => { ... } }`, followed by the matched value
+    /// and a block of arms. Each arm is a variant name or `_`, the name its payload is bound to
+    /// (`_` to ignore it) and a block
+    MatchStmt,
 }
 
 impl Transmute for Expression {
@@ -25,55 +44,125 @@ impl Transmute for Expression {
             Expression::UnaryOp(op, l) => op.size() + l.size(),
             Expression::StaticAccess(i) => i.size(),
             Expression::InvokeStatic(i, p) => i.size() + p.size(),
+            Expression::Instantiate(i, p) => i.size() + p.size(),
+            Expression::InstanceAccess(i, f) => i.size() + f.size(),
+            Expression::InvokeInstance(i, f, p) => i.size() + f.size() + p.size(),
+            Expression::InvokeChained(r, f, p) => r.size() + f.size() + p.size(),
+            Expression::Array(v) => v.size(),
+            Expression::Ternary(c, t, e) => c.size() + t.size() + e.size(),
+            Expression::Index(v, i) => v.size() + i.size(),
             _ => 0,
         }
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
         match self {
             Expression::BinaryOp(op, l, r) => {
-                0x00u8.write(buf)?;
-                op.write(buf)?;
-                l.write(buf)?;
-                r.write(buf)?;
+                0x00u8.write_to(buf)?;
+                op.write_to(buf)?;
+                l.write_to(buf)?;
+                r.write_to(buf)?;
             }
             Expression::UnaryOp(op, l) => {
-                0x01u8.write(buf)?;
-                op.write(buf)?;
-                l.write(buf)?;
+                0x01u8.write_to(buf)?;
+                op.write_to(buf)?;
+                l.write_to(buf)?;
             }
             Expression::StaticAccess(i) => {
-                0x02u8.write(buf)?;
-                i.write(buf)?;
+                0x02u8.write_to(buf)?;
+                i.write_to(buf)?;
             }
             Expression::InvokeStatic(i, p) => {
-                0x03u8.write(buf)?;
-                i.write(buf)?;
-                p.write(buf)?;
+                0x03u8.write_to(buf)?;
+                i.write_to(buf)?;
+                p.write_to(buf)?;
+            }
+            Expression::IfStmt => 0x04u8.write_to(buf)?,
+            Expression::ElseStmt => 0x05u8.write_to(buf)?,
+            Expression::WhileStmt => 0x06u8.write_to(buf)?,
+            Expression::ElifStmt => 0x07u8.write_to(buf)?,
+            Expression::DoWhileStmt => 0x0Eu8.write_to(buf)?,
+            Expression::MatchStmt => 0x0Fu8.write_to(buf)?,
+            Expression::Instantiate(i, p) => {
+                0x09u8.write_to(buf)?;
+                i.write_to(buf)?;
+                p.write_to(buf)?;
+            }
+            Expression::InstanceAccess(i, f) => {
+                0x0Au8.write_to(buf)?;
+                i.write_to(buf)?;
+                f.write_to(buf)?;
+            }
+            Expression::InvokeInstance(i, f, p) => {
+                0x0Bu8.write_to(buf)?;
+                i.write_to(buf)?;
+                f.write_to(buf)?;
+                p.write_to(buf)?;
+            }
+            Expression::InvokeChained(r, f, p) => {
+                0x10u8.write_to(buf)?;
+                r.write_to(buf)?;
+                f.write_to(buf)?;
+                p.write_to(buf)?;
+            }
+            Expression::Array(v) => {
+                0x0Cu8.write_to(buf)?;
+                v.write_to(buf)?;
+            }
+            Expression::Ternary(c, t, e) => {
+                0x0Du8.write_to(buf)?;
+                c.write_to(buf)?;
+                t.write_to(buf)?;
+                e.write_to(buf)?;
+            }
+            Expression::Index(v, i) => {
+                0x11u8.write_to(buf)?;
+                v.write_to(buf)?;
+                i.write_to(buf)?;
             }
-            Expression::IfStmt => 0x04u8.write(buf)?,
-            Expression::ElseStmt => 0x05u8.write(buf)?,
-            Expression::WhileStmt => 0x06u8.write(buf)?,
-            Expression::ElifStmt => 0x07u8.write(buf)?,
         };
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(match u8::read(buf)? {
-            0x00 => {
-                Expression::BinaryOp(BinaryOp::read(buf)?, Token::read(buf)?, Token::read(buf)?)
-            }
-            0x01 => Expression::UnaryOp(UnaryOp::read(buf)?, Token::read(buf)?),
-            0x03 => Expression::InvokeStatic(Ident::read(buf)?, TokenChain::read(buf)?),
-            0x05 => Expression::IfStmt,
-            0x06 => Expression::ElseStmt,
-            0x07 => Expression::WhileStmt,
-            0x08 => Expression::ElifStmt,
-            _ => bail!("Invalid expression provided!"),
+        Ok(match u8::read_from(buf)? {
+            0x00 => Expression::BinaryOp(
+                BinaryOp::read_from(buf)?,
+                Token::read_from(buf)?,
+                Token::read_from(buf)?,
+            ),
+            0x01 => Expression::UnaryOp(UnaryOp::read_from(buf)?, Token::read_from(buf)?),
+            0x02 => Expression::StaticAccess(Vec::read_from(buf)?),
+            0x03 => Expression::InvokeStatic(Ident::read_from(buf)?, TokenChain::read_from(buf)?),
+            0x04 => Expression::IfStmt,
+            0x05 => Expression::ElseStmt,
+            0x06 => Expression::WhileStmt,
+            0x07 => Expression::ElifStmt,
+            0x09 => Expression::Instantiate(Ident::read_from(buf)?, TokenChain::read_from(buf)?),
+            0x0A => Expression::InstanceAccess(Ident::read_from(buf)?, Ident::read_from(buf)?),
+            0x0B => Expression::InvokeInstance(
+                Ident::read_from(buf)?,
+                Ident::read_from(buf)?,
+                TokenChain::read_from(buf)?,
+            ),
+            0x0C => Expression::Array(TokenChain::read_from(buf)?),
+            0x0D => Expression::Ternary(
+                Token::read_from(buf)?,
+                Token::read_from(buf)?,
+                Token::read_from(buf)?,
+            ),
+            0x0E => Expression::DoWhileStmt,
+            0x0F => Expression::MatchStmt,
+            0x10 => Expression::InvokeChained(
+                Token::read_from(buf)?,
+                Ident::read_from(buf)?,
+                TokenChain::read_from(buf)?,
+            ),
+            0x11 => Expression::Index(Token::read_from(buf)?, Token::read_from(buf)?),
+            tag => bail!("Unknown expression tag {:#04x}!", tag),
         })
     }
 }
@@ -100,10 +189,11 @@ macro_rules! _tkbool {
 macro_rules! _tk2lit {
     ($v:ident $visitor:ident) => {
         match $v {
+            Token::Literal(Literal::Ident(name)) => $visitor.resolve_any_var(name),
             Token::Literal(lit) => lit.to_owned(),
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Expected literal, got {:?}", $v),
         }
@@ -133,6 +223,7 @@ impl Visitable for Expression {
                         let l = match lit {
                             Literal::Number(num) => Literal::Number(-num),
                             Literal::Float(f) => Literal::Float(-f),
+                            Literal::Decimal(d) => Literal::Decimal(-d),
                             _ => panic!("Invalid literal provided!"),
                         };
                         visitor.push_stack(l);
@@ -141,53 +232,210 @@ impl Visitable for Expression {
                 }
             }
             Expression::StaticAccess(path) => {
-                // TODO: table handling
-                return Ok(());
+                let (name, scope) = match path.split_last() {
+                    Some((name, scope)) if !scope.is_empty() => (name, scope.join("::")),
+                    _ => bail!("Expected a scope to access, got {:?}!", path),
+                };
+                if let Ok(template) = visitor.resolve_enum(&scope) {
+                    visitor.push_stack(template.instantiate(name, Literal::Void)?);
+                    return Ok(());
+                }
+                visitor.check_access(&scope, name)?;
+                let scope = visitor.get_scope(scope);
+                let value = match scope.get_const(name).or_else(|| scope.get_var(name)) {
+                    Some(value) => value,
+                    None => bail!("Could not find static value {}!", path.join(".")),
+                };
+                drop(scope);
+                visitor.push_stack(value);
+                Ok(())
             }
             Expression::InvokeStatic(path, params) => {
+                if let Some((name, variant)) = path.rsplit_once("::") {
+                    if let Ok(template) = visitor.resolve_enum(name) {
+                        let payload = match params.as_mut_slice() {
+                            [] => Literal::Void,
+                            [payload] => payload.as_lit_advanced(visitor, "Expected a variant payload!")?,
+                            _ => bail!("Variant {} takes a single payload, got {}!", path, params.len()),
+                        };
+                        visitor.push_stack(template.instantiate(variant, payload)?);
+                        return Ok(());
+                    }
+                }
                 let lit = visitor.call_static_fn(path.to_owned(), params.to_vec());
                 visitor.push_stack(lit);
                 return Ok(());
             }
-            Expression::IfStmt => _visit_if(visitor),
-            Expression::WhileStmt => {
-                let mut condition = visitor.next_token()?;
-
-                if _tkbool!(condition.as_lit_advanced(visitor, "Could not process while condition!"))
-                {
-                    let _lbracket = visitor.next_token()?;
-                    let mut chain = TokenChain::new();
-                    while visitor.peek_token()? != Token::RBracket {
-                        chain.push(visitor.next_token()?);
+            Expression::Instantiate(type_name, fields) => {
+                let template = visitor.resolve_type(type_name)?;
+                let mut instance = StructureInstance::from_template(&template);
+                for field in fields {
+                    match field {
+                        Token::Expression(expr) => match expr.as_mut() {
+                            Expression::BinaryOp(BinaryOp::Assign, Token::Literal(Literal::Ident(name)), value) => {
+                                let value = value.as_lit_advanced(visitor, "Expected a field value!")?;
+                                instance.set_field(&template, name, value)?;
+                            }
+                            _ => bail!("Expected a field assignment, got {:?}!", expr),
+                        },
+                        _ => bail!("Expected a field assignment, got {:?}!", field),
                     }
-
-                    chain.reverse();
-                    let _rbracket = visitor.next_token()?;
-
-                    while _tkbool!(
-                        condition.as_lit_advanced(visitor, "Could not process while condition!")
-                    ) {
-                        for ele in &chain {
-                            visitor.insert_token(ele.to_owned(), 0);
+                }
+                instance.validate(&template)?;
+                visitor.push_stack(Literal::Struct(Box::new(instance)));
+                Ok(())
+            }
+            Expression::InstanceAccess(receiver, field) => {
+                let value = match visitor.resolve_any_var(receiver) {
+                    Literal::Struct(instance) => match instance.get_field(field) {
+                        Some(value) => {
+                            visitor.check_access(&instance.type_name(), field)?;
+                            value
                         }
-                        visitor.process_until(chain.len());
+                        None => bail!("Field {}.{} is not set!", receiver, field),
+                    },
+                    other => bail!("Tried to access field {} of non-struct value {}!", field, other),
+                };
+                visitor.push_stack(value);
+                Ok(())
+            }
+            Expression::InvokeInstance(receiver, name, params) => {
+                let lit = visitor.call_inst_fn(receiver.to_owned(), name.to_owned(), params.to_vec());
+                visitor.push_stack(lit);
+                Ok(())
+            }
+            Expression::InvokeChained(receiver, name, params) => {
+                let instance = receiver.as_lit_advanced(visitor, "Expected a receiver of instance function!")?;
+                let lit = visitor.call_inst_fn_on(instance, name.to_owned(), params.to_vec());
+                visitor.push_stack(lit);
+                Ok(())
+            }
+            Expression::Array(values) => {
+                let values = values
+                    .iter_mut()
+                    .map(|it| it.as_lit_advanced(visitor, "Expected an array element!"))
+                    .collect::<anyhow::Result<Vec<Literal>>>()?;
+                visitor.push_stack(Literal::Array(values));
+                Ok(())
+            }
+            Expression::Ternary(condition, then, otherwise) => {
+                let condition = condition.as_lit_advanced(visitor, "Could not process ternary condition!")?;
+                let branch = if _tkbool!(condition) { then } else { otherwise };
+                let value = branch.as_lit_advanced(visitor, "Expected a ternary branch value!")?;
+                visitor.push_stack(value);
+                Ok(())
+            }
+            Expression::Index(value, index) => _index_handler(visitor, value, index),
+            Expression::IfStmt => _visit_if(visitor),
+            Expression::WhileStmt => _visit_while(visitor),
+            Expression::MatchStmt => _visit_match(visitor),
+            Expression::DoWhileStmt => {
+                let mut body = read_block(visitor)?;
+                let mut condition = visitor.next_token()?;
+                loop {
+                    visitor.process_isolated(&mut body);
+                    if !_tkbool!(condition.as_lit_advanced(visitor, "Could not process while condition!")?) {
+                        return Ok(());
                     }
-                } else {
-                    visitor.push_stack(Literal::Void);
                 }
-                return Ok(());
             }
             _ => bail!("Unexpected unbounded {:?} token!", self),
         }
     }
 }
 
+/// Runs a `while` loop, with an optional `else` block that runs when the loop body never did
+fn _visit_while<V>(visitor: &mut V) -> anyhow::Result<()>
+where
+    V: Visitor,
+{
+    let mut condition = visitor.next_token()?;
+    let mut body = read_block(visitor)?;
+    let mut otherwise = match visitor.peek_token() {
+        Ok(tk) if matches!(tk.as_expr(), Some(Expression::ElseStmt)) => {
+            let _ = visitor.next_token()?;
+            Some(read_block(visitor)?)
+        }
+        _ => None,
+    };
+
+    let mut ran = false;
+    while _tkbool!(condition.as_lit_advanced(visitor, "Could not process while condition!")?) {
+        visitor.process_isolated(&mut body);
+        ran = true;
+    }
+    match &mut otherwise {
+        Some(otherwise) if !ran => visitor.process_isolated(otherwise),
+        None if !ran => visitor.push_stack(Literal::Void),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Runs the first arm of a `match` that names the variant of the value, or `_`
+fn _visit_match<V>(visitor: &mut V) -> anyhow::Result<()>
+where
+    V: Visitor,
+{
+    let mut value = visitor.next_token()?;
+    let (path, payload) = match value.as_lit_advanced(visitor, "Expected a value to match!")? {
+        Literal::Enum(path, payload) => (path, payload),
+        other => bail!("Expected an enum value to match, got {}!", other),
+    };
+    let variant = path.rsplit_once("::").map(|it| it.1).unwrap_or(&path);
+
+    let mut arms = read_block(visitor)?.into_iter();
+    let mut matched = None;
+    while let Some(pattern) = arms.next() {
+        let pattern = match pattern {
+            Token::Literal(Literal::TypeName(pattern)) => pattern,
+            other => bail!("Expected a match pattern, got {:?}!", other),
+        };
+        let binding = match arms.next() {
+            Some(Token::Literal(Literal::Ident(binding))) => binding,
+            other => bail!("Expected a name for the payload of {}, got {:?}!", pattern, other),
+        };
+        if arms.next() != Some(Token::LBracket) {
+            bail!("Expected a body of match arm {}!", pattern)
+        }
+        let mut body = TokenChain::new();
+        let mut depth = 0;
+        loop {
+            let tk = match arms.next() {
+                Some(tk) => tk,
+                None => bail!("Unclosed body of match arm {}!", pattern),
+            };
+            match tk {
+                Token::LBracket => depth += 1,
+                Token::RBracket if depth == 0 => break,
+                Token::RBracket => depth -= 1,
+                _ => {}
+            }
+            body.push(tk);
+        }
+        if matched.is_none() && (pattern == variant || pattern == "_") {
+            matched = Some((binding, body));
+        }
+    }
+
+    match matched {
+        Some((binding, mut body)) => {
+            if binding != "_" {
+                visitor.declare_var(binding, *payload, None);
+            }
+            visitor.process_isolated(&mut body);
+            Ok(())
+        }
+        None => bail!("No arm of match covers {}!", path),
+    }
+}
+
 fn _visit_if<V>(visitor: &mut V) -> anyhow::Result<()>
 where
     V: Visitor,
 {
     let mut next = visitor.next_token()?;
-    let next = next.as_lit_advanced(visitor, "Expected a literal-like statement!");
+    let next = next.as_lit_advanced(visitor, "Expected a literal-like statement!")?;
     let boolean = match next {
         Literal::Number(n) => n != 0,
         Literal::Bool(b) => b,
@@ -212,8 +460,8 @@ where
 
         while let Ok(_) = &mut visitor.peek_token() {
             let mut expr = visitor.peek_token()?;
-            if let Token::Expression(box expr) = &mut expr {
-                match expr {
+            if let Token::Expression(expr) = &mut expr {
+                match expr.as_mut() {
                     Expression::ElifStmt => {
                         let _ = _visit_elif(visitor, true);
                     }
@@ -236,12 +484,12 @@ where
         // trying to find elif's and else's
         let mut matched = false;
         while let Ok(Token::Expression(expr)) = &mut visitor.peek_token() {
-            match expr {
-                box Expression::ElseStmt => {
+            match expr.as_ref() {
+                Expression::ElseStmt => {
                     _visit_else(visitor, matched)?;
                     return Ok(());
                 }
-                box Expression::ElifStmt => {
+                Expression::ElifStmt => {
                     let success = _visit_elif(visitor, matched);
                     matched = success.is_ok();
                 }
@@ -289,7 +537,7 @@ where
     // consuming current token
     let _ = visitor.next_token()?;
     let mut next = visitor.next_token()?;
-    let next = next.as_lit_advanced(visitor, "Expected a boolean!");
+    let next = next.as_lit_advanced(visitor, "Expected a boolean!")?;
     let boolean = _tkbool!(next);
     // consuming tokens, dropping them anyways if not needed
     let _lbracket = visitor.next_token()?;
@@ -1,5 +1,8 @@
-use crate::tks::{BinaryOp, Literal, Token};
+use crate::marshal::FromLiteral;
+use crate::stdlib::range::Range;
+use crate::tks::{BinaryOp, Decimal, Expression, Literal, Token};
 use crate::visit::{Visitable, Visitor};
+use crate::warn::WarningCode;
 use anyhow::bail;
 
 //#region bits + bools
@@ -11,7 +14,7 @@ macro_rules! _sh_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -21,7 +24,7 @@ macro_rules! _sh_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -57,7 +60,7 @@ macro_rules! _bit_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -67,7 +70,7 @@ macro_rules! _bit_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -103,7 +106,7 @@ macro_rules! _bool_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -113,7 +116,7 @@ macro_rules! _bool_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -127,7 +130,7 @@ macro_rules! _bool_impl {
         } else {
             rh
         };
-        match &mut lh {
+        let d = match &mut lh {
             Literal::Bool(lb) => {
                 if let Literal::Bool(rb) = rh {
                     Literal::Bool(*lb $oper rb)
@@ -135,8 +138,38 @@ macro_rules! _bool_impl {
                     panic!("Invalid operand provided!")
                 }
             }
+            // decimals are compared by their value, so `1.5d == 1.50d`
+            Literal::Decimal(ld) => match rh {
+                Literal::Decimal(rd) => Literal::Bool(*ld $oper rd),
+                Literal::Number(rnum) => Literal::Bool(*ld $oper Decimal::from(rnum)),
+                Literal::Float(rf) => Literal::Bool(ld.to_f64() $oper rf),
+                _ => panic!("Invalid operand provided!")
+            }
+            Literal::Number(lnum) => match rh {
+                Literal::Number(rnum) => Literal::Bool(*lnum $oper rnum),
+                Literal::Decimal(rd) => Literal::Bool(Decimal::from(*lnum) $oper rd),
+                Literal::Float(rf) => Literal::Bool(*lnum as f64 $oper rf),
+                _ => panic!("Invalid operand provided!")
+            }
+            Literal::Float(lf) => match rh {
+                Literal::Float(rf) => Literal::Bool(*lf $oper rf),
+                Literal::Number(rnum) => Literal::Bool(*lf $oper rnum as f64),
+                Literal::Decimal(rd) => Literal::Bool(*lf $oper rd.to_f64()),
+                _ => panic!("Invalid operand provided!")
+            }
+            Literal::String(ls) => match rh {
+                Literal::String(rs) => Literal::Bool(*ls $oper rs),
+                Literal::Char(rc) => Literal::Bool(*ls $oper rc.to_string()),
+                _ => panic!("Invalid operand provided!")
+            }
+            Literal::Char(lc) => match rh {
+                Literal::Char(rc) => Literal::Bool(*lc $oper rc),
+                Literal::String(rs) => Literal::Bool(lc.to_string() $oper rs),
+                _ => panic!("Invalid operand provided!")
+            }
             _ => panic!("Invalid operand provided!")
-        }
+        };
+        $visitor.push_stack(d);
     }
 }
 //#endregion bits + bools
@@ -149,7 +182,7 @@ macro_rules! _bin_expr_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -159,7 +192,7 @@ macro_rules! _bin_expr_impl {
             }
             Token::Expression(expr) => {
                 expr.visit($visitor)?;
-                $visitor.pop_stack()
+                $visitor.pop_stack()?
             }
             _ => panic!("Invalid operand provided!")
         };
@@ -183,6 +216,7 @@ macro_rules! _visit_impl {
             $(
             Literal::String(str) => {
                 let _ = $str;
+                _warn_conversion($visitor, &rh);
                 match rh {
                     Literal::Number(num) => {
                         Literal::String(str.to_owned() $oper &num.to_string())
@@ -190,6 +224,9 @@ macro_rules! _visit_impl {
                     Literal::Float(f) => {
                         Literal::String(str.to_owned() $oper &f.to_string())
                     }
+                    Literal::Decimal(d) => {
+                        Literal::String(str.to_owned() $oper &d.to_string())
+                    }
                     Literal::String(rstr) => {
                         Literal::String(str.to_owned() $oper &rstr)
                     }
@@ -200,12 +237,16 @@ macro_rules! _visit_impl {
                 }
             }
             )?
-            Literal::Number(lnum) => {
-                if let Literal::Number(rnum) = rh {
-                    Literal::Number(*lnum $oper rnum)
-                } else {
-                    panic!("Invalid operand provided!")
-                }
+            Literal::Number(lnum) => match rh {
+                Literal::Number(rnum) => Literal::Number(*lnum $oper rnum),
+                Literal::Decimal(rd) => Literal::Decimal(Decimal::from(*lnum) $oper rd),
+                _ => panic!("Invalid operand provided!")
+            }
+            // nums are exact, so they mix with decimals, floats do not
+            Literal::Decimal(ld) => match rh {
+                Literal::Decimal(rd) => Literal::Decimal(*ld $oper rd),
+                Literal::Number(rnum) => Literal::Decimal(*ld $oper Decimal::from(rnum)),
+                _ => panic!("Invalid operand provided!")
             }
             Literal::Float(f) => {
                 if let Literal::Float(rnum) = rh {
@@ -232,8 +273,8 @@ macro_rules! _visit_impl {
 
 macro_rules! _lt_gt_impl {
     ($visitor:ident $oper:tt $lh:ident $rh:ident) => {
-        let lh = $lh.as_lit_advanced($visitor, "Expected a literal-like!");
-        let rh = $rh.as_lit_advanced($visitor, "Expected a literal-like!");
+        let lh = $lh.as_lit_advanced($visitor, "Expected a literal-like!")?;
+        let rh = $rh.as_lit_advanced($visitor, "Expected a literal-like!")?;
         let mut lh = if let Literal::Ident(name) = lh {
             $visitor.resolve_any_var(name.to_owned().as_str())
         } else {
@@ -246,6 +287,7 @@ macro_rules! _lt_gt_impl {
         };
         let d = match &mut lh {
             Literal::String(str) => {
+                _warn_conversion($visitor, &rh);
                 match rh {
                     Literal::Number(num) => {
                         Literal::Bool(str.to_owned() $oper num.to_string())
@@ -253,6 +295,9 @@ macro_rules! _lt_gt_impl {
                     Literal::Float(f) => {
                         Literal::Bool(str.to_owned() $oper f.to_string())
                     }
+                    Literal::Decimal(d) => {
+                        Literal::Bool(*str $oper d.to_string())
+                    }
                     Literal::String(rstr) => {
                         Literal::Bool(str.to_owned() $oper rstr)
                     }
@@ -262,12 +307,15 @@ macro_rules! _lt_gt_impl {
                     _ => panic!("Invalid operand provided!")
                 }
             }
-            Literal::Number(lnum) => {
-                if let Literal::Number(rnum) = rh {
-                    Literal::Bool(*lnum $oper rnum)
-                } else {
-                    panic!("Invalid operand provided!")
-                }
+            Literal::Number(lnum) => match rh {
+                Literal::Number(rnum) => Literal::Bool(*lnum $oper rnum),
+                Literal::Decimal(rd) => Literal::Bool(Decimal::from(*lnum) $oper rd),
+                _ => panic!("Invalid operand provided!")
+            }
+            Literal::Decimal(ld) => match rh {
+                Literal::Decimal(rd) => Literal::Bool(*ld $oper rd),
+                Literal::Number(rnum) => Literal::Bool(*ld $oper Decimal::from(rnum)),
+                _ => panic!("Invalid operand provided!")
             }
             Literal::Float(f) => {
                 if let Literal::Float(rnum) = rh {
@@ -283,6 +331,18 @@ macro_rules! _lt_gt_impl {
 }
 //#endregion binary expr impl
 
+fn _warn_conversion<V>(visitor: &mut V, value: &Literal)
+where
+    V: Visitor,
+{
+    if !matches!(value, Literal::String(_)) {
+        visitor.warn(
+            WarningCode::ImplicitConversion,
+            format!("Value of type {} is implicitly converted to str", value.this_type()),
+        )
+    }
+}
+
 pub(crate) fn _binary_op_handler<V>(
     visitor: &mut V,
     op: &mut BinaryOp,
@@ -294,13 +354,18 @@ where
 {
     match op {
         BinaryOp::Assign => {
+            if let Token::Expression(expr) = lh {
+                if let Expression::InstanceAccess(receiver, field) = expr.as_ref() {
+                    return _field_assign(visitor, receiver, field, rh);
+                }
+            }
             let lh = lh.as_lit("Expected a variable name to set!");
             if let Literal::Ident(lh) = lh {
                 let rh = match rh {
                     Token::Literal(lit) => lit.to_owned(),
                     Token::Expression(expr) => {
                         expr.visit(visitor)?;
-                        visitor.pop_stack()
+                        visitor.pop_stack()?
                     }
                     _ => bail!("Invalid operand provided!"),
                 };
@@ -324,12 +389,8 @@ where
         BinaryOp::Mod => {
             _bin_expr_impl!(visitor % lh rh);
         }
-        BinaryOp::And => {
-            _bool_impl!(visitor && lh rh);
-        }
-        BinaryOp::Or => {
-            _bool_impl!(visitor || lh rh);
-        }
+        BinaryOp::And => _short_circuit(visitor, true, lh, rh)?,
+        BinaryOp::Or => _short_circuit(visitor, false, lh, rh)?,
         BinaryOp::Eq => {
             _bool_impl!(visitor == lh rh);
         }
@@ -360,3 +421,87 @@ where
     };
     Ok(())
 }
+
+/// Evaluates `&&` (when `is_and`) or `||`, the right operand is only visited
+/// when the left one does not already determine the result
+fn _short_circuit<V>(visitor: &mut V, is_and: bool, lh: &mut Token, rh: &mut Token) -> anyhow::Result<()>
+where
+    V: Visitor,
+{
+    let lh = _bool_operand(visitor, lh)?;
+    let result = if lh != is_and { lh } else { _bool_operand(visitor, rh)? };
+    visitor.push_stack(Literal::Bool(result));
+    Ok(())
+}
+
+fn _bool_operand<V>(visitor: &mut V, tk: &mut Token) -> anyhow::Result<bool>
+where
+    V: Visitor,
+{
+    match tk.as_lit_advanced(visitor, "Invalid operand provided!")? {
+        Literal::Bool(b) => Ok(b),
+        other => bail!("Expected a bool operand, got {}!", other),
+    }
+}
+
+fn _field_assign<V>(visitor: &mut V, receiver: &str, field: &str, rh: &mut Token) -> anyhow::Result<()>
+where
+    V: Visitor,
+{
+    let mut instance = match visitor.resolve_var(receiver) {
+        Ok(Literal::Struct(instance)) => instance,
+        Ok(other) => bail!("Tried to set field {} of non-struct value {}!", field, other),
+        Err(_) => bail!("Can not mutate constant or non-existent variable {}!", receiver),
+    };
+    visitor.check_access(&instance.type_name(), field)?;
+    let template = visitor.resolve_type(&instance.type_name())?;
+    let value = rh.as_lit_advanced(visitor, "Expected a field value!")?;
+    instance.set_field(&template, field, value)?;
+    visitor.add_var(receiver.to_string(), Literal::Struct(instance));
+    Ok(())
+}
+
+/// Evaluates `value[index]` on an array, or on the chars of a string. Negative indices count
+/// from the end, so `-1` is the last element, and indices past either end fail.
+///
+/// Indexing with a range, like `value[1..3]`, `value[2..]` or `value[..=-2]`, copies a slice
+/// into a new array or string. Negative bounds count from the end as well, but bounds past
+/// either end are clamped to it, so slicing never fails and ranges that end before they
+/// start are empty
+pub(crate) fn _index_handler<V>(visitor: &mut V, value: &mut Token, index: &mut Token) -> anyhow::Result<()>
+where
+    V: Visitor,
+{
+    let value = value.as_lit_advanced(visitor, "Expected a value to index!")?;
+    let index = index.as_lit_advanced(visitor, "Expected an index!")?;
+    let (values, is_str) = match value {
+        Literal::Array(values) => (values, false),
+        Literal::String(str) => (str.chars().map(Literal::Char).collect::<Vec<_>>(), true),
+        other => bail!("Can not index a value of type {}!", other.this_type()),
+    };
+    let len = values.len() as i64;
+    let relative = |at: i64| if at < 0 { at.saturating_add(len) } else { at };
+    let lit = match index {
+        Literal::Number(index) => {
+            let at = relative(index);
+            if at < 0 || at >= len {
+                bail!("Index {} is out of bounds for length {}!", index, len)
+            }
+            values[at as usize].to_owned()
+        }
+        Literal::Struct(range) if range.type_name() == "Range" => {
+            let range = Range::from_literal(Literal::Struct(range))?;
+            let start = relative(range.start).clamp(0, len);
+            let end = relative(range.end).saturating_add(range.inclusive as i64).clamp(start, len);
+            let slice = values[start as usize..end as usize].to_vec();
+            if is_str {
+                Literal::String(slice.iter().map(|it| it.to_string()).collect())
+            } else {
+                Literal::Array(slice)
+            }
+        }
+        other => bail!("Expected a num or a range as index, got {}!", other),
+    };
+    visitor.push_stack(lit);
+    Ok(())
+}
@@ -1,17 +1,30 @@
-use crate::tks::{Ident, Literal, Token, TokenChain};
+use crate::structs::EnumTemplate;
+use crate::tks::{read_block, Ident, Literal, Token};
+use crate::var::{_typed_value, ContainingScope};
 use crate::visit::{Scope, Visitable, Visitor};
 use crate::vm::Transmute;
+use crate::warn::WarningCode;
 use anyhow::bail;
-use std::io::Cursor;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Keyword {
-    Export,   // export
-    Import,   // import
-    Let,      // let
-    Const,    // const
-    Function, // fn
-    Return,   // return
+    Export,    // export
+    Import,    // import
+    Let,       // let
+    Const,     // const
+    Function,  // fn
+    Return,    // return
+    Namespace, // namespace
+    Test,      // test
+    Enum,      // enum
+    Static,    // static
+    Pub,       // pub
+    Priv,      // priv
+    Init,      // init
 }
 
 impl Transmute for Keyword {
@@ -19,30 +32,64 @@ impl Transmute for Keyword {
         1
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
         match self {
-            Keyword::Export => 0x01,
+            Keyword::Export => 0x01u8,
             Keyword::Import => 0x02,
             Keyword::Let => 0x03,
             Keyword::Const => 0x04,
             Keyword::Function => 0x05,
             Keyword::Return => 0x06,
+            Keyword::Namespace => 0x07,
+            Keyword::Test => 0x08,
+            Keyword::Enum => 0x09,
+            Keyword::Static => 0x0A,
+            Keyword::Pub => 0x0B,
+            Keyword::Priv => 0x0C,
+            Keyword::Init => 0x0D,
         }
-        .write(buf)
+        .write_to(buf)
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(match u8::read(buf)? {
+        Ok(match u8::read_from(buf)? {
             0x01 => Keyword::Export,
             0x02 => Keyword::Import,
             0x03 => Keyword::Let,
             0x04 => Keyword::Const,
             0x05 => Keyword::Function,
             0x06 => Keyword::Return,
-            _ => panic!("Invalid keyword type provided!"),
+            0x07 => Keyword::Namespace,
+            0x08 => Keyword::Test,
+            0x09 => Keyword::Enum,
+            0x0A => Keyword::Static,
+            0x0B => Keyword::Pub,
+            0x0C => Keyword::Priv,
+            0x0D => Keyword::Init,
+            tag => bail!("Unknown keyword tag {:#04x}!", tag),
+        })
+    }
+}
+
+impl Display for Keyword {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Keyword::Export => "export",
+            Keyword::Import => "import",
+            Keyword::Let => "let",
+            Keyword::Const => "const",
+            Keyword::Function => "fn",
+            Keyword::Return => "return",
+            Keyword::Namespace => "namespace",
+            Keyword::Test => "test",
+            Keyword::Enum => "enum",
+            Keyword::Static => "static",
+            Keyword::Pub => "pub",
+            Keyword::Priv => "priv",
+            Keyword::Init => "init",
         })
     }
 }
@@ -54,14 +101,14 @@ impl Visitable for Keyword {
     {
         match *self {
             Keyword::Export => {
-                if let Literal::Ident(name) = &mut visitor.next_token()?.as_lit_no_ident(visitor, "Expected an element to export!") {
+                if let Literal::Ident(name) = &mut visitor.next_token()?.as_lit_no_ident(visitor, "Expected an element to export!")? {
                     visitor.export(name.to_owned());
                 } else {
                     bail!("Expected an ident to be exported!")
                 }
             }
             Keyword::Import => {
-                if let Literal::Ident(name) = &mut visitor.next_token()?.as_lit_no_ident(visitor, "Expected an element to import!") {
+                if let Literal::Ident(name) = &mut visitor.next_token()?.as_lit_no_ident(visitor, "Expected an element to import!")? {
                     let (scope, name) = name.rsplit_once("::").unwrap();
                     visitor.import(
                         scope.to_string(),
@@ -72,13 +119,23 @@ impl Visitable for Keyword {
                 }
             }
             Keyword::Let => {
-                if let Literal::Ident(name) =
+                if let Some(pattern) = _read_pattern(visitor)? {
+                    let value = visitor
+                        .next_token()?
+                        .as_lit_advanced(visitor, "Expected a value to destructure!")?;
+                    for (name, value) in pattern.destructure(value, visitor)? {
+                        _warn_shadowed(visitor, &name);
+                        visitor.declare_var(name, value, None)
+                    }
+                } else if let Literal::Ident(name) =
                     &mut visitor.next_token()?.as_lit("Expected a variable name!")
                 {
+                    let ty = _declared_type(visitor)?;
                     let value = visitor
                         .next_token()?
-                        .as_lit_advanced(visitor, "Expected a variable value!");
-                    visitor.add_var(name.to_owned(), value)
+                        .as_lit_advanced(visitor, "Expected a variable value!")?;
+                    _warn_shadowed(visitor, name);
+                    visitor.declare_var(name.to_owned(), value, ty)
                 } else {
                     panic!("Expected an ident name for variable!")
                 }
@@ -87,15 +144,51 @@ impl Visitable for Keyword {
                 if let Literal::Ident(name) =
                     &mut visitor.next_token()?.as_lit("Expected a variable name!")
                 {
-                    let value = visitor
+                    let ty = _declared_type(visitor)?;
+                    let mut value = visitor
                         .next_token()?
-                        .as_lit_advanced(visitor, "Expected a variable value!");
+                        .as_lit_advanced(visitor, "Expected a variable value!")?;
+                    if let Some(ty) = ty {
+                        value = _typed_value(name, &ty, value);
+                    }
+                    _warn_shadowed(visitor, name);
                     visitor.add_const(name.to_owned(), value);
                 }
             }
+            Keyword::Static => {
+                let name = match visitor.next_token()? {
+                    Token::Literal(Literal::Ident(name)) => name,
+                    other => bail!("Expected a static name, got {:?}!", other),
+                };
+                let ty = _declared_type(visitor)?;
+                let value = visitor
+                    .next_token()?
+                    .as_lit_advanced(visitor, "Expected a static value!")?;
+                visitor.declare_static(name, value, ty);
+            }
+            // members are public unless they are marked with `priv`
+            Keyword::Pub | Keyword::Priv => {
+                if visitor.scope_level() != Scope::Struct {
+                    bail!("Access modifier {} can only be used inside of a structure!", self)
+                }
+                if *self == Keyword::Priv {
+                    visitor.add_attr("private".to_string(), Literal::Bool(true));
+                }
+            }
+            // the block runs once every member of the structure is registered, see `_visit_struct`
+            Keyword::Init => {
+                if visitor.scope_level() != Scope::Struct {
+                    bail!("Initializer blocks can only be declared inside of a structure!")
+                }
+                let chain = read_block(visitor)?;
+                visitor.add_struct_init(chain);
+            }
             Keyword::Function => {
-                if let Token::Keyword(_) = visitor.peek_token()? {
+                if let Token::Keyword(mut modifier) = visitor.peek_token()? {
                     visitor.next_token()?;
+                    if matches!(modifier, Keyword::Pub | Keyword::Priv) {
+                        modifier.visit(visitor)?;
+                    }
                 }
                 let out_ty = if let Literal::TypeName(name) = visitor
                     .next_token()?
@@ -130,19 +223,18 @@ impl Visitable for Keyword {
                         param_names.push(lit.to_owned())
                     }
                     let _rparen = visitor.next_token()?;
-                    let _lbracket = visitor.next_token()?;
-                    let mut chain = TokenChain::new();
-                    while visitor.peek_token().expect("Unexpected end of token chain")
-                        != Token::RBracket
+                    // nested blocks, like struct patterns or ifs, are part of the body
+                    let chain = read_block(visitor)?;
+
+                    if visitor.scope_level() == Scope::Struct
+                        && param_names.first().map(|it| it == "this").unwrap_or(false)
                     {
-                        chain.push(visitor.next_token().unwrap());
+                        visitor.add_inst_fn(name, out_ty, param_names, chain);
+                    } else {
+                        visitor.add_static_fn(name, out_ty, param_names, chain);
                     }
-                    let _rbracket = visitor.next_token()?;
-
-                    // TODO: possible table handling?
-                    visitor.add_static_fn(name, out_ty, param_names, chain);
                 } else if let Literal::String(_native) = pop {
-                    if let Literal::Ident(_name) = &mut visitor.pop_stack() {
+                    if let Literal::Ident(_name) = &mut visitor.pop_stack()? {
                         panic!("Native functions are not yet supported!")
                     } else {
                         panic!("Expected a name for an extern function!")
@@ -152,16 +244,203 @@ impl Visitable for Keyword {
             Keyword::Return => {
                 let tk = visitor.next_token()?;
                 let lit = match tk {
+                    // values are resolved in the function scope, so `return this;` returns the instance
+                    Token::Literal(Literal::Ident(name)) => visitor
+                        .resolve_var(&name)
+                        .or_else(|_| visitor.resolve_const(&name))
+                        .unwrap_or(Literal::Ident(name)),
                     Token::Literal(lit) => lit,
                     Token::Expression(expr) => {
                         expr.clone().visit(visitor)?;
-                        visitor.pop_stack()
+                        visitor.pop_stack()?
                     }
                     _ => panic!("Expected a literal or expression!"),
                 };
                 visitor.push_stack(lit)
             }
+            Keyword::Namespace => {
+                let name = match visitor.next_token()? {
+                    Token::Literal(Literal::Ident(name)) => name,
+                    other => bail!("Expected a namespace name, got {:?}!", other),
+                };
+                if !matches!(visitor.scope_level(), Scope::Global | Scope::Namespace) {
+                    bail!("Namespace {} can only be declared at the top level!", name)
+                }
+                let mut chain = read_block(visitor)?;
+
+                // nested namespaces are addressed by their full path, e.g. `outer::inner`
+                let cached = visitor.scope_name();
+                let path = if visitor.scope_level() == Scope::Global {
+                    name
+                } else {
+                    format!("{}::{}", cached, name)
+                };
+                // reopened std and shared scopes would change them for every other Vm
+                if path == "std" || path.starts_with("std::") {
+                    bail!("Namespace {} belongs to the standard library and can not be reopened!", path)
+                }
+                if visitor.is_shared_scope(&path) {
+                    bail!("Namespace {} is shared with other Vms and can not be reopened!", path)
+                }
+                if !visitor.has_scope(&path) {
+                    visitor.push_scope(path.clone(), ContainingScope::new());
+                }
+                visitor.push_scope_level(Scope::Namespace);
+                visitor.move_scope(path);
+
+                visitor.process_isolated(&mut chain);
+
+                visitor.move_scope(cached);
+                visitor.pop_scope_level();
+            }
+            Keyword::Test => {
+                let name = match visitor.next_token()? {
+                    Token::Literal(Literal::String(name)) => name,
+                    other => bail!("Expected a test name, got {:?}!", other),
+                };
+                if !matches!(visitor.scope_level(), Scope::Global | Scope::Namespace) {
+                    bail!("Test {} can only be declared at the top level!", name)
+                }
+                let chain = read_block(visitor)?;
+                visitor.add_test(name, chain);
+            }
+            Keyword::Enum => {
+                let name = match visitor.next_token()? {
+                    Token::Literal(Literal::Ident(name)) => name,
+                    other => bail!("Expected an enum name, got {:?}!", other),
+                };
+                let mut template = EnumTemplate::new(name);
+                let mut variants = read_block(visitor)?.into_iter();
+                while let Some(tk) = variants.next() {
+                    match (tk, variants.next()) {
+                        (Token::Literal(Literal::Ident(variant)), Some(Token::Literal(Literal::TypeName(ty)))) => {
+                            template.add_variant(&variant, ty)
+                        }
+                        (other, _) => bail!("Expected an enum variant, got {:?}!", other),
+                    }
+                }
+                visitor.add_enum(template);
+            }
         }
         Ok(())
     }
 }
+
+/// Names bound by a destructuring `let`, names that are `_` are not bound
+enum Pattern {
+    /// `[a, b, rest..]` or `(a, b)`, the optional rest takes the remaining values as an array
+    Array(Vec<Ident>, Option<Ident>),
+    /// `{x, y}`, binding fields of a structure by their names
+    Struct(Vec<Ident>),
+}
+
+impl Pattern {
+    /// Pairs every bound name with its part of the value
+    fn destructure<V>(self, value: Literal, visitor: &V) -> anyhow::Result<Vec<(Ident, Literal)>>
+    where
+        V: Visitor,
+    {
+        let bound = match (self, value) {
+            (Pattern::Array(names, rest), Literal::Array(mut values)) => {
+                match rest {
+                    None if values.len() != names.len() => bail!(
+                        "Can not destructure array of {} value(s) into {} name(s)!",
+                        values.len(),
+                        names.len()
+                    ),
+                    Some(_) if values.len() < names.len() => bail!(
+                        "Can not destructure array of {} value(s) into at least {} name(s)!",
+                        values.len(),
+                        names.len()
+                    ),
+                    _ => {}
+                }
+                let remaining = values.split_off(names.len());
+                let mut bound: Vec<(Ident, Literal)> = names.into_iter().zip(values).collect();
+                if let Some(rest) = rest {
+                    bound.push((rest, Literal::Array(remaining)));
+                }
+                bound
+            }
+            (Pattern::Struct(names), Literal::Struct(instance)) => names
+                .into_iter()
+                .map(|name| match instance.get_field(&name) {
+                    Some(value) => {
+                        visitor.check_access(&instance.type_name(), &name)?;
+                        Ok((name, value))
+                    }
+                    None => bail!(
+                        "Structure {} has no field {} to destructure!",
+                        instance.type_name(),
+                        name
+                    ),
+                })
+                .collect::<anyhow::Result<_>>()?,
+            (Pattern::Array(..), other) => bail!("Expected an array to destructure, got {}!", other),
+            (Pattern::Struct(_), other) => bail!("Expected a structure to destructure, got {}!", other),
+        };
+        Ok(bound.into_iter().filter(|(name, _)| name != "_").collect())
+    }
+}
+
+/// Takes the pattern of a destructuring `let`, if the next token opens one.
+/// The rest of an array pattern is an ident ending with `..`
+fn _read_pattern<V>(visitor: &mut V) -> anyhow::Result<Option<Pattern>>
+where
+    V: Visitor,
+{
+    let closing = match visitor.peek_token()? {
+        Token::LSquare => Token::RSquare,
+        Token::LParen => Token::RParen,
+        Token::LBracket => Token::RBracket,
+        _ => return Ok(None),
+    };
+    visitor.next_token()?;
+    let mut names = vec![];
+    let mut rest = None;
+    loop {
+        match visitor.next_token()? {
+            tk if tk == closing => break,
+            Token::Literal(Literal::Ident(name)) => {
+                if rest.is_some() {
+                    bail!("Rest of a pattern has to be its last name!")
+                }
+                match name.strip_suffix("..") {
+                    Some(name) if closing != Token::RBracket => rest = Some(name.to_string()),
+                    Some(_) => bail!("Structure patterns can not have a rest!"),
+                    None => names.push(name),
+                }
+            }
+            other => bail!("Expected a name in pattern, got {:?}!", other),
+        }
+    }
+    Ok(Some(match closing {
+        Token::RBracket => Pattern::Struct(names),
+        _ => Pattern::Array(names, rest),
+    }))
+}
+
+/// Takes the type annotation of a `let` or `const`, which follows its name
+fn _declared_type<V>(visitor: &mut V) -> anyhow::Result<Option<String>>
+where
+    V: Visitor,
+{
+    if let Token::Literal(Literal::TypeName(_)) = visitor.peek_token()? {
+        if let Token::Literal(Literal::TypeName(ty)) = visitor.next_token()? {
+            return Ok(Some(ty));
+        }
+    }
+    Ok(None)
+}
+
+fn _warn_shadowed<V>(visitor: &mut V, name: &str)
+where
+    V: Visitor,
+{
+    if visitor.resolve_var(name).is_ok() || visitor.resolve_const(name).is_ok() {
+        visitor.warn(
+            WarningCode::ShadowedVariable,
+            format!("Declaration of {} shadows an existing value", name),
+        )
+    }
+}
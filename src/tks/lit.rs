@@ -1,8 +1,11 @@
-use crate::tks::Ident;
-use crate::visit::{Visitable, Visitor};
+use crate::structs::{StructureInstance, StructureTemplate};
+use crate::tks::{read_block, Decimal, Ident, Token};
+use crate::var::ContainingScope;
+use crate::visit::{Scope, Visitable, Visitor};
 use crate::vm::Transmute;
+use anyhow::bail;
 use std::fmt::{Display, Formatter};
-use std::io::Cursor;
+use std::io::{Read, Write};
 
 macro_rules! int_into_lit {
     ($($i:ident),*) => {
@@ -56,6 +59,8 @@ impl Into<Literal> for bool {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Literal {
     Number(i64),
     Float(f64),
@@ -64,6 +69,13 @@ pub enum Literal {
     Ident(Ident),
     Bool(bool),
     TypeName(String),
+    Struct(Box<StructureInstance>),
+    Array(Vec<Literal>),
+    Bytes(Vec<u8>),
+    /// Value of an enum, the full `Enum::Variant` path followed by the payload, which is void for unit variants
+    Enum(String, Box<Literal>),
+    /// Exact base-10 number, written as `12.50d`
+    Decimal(Decimal),
     Void,
 }
 
@@ -77,59 +89,91 @@ impl Transmute for Literal {
             Literal::Ident(v) => v.size(),
             Literal::Bool(v) => v.size(),
             Literal::TypeName(v) => v.size(),
+            Literal::Struct(v) => v.size(),
+            Literal::Array(v) => v.size(),
+            Literal::Bytes(v) => v.size(),
+            Literal::Enum(variant, payload) => variant.size() + payload.size(),
+            Literal::Decimal(_) => 16 + 4,
             Literal::Void => 0,
         }
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
         match self {
             Literal::Number(v) => {
-                0x01u8.write(buf)?;
-                v.write(buf)?
+                0x01u8.write_to(buf)?;
+                v.write_to(buf)?
             }
             Literal::Float(v) => {
-                0x02u8.write(buf)?;
-                v.write(buf)?
+                0x02u8.write_to(buf)?;
+                v.write_to(buf)?
             }
             Literal::String(v) => {
-                0x03u8.write(buf)?;
-                v.write(buf)?
+                0x03u8.write_to(buf)?;
+                v.write_to(buf)?
             }
             Literal::Char(v) => {
-                0x04u8.write(buf)?;
-                v.write(buf)?
+                0x04u8.write_to(buf)?;
+                v.write_to(buf)?
             }
             Literal::Ident(v) => {
-                0x05u8.write(buf)?;
-                v.write(buf)?
+                0x05u8.write_to(buf)?;
+                v.write_to(buf)?
             }
             Literal::Bool(v) => {
-                0x06u8.write(buf)?;
-                v.write(buf)?
+                0x06u8.write_to(buf)?;
+                v.write_to(buf)?
             }
             Literal::TypeName(v) => {
-                0x07u8.write(buf)?;
-                v.write(buf)?
+                0x07u8.write_to(buf)?;
+                v.write_to(buf)?
             }
-            Literal::Void => 0x00u8.write(buf)?,
+            Literal::Struct(v) => {
+                0x08u8.write_to(buf)?;
+                v.write_to(buf)?
+            }
+            Literal::Array(v) => {
+                0x09u8.write_to(buf)?;
+                v.write_to(buf)?
+            }
+            Literal::Bytes(v) => {
+                0x0Au8.write_to(buf)?;
+                v.write_to(buf)?
+            }
+            Literal::Enum(variant, payload) => {
+                0x0Bu8.write_to(buf)?;
+                variant.write_to(buf)?;
+                payload.write_to(buf)?
+            }
+            Literal::Decimal(v) => {
+                0x0Cu8.write_to(buf)?;
+                v.mantissa().write_to(buf)?;
+                v.scale().write_to(buf)?
+            }
+            Literal::Void => 0x00u8.write_to(buf)?,
         };
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(match u8::read(buf)? {
+        Ok(match u8::read_from(buf)? {
             0x00 => Literal::Void,
-            0x01 => Literal::Number(i64::read(buf)?),
-            0x02 => Literal::Float(f64::read(buf)?),
-            0x03 => Literal::String(String::read(buf)?),
-            0x04 => Literal::Char(char::read(buf)?),
-            0x05 => Literal::Ident(Ident::read(buf)?),
-            0x06 => Literal::Bool(bool::read(buf)?),
-            0x07 => Literal::TypeName(String::read(buf)?),
-            _ => panic!("Invalid LitID provided!"),
+            0x01 => Literal::Number(i64::read_from(buf)?),
+            0x02 => Literal::Float(f64::read_from(buf)?),
+            0x03 => Literal::String(String::read_from(buf)?),
+            0x04 => Literal::Char(char::read_from(buf)?),
+            0x05 => Literal::Ident(Ident::read_from(buf)?),
+            0x06 => Literal::Bool(bool::read_from(buf)?),
+            0x07 => Literal::TypeName(String::read_from(buf)?),
+            0x08 => Literal::Struct(Box::new(StructureInstance::read_from(buf)?)),
+            0x09 => Literal::Array(Vec::read_from(buf)?),
+            0x0A => Literal::Bytes(Vec::read_from(buf)?),
+            0x0B => Literal::Enum(String::read_from(buf)?, Box::new(Literal::read_from(buf)?)),
+            0x0C => Literal::Decimal(Decimal::new(i128::read_from(buf)?, u32::read_from(buf)?)?),
+            tag => bail!("Unknown literal tag {:#04x}!", tag),
         })
     }
 }
@@ -144,6 +188,17 @@ impl Display for Literal {
             Literal::Ident(v) => f.write_str(&v),
             Literal::Bool(v) => f.write_str(&v.to_string()),
             Literal::TypeName(v) => f.write_str(&v),
+            Literal::Struct(v) => f.write_str(&v.to_string()),
+            Literal::Array(v) => {
+                let values: Vec<String> = v.iter().map(|it| it.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            Literal::Bytes(v) => write!(f, "b\"{}\"", v.escape_ascii()),
+            Literal::Enum(variant, payload) => match payload.as_ref() {
+                Literal::Void => f.write_str(variant),
+                payload => write!(f, "{}({})", variant, payload),
+            },
+            Literal::Decimal(v) => write!(f, "{}", v),
             Literal::Void => f.write_str("*"),
         }
     }
@@ -159,6 +214,11 @@ impl Literal {
             Literal::Ident(_) => "void".to_string(),
             Literal::Bool(_) => "bool".to_string(),
             Literal::TypeName(_) => "typename".to_string(),
+            Literal::Struct(v) => v.type_name(),
+            Literal::Array(_) => "array".to_string(),
+            Literal::Bytes(_) => "bytes".to_string(),
+            Literal::Enum(variant, _) => _enum_name(variant).to_string(),
+            Literal::Decimal(_) => "decimal".to_string(),
             Literal::Void => "void".to_string(),
         }
     }
@@ -172,6 +232,11 @@ impl Literal {
             Literal::Ident(_) => true,
             Literal::Bool(_) => tn == "bool",
             Literal::TypeName(_) => tn == "typename",
+            Literal::Struct(v) => tn == v.type_name(),
+            Literal::Array(_) => tn == "array",
+            Literal::Bytes(_) => tn == "bytes",
+            Literal::Enum(variant, _) => tn == _enum_name(variant),
+            Literal::Decimal(_) => tn == "decimal",
             Literal::Void => tn == "void",
         }
     }
@@ -227,6 +292,19 @@ impl Literal {
                     false
                 }
             }
+            Literal::Struct(v) => {
+                if let Literal::Struct(o) = other {
+                    v.type_name() == o.type_name()
+                } else {
+                    false
+                }
+            }
+            Literal::Array(_) => matches!(other, Literal::Array(_)),
+            Literal::Bytes(_) => matches!(other, Literal::Bytes(_)),
+            Literal::Decimal(_) => matches!(other, Literal::Decimal(_)),
+            Literal::Enum(variant, _) => {
+                matches!(other, Literal::Enum(o, _) if _enum_name(variant) == _enum_name(o))
+            }
             _ => true,
         }
     }
@@ -237,7 +315,58 @@ impl Visitable for Literal {
     where
         V: Visitor,
     {
+        if let Literal::Ident(name) = self {
+            match visitor.peek_token() {
+                // `Name { ... }` declares a new structure
+                Ok(Token::LBracket) => return _visit_struct(visitor, name.to_owned()),
+                // `field typename [default]` declares an instance field inside a structure
+                Ok(Token::Literal(Literal::TypeName(ty))) if visitor.scope_level() == Scope::Struct => {
+                    let _ = visitor.next_token()?;
+                    let default = match visitor.peek_token() {
+                        Ok(Token::Literal(Literal::Ident(_))) | Ok(Token::Literal(Literal::TypeName(_))) => None,
+                        Ok(Token::Literal(lit)) => {
+                            let _ = visitor.next_token()?;
+                            if ty != "unknown" && !lit.type_str(&ty) {
+                                bail!("Invalid default value {} provided for field {} of type {:?}!", lit, name, ty)
+                            }
+                            Some(lit)
+                        }
+                        _ => None,
+                    };
+                    visitor.add_inst_var(name.to_owned(), ty, default);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
         visitor.push_stack(self.to_owned());
         Ok(())
     }
 }
+
+/// Name of the enum a full `Enum::Variant` path belongs to
+pub(crate) fn _enum_name(variant: &str) -> &str {
+    variant.rsplit_once("::").map(|it| it.0).unwrap_or(variant)
+}
+
+fn _visit_struct<V>(visitor: &mut V, name: String) -> anyhow::Result<()>
+where
+    V: Visitor,
+{
+    let mut chain = read_block(visitor)?;
+
+    let cached = visitor.scope_name();
+    visitor.add_struct(StructureTemplate::new(name.clone()));
+    visitor.add_struct_name(name.clone());
+    visitor.push_scope_level(Scope::Struct);
+    visitor.push_scope(name.clone(), ContainingScope::new());
+    visitor.move_scope(name.clone());
+
+    visitor.process_isolated(&mut chain);
+    visitor.init_struct(name);
+
+    visitor.move_scope(cached);
+    visitor.pop_scope_level();
+    visitor.pop_struct_name();
+    Ok(())
+}
@@ -1,8 +1,11 @@
 use crate::vm::Transmute;
 use anyhow::bail;
-use std::io::Cursor;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
 
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum BinaryOp {
     Assign, // =, unused by default
     Add,    // +
@@ -28,7 +31,7 @@ impl Transmute for BinaryOp {
         1
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
         match self {
             BinaryOp::Assign => 0x00u8,
             BinaryOp::Add => 0x01,
@@ -48,14 +51,14 @@ impl Transmute for BinaryOp {
             BinaryOp::Lt => 0x0F,
             BinaryOp::Gt => 0x10,
         }
-        .write(buf)
+        .write_to(buf)
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(match u8::read(buf)? {
+        Ok(match u8::read_from(buf)? {
             0x00 => BinaryOp::Assign,
             0x01 => BinaryOp::Add,
             0x02 => BinaryOp::Sub,
@@ -73,12 +76,14 @@ impl Transmute for BinaryOp {
             0x0E => BinaryOp::BitLsh,
             0x0F => BinaryOp::Lt,
             0x10 => BinaryOp::Gt,
-            _ => bail!("Invalid binary operator provided!"),
+            tag => bail!("Unknown binary operator tag {:#04x}!", tag),
         })
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum UnaryOp {
     Neg, // !
     Rev, // ~
@@ -89,22 +94,55 @@ impl Transmute for UnaryOp {
         1
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
         match self {
             UnaryOp::Neg => 0x00u8,
             UnaryOp::Rev => 0x01,
         }
-        .write(buf)
+        .write_to(buf)
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(match u8::read(buf)? {
+        Ok(match u8::read_from(buf)? {
             0x00 => UnaryOp::Neg,
             0x01 => UnaryOp::Rev,
-            _ => bail!("Invalid unary operator provided!"),
+            tag => bail!("Unknown unary operator tag {:#04x}!", tag),
+        })
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BinaryOp::Assign => "=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Div => "/",
+            BinaryOp::Mul => "*",
+            BinaryOp::Mod => "%",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::Eq => "==",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Neq => "!=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitRsh => ">>",
+            BinaryOp::BitLsh => "<<",
+        })
+    }
+}
+
+impl Display for UnaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            UnaryOp::Neg => "!",
+            UnaryOp::Rev => "~",
         })
     }
 }
@@ -0,0 +1,103 @@
+use crate::span::Span;
+use crate::tks::{Literal, Token};
+use colored::Colorize;
+use std::fmt::{Display, Formatter};
+
+/// Selects which events are traced, and where they end up
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TraceConfig {
+    pub tokens: bool,
+    pub stack: bool,
+    pub scopes: bool,
+    pub calls: bool,
+    /// Collect entries into a buffer instead of printing them
+    pub buffered: bool,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            tokens: true,
+            stack: true,
+            scopes: true,
+            calls: true,
+            buffered: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    Token(Token),
+    Push(Literal),
+    Pop(Literal),
+    MoveScope(String),
+    Call(String),
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceEvent::Token(tk) => write!(f, "visit {:?}", tk),
+            TraceEvent::Push(lit) => write!(f, "push {}", lit),
+            TraceEvent::Pop(lit) => write!(f, "pop {}", lit),
+            TraceEvent::MoveScope(name) => write!(f, "scope {}", name),
+            TraceEvent::Call(name) => write!(f, "call {}", name),
+        }
+    }
+}
+
+/// A single traced event, along with the call depth and source position it happened at
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub depth: usize,
+    pub span: Option<Span>,
+    pub event: TraceEvent,
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", "  ".repeat(self.depth), self.event)?;
+        if let Some(span) = self.span {
+            write!(f, " @ {}", span)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    config: TraceConfig,
+    entries: Vec<TraceEntry>,
+}
+
+impl Tracer {
+    pub fn new(config: TraceConfig) -> Self {
+        Self {
+            config,
+            entries: vec![],
+        }
+    }
+
+    pub fn record(&mut self, depth: usize, span: Option<Span>, event: TraceEvent) {
+        let enabled = match event {
+            TraceEvent::Token(_) => self.config.tokens,
+            TraceEvent::Push(_) | TraceEvent::Pop(_) => self.config.stack,
+            TraceEvent::MoveScope(_) => self.config.scopes,
+            TraceEvent::Call(_) => self.config.calls,
+        };
+        if !enabled {
+            return;
+        }
+        let entry = TraceEntry { depth, span, event };
+        if self.config.buffered {
+            self.entries.push(entry);
+        } else {
+            println!("{} {}", "[Trace]".bright_black(), entry);
+        }
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+}
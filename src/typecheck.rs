@@ -0,0 +1,686 @@
+//! Optional static type checking of a token chain, see [`Vm::typecheck`](crate::visit::Vm::typecheck).
+//!
+//! Types are inferred from literals, declared `let` and `const` types, output types of functions
+//! and parameter types of typed host functions. Values whose type can not be known before
+//! execution, like parameters of gale functions or structure fields, are `unknown`,
+//! and `unknown` values are accepted everywhere.
+
+use crate::check::{_check_brackets, Diagnostic, DiagnosticKind};
+use crate::fns::StaticFnType;
+use crate::structs::EnumTemplate;
+use crate::tks::{BinaryOp, Expression, Keyword, Literal, Token, TokenChain, UnaryOp};
+use std::collections::HashMap;
+use std::mem;
+
+const UNKNOWN: &str = "unknown";
+
+/// Parameter and output types of a function, arguments of `varargs` functions are not checked
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FnType {
+    pub param_types: Vec<String>,
+    pub varargs: bool,
+    pub out_ty: String,
+}
+
+impl From<&StaticFnType> for FnType {
+    fn from(fnc: &StaticFnType) -> Self {
+        Self {
+            param_types: fnc.param_types(),
+            varargs: fnc.param_names().iter().any(|it| it == "varargs"),
+            out_ty: fnc.out_ty().to_string(),
+        }
+    }
+}
+
+/// Types known before the chain is checked
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TypeEnv {
+    /// Functions by the name or the full path they are called by
+    pub fns: HashMap<String, FnType>,
+    /// Types of global variables and constants
+    pub vars: HashMap<String, String>,
+    /// Enums by their name
+    pub enums: HashMap<String, EnumTemplate>,
+    /// Types of statics, which are visible in every scope
+    pub statics: HashMap<String, String>,
+}
+
+/// Checks call-site argument types, returned values and operand types without executing anything.
+///
+/// Like [`check`](crate::check::check), function bodies only see their own values,
+/// their parameters are `unknown`.
+pub fn typecheck(chain: &TokenChain, env: &TypeEnv) -> Vec<Diagnostic> {
+    let unbalanced = _check_brackets(chain);
+    if !unbalanced.is_empty() {
+        return unbalanced;
+    }
+
+    let global = TypeScope {
+        vars: env.vars.clone(),
+        fns: HashMap::new(),
+    };
+    let mut checker = TypeChecker {
+        tks: chain,
+        pos: 0,
+        env,
+        scopes: vec![global],
+        enums: env.enums.clone(),
+        statics: env.statics.clone(),
+        namespace_path: vec![],
+        out_ty: None,
+        diagnostics: vec![],
+    };
+    checker.statements(false);
+    checker.diagnostics
+}
+
+/// Whether a value of type `actual` can be assigned or passed where `expected` is required,
+/// numbers are widened to floats and decimals
+fn _assignable(expected: &str, actual: &str) -> bool {
+    expected == UNKNOWN
+        || actual == UNKNOWN
+        || expected == actual
+        || (matches!(expected, "float" | "decimal") && actual == "num")
+}
+
+/// Type of a binary operation on known operand types, `None` if the operation is invalid
+fn _binary_type(op: BinaryOp, lh: &str, rh: &str) -> Option<&'static str> {
+    let textual = matches!(rh, "num" | "float" | "decimal" | "str" | "char");
+    // nums are exact, so they mix with decimals
+    let decimal = matches!(
+        (lh, rh),
+        ("decimal", "decimal") | ("decimal", "num") | ("num", "decimal")
+    );
+    match op {
+        BinaryOp::Add if lh == "str" && textual => Some("str"),
+        BinaryOp::Add if lh == "char" && rh == "char" => Some("str"),
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+            match (lh, rh) {
+                ("num", "num") => Some("num"),
+                ("float", "float") => Some("float"),
+                _ if decimal => Some("decimal"),
+                _ => None,
+            }
+        }
+        BinaryOp::Eq | BinaryOp::Neq if decimal => Some("bool"),
+        BinaryOp::Eq
+        | BinaryOp::Neq
+        | BinaryOp::And
+        | BinaryOp::Or
+        | BinaryOp::BitAnd
+        | BinaryOp::BitOr
+        | BinaryOp::BitXor => (lh == "bool" && rh == "bool").then_some("bool"),
+        BinaryOp::BitLsh | BinaryOp::BitRsh => (lh == "num" && rh == "num").then_some("num"),
+        BinaryOp::Lt | BinaryOp::Gt => match (lh, rh) {
+            ("str", _) if textual => Some("bool"),
+            ("num", "num") | ("float", "float") => Some("bool"),
+            _ if decimal => Some("bool"),
+            _ => None,
+        },
+        BinaryOp::Assign => Some("void"),
+    }
+}
+
+/// Types of values and functions visible in a single scope
+#[derive(Debug, Clone, Default)]
+struct TypeScope {
+    vars: HashMap<String, String>,
+    fns: HashMap<String, FnType>,
+}
+
+struct TypeChecker<'a> {
+    tks: &'a [Token],
+    pos: usize,
+    env: &'a TypeEnv,
+    scopes: Vec<TypeScope>,
+    enums: HashMap<String, EnumTemplate>,
+    statics: HashMap<String, String>,
+    namespace_path: Vec<String>,
+    /// Output type of the function that is being checked
+    out_ty: Option<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn report(&mut self, kind: DiagnosticKind, message: String) {
+        self.diagnostics.push(Diagnostic {
+            index: self.pos.saturating_sub(1),
+            span: None,
+            kind,
+            message,
+        })
+    }
+
+    fn scope(&mut self) -> &mut TypeScope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tks.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let tk = self.tks.get(self.pos);
+        self.pos += 1;
+        tk
+    }
+
+    fn statements(&mut self, nested: bool) {
+        while let Some(tk) = self.peek() {
+            if nested && *tk == Token::RBracket {
+                return;
+            }
+            self.statement();
+        }
+    }
+
+    fn block(&mut self) {
+        if self.peek() != Some(&Token::LBracket) {
+            return;
+        }
+        self.next();
+        self.statements(true);
+        self.next();
+    }
+
+    /// Checks a block in its own scope, `out_ty` is the output type of the function it belongs to
+    fn isolated_block(&mut self, scope: TypeScope, out_ty: Option<String>) -> TypeScope {
+        let cached = mem::replace(&mut self.out_ty, out_ty);
+        self.scopes.push(scope);
+        self.block();
+        self.out_ty = cached;
+        self.scopes.pop().unwrap()
+    }
+
+    fn statement(&mut self) {
+        let tk = match self.next() {
+            Some(tk) => tk,
+            None => return,
+        };
+        match tk {
+            Token::Keyword(kw) => self.keyword(*kw),
+            Token::Literal(Literal::Ident(_)) => match self.peek() {
+                Some(Token::LBracket) => {
+                    self.isolated_block(TypeScope::default(), None);
+                }
+                // structure fields
+                Some(Token::Literal(Literal::TypeName(_))) => {
+                    self.next();
+                    if let Some(Token::Literal(lit)) = self.peek() {
+                        if !matches!(lit, Literal::Ident(_)) {
+                            self.next();
+                        }
+                    }
+                }
+                _ => {
+                    self.type_of(tk);
+                }
+            },
+            Token::Expression(expr) => match expr.as_ref() {
+                Expression::IfStmt | Expression::ElifStmt | Expression::WhileStmt => {
+                    if let Some(condition) = self.next() {
+                        self.type_of(condition);
+                    }
+                    self.block()
+                }
+                Expression::ElseStmt => self.block(),
+                Expression::DoWhileStmt => {
+                    self.block();
+                    if let Some(condition) = self.next() {
+                        self.type_of(condition);
+                    }
+                }
+                Expression::MatchStmt => self.match_arms(),
+                _ => {
+                    self.type_of(tk);
+                }
+            },
+            Token::LBracket => {
+                self.pos -= 1;
+                self.block()
+            }
+            _ => {
+                self.type_of(tk);
+            }
+        }
+    }
+
+    fn keyword(&mut self, kw: Keyword) {
+        match kw {
+            // parts of destructured values are not known
+            Keyword::Let
+                if matches!(
+                    self.peek(),
+                    Some(Token::LSquare | Token::LParen | Token::LBracket)
+                ) =>
+            {
+                self.next();
+                let mut names = vec![];
+                while let Some(Token::Literal(Literal::Ident(name))) = self.peek() {
+                    self.next();
+                    names.push(match name.strip_suffix("..") {
+                        Some(rest) => (rest.to_owned(), "array".to_string()),
+                        None => (name.to_owned(), UNKNOWN.to_string()),
+                    });
+                }
+                self.next();
+                if let Some(value) = self.next() {
+                    self.type_of(value);
+                }
+                self.scope().vars.extend(names);
+            }
+            Keyword::Let | Keyword::Const => {
+                let name = match self.next() {
+                    Some(Token::Literal(Literal::Ident(name))) => name,
+                    _ => return,
+                };
+                let declared = match self.peek() {
+                    Some(Token::Literal(Literal::TypeName(ty))) => {
+                        self.next();
+                        Some(ty.to_owned())
+                    }
+                    _ => None,
+                };
+                let ty = match self.next() {
+                    Some(value) => self.type_of(value),
+                    None => return,
+                };
+                let ty = match declared {
+                    Some(declared) => {
+                        if !_assignable(&declared, &ty) {
+                            self.report(
+                                DiagnosticKind::TypeMismatch,
+                                format!(
+                                    "Can not assign {} to {}, which is of type {}!",
+                                    ty, name, declared
+                                ),
+                            )
+                        }
+                        declared
+                    }
+                    // void values do not lock the type of a variable
+                    None if ty == "void" => UNKNOWN.to_string(),
+                    None => ty,
+                };
+                self.scope().vars.insert(name.to_owned(), ty);
+            }
+            // statics are only locked to their declared type
+            Keyword::Static => {
+                let name = match self.next() {
+                    Some(Token::Literal(Literal::Ident(name))) => name,
+                    _ => return,
+                };
+                let declared = match self.peek() {
+                    Some(Token::Literal(Literal::TypeName(ty))) => {
+                        self.next();
+                        ty.to_owned()
+                    }
+                    _ => UNKNOWN.to_string(),
+                };
+                let ty = match self.next() {
+                    Some(value) => self.type_of(value),
+                    None => return,
+                };
+                if !_assignable(&declared, &ty) {
+                    self.report(
+                        DiagnosticKind::TypeMismatch,
+                        format!(
+                            "Can not assign {} to {}, which is of type {}!",
+                            ty, name, declared
+                        ),
+                    )
+                }
+                self.statics.insert(name.to_owned(), declared);
+            }
+            Keyword::Import => {
+                if let Some(Token::Literal(Literal::Ident(path))) = self.next() {
+                    let name = path.rsplit_once("::").map(|it| it.1).unwrap_or(path);
+                    if let Some(fnc) = self.env.fns.get(path) {
+                        self.scope().fns.insert(name.to_owned(), fnc.to_owned());
+                    }
+                }
+            }
+            Keyword::Export => {
+                self.next();
+            }
+            Keyword::Return => {
+                let ty = match self.next() {
+                    Some(value) => self.type_of(value),
+                    None => return,
+                };
+                if let Some(out_ty) = self.out_ty.clone() {
+                    // returned values are not widened
+                    if out_ty != UNKNOWN && ty != UNKNOWN && out_ty != ty {
+                        self.report(
+                            DiagnosticKind::ReturnTypeMismatch,
+                            format!(
+                                "Function returns {}, but is declared to return {}!",
+                                ty, out_ty
+                            ),
+                        )
+                    }
+                }
+            }
+            Keyword::Function => self.function(),
+            Keyword::Namespace => self.namespace(),
+            Keyword::Test => {
+                self.next();
+                self.isolated_block(TypeScope::default(), None);
+            }
+            Keyword::Enum => self.enumeration(),
+            Keyword::Pub | Keyword::Priv => {}
+            Keyword::Init => self.block(),
+        }
+    }
+
+    fn enumeration(&mut self) {
+        let mut template = match self.next() {
+            Some(Token::Literal(Literal::Ident(name))) => EnumTemplate::new(name.to_owned()),
+            _ => return,
+        };
+        if self.peek() == Some(&Token::LBracket) {
+            self.next();
+            while let Some(tk) = self.next() {
+                match (tk, self.peek()) {
+                    (Token::RBracket, _) => break,
+                    (
+                        Token::Literal(Literal::Ident(variant)),
+                        Some(Token::Literal(Literal::TypeName(ty))),
+                    ) => {
+                        self.next();
+                        template.add_variant(variant, ty.to_owned());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.enums.insert(template.name(), template);
+    }
+
+    /// Payloads are bound with the type of their variant, or `unknown` if the enum is not known
+    fn match_arms(&mut self) {
+        let ty = match self.next() {
+            Some(value) => self.type_of(value),
+            None => return,
+        };
+        if self.peek() != Some(&Token::LBracket) {
+            return;
+        }
+        self.next();
+        while let Some(Token::Literal(Literal::TypeName(pattern))) = self.peek() {
+            self.next();
+            if let Some(Token::Literal(Literal::Ident(binding))) = self.next() {
+                if binding != "_" {
+                    let payload_ty = self
+                        .enums
+                        .get(&ty)
+                        .and_then(|it| it.get_variant_type(pattern))
+                        .filter(|it| it != "void")
+                        .unwrap_or_else(|| UNKNOWN.to_string());
+                    self.scope().vars.insert(binding.to_owned(), payload_ty);
+                }
+            }
+            self.block();
+        }
+        self.next();
+    }
+
+    fn function(&mut self) {
+        if let Some(Token::Keyword(_)) = self.peek() {
+            self.next();
+        }
+        let out_ty = match self.next() {
+            Some(Token::Literal(Literal::TypeName(ty))) => ty.to_owned(),
+            _ => UNKNOWN.to_string(),
+        };
+        let name = match self.next() {
+            Some(Token::Literal(Literal::Ident(name))) => Some(name.to_owned()),
+            _ => None,
+        };
+        let mut params = vec![];
+        if self.peek() == Some(&Token::LParen) {
+            self.next();
+            while let Some(tk) = self.next() {
+                match tk {
+                    Token::RParen => break,
+                    Token::Literal(Literal::Ident(param)) => params.push(param.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(name) = name {
+            let fnc = FnType {
+                param_types: vec![UNKNOWN.to_string(); params.len()],
+                varargs: params.iter().any(|it| it == "varargs"),
+                out_ty: out_ty.clone(),
+            };
+            self.scope().fns.insert(name, fnc);
+        }
+        self.isolated_block(TypeScope::default(), Some(out_ty));
+    }
+
+    /// Functions of a namespace are kept in the global scope by their full path
+    fn namespace(&mut self) {
+        let name = match self.next() {
+            Some(Token::Literal(Literal::Ident(name))) => name.to_owned(),
+            _ => return,
+        };
+        self.namespace_path.push(name);
+        let path = self.namespace_path.join("::");
+        let scope = self.isolated_block(TypeScope::default(), None);
+        for (name, fnc) in scope.fns {
+            self.scopes[0]
+                .fns
+                .insert(format!("{}::{}", path, name), fnc);
+        }
+        self.namespace_path.pop();
+    }
+
+    fn var_type(&mut self, name: &str) -> String {
+        self.scopes
+            .last()
+            .unwrap()
+            .vars
+            .get(name)
+            .or_else(|| self.statics.get(name))
+            .cloned()
+            .unwrap_or_else(|| UNKNOWN.to_string())
+    }
+
+    fn resolve_fn(&self, name: &str) -> Option<FnType> {
+        self.scopes
+            .last()
+            .unwrap()
+            .fns
+            .get(name)
+            .or_else(|| self.scopes[0].fns.get(name))
+            .or_else(|| self.env.fns.get(name))
+            .cloned()
+    }
+
+    /// Infers the type of a value, reporting invalid operations inside of it
+    fn type_of(&mut self, tk: &Token) -> String {
+        match tk {
+            Token::Literal(Literal::Ident(name)) => self.var_type(name),
+            Token::Literal(lit) => lit.this_type(),
+            Token::Expression(expr) => self.expr_type(expr),
+            _ => UNKNOWN.to_string(),
+        }
+    }
+
+    fn expr_type(&mut self, expr: &Expression) -> String {
+        match expr {
+            Expression::BinaryOp(BinaryOp::Assign, lh, rh) => {
+                let ty = self.type_of(rh);
+                if let Token::Literal(Literal::Ident(name)) = lh {
+                    let locked = self.var_type(name);
+                    if locked == UNKNOWN {
+                        if ty != "void" {
+                            self.scope().vars.insert(name.to_owned(), ty);
+                        }
+                    } else if !_assignable(&locked, &ty) {
+                        self.report(
+                            DiagnosticKind::TypeMismatch,
+                            format!(
+                                "Can not assign {} to {}, which is of type {}!",
+                                ty, name, locked
+                            ),
+                        )
+                    }
+                }
+                "void".to_string()
+            }
+            Expression::BinaryOp(op, lh, rh) => {
+                let lh = self.type_of(lh);
+                let rh = self.type_of(rh);
+                if lh == UNKNOWN || rh == UNKNOWN {
+                    return match op {
+                        BinaryOp::Add
+                        | BinaryOp::Sub
+                        | BinaryOp::Mul
+                        | BinaryOp::Div
+                        | BinaryOp::Mod => UNKNOWN,
+                        BinaryOp::BitLsh | BinaryOp::BitRsh => "num",
+                        _ => "bool",
+                    }
+                    .to_string();
+                }
+                match _binary_type(*op, &lh, &rh) {
+                    Some(ty) => ty.to_string(),
+                    None => {
+                        self.report(
+                            DiagnosticKind::OperandTypeMismatch,
+                            format!("Operator {} can not be applied to {} and {}!", op, lh, rh),
+                        );
+                        UNKNOWN.to_string()
+                    }
+                }
+            }
+            Expression::UnaryOp(op, value) => {
+                let ty = self.type_of(value);
+                let valid = match op {
+                    UnaryOp::Neg => ty == "bool",
+                    UnaryOp::Rev => matches!(ty.as_str(), "num" | "float" | "decimal"),
+                };
+                if ty != UNKNOWN && !valid {
+                    self.report(
+                        DiagnosticKind::OperandTypeMismatch,
+                        format!("Operator {} can not be applied to {}!", op, ty),
+                    );
+                    return UNKNOWN.to_string();
+                }
+                ty
+            }
+            Expression::Ternary(condition, then, otherwise) => {
+                self.type_of(condition);
+                let then = self.type_of(then);
+                let otherwise = self.type_of(otherwise);
+                if then == otherwise {
+                    then
+                } else {
+                    UNKNOWN.to_string()
+                }
+            }
+            Expression::InvokeStatic(name, params) => {
+                let types: Vec<String> = params.iter().map(|it| self.type_of(it)).collect();
+                if let Some((enum_name, variant)) = name.rsplit_once("::") {
+                    if let Some(template) = self.enums.get(enum_name) {
+                        let expected = template.get_variant_type(variant);
+                        let actual = types.first().map(String::as_str).unwrap_or("void");
+                        match expected {
+                            Some(expected) if !_assignable(&expected, actual) => self.report(
+                                DiagnosticKind::ArgumentTypeMismatch,
+                                format!(
+                                    "Payload of {} has to be {}, got {}!",
+                                    name, expected, actual
+                                ),
+                            ),
+                            _ => {}
+                        }
+                        return enum_name.to_owned();
+                    }
+                }
+                let fnc = match self.resolve_fn(name) {
+                    Some(fnc) => fnc,
+                    None => return UNKNOWN.to_string(),
+                };
+                if !fnc.varargs {
+                    for (index, (expected, actual)) in
+                        fnc.param_types.iter().zip(&types).enumerate()
+                    {
+                        if !_assignable(expected, actual) {
+                            self.report(
+                                DiagnosticKind::ArgumentTypeMismatch,
+                                format!(
+                                    "Argument #{} of {} has to be {}, got {}!",
+                                    index + 1,
+                                    name,
+                                    expected,
+                                    actual
+                                ),
+                            )
+                        }
+                    }
+                }
+                fnc.out_ty
+            }
+            Expression::Instantiate(name, fields) => {
+                for field in fields {
+                    match field.as_expr() {
+                        Some(Expression::BinaryOp(BinaryOp::Assign, _, value)) => {
+                            self.type_of(value);
+                        }
+                        _ => {
+                            self.type_of(field);
+                        }
+                    }
+                }
+                name.to_owned()
+            }
+            Expression::InvokeInstance(_, _, params) => {
+                for param in params {
+                    self.type_of(param);
+                }
+                UNKNOWN.to_string()
+            }
+            Expression::InvokeChained(receiver, _, params) => {
+                self.type_of(receiver);
+                for param in params {
+                    self.type_of(param);
+                }
+                UNKNOWN.to_string()
+            }
+            // slices keep the type of the value, elements are unknown
+            Expression::Index(value, index) => {
+                let ty = self.type_of(value);
+                match self.type_of(index).as_str() {
+                    "Range" if ty == "array" || ty == "str" => ty,
+                    "num" if ty == "str" => "char".to_string(),
+                    _ => UNKNOWN.to_string(),
+                }
+            }
+            Expression::Array(values) => {
+                for value in values {
+                    self.type_of(value);
+                }
+                "array".to_string()
+            }
+            Expression::StaticAccess(path) if path.len() > 1 => {
+                let enum_name = path[..path.len() - 1].join("::");
+                if self.enums.contains_key(&enum_name) {
+                    enum_name
+                } else {
+                    UNKNOWN.to_string()
+                }
+            }
+            Expression::StaticAccess(_)
+            | Expression::InstanceAccess(_, _)
+            | Expression::IfStmt
+            | Expression::ElseStmt
+            | Expression::ElifStmt
+            | Expression::WhileStmt
+            | Expression::DoWhileStmt
+            | Expression::MatchStmt => UNKNOWN.to_string(),
+        }
+    }
+}
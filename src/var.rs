@@ -1,9 +1,13 @@
-use crate::fns::{ExternFn, StaticFn, StaticFnType};
+use crate::fns::{ExternFn, NativeFn, StaticFn, StaticFnType};
+use crate::snapshot::ScopeSnapshot;
 use crate::tks::{Literal, TokenChain};
-use crate::vm::Transmute;
-use std::collections::HashMap;
-use std::io::Cursor;
+use crate::vm::{_bounded_capacity, _write_len, _write_str, Transmute};
+use anyhow::bail;
+use std::cell::{RefCell, RefMut};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Write};
 use std::mem;
+use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Mutex, MutexGuard};
 
 #[inline]
@@ -13,18 +17,7 @@ pub fn merge_scopes(first: &mut ContainingScope, second: &mut MutexGuard<Contain
     first.mutables = second.mutables.clone();
     first.static_fns = second.static_fns.clone();
     first.consts = second.consts.clone();
-}
-
-fn _string_size(str: &String) -> usize {
-    str.len() + 2
-}
-
-fn _write_str(str: &String, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-    let bytes = str.as_bytes();
-    let vec: [u8; 2] = unsafe { mem::transmute(str.len() as u16) };
-    buf.extend_from_slice(&vec);
-    buf.extend_from_slice(&bytes);
-    Ok(())
+    first.types = second.types.clone();
 }
 
 impl<V> Transmute for HashMap<String, V>
@@ -32,37 +25,37 @@ where
     V: Transmute,
 {
     fn size(&mut self) -> usize {
-        let mut s = 0;
-        let size: Vec<u64> = self
+        4 + self
             .iter_mut()
-            .map(|(k, v)| ((_string_size(k) + v.size()) as u64))
-            .collect();
-        for i in size {
-            s += i;
-        }
-        (s + 4) as usize
+            .map(|(k, v)| k.len() + 4 + v.size())
+            .sum::<usize>()
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        (self.len() as u32).write(buf)?;
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        _write_len(self.len(), buf)?;
 
-        for (k, v) in self {
+        // keys are sorted, so the same map is always written to the same bytes
+        let mut entries: Vec<(&String, &mut V)> = self.iter_mut().collect();
+        entries.sort_by_key(|(k, _)| *k);
+        for (k, v) in entries {
             _write_str(k, buf)?;
-            v.write(buf)?;
+            v.write_to(buf)?;
         }
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        let len = u32::read(buf)? as usize;
-        let mut map = HashMap::new();
+        let len = u32::read_from(buf)? as usize;
+        let mut map = HashMap::with_capacity(_bounded_capacity(len));
         for _ in 0..len {
-            let key = String::read(buf)?;
-            let value = V::read(buf)?;
-            map.insert(key, value).unwrap();
+            let key = String::read_from(buf)?;
+            let value = V::read_from(buf)?;
+            if map.insert(key.clone(), value).is_some() {
+                bail!("Duplicate key {} in a serialized map!", key)
+            }
         }
         Ok(map)
     }
@@ -76,6 +69,10 @@ pub struct ContainingScope {
     static_fns: HashMap<String, Box<StaticFnType>>,
     exports: Vec<String>,
     imports: HashMap<String, Vec<String>>,
+    /// Names of values that were copied from imports, and may be shadowed by declarations
+    imported: HashSet<String>,
+    /// Types variables are locked to, either declared or inferred from their first non-void value
+    types: HashMap<String, String>,
 }
 
 impl Transmute for ContainingScope {
@@ -85,28 +82,31 @@ impl Transmute for ContainingScope {
             + self.static_fns.size()
             + self.exports.size()
             + self.imports.size()
+            + self.types.size()
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        self.mutables.write(buf)?;
-        self.consts.write(buf)?;
-        self.consts.write(buf)?;
-        self.static_fns.write(buf)?;
-        self.exports.write(buf)?;
-        self.imports.write(buf)?;
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        self.mutables.write_to(buf)?;
+        self.consts.write_to(buf)?;
+        self.static_fns.write_to(buf)?;
+        self.exports.write_to(buf)?;
+        self.imports.write_to(buf)?;
+        self.types.write_to(buf)?;
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
         Ok(ContainingScope {
-            mutables: HashMap::read(buf)?,
-            consts: HashMap::read(buf)?,
-            static_fns: HashMap::read(buf)?,
-            exports: Vec::read(buf)?,
-            imports: HashMap::read(buf)?,
+            mutables: HashMap::read_from(buf)?,
+            consts: HashMap::read_from(buf)?,
+            static_fns: HashMap::read_from(buf)?,
+            exports: Vec::read_from(buf)?,
+            imports: HashMap::read_from(buf)?,
+            imported: Default::default(),
+            types: HashMap::read_from(buf)?,
         })
     }
 }
@@ -119,32 +119,138 @@ impl ContainingScope {
             static_fns: Default::default(),
             exports: vec![],
             imports: Default::default(),
+            imported: Default::default(),
+            types: Default::default(),
         }
     }
 
+    /// Assigns a variable, which is only allowed if no constant with the same name
+    /// was declared in this scope. The value has to match the type the variable is locked to,
+    /// variables without a locked type get locked to the type of their first non-void value
     pub fn add_var(&mut self, name: &str, var: Literal) {
-        if self.mutables.contains_key(name) {
-            self.mutables.remove(name);
+        self.shadow_import(name);
+        if self.consts.contains_key(name) {
+            panic!("Can not reassign constant {}!", name)
         }
+        let var = match self.types.get(name) {
+            Some(ty) => _typed_value(name, ty, var),
+            None => {
+                self.lock_type(name, &var);
+                var
+            }
+        };
         self.mutables.insert(name.to_string(), var);
     }
 
-    pub fn add_const(&mut self, name: &str, var: Literal) {
+    /// Declares a variable, dropping the type a previous declaration was locked to.
+    /// Declared types are locked right away, otherwise the type is inferred like in [`Self::add_var`]
+    pub fn declare_var(&mut self, name: &str, var: Literal, ty: Option<String>) {
+        self.shadow_import(name);
         if self.consts.contains_key(name) {
             panic!("Can not reassign constant {}!", name)
         }
+        self.types.remove(name);
+        let var = match ty {
+            Some(ty) if ty != "unknown" => {
+                let var = _typed_value(name, &ty, var);
+                self.types.insert(name.to_string(), ty);
+                var
+            }
+            _ => {
+                self.lock_type(name, &var);
+                var
+            }
+        };
+        self.mutables.insert(name.to_string(), var);
+    }
+
+    /// Type the variable is locked to, if it was declared or assigned a non-void value
+    pub fn var_type(&self, name: &str) -> Option<&str> {
+        self.types.get(name).map(|ty| ty.as_str())
+    }
+
+    fn lock_type(&mut self, name: &str, var: &Literal) {
+        if !matches!(var, Literal::Void | Literal::Ident(_)) {
+            self.types.insert(name.to_string(), var.this_type());
+        }
+    }
+
+    /// Declares a constant, names can not be redeclared as constants in the same scope
+    pub fn add_const(&mut self, name: &str, var: Literal) {
+        self.shadow_import(name);
+        if self.consts.contains_key(name) || self.mutables.contains_key(name) {
+            panic!("Can not redeclare {} as a constant in the same scope!", name)
+        }
         self.consts.insert(name.to_string(), var);
     }
 
-    pub fn mutate(&mut self, name: &str, var: Literal) {
-        if self.mutables.get(name).unwrap().type_matches(&var) {
+    /// Whether this scope declares or already imported a value or a function with the provided name
+    pub fn declares(&self, name: &str) -> bool {
+        self.consts.contains_key(name)
+            || self.mutables.contains_key(name)
+            || self.static_fns.contains_key(name)
+    }
+
+    /// Copies an imported value into this scope
+    pub fn add_imported(&mut self, name: &str, value: ScopedValue) {
+        match value {
+            ScopedValue::Constant(v) => self.consts.insert(name.to_string(), v),
+            ScopedValue::Mutable(v) => self.mutables.insert(name.to_string(), v),
+            ScopedValue::StaticFn(v) => {
+                self.static_fns.insert(name.to_string(), Box::new(v));
+                None
+            }
+        };
+        self.imported.insert(name.to_string());
+    }
+
+    /// Drops values copied from the `from` scope, so they are imported again on next lookup
+    pub fn forget_imported(&mut self, from: &str) {
+        let names = match self.imports.get(from) {
+            Some(names) => names.clone(),
+            None => return,
+        };
+        for name in names {
+            if self.imported.remove(&name) {
+                self.consts.remove(&name);
+                self.mutables.remove(&name);
+                self.static_fns.remove(&name);
+                self.types.remove(&name);
+            }
+        }
+    }
+
+    /// Carries over variables of the `old` scope, unless they are now declared as constants
+    pub fn restore_mutables(&mut self, old: &ContainingScope) {
+        for (name, value) in &old.mutables {
+            if !self.consts.contains_key(name) && !old.imported.contains(name) {
+                self.mutables.insert(name.to_owned(), value.to_owned());
+                if let Some(ty) = old.types.get(name) {
+                    self.types.insert(name.to_owned(), ty.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Drops the imported value, so a local declaration can take its place
+    fn shadow_import(&mut self, name: &str) {
+        if self.imported.remove(name) {
+            self.consts.remove(name);
             self.mutables.remove(name);
-            self.mutables.insert(name.to_string(), var);
-        } else {
-            panic!("Tried to mutate variable of different type!")
+            self.types.remove(name);
         }
     }
 
+    /// Changes the value of an existing variable, keeping the type it is locked to
+    pub fn mutate(&mut self, name: &str, var: Literal) {
+        let var = match self.types.get(name) {
+            Some(ty) => _typed_value(name, ty, var),
+            None if self.mutables.get(name).unwrap().type_matches(&var) => var,
+            None => panic!("Tried to mutate variable of different type!"),
+        };
+        self.mutables.insert(name.to_string(), var);
+    }
+
     pub fn get_var(&self, name: &str) -> Option<Literal> {
         self.mutables.get(name).map(|l| l.to_owned())
     }
@@ -157,6 +263,10 @@ impl ContainingScope {
         self.exports.push(export.to_string())
     }
 
+    pub fn is_exported(&self, name: &str) -> bool {
+        self.exports.iter().any(|it| it == name)
+    }
+
     pub fn import(&mut self, from: &str, import: &str) {
         if self.imports.contains_key(from) {
             let mut imports = self.imports.remove(from).unwrap().clone();
@@ -192,6 +302,30 @@ impl ContainingScope {
          Box::new(StaticFnType::Extern(ExternFn::new(output_ty, param_names, handler_ptr))));
     }
 
+    /// Adds an extern function whose arguments are checked against `param_types` before it is called
+    pub fn add_typed_extern_fn(
+        &mut self,
+        name: &str,
+        output_ty: String,
+        param_names: Vec<String>,
+        param_types: Vec<String>,
+        handler_ptr: usize
+    ) {
+        let fnc = ExternFn::new(output_ty, param_names, handler_ptr).with_param_types(param_types);
+        self.static_fns.insert(name.to_string(), Box::new(StaticFnType::Extern(fnc)));
+    }
+
+    pub fn add_native_fn(
+        &mut self,
+        name: &str,
+        output_ty: String,
+        param_names: Vec<String>,
+        handler_ptr: usize
+    ) {
+        self.static_fns.insert(name.to_string(),
+         Box::new(StaticFnType::Native(NativeFn::new(output_ty, param_names, handler_ptr))));
+    }
+
     pub fn add_prebuilt_static_fn(&mut self, name: &str, sf: StaticFn) {
         self.static_fns.insert(name.to_string(), Box::new(StaticFnType::Standard(sf)));
     }
@@ -200,10 +334,43 @@ impl ContainingScope {
         self.static_fns.insert(name.to_string(), Box::new(StaticFnType::Extern(ef)));
     }
 
+    pub fn add_prebuilt_native_fn(&mut self, name: &str, nf: NativeFn) {
+        self.static_fns.insert(name.to_string(), Box::new(StaticFnType::Native(nf)));
+    }
+
     pub fn get_static_fn(&mut self, name: &str) -> Option<StaticFnType> {
         self.static_fns.get(name).map(|f| *f.clone())
     }
 
+    /// Removes a function from this scope along with its export
+    pub fn remove_static_fn(&mut self, name: &str) -> Option<StaticFnType> {
+        self.exports.retain(|it| it != name);
+        self.imported.remove(name);
+        self.static_fns.remove(name).map(|f| *f)
+    }
+
+    /// Stops importing `name` from the `from` scope, dropping the copy that was already imported
+    pub fn remove_import(&mut self, from: &str, name: &str) {
+        if let Some(names) = self.imports.get_mut(from) {
+            names.retain(|it| it != name);
+            if names.is_empty() {
+                self.imports.remove(from);
+            }
+        }
+        if self.imported.remove(name) {
+            self.consts.remove(name);
+            self.mutables.remove(name);
+            self.static_fns.remove(name);
+            self.types.remove(name);
+        }
+    }
+
+    /// Stops importing anything from the `from` scope
+    pub fn remove_imports(&mut self, from: &str) {
+        self.forget_imported(from);
+        self.imports.remove(from);
+    }
+
     pub fn get_any_value(&mut self, name: &str) -> Option<ScopedValue> {
         let c = self.get_const(name);
         if c.is_some() {
@@ -211,7 +378,7 @@ impl ContainingScope {
         }
         let m = self.get_var(name);
         if m.is_some() {
-            return Some(ScopedValue::Mutable(c?));
+            return Some(ScopedValue::Mutable(m?));
         }
         let sf = self.get_static_fn(name);
         if sf.is_some() {
@@ -223,6 +390,60 @@ impl ContainingScope {
     pub fn imports(&mut self) -> HashMap<String, Vec<String>> {
         self.imports.to_owned()
     }
+
+    pub fn static_fns(&self) -> impl Iterator<Item = (&String, &StaticFnType)> {
+        self.static_fns.iter().map(|(name, fnc)| (name, fnc.as_ref()))
+    }
+
+    /// Variables and constants in this scope, including imported ones
+    pub fn values(&self) -> BTreeMap<String, Literal> {
+        self.mutables
+            .iter()
+            .chain(&self.consts)
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    /// Variables and constants declared in this scope, without imported ones
+    pub fn declared_values(&self) -> BTreeMap<String, Literal> {
+        self.mutables
+            .iter()
+            .chain(&self.consts)
+            .filter(|(name, _)| !self.imported.contains(*name))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> ScopeSnapshot {
+        let mut functions: Vec<String> = self.static_fns.keys().cloned().collect();
+        functions.sort();
+        let mut exports = self.exports.clone();
+        exports.sort();
+        ScopeSnapshot {
+            variables: self.mutables.clone().into_iter().collect(),
+            constants: self.consts.clone().into_iter().collect(),
+            functions,
+            exports,
+            imports: self.imports.clone().into_iter().collect(),
+        }
+    }
+}
+
+/// Makes sure a value assigned to `name` has the type `ty`,
+/// numbers are widened to floats or decimals where those are expected
+pub(crate) fn _typed_value(name: &str, ty: &str, value: Literal) -> Literal {
+    match value {
+        Literal::Number(num) if ty == "float" => Literal::Float(num as f64),
+        Literal::Number(num) if ty == "decimal" => Literal::Decimal(num.into()),
+        value if ty == "unknown" || value.type_str(ty) => value,
+        other => panic!(
+            "Can not assign {} {} to {}, which is of type {}!",
+            other.this_type(),
+            other,
+            name,
+            ty
+        ),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -231,3 +452,72 @@ pub enum ScopedValue {
     Mutable(Literal),
     StaticFn(StaticFnType),
 }
+
+/// How a [`Vm`](crate::visit::Vm) stores its scopes
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ScopeMode {
+    /// Every scope is behind an `Arc<Mutex<_>>`, so scopes can be shared between Vms and threads
+    #[default]
+    Shared,
+    /// Scopes live in an arena owned by the Vm and are accessed by index through a `RefCell`,
+    /// which avoids locking on every access. Freed slots are reused by the next scopes.
+    SingleThreaded,
+}
+
+/// Exclusive access to a scope, regardless of where it is stored
+pub enum ScopeGuard<'a> {
+    Locked(MutexGuard<'a, ContainingScope>),
+    Borrowed(RefMut<'a, ContainingScope>),
+}
+
+impl Deref for ScopeGuard<'_> {
+    type Target = ContainingScope;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ScopeGuard::Locked(scope) => scope,
+            ScopeGuard::Borrowed(scope) => scope,
+        }
+    }
+}
+
+impl DerefMut for ScopeGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            ScopeGuard::Locked(scope) => scope,
+            ScopeGuard::Borrowed(scope) => scope,
+        }
+    }
+}
+
+/// Scopes of a single threaded Vm, addressed by their slot index
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ScopeArena {
+    slots: Vec<RefCell<ContainingScope>>,
+    free: Vec<usize>,
+}
+
+impl ScopeArena {
+    pub fn alloc(&mut self, scope: ContainingScope) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                *self.slots[index].get_mut() = scope;
+                index
+            }
+            None => {
+                self.slots.push(RefCell::new(scope));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    pub fn get(&self, index: usize) -> RefMut<'_, ContainingScope> {
+        self.slots[index].borrow_mut()
+    }
+
+    /// Takes the scope out of its slot, so the slot can be reused
+    pub fn free(&mut self, index: usize) -> ContainingScope {
+        self.free.push(index);
+        mem::replace(self.slots[index].get_mut(), ContainingScope::new())
+    }
+}
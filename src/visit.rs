@@ -1,40 +1,90 @@
-use crate::tks::{Literal, Token, TokenChain};
-use crate::var::{ContainingScope, ScopedValue};
+use crate::asm::{assemble_with, AssembleOptions};
+use crate::tks::{Expression, Literal, Token, TokenChain};
+use crate::var::{_typed_value, ContainingScope, ScopeArena, ScopeGuard, ScopeMode};
 use crate::ToResult;
-use anyhow::bail;
-use std::collections::{HashMap, VecDeque};
+use anyhow::{anyhow, bail};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 use colored::Colorize;
 use rand::RngCore;
 use crate::features::StdFeature;
-use crate::fns::{EXTERN_FNS, StaticFnType};
+use crate::fns::{_drop_owned, replace_extern_fn, EXTERN_FNS, Metadata, Parameters, StaticFn, StaticFnType};
+use crate::check::{check, Diagnostic};
+use crate::diagnostics::did_you_mean;
+use crate::typecheck::{typecheck, FnType, TypeEnv};
+use crate::manifest::{HostCapabilities, Version};
+use crate::marshal::{FromLiteral, IntoLiteral};
+use crate::span::{SourceMap, Span};
+use crate::determinism::{Deterministic, Entropy};
+use crate::io::{SharedIo, StdIo};
+use crate::handles::Handles;
+use crate::stdlib::log::{Level, Logger};
+use crate::stdlib::test::{_panic_message, _run_test, TestCase, TestReport, TestResult};
+use crate::snapshot::{is_temporary_scope, VmStateSnapshot};
+use crate::statics::Statics;
+use crate::stream::{SourceSlot, TokenSource};
+use crate::structs::{EnumTemplate, StructureTemplate};
+use crate::warn::{Warning, WarningCode, WarningLevel};
+use crate::trace::{TraceConfig, TraceEntry, TraceEvent, Tracer};
+use std::fmt::{Debug, Formatter};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use crate::platform::grow_stack;
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Scope {
     Struct,
     StaticFunction,
     InstanceFunction,
+    Namespace,
     Global,
 }
 
+/// Stream of tokens a visitor processes.
+///
+/// The stream is a queue addressed from its end: [`TokenProvider::add_token`] puts a token
+/// in front of every queued one, and position `0` of [`TokenProvider::insert_token`]
+/// is behind the last queued token
 pub trait TokenProvider {
+    /// Takes the next token, failing if the stream is empty
     fn next_token(&mut self) -> anyhow::Result<Token>;
+    /// Next token without taking it, failing if the stream is empty
     fn peek_token(&mut self) -> anyhow::Result<Token>;
+    /// Queues a token in front of the queued ones, so a chain is loaded by adding its tokens in order
     fn add_token(&mut self, tk: Token);
+    /// Inserts a token `at` tokens away from the end of the stream
     fn insert_token(&mut self, tk: Token, at: usize);
 }
 
+/// Values, functions and types declared by the processed tokens, along with the services
+/// the standard library relies on
 pub trait ScopeProvider {
     fn add_std_feature(&mut self, feature: StdFeature);
 
     fn resolve_var(&self, name: &str) -> anyhow::Result<Literal>;
     fn resolve_const(&self, name: &str) -> anyhow::Result<Literal>;
+    /// Variables, constants and statics visible from the current scope with their values,
+    /// whose names are suggested when a name can not be resolved
+    fn visible_values(&self) -> BTreeMap<String, Literal>;
+    /// Functions callable from the current scope without a path
+    fn visible_fns(&self) -> BTreeMap<String, StaticFnType>;
+    /// Literals on the stack, the topmost one last
+    fn stack_values(&self) -> Vec<Literal>;
+    /// Name of the scope being processed, see [`LiteralStack::scope_name`]
+    fn current_scope_name(&self) -> String;
 
     fn import(&mut self, from: String, name: String);
     fn export(&mut self, name: String);
 
+    fn warn(&mut self, code: WarningCode, message: String);
+
     fn add_var(&mut self, name: String, var: Literal);
+    /// Declares a variable with `let`, optionally locking it to the declared type
+    fn declare_var(&mut self, name: String, var: Literal, ty: Option<String>);
     fn add_const(&mut self, name: String, var: Literal);
+    /// Declares a `static`, which is visible from every scope. Existing statics are kept
+    fn declare_static(&mut self, name: String, var: Literal, ty: Option<String>);
 
     fn add_static_fn(
         &mut self,
@@ -50,50 +100,125 @@ pub trait ScopeProvider {
         param_names: Vec<String>,
         ptr: usize
     );
+    fn add_typed_extern_fn(
+        &mut self,
+        name: String,
+        output_ty: String,
+        param_names: Vec<String>,
+        param_types: Vec<String>,
+        ptr: usize
+    );
+
+    /// Adds an attribute that will be attached to the next declared function or structure
+    fn add_attr(&mut self, name: String, value: Literal);
+
+    /// Backend the `std::io` functions write to and read from
+    fn io(&self) -> SharedIo;
+    /// Host values the standard library hands out to programs by handles, see [`Handles`]
+    fn handles(&self) -> Handles;
+    /// Passes a message logged through `std::log` to the logger of the host
+    fn log(&self, level: Level, message: &str);
+    /// Source of randomness, time and temporary scope identifiers, see [`Vm::set_deterministic`]
+    fn entropy(&self) -> Entropy;
+    /// Collects a test declared in the current scope, see [`Vm::run_tests`]
+    fn add_test(&mut self, name: String, chain: TokenChain);
 
     fn current_struct_name(&self) -> Option<String>;
     fn add_struct_name(&mut self, name: String);
+    fn pop_struct_name(&mut self) -> Option<String>;
+
+    fn add_struct(&mut self, template: StructureTemplate);
+    fn resolve_type(&self, name: &str) -> anyhow::Result<StructureTemplate>;
+    fn add_enum(&mut self, template: EnumTemplate);
+    fn resolve_enum(&self, name: &str) -> anyhow::Result<EnumTemplate>;
+    fn add_inst_var(&mut self, name: String, ty: String, default: Option<Literal>);
+    /// Fails if `member` of the structure is private and is not accessed from one of its functions
+    fn check_access(&self, structure: &str, member: &str) -> anyhow::Result<()>;
+    /// Collects an `init` block of the current structure, see [`ScopeProvider::init_struct`]
+    fn add_struct_init(&mut self, chain: TokenChain);
+    /// Runs the collected `init` blocks of a structure in its scope, once all of its members are declared
+    fn init_struct(&mut self, name: String);
+    fn add_inst_fn(
+        &mut self,
+        name: String,
+        output_ty: String,
+        param_names: Vec<String>,
+        tks: TokenChain,
+    );
 
     fn call_static_fn(&mut self, name: String, params: TokenChain) -> Literal;
+    fn resolve_fn(&self, name: &str) -> anyhow::Result<StaticFnType>;
     fn call_ptr_fn(&mut self, ptr: usize, params: TokenChain) -> Literal;
+    fn call_inst_fn(&mut self, receiver: String, name: String, params: TokenChain) -> Literal;
+    /// Calls an instance function on a temporary value, like the result of another call
+    fn call_inst_fn_on(&mut self, instance: Literal, name: String, params: TokenChain) -> Literal;
 
     fn resolve_any_var(&self, name: &str) -> Literal {
         let var = self.resolve_var(name);
         if var.is_ok() {
             var.unwrap().to_owned()
         } else {
-            self.resolve_const(name).unwrap().to_owned()
+            self.resolve_const(name).unwrap_or_else(|_| {
+                panic!(
+                    "Could not find variable or constant {}!{}",
+                    name,
+                    did_you_mean(name, self.visible_values().keys())
+                )
+            })
         }
     }
 }
 
+/// Kinds of the nested blocks that are being processed, and storage of named scopes.
+///
+/// Scopes are named `global` for the top level, by their full path for namespaces and
+/// libraries, like `outer::inner` or `std::io`, and by their name for structures.
+/// Function calls run in temporary scopes, which are dropped once the call returns
 pub trait GlobalScope {
+    /// Enters a block of the provided kind, until the matching [`GlobalScope::pop_scope_level`]
     fn push_scope_level(&mut self, scope: Scope);
     fn pop_scope_level(&mut self) -> Scope;
+    /// Kind of the innermost block that is being processed
     fn scope_level(&mut self) -> Scope;
+    /// Stores a scope under `name`, replacing a scope with the same name
     fn push_scope(&mut self, name: String, scope: ContainingScope);
 }
 
+/// Stack of evaluated literals, and navigation between the stored scopes
 pub trait LiteralStack {
     fn push_stack(&mut self, value: Literal);
-    fn pop_stack(&mut self) -> Literal;
+    /// Pops the topmost literal, failing on stack underflow
+    fn pop_stack(&mut self) -> anyhow::Result<Literal>;
 
+    /// Makes the scope with the provided name the current one, declarations go into the current scope
     fn move_scope(&mut self, name: String);
     fn scope_name(&self) -> String;
-    fn drop_scope(&mut self, name: String) -> Arc<Mutex<ContainingScope>>;
+    /// Removes a stored scope, returning its final state
+    fn drop_scope(&mut self, name: String) -> ContainingScope;
 
-    fn get_scope(&self, name: String) -> &Arc<Mutex<ContainingScope>>;
+    fn get_scope(&self, name: String) -> ScopeGuard<'_>;
+    fn has_scope(&self, name: &str) -> bool;
+    /// Whether other Vms reference the scope too, like the Vms of a [`SharedRuntime`](crate::runtime::SharedRuntime)
+    fn is_shared_scope(&self, name: &str) -> bool;
 }
 
-pub trait Visitor: TokenProvider + Clone + ScopeProvider + GlobalScope + LiteralStack {
-    fn visit<V>(&mut self, visitable: &mut V)
-    where
-        V: Visitable;
-
+/// Object safe part of a [`Visitor`], which processes the token stream.
+///
+/// Custom backends implement it along with its supertraits, and [`Visitor`] on top of it.
+/// Code that only drives processing can work with a `&mut dyn VisitorCore`
+pub trait VisitorCore: TokenProvider + ScopeProvider + GlobalScope + LiteralStack {
+    /// Visits tokens until the stream is empty. Declarations and values left on the stack
+    /// persist between calls, so chains can be loaded and processed one after another
     fn process(&mut self);
 
+    /// Visits the `until` tokens at the end of the stream, at least one, which are the ones
+    /// inserted at position `0` last. Tokens taken by them are still taken from the front
     fn process_until(&mut self, until: usize);
+    /// Visits the tokens between the provided positions of the stream on a clone of this visitor,
+    /// leaving the stream as it is
     fn process_between(&mut self, from: usize, to: usize);
+    /// Visits a whole chain in the current scope, without touching tokens that are still queued
+    fn process_isolated(&mut self, chain: &mut TokenChain);
 
     fn load_chain(&mut self, chain: &mut TokenChain) {
         let iter = chain.iter();
@@ -108,6 +233,14 @@ pub trait Visitor: TokenProvider + Clone + ScopeProvider + GlobalScope + Literal
             self.add_token(ele.to_owned());
         }
     }
+}
+
+/// Visitor the tokens are visited with. Visitors are cloned to process parts of the stream
+/// on their own, clones share the stored scopes
+pub trait Visitor: VisitorCore + Clone {
+    fn visit<V>(&mut self, visitable: &mut V)
+    where
+        V: Visitable;
 
     fn process_chain(&mut self, chain: &mut TokenChain) {
         for ele in chain {
@@ -122,75 +255,900 @@ pub trait Visitable {
         V: Visitor;
 }
 
+/// What the visitor should do with a token after an interceptor inspected it
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterceptAction {
+    Continue,
+    Skip,
+    Replace(Token),
+}
+
+pub type Interceptor = dyn Fn(&mut Vm, &Token) -> InterceptAction + Send + Sync;
+
+#[derive(Clone, Default)]
+struct Interceptors(Vec<Arc<Interceptor>>);
+
+impl Debug for Interceptors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interceptors({})", self.0.len())
+    }
+}
+
+#[derive(Clone, Default)]
+struct LoggerSlot(Option<Arc<Logger>>);
+
+impl Debug for LoggerSlot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LoggerSlot({})", if self.0.is_some() { "custom" } else { "default" })
+    }
+}
+
+/// Where a single scope of the [`Vm`] is stored, as decided by its [`ScopeMode`]
+#[derive(Debug, Clone)]
+enum ScopeSlot {
+    Shared(Arc<Mutex<ContainingScope>>),
+    Arena(usize),
+}
+
 #[derive(Debug, Clone)]
 #[repr(C)]
 pub struct Vm {
     free: usize,
     pos: usize,
     tks: VecDeque<Token>,
+    spans: VecDeque<Option<Span>>,
+    span: Option<Span>,
     lit_stack: Vec<Literal>,
     current_scope: String,
-    scopes: HashMap<String, Arc<Mutex<ContainingScope>>>,
+    scopes: HashMap<String, ScopeSlot>,
+    arena: ScopeArena,
+    scope_mode: ScopeMode,
     struct_names: VecDeque<String>,
+    structs: HashMap<String, StructureTemplate>,
+    enums: HashMap<String, EnumTemplate>,
+    statics: Statics,
+    /// Structure every running function belongs to, `None` for functions outside of structures
+    member_of: Vec<Option<String>>,
+    /// `init` blocks of the structures that are being declared
+    struct_inits: HashMap<String, Vec<TokenChain>>,
+    attrs: Metadata,
+    interceptors: Interceptors,
+    tracer: Option<Tracer>,
+    io: SharedIo,
+    handles: Handles,
+    logger: LoggerSlot,
+    source: SourceSlot,
+    tests: Vec<TestCase>,
+    entropy: Entropy,
+    warnings: Vec<Warning>,
+    warning_level: WarningLevel,
+    warning_levels: HashMap<WarningCode, WarningLevel>,
+    used_names: Arc<Mutex<HashSet<String>>>,
+    processing: bool,
+    libraries: HashSet<String>,
+    features: HashSet<StdFeature>,
+    call_depth: usize,
+    max_call_depth: usize,
     scope_types: VecDeque<Scope>,
+    assemble_options: AssembleOptions,
 }
 
+/// Default limit of nested gale function calls, see [`Vm::set_max_call_depth`]
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+/// Function programs are started with, unless their manifest says otherwise
+pub const DEFAULT_ENTRYPOINT: &str = "main";
+
 impl Vm {
     pub fn new() -> Self {
-        Self {
+        Self::with_scope_mode(ScopeMode::Shared)
+    }
+
+    /// Creates a Vm, that stores its scopes as described by the `mode`
+    pub fn with_scope_mode(mode: ScopeMode) -> Self {
+        let mut vm = Self {
             free: 0,
             pos: 0,
             tks: VecDeque::new(),
+            spans: VecDeque::new(),
+            span: None,
             lit_stack: vec![],
             current_scope: "global".to_string(),
-            scopes: HashMap::from([(
-                "global".to_string(),
-                Arc::new(Mutex::new(ContainingScope::new())),
-            )]),
+            scopes: HashMap::new(),
+            arena: Default::default(),
+            scope_mode: mode,
             struct_names: Default::default(),
+            structs: Default::default(),
+            enums: Default::default(),
+            statics: Default::default(),
+            member_of: vec![],
+            struct_inits: Default::default(),
+            attrs: Default::default(),
+            interceptors: Default::default(),
+            tracer: None,
+            io: Arc::new(StdIo),
+            handles: Default::default(),
+            logger: Default::default(),
+            source: Default::default(),
+            tests: vec![],
+            entropy: Default::default(),
+            warnings: vec![],
+            warning_level: WarningLevel::default(),
+            warning_levels: Default::default(),
+            used_names: Default::default(),
+            processing: false,
+            libraries: Default::default(),
+            features: Default::default(),
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
             scope_types: VecDeque::from(vec![Scope::Global]),
+            assemble_options: AssembleOptions::default(),
+        };
+        vm.push_scope("global".to_string(), ContainingScope::new());
+        vm
+    }
+
+    /// Creates a Vm, that references already built scopes of the `template` instead of
+    /// registering std features again. The global scope is created anew with the same imports.
+    ///
+    /// Scopes of a single threaded template can not be referenced, so they are copied.
+    pub(crate) fn from_template(template: &Vm) -> Self {
+        let mut vm = Vm::with_scope_mode(template.scope_mode);
+        for (name, slot) in &template.scopes {
+            if name == "global" {
+                continue;
+            }
+            let slot = match slot {
+                ScopeSlot::Shared(scope) => ScopeSlot::Shared(scope.clone()),
+                ScopeSlot::Arena(index) => {
+                    ScopeSlot::Arena(vm.arena.alloc(template.arena.get(*index).clone()))
+                }
+            };
+            vm.scopes.insert(name.to_owned(), slot);
+        }
+        let imports = template.scope("global").imports();
+        let mut global = vm.scope("global");
+        for (from, names) in imports {
+            for name in names {
+                global.import(&from, &name);
+            }
         }
+        drop(global);
+        vm.features = template.features.clone();
+        vm.libraries = template.libraries.clone();
+        vm.max_call_depth = template.max_call_depth;
+        vm.io = template.io.clone();
+        vm.logger = template.logger.clone();
+        vm.entropy = template.entropy.clone();
+        vm
+    }
+
+    /// Adds an interceptor, that is invoked before each token popped for visiting.
+    ///
+    /// Interceptors run in the order they were added, and can skip or replace the token.
+    pub fn add_interceptor(&mut self, interceptor: Box<Interceptor>) {
+        self.interceptors.0.push(Arc::from(interceptor));
+    }
+
+    /// Executes the chain inside a dedicated scope, which can then be imported from
+    /// the same way as a [`StdFeature`].
+    ///
+    /// Only names that were exported by the chain can be imported from a library.
+    pub fn add_library(&mut self, name: &str, mut chain: TokenChain) -> anyhow::Result<()> {
+        if self.scopes.contains_key(name) {
+            bail!("Scope {} is already defined!", name)
+        }
+        self.push_scope(name.to_string(), ContainingScope::new());
+        self.libraries.insert(name.to_string());
+        self.run_library(name, &mut chain);
+        Ok(())
+    }
+
+    /// Replaces functions and constants of a library added with [`Vm::add_library`],
+    /// while values of its variables and the global state are kept.
+    ///
+    /// Values other scopes imported from the library are imported again on their next lookup.
+    pub fn reload_library(&mut self, name: &str, mut chain: TokenChain) -> anyhow::Result<()> {
+        if !self.libraries.contains(name) {
+            bail!("Library {} is not loaded!", name)
+        }
+        let old = self.drop_scope(name.to_string());
+        self.push_scope(name.to_string(), ContainingScope::new());
+        self.run_library(name, &mut chain);
+        self.scope(name).restore_mutables(&old);
+        for scope in self.scopes.keys() {
+            self.scope(scope).forget_imported(name);
+        }
+        Ok(())
+    }
+
+    fn run_library(&mut self, name: &str, chain: &mut TokenChain) {
+        let cached = mem::replace(&mut self.current_scope, name.to_string());
+        self.push_scope_level(Scope::Namespace);
+        self.process_isolated(chain);
+        self.pop_scope_level();
+        self.current_scope = cached;
+    }
+
+    /// Std features and host functions this Vm provides to programs
+    pub fn capabilities(&self) -> HostCapabilities {
+        let mut features: Vec<StdFeature> = self.features.iter().copied().collect();
+        features.sort();
+        let mut extern_fns = vec![];
+        for name in self.scopes.keys().filter(|name| !is_temporary_scope(name)) {
+            for (fn_name, fnc) in self.scope(name).static_fns() {
+                let path = if name == "global" { fn_name.to_owned() } else { format!("{}::{}", name, fn_name) };
+                extern_fns.extend(fnc.host_signature(path));
+            }
+        }
+        extern_fns.sort();
+        HostCapabilities {
+            vm_version: Version::current(),
+            features,
+            extern_fns,
+        }
+    }
+
+    /// Drops the state of the current run, like one that was interrupted by a panic: queued
+    /// tokens, values on the stack, scopes of unfinished calls and the blocks being processed.
+    ///
+    /// Declared scopes, values, functions and types persist, and the next run starts
+    /// at the top level of the global scope. Host values behind [`Handles`], like open
+    /// files, are dropped, so handles held by the persisted values are no longer valid
+    pub fn reset(&mut self) {
+        self.tks.clear();
+        self.spans.clear();
+        self.span = None;
+        self.clear_stack();
+        let temporary: Vec<String> = self.scopes.keys().filter(|name| is_temporary_scope(name)).cloned().collect();
+        for name in temporary {
+            self.drop_scope(name);
+        }
+        self.current_scope = "global".to_string();
+        self.scope_types = VecDeque::from(vec![Scope::Global]);
+        self.struct_names.clear();
+        self.struct_inits.clear();
+        self.attrs.clear();
+        self.member_of.clear();
+        self.call_depth = 0;
+        self.processing = false;
+        self.handles.clear();
+    }
+
+    /// Drops the values left on the stack, which are kept between runs until they are popped
+    pub fn clear_stack(&mut self) {
+        self.lit_stack.clear();
+    }
+
+    /// Takes a deterministic snapshot of scopes, variables, the literal stack and pending tokens
+    pub fn dump_state(&self) -> VmStateSnapshot {
+        let scopes = self
+            .scopes
+            .iter()
+            .filter(|(name, _)| !is_temporary_scope(name))
+            .map(|(name, _)| (name.to_owned(), self.scope(name).snapshot()))
+            .collect();
+        let mut structs: Vec<String> = self.structs.keys().cloned().collect();
+        structs.sort();
+        let enums = self
+            .enums
+            .iter()
+            .map(|(name, template)| (name.to_owned(), template.variant_names()))
+            .collect();
+        VmStateSnapshot {
+            scopes,
+            current: self.scope(&self.current_scope).snapshot(),
+            scope_levels: self.scope_types.iter().copied().collect(),
+            structs,
+            enums,
+            statics: self.statics.values(),
+            stack: self.lit_stack.clone(),
+            pending_tokens: self.tks.len(),
+        }
+    }
+
+    /// Names of all scopes, sorted. Temporary function scopes are not listed
+    pub fn list_scopes(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .scopes
+            .keys()
+            .filter(|name| !is_temporary_scope(name))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Variables and constants declared in a scope, sorted by name
+    pub fn list_vars(&self, scope: &str) -> anyhow::Result<BTreeMap<String, Literal>> {
+        if !self.has_scope(scope) {
+            bail!("Could not find scope {}!", scope)
+        }
+        Ok(self.scope(scope).declared_values())
+    }
+
+    /// Value of a global variable or constant
+    pub fn get_global(&self, name: &str) -> Option<Literal> {
+        let scope = self.scope("global");
+        scope.get_var(name).or_else(|| scope.get_const(name))
+    }
+
+    /// Declares or overwrites a global variable, constants can not be overwritten.
+    /// The variable is declared again, so its type is inferred from the new value
+    pub fn set_global(&mut self, name: &str, value: Literal) -> anyhow::Result<()> {
+        let mut scope = self.scope("global");
+        if scope.get_const(name).is_some() {
+            bail!("Can not reassign constant {}!", name)
+        }
+        scope.declare_var(name, value, None);
+        Ok(())
+    }
+
+    /// Value of a `static`, which is shared with every clone of this Vm
+    pub fn get_static(&self, name: &str) -> Option<Literal> {
+        self.statics.get(name)
+    }
+
+    /// Declares a `static` or changes the value of an existing one,
+    /// checking it against the declared type of the static
+    pub fn set_static(&mut self, name: &str, value: Literal) {
+        if !self.statics.set(name, value.clone()) {
+            self.statics.declare(name, value, None);
+        }
+    }
+
+    /// Loads the chain along with source positions of its tokens
+    pub fn load_spanned(&mut self, chain: &mut TokenChain, source_map: &SourceMap) {
+        for (index, tk) in chain.iter().enumerate() {
+            self.tks.push_front(tk.to_owned());
+            self.spans.push_front(source_map.get(index));
+        }
+    }
+
+    /// Source position of the token that was taken last, if it is known
+    pub fn current_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    fn take_token(&mut self, back: bool) -> Option<Token> {
+        if back {
+            self.pull_token();
+        }
+        let (tk, span) = if back {
+            (self.tks.pop_back(), self.spans.pop_back())
+        } else {
+            (self.tks.pop_front(), self.spans.pop_front())
+        };
+        self.span = span.flatten();
+        tk
+    }
+
+    /// Limits how deep gale function calls can be nested, exceeding the limit raises an error
+    /// instead of overflowing the host stack. Defaults to [`DEFAULT_MAX_CALL_DEPTH`].
+    ///
+    /// Calls move to a new segment of host stack when the current one runs low, see
+    /// [`crate::platform::grow_stack`], so the limit is only bound by memory. `wasm32` can not
+    /// grow its stack, so there the limit should be lowered to what the host stack holds.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Defines a name for the preprocessor of sources assembled by this Vm, like in [`Vm::eval`].
+    /// An empty value defines a flag for `#if`, see [`AssembleOptions`]
+    pub fn define<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.assemble_options.defines.insert(name.into(), value.into());
+    }
+
+    /// Options sources should be assembled with to run on this Vm
+    pub fn assemble_options(&self) -> &AssembleOptions {
+        &self.assemble_options
+    }
+
+    /// Sets how all warnings are handled, unless overridden for a specific code
+    pub fn set_warning_level(&mut self, level: WarningLevel) {
+        self.warning_level = level;
+    }
+
+    /// Overrides how warnings with the provided code are handled
+    pub fn set_code_warning_level(&mut self, code: WarningCode, level: WarningLevel) {
+        self.warning_levels.insert(code, level);
+    }
+
+    /// Warnings collected so far
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+
+    fn mark_used(&self, name: &str) {
+        self.used_names.lock().unwrap().insert(name.to_string());
+    }
+
+    /// Marks every name the token refers to as used, so imports only used in the bodies
+    /// of functions, which run after the chain is processed, are not reported as unused
+    fn mark_referenced(&self, tk: &Token) {
+        match tk {
+            Token::Literal(Literal::Ident(name)) => self.mark_used(name),
+            Token::Expression(expr) => match expr.as_ref() {
+                Expression::BinaryOp(_, lh, rh) | Expression::Index(lh, rh) => {
+                    self.mark_referenced(lh);
+                    self.mark_referenced(rh);
+                }
+                Expression::UnaryOp(_, value) => self.mark_referenced(value),
+                Expression::Ternary(cond, then, otherwise) => {
+                    self.mark_referenced(cond);
+                    self.mark_referenced(then);
+                    self.mark_referenced(otherwise);
+                }
+                Expression::StaticAccess(path) => path.iter().for_each(|name| self.mark_used(name)),
+                Expression::InstanceAccess(name, _) => self.mark_used(name),
+                Expression::InvokeStatic(name, params)
+                | Expression::Instantiate(name, params)
+                | Expression::InvokeInstance(name, _, params) => {
+                    self.mark_used(name);
+                    params.iter().for_each(|it| self.mark_referenced(it));
+                }
+                Expression::InvokeChained(receiver, _, params) => {
+                    self.mark_referenced(receiver);
+                    params.iter().for_each(|it| self.mark_referenced(it));
+                }
+                Expression::Array(values) => values.iter().for_each(|it| self.mark_referenced(it)),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Warns about imports made by the program, that were never used
+    fn lint_imports(&mut self, before: &HashMap<String, Vec<String>>) {
+        let imports = self.scope("global").imports();
+        let mut unused = vec![];
+        for (from, names) in imports {
+            for name in names {
+                let imported_before = before.get(&from).map(|it| it.contains(&name)).unwrap_or(false);
+                if !imported_before && !self.used_names.lock().unwrap().contains(&name) {
+                    unused.push(format!("{}::{}", from, name));
+                }
+            }
+        }
+        unused.sort();
+        for import in unused {
+            self.warn(WarningCode::UnusedImport, format!("Import {} is never used", import));
+        }
+    }
+
+    /// Statically checks the chain against the current state, without executing it
+    pub fn check(&self, chain: &TokenChain) -> Vec<Diagnostic> {
+        check(chain, &self.dump_state())
+    }
+
+    /// Statically checks the types in the chain against the current state, see [`typecheck`]
+    pub fn typecheck(&self, chain: &TokenChain) -> Vec<Diagnostic> {
+        typecheck(chain, &self.type_env())
+    }
+
+    /// Types of the functions in every scope and of the values declared in the global scope
+    pub fn type_env(&self) -> TypeEnv {
+        let mut env = TypeEnv::default();
+        for name in self.scopes.keys().filter(|name| !is_temporary_scope(name)) {
+            for (fn_name, fnc) in self.scope(name).static_fns() {
+                let path = if name == "global" { fn_name.to_owned() } else { format!("{}::{}", name, fn_name) };
+                env.fns.insert(path, FnType::from(fnc));
+            }
+        }
+        let mut global = self.scope("global");
+        for (from, names) in global.imports() {
+            for name in names {
+                if let Some(fnc) = env.fns.get(&format!("{}::{}", from, name)).cloned() {
+                    env.fns.insert(name, fnc);
+                }
+            }
+        }
+        for (name, value) in global.declared_values() {
+            let ty = match global.var_type(&name) {
+                Some(ty) => ty.to_owned(),
+                None if value == Literal::Void => continue,
+                None => value.this_type(),
+            };
+            env.vars.insert(name, ty);
+        }
+        env.enums = self.enums.clone();
+        for name in self.statics.values().into_keys() {
+            let ty = self.statics.type_of(&name).unwrap_or_else(|| "unknown".to_string());
+            env.statics.insert(name, ty);
+        }
+        env
+    }
+
+    /// Calls a function by its name or full path with already evaluated parameters.
+    /// The chain declaring the function has to be processed beforehand.
+    pub fn run_function(&mut self, name: &str, params: Parameters) -> anyhow::Result<Literal> {
+        let fnc = self.resolve_fn(name)?;
+        let param_names = fnc.param_names();
+        if !param_names.iter().any(|it| it == "varargs") && param_names.len() != params.len() {
+            bail!("Function {} expects {} arg(s), got {}!", name, param_names.len(), params.len())
+        }
+        self.in_run(|vm| {
+            let structure = name.rsplit_once('.').map(|(structure, _)| structure.to_string());
+            if let Some((structure, member)) = name.rsplit_once('.') {
+                vm.check_access(structure, member)?;
+            }
+            vm.trace(TraceEvent::Call(name.to_string()));
+            vm.enter_call(name);
+            vm.member_of.push(structure);
+            let output = grow_stack(|| fnc.call(params, Some(vm)));
+            vm.member_of.pop();
+            vm.call_depth -= 1;
+            Ok(output)
+        })
+    }
+
+    /// Calls a function like [`Vm::run_function`], converting its output into a Rust type
+    pub fn run_function_as<T: FromLiteral>(&mut self, name: &str, params: Parameters) -> anyhow::Result<T> {
+        T::from_literal(self.run_function(name, params)?)
+    }
+
+    /// Evaluates a single expression, like `limit * 2`, against the processed declarations,
+    /// see [`Vm::eval_chain`]. The source is preprocessed with the names of [`Vm::define`]
+    pub fn eval(&mut self, src: &str) -> anyhow::Result<Literal> {
+        let mut chain = assemble_with(src, &self.assemble_options)?;
+        self.eval_chain(&mut chain)
+    }
+
+    /// Processes a chain in the current scope and returns the value of its last expression.
+    ///
+    /// Queued tokens and the stack are left as they were, while declarations and assignments
+    /// made by the chain persist. Fails if the chain does not produce a value
+    pub fn eval_chain(&mut self, chain: &mut TokenChain) -> anyhow::Result<Literal> {
+        let depth = self.lit_stack.len();
+        self.in_run(|vm| vm.process_isolated(chain));
+        let output = if self.lit_stack.len() > depth { self.lit_stack.pop() } else { None };
+        self.lit_stack.truncate(depth);
+        match output {
+            Some(Literal::Ident(name)) => self
+                .resolve_var(&name)
+                .or_else(|_| self.resolve_const(&name))
+                .map_err(|_| {
+                    anyhow!(
+                        "Could not find variable or constant {}!{}",
+                        name,
+                        did_you_mean(&name, self.visible_values().keys())
+                    )
+                }),
+            Some(value) => Ok(value),
+            None => bail!("Expression did not produce a value!"),
+        }
+    }
+
+    /// Runs the conventional [`DEFAULT_ENTRYPOINT`] of a processed program, see [`Vm::run_entrypoint`]
+    pub fn run_main(&mut self, args: Vec<String>) -> anyhow::Result<i32> {
+        self.run_entrypoint(DEFAULT_ENTRYPOINT, args)
+    }
+
+    /// Runs the entrypoint of a processed program, passing `args` to it as an array of strings
+    /// if it takes a parameter. A returned number becomes the exit code, returning nothing means success.
+    pub fn run_entrypoint(&mut self, name: &str, args: Vec<String>) -> anyhow::Result<i32> {
+        let params = if self.resolve_fn(name)?.param_names().is_empty() {
+            vec![]
+        } else {
+            vec![args.into_literal()]
+        };
+        match self.run_function(name, params)? {
+            Literal::Void => Ok(0),
+            Literal::Number(code) => match i32::try_from(code) {
+                Ok(code) => Ok(code),
+                Err(_) => bail!("Exit code {} returned by {} is out of range!", code, name),
+            },
+            other => bail!("Entrypoint {} should return a number or nothing, got {}!", name, other),
+        }
+    }
+
+    /// Tests declared by the processed chains, in declaration order
+    pub fn tests(&self) -> &[TestCase] {
+        &self.tests
+    }
+
+    /// Runs every declared test in the scope it was declared in. Each test runs on a clone
+    /// of this Vm, so a failed one does not leave it in the middle of a call, though changes
+    /// made to the shared scopes stay visible to the following tests
+    pub fn run_tests(&mut self) -> TestReport {
+        let results = self.tests.clone().into_iter().map(|test| {
+            let mut vm = self.clone();
+            vm.processing = true;
+            vm.call_depth = 0;
+            vm.member_of.clear();
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| _run_test(&mut vm, &test)));
+            TestResult {
+                name: test.name,
+                failure: outcome.err().map(|err| _panic_message(&*err)),
+            }
+        }).collect();
+        TestReport { results }
+    }
+
+    /// Removes a host function by its full path, or by its name if it was declared in the current
+    /// or global scope. Its handler is released, and every scope stops importing it.
+    ///
+    /// A scope shared with other Vms is replaced by a private copy without the function first,
+    /// and the handler is kept for the Vms that still call it
+    pub fn remove_extern_fn(&mut self, name: &str) -> anyhow::Result<()> {
+        let (scope_name, fn_name) = self.host_fn_location(name)?;
+        let shared = self.is_shared_scope(&scope_name);
+        if shared {
+            self.detach_scope(&scope_name);
+        }
+        if let Some(fnc) = self.scope(&scope_name).remove_static_fn(&fn_name) {
+            if !shared {
+                fnc.unregister();
+            }
+        }
+        for other in self.scopes.keys() {
+            self.scope(other).remove_import(&scope_name, &fn_name);
+        }
+        Ok(())
+    }
+
+    /// Binds another handler to an extern function. The handler pointer stays the same,
+    /// so copies of the function that were already imported call the new handler as well
+    pub fn replace_extern_fn<F>(&mut self, name: &str, fun: F) -> anyhow::Result<()>
+    where
+        F: Fn(Parameters) -> Literal + Sync + Send + 'static,
+    {
+        let (scope_name, fn_name) = self.host_fn_location(name)?;
+        match self.scope(&scope_name).get_static_fn(&fn_name) {
+            Some(StaticFnType::Extern(ext)) if replace_extern_fn(ext.handler(), fun) => Ok(()),
+            _ => bail!("Function {} is not a registered extern function!", name),
+        }
+    }
+
+    /// Removes a scope, along with imports of it in other scopes. Host functions declared
+    /// in the scope are released and structures it owns are dropped, unless the scope
+    /// is still shared with other Vms
+    pub fn remove_scope(&mut self, name: &str) -> anyhow::Result<ContainingScope> {
+        if name == "global" || name == self.current_scope {
+            bail!("Can not remove scope {} while it is in use!", name)
+        }
+        if !self.has_scope(name) {
+            bail!("Could not find scope {}!", name)
+        }
+        let shared = self.is_shared_scope(name);
+        let scope = self.drop_scope(name.to_string());
+        if !shared {
+            for (_, fnc) in scope.static_fns() {
+                fnc.unregister();
+            }
+            self.in_run(|vm| _drop_owned(vm, &scope, &Default::default(), &Literal::Void));
+        }
+        for other in self.scopes.keys() {
+            self.scope(other).remove_imports(name);
+        }
+        Ok(scope)
+    }
+
+    /// Names of the static functions in a scope, suggested when a call can not be resolved
+    fn fn_names(&self, scope: &str) -> Vec<String> {
+        self.scope(scope).static_fns().map(|(name, _)| name.to_owned()).collect()
+    }
+
+    /// Scope and name of a host function
+    fn host_fn_location(&self, name: &str) -> anyhow::Result<(String, String)> {
+        let (scope_name, fn_name) = match name.rsplit_once("::") {
+            Some((scope_name, fn_name)) => (scope_name.to_string(), fn_name.to_string()),
+            None if self.scope(&self.current_scope).get_static_fn(name).is_some() => {
+                (self.current_scope.clone(), name.to_string())
+            }
+            None => ("global".to_string(), name.to_string()),
+        };
+        if !self.has_scope(&scope_name) {
+            bail!("Could not find scope {}!", scope_name)
+        }
+        match self.scope(&scope_name).get_static_fn(&fn_name) {
+            Some(fnc) if fnc.handler().is_some() => Ok((scope_name, fn_name)),
+            Some(_) => bail!("Function {} is not a host function!", name),
+            None => bail!("Could not find function {} in scope {}!", fn_name, scope_name),
+        }
+    }
+
+    /// Routes output and input of the `std::io` functions through `io` instead of the
+    /// standard streams. The backend is shared by all clones of this Vm
+    pub fn set_io(&mut self, io: SharedIo) {
+        self.io = io;
+    }
+
+    /// Passes messages of `std::log` to the `logger`, so they end up wherever the host
+    /// collects its own logs. Without a logger they are written to the error output
+    /// of the [`IoBackend`](crate::io::IoBackend)
+    pub fn set_logger(&mut self, logger: Box<Logger>) {
+        self.logger = LoggerSlot(Some(Arc::from(logger)));
+    }
+
+    /// Pulls tokens from `source` whenever the queue runs empty while processing, instead of
+    /// requiring the whole program to be loaded up front. Chains of functions and blocks are
+    /// processed on their own, so only the top level reads from the source
+    pub fn set_source(&mut self, source: impl TokenSource + 'static) {
+        self.source = SourceSlot::new(source);
+    }
+
+    /// Stops pulling tokens from the source set with [`Vm::set_source`]
+    pub fn clear_source(&mut self) {
+        self.source = SourceSlot::default();
+    }
+
+    /// Queues tokens like [`VisitorCore::load_chain`], without collecting them into a chain first
+    pub fn feed(&mut self, tokens: &mut impl Iterator<Item = Token>) {
+        for tk in tokens {
+            self.add_token(tk);
+        }
+    }
+
+    /// Queues the next token of the source, if the queue is empty
+    fn pull_token(&mut self) {
+        if self.tks.is_empty() {
+            if let Some(tk) = self.source.pull() {
+                self.mark_referenced(&tk);
+                self.add_token(tk);
+            }
+        }
+    }
+
+    /// Makes runs reproducible: `std::rand` draws from a PRNG seeded with `seed`, `std::time`
+    /// reads a virtual clock starting at the unix epoch, which only moves when sleeping or when
+    /// advanced through [`Vm::deterministic`], and temporary scopes are numbered sequentially.
+    /// The state is shared by all clones of this Vm
+    pub fn set_deterministic(&mut self, seed: u64) {
+        self.entropy = Entropy::deterministic(seed);
+    }
+
+    /// State of the deterministic mode, if it is enabled
+    pub fn deterministic(&self) -> Option<&Deterministic> {
+        self.entropy.state()
+    }
+
+    /// Starts tracing visited tokens, stack operations, scope moves and function calls
+    pub fn enable_trace(&mut self, config: TraceConfig) {
+        self.tracer = Some(Tracer::new(config));
+    }
+
+    /// Stops tracing, returning all the buffered entries
+    pub fn disable_trace(&mut self) -> Vec<TraceEntry> {
+        self.tracer
+            .take()
+            .map(|tracer| tracer.entries().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Entries collected so far by a buffered trace
+    pub fn trace_entries(&self) -> &[TraceEntry] {
+        self.tracer.as_ref().map(|tracer| tracer.entries()).unwrap_or(&[])
+    }
+
+    fn trace(&mut self, event: TraceEvent) {
+        if let Some(tracer) = &mut self.tracer {
+            let depth = self
+                .scope_types
+                .iter()
+                .filter(|it| matches!(it, Scope::StaticFunction | Scope::InstanceFunction))
+                .count();
+            tracer.record(depth, self.span, event);
+        }
+    }
+
+    fn visit_token(&mut self, tk: &mut Token) {
+        let interceptors = self.interceptors.0.clone();
+        for interceptor in interceptors {
+            match interceptor(self, tk) {
+                InterceptAction::Continue => {}
+                InterceptAction::Skip => return,
+                InterceptAction::Replace(other) => *tk = other,
+            }
+        }
+        self.trace(TraceEvent::Token(tk.to_owned()));
+        self.visit(tk)
+    }
+
+    /// Marks the member that is being declared as private, if it was preceded by `priv`
+    fn take_private(&mut self, member: &str) {
+        if self.attrs.remove("private").is_none() {
+            return;
+        }
+        if let Some(current) = self.current_struct_name() {
+            if let Some(template) = self.structs.get_mut(&current) {
+                template.set_private(member);
+            }
+        }
+    }
+
+    /// Runs `run` as a part of the current run, or as a new one if nothing is processed.
+    /// Function bodies are processed as nested chains, which must not reset the call depth
+    /// of the run they belong to
+    fn in_run<T>(&mut self, run: impl FnOnce(&mut Self) -> T) -> T {
+        let outermost = !self.processing;
+        if outermost {
+            self.processing = true;
+            self.call_depth = 0;
+            self.member_of.clear();
+        }
+        let output = run(self);
+        if outermost {
+            self.processing = false;
+        }
+        output
+    }
+
+    fn enter_call(&mut self, name: &str) {
+        if self.call_depth >= self.max_call_depth {
+            self.emit_error(&format!(
+                "Maximum call depth of {} exceeded while calling {}!",
+                self.max_call_depth, name
+            ))
+        }
+        self.call_depth += 1;
     }
 
     pub fn emit_error(&self, message: &str) -> ! {
-        println!("{} {}", "[Error]".red(), message.bright_red());
+        match self.span {
+            Some(span) => println!("{} {} {}", "[Error]".red(), format!("at {}:", span).red(), message.bright_red()),
+            None => println!("{} {}", "[Error]".red(), message.bright_red()),
+        }
         panic!("Failure")
     }
 
-    pub fn merged_scope(&self) -> Arc<Mutex<ContainingScope>> {
-        let current = self.scopes.get(&self.current_scope).unwrap().clone();
-        let imports = current.lock().unwrap().imports().clone();
+    /// Current scope with all of its imports resolved.
+    ///
+    /// Names declared in the current scope shadow the imported ones.
+    pub fn merged_scope(&self) -> ScopeGuard<'_> {
+        let imports = self.scope(&self.current_scope).imports();
         for (scope, values) in imports {
-            let scope = self.scopes.get(&scope).unwrap().clone();
             for name in values {
-                let value = scope.lock().unwrap().get_any_value(&name.clone());
+                if self.scope(&self.current_scope).declares(&name) {
+                    continue;
+                }
+                let value = self.scope(&scope).get_any_value(&name.clone());
                 match value {
                     None => {
                         panic!("Tried to import non-existent value {:?}!", name)
                     }
-                    Some(scoped) => match scoped {
-                        ScopedValue::Constant(v) => current.lock().unwrap().add_const(&name, v),
-                        ScopedValue::Mutable(v) => current.lock().unwrap().add_var(&name, v),
-                        ScopedValue::StaticFn(v) => {
-                            match v {
-                                StaticFnType::Standard(std) => current.lock().unwrap().add_prebuilt_static_fn(&name, std),
-                                StaticFnType::Extern(ext) => current.lock().unwrap().add_prebuilt_extern_fn(&name, ext)
-                            }
-                        },
-                    },
+                    Some(scoped) => self.scope(&self.current_scope).add_imported(&name, scoped),
                 }
             }
         }
-        current
+        self.scope(&self.current_scope)
+    }
+
+    /// Locks or borrows the scope with provided name
+    fn scope(&self, name: &str) -> ScopeGuard<'_> {
+        match &self.scopes[name] {
+            ScopeSlot::Shared(scope) => ScopeGuard::Locked(scope.lock().unwrap()),
+            ScopeSlot::Arena(index) => ScopeGuard::Borrowed(self.arena.get(*index)),
+        }
+    }
+
+    /// Replaces a scope shared with other Vms by a copy of it, so changes to it stay in this Vm
+    fn detach_scope(&mut self, name: &str) {
+        if let Some(ScopeSlot::Shared(scope)) = self.scopes.get(name) {
+            let copy = scope.lock().unwrap().clone();
+            self.scopes.insert(name.to_string(), ScopeSlot::Shared(Arc::new(Mutex::new(copy))));
+        }
+    }
+
+    /// Handle of the scope if it is kept in shared storage, `None` for single threaded scopes
+    pub fn shared_scope(&self, name: &str) -> Option<Arc<Mutex<ContainingScope>>> {
+        match self.scopes.get(name)? {
+            ScopeSlot::Shared(scope) => Some(scope.clone()),
+            ScopeSlot::Arena(_) => None,
+        }
     }
 }
 
 impl TokenProvider for Vm {
     fn next_token(&mut self) -> anyhow::Result<Token> {
-        Ok(self.tks.pop_back().unwrap())
+        Ok(self.take_token(true).unwrap())
     }
 
     fn peek_token(&mut self) -> anyhow::Result<Token> {
+        self.pull_token();
         let tks = self.tks.clone();
         let mut iter = tks.iter().rev().peekable();
         let peek = iter.peek();
@@ -202,73 +1160,128 @@ impl TokenProvider for Vm {
     }
 
     fn add_token(&mut self, tk: Token) {
-        self.tks.push_front(tk)
+        self.tks.push_front(tk);
+        self.spans.push_front(None);
     }
 
     fn insert_token(&mut self, tk: Token, at: usize) {
         self.tks.insert(at, tk);
+        self.spans.insert(at, None);
     }
 }
 
 impl ScopeProvider for Vm {
     fn add_std_feature(&mut self, feature: StdFeature) {
-        feature.include(self)
+        if self.features.insert(feature) {
+            feature.include(self)
+        }
     }
 
     fn resolve_var(&self, name: &str) -> anyhow::Result<Literal> {
         let value = self
-            .scopes
-            .get(&self.current_scope)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .get_var(name);
+            .scope(&self.current_scope)
+            .get_var(name)
+            .or_else(|| self.statics.get(name));
         value.to_result()
     }
 
     fn resolve_const(&self, name: &str) -> anyhow::Result<Literal> {
+        let value = self.merged_scope().get_const(name).to_result();
+        if value.is_ok() {
+            self.mark_used(name);
+        }
+        value
+    }
+
+    fn visible_values(&self) -> BTreeMap<String, Literal> {
+        // values of the scope shadow statics, like in `resolve_var`
+        let mut values = self.statics.values();
+        values.extend(self.merged_scope().values());
+        values
+    }
+
+    fn visible_fns(&self) -> BTreeMap<String, StaticFnType> {
         self.merged_scope()
-            .lock()
-            .unwrap()
-            .get_const(name)
-            .to_result()
+            .static_fns()
+            .map(|(name, fnc)| (name.to_owned(), fnc.to_owned()))
+            .collect()
+    }
+
+    fn stack_values(&self) -> Vec<Literal> {
+        self.lit_stack.clone()
+    }
+
+    fn current_scope_name(&self) -> String {
+        self.current_scope.clone()
+    }
+
+    fn warn(&mut self, code: WarningCode, message: String) {
+        let level = self
+            .warning_levels
+            .get(&code)
+            .copied()
+            .unwrap_or(self.warning_level);
+        match level {
+            WarningLevel::Allow => {}
+            WarningLevel::Warn => self.warnings.push(Warning {
+                code,
+                span: self.span,
+                message,
+            }),
+            WarningLevel::Deny => self.emit_error(&format!("[{}] {}", code, message)),
+        }
     }
 
     fn import(&mut self, from: String, name: String) {
-        self.scopes
-            .get(&self.current_scope)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .import(&from, &name);
+        if from.starts_with("std") && !self.scopes.contains_key(&from) {
+            self.warn(
+                WarningCode::UndeclaredFeature,
+                format!("Imported {}::{} from a std feature that was not added", from, name),
+            );
+        }
+        if self.libraries.contains(&from) && !self.scope(&from).is_exported(&name) {
+            self.emit_error(&format!("{} is not exported from library {}!", name, from))
+        }
+        self.scope(&self.current_scope).import(&from, &name);
     }
 
     fn export(&mut self, name: String) {
-        self.scopes
-            .get(&self.current_scope)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .export(&name);
+        self.scope(&self.current_scope).export(&name);
     }
 
 
     fn add_var(&mut self, name: String, var: Literal) {
-        self.scopes
-            .get(&self.current_scope)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .add_var(&name, var);
+        // locals shadow statics of the same name
+        let is_local = self.scope(&self.current_scope).get_var(&name).is_some();
+        if !is_local && self.statics.contains(&name) {
+            self.statics.set(&name, var);
+            return;
+        }
+        // checked before locking the scope, so a type error does not poison it
+        let ty = self.scope(&self.current_scope).var_type(&name).map(str::to_owned);
+        let var = match ty {
+            Some(ty) => _typed_value(&name, &ty, var),
+            None => var,
+        };
+        self.scope(&self.current_scope).add_var(&name, var);
+    }
+
+    fn declare_var(&mut self, name: String, var: Literal, ty: Option<String>) {
+        let var = match &ty {
+            Some(ty) => _typed_value(&name, ty, var),
+            None => var,
+        };
+        self.take_private(&name);
+        self.scope(&self.current_scope).declare_var(&name, var, ty);
     }
 
     fn add_const(&mut self, name: String, var: Literal) {
-        self.scopes
-            .get(&self.current_scope)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .add_const(&name, var)
+        self.take_private(&name);
+        self.scope(&self.current_scope).add_const(&name, var)
+    }
+
+    fn declare_static(&mut self, name: String, var: Literal, ty: Option<String>) {
+        self.statics.declare(&name, var, ty);
     }
 
     fn add_static_fn(
@@ -278,21 +1291,48 @@ impl ScopeProvider for Vm {
         param_names: Vec<String>,
         tks: TokenChain,
     ) {
-        self.scopes
-            .get(&self.current_scope)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .add_static_fn(&name, output_ty, param_names, tks);
+        self.take_private(&name);
+        let meta = mem::take(&mut self.attrs);
+        self.scope(&self.current_scope).add_prebuilt_static_fn(&name, StaticFn::new(output_ty, param_names, tks).with_meta(meta));
     }
 
     fn add_extern_fn(&mut self, name: String, output_ty: String, param_names: Vec<String>, ptr: usize) {
-        self.scopes
-            .get(&self.current_scope)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .add_extern_fn(&name, output_ty, param_names, ptr);
+        self.scope(&self.current_scope).add_extern_fn(&name, output_ty, param_names, ptr);
+    }
+
+    fn add_typed_extern_fn(&mut self, name: String, output_ty: String, param_names: Vec<String>, param_types: Vec<String>, ptr: usize) {
+        self.scope(&self.current_scope).add_typed_extern_fn(&name, output_ty, param_names, param_types, ptr);
+    }
+
+    fn add_attr(&mut self, name: String, value: Literal) {
+        self.attrs.insert(name, value);
+    }
+
+    fn io(&self) -> SharedIo {
+        self.io.clone()
+    }
+
+    fn handles(&self) -> Handles {
+        self.handles.clone()
+    }
+
+    fn entropy(&self) -> Entropy {
+        self.entropy.clone()
+    }
+
+    fn add_test(&mut self, name: String, chain: TokenChain) {
+        self.tests.push(TestCase {
+            name,
+            scope: self.current_scope.clone(),
+            chain,
+        });
+    }
+
+    fn log(&self, level: Level, message: &str) {
+        match &self.logger.0 {
+            Some(logger) => logger(level, message),
+            None => self.io.write_err(&format!("[{}] {}\n", level, message)),
+        }
     }
 
     fn current_struct_name(&self) -> Option<String> {
@@ -303,39 +1343,180 @@ impl ScopeProvider for Vm {
         self.struct_names.push_front(name);
     }
 
+    fn pop_struct_name(&mut self) -> Option<String> {
+        self.struct_names.pop_front()
+    }
+
+    fn add_struct(&mut self, template: StructureTemplate) {
+        let meta = mem::take(&mut self.attrs);
+        self.structs.insert(template.name(), template.with_meta(meta));
+    }
+
+    fn resolve_type(&self, name: &str) -> anyhow::Result<StructureTemplate> {
+        match self.structs.get(name) {
+            Some(template) => Ok(template.to_owned()),
+            None => bail!("Could not find structure {}!{}", name, did_you_mean(name, self.structs.keys())),
+        }
+    }
+
+    fn add_enum(&mut self, template: EnumTemplate) {
+        self.enums.insert(template.name(), template);
+    }
+
+    fn check_access(&self, structure: &str, member: &str) -> anyhow::Result<()> {
+        let private = self
+            .structs
+            .get(structure)
+            .map(|it| it.is_private(member))
+            .unwrap_or(false);
+        let inside = matches!(self.member_of.last(), Some(Some(current)) if current == structure);
+        if private && !inside {
+            bail!("{} of structure {} is private!", member, structure)
+        }
+        Ok(())
+    }
+
+    fn add_struct_init(&mut self, chain: TokenChain) {
+        let current = self
+            .current_struct_name()
+            .expect("Initializer blocks can only be declared inside a struct!");
+        self.struct_inits.entry(current).or_default().push(chain);
+    }
+
+    fn init_struct(&mut self, name: String) {
+        let inits = self.struct_inits.remove(&name).unwrap_or_default();
+        if inits.is_empty() {
+            return;
+        }
+        // initializers can call imported functions, like static functions of the structure
+        for (from, imports) in self.scope("global").imports() {
+            for import in imports {
+                self.scope(&self.current_scope).import(&from, &import);
+            }
+        }
+        self.push_scope_level(Scope::StaticFunction);
+        self.member_of.push(Some(name));
+        for mut chain in inits {
+            self.process_isolated(&mut chain);
+        }
+        self.member_of.pop();
+        self.pop_scope_level();
+    }
+
+    fn resolve_enum(&self, name: &str) -> anyhow::Result<EnumTemplate> {
+        match self.enums.get(name) {
+            Some(template) => Ok(template.to_owned()),
+            None => bail!("Could not find enum {}!", name),
+        }
+    }
+
+    fn add_inst_var(&mut self, name: String, ty: String, default: Option<Literal>) {
+        let current = self
+            .current_struct_name()
+            .expect("Instance fields can only be declared inside a struct!");
+        self.take_private(&name);
+        self.structs
+            .get_mut(&current)
+            .unwrap()
+            .add_inst_var(&name, ty, default);
+    }
+
+    fn add_inst_fn(
+        &mut self,
+        name: String,
+        output_ty: String,
+        param_names: Vec<String>,
+        tks: TokenChain,
+    ) {
+        let current = self
+            .current_struct_name()
+            .expect("Instance functions can only be declared inside a struct!");
+        // `Self` stands for the structure itself, so functions can return their modified `this`
+        let output_ty = if output_ty == "Self" { current.clone() } else { output_ty };
+        self.take_private(&name);
+        let meta = mem::take(&mut self.attrs);
+        self.structs
+            .get_mut(&current)
+            .unwrap()
+            .add_inst_fn(&name, output_ty, param_names, tks, meta);
+    }
+
     fn call_static_fn(&mut self, name: String, params: TokenChain) -> Literal {
         if self.scope_level() == Scope::Struct {
             self.emit_error("Can not call functions inside a raw struct scope!")
         }
+        self.trace(TraceEvent::Call(name.clone()));
 
         let mut params = params.clone();
         let params = params
             .iter_mut()
             .map(|it| it.as_lit_advanced(self, "Expected a literal-like!"))
-            .collect();
-        if name.contains(".") {
-            // TODO: table handling
-            return Literal::Void
+            .collect::<anyhow::Result<Vec<Literal>>>()
+            .unwrap_or_else(|err| self.emit_error(&err.to_string()));
+        let fnc = self.resolve_fn(&name).unwrap_or_else(|err| self.emit_error(&err.to_string()));
+        // static functions of structures are called as `Structure.name`
+        let structure = name.rsplit_once('.').map(|(structure, _)| structure.to_string());
+        if let Some((structure, member)) = name.rsplit_once('.') {
+            if let Err(err) = self.check_access(structure, member) {
+                self.emit_error(&err.to_string())
+            }
+        }
+        // visitors driving the Vm can call functions without processing anything
+        self.in_run(|vm| {
+            vm.enter_call(&name);
+            vm.member_of.push(structure);
+            let output = grow_stack(|| fnc.call(params, Some(vm)));
+            vm.member_of.pop();
+            vm.call_depth -= 1;
+            output
+        })
+    }
+
+    fn resolve_fn(&self, name: &str) -> anyhow::Result<StaticFnType> {
+        if name.contains('.') {
+            let (structure, fnc_name) = name.rsplit_once('.').unwrap();
+            if !self.scopes.contains_key(structure) {
+                bail!("Could not find structure {}!{}", structure, did_you_mean(structure, self.structs.keys()))
+            }
+            // the scope stays locked until the end of a match on it
+            let fnc = self.scope(structure).get_static_fn(fnc_name);
+            match fnc {
+                Some(fnc) => Ok(fnc),
+                None => bail!(
+                    "Could not find function {} in structure {}!{}",
+                    fnc_name,
+                    structure,
+                    did_you_mean(fnc_name, self.fn_names(structure))
+                ),
+            }
         } else if name.contains("::") {
-            let (scope, fnc_name) = name.rsplit_once("::").unwrap();
-            let fnc = self
-                .get_scope(scope.to_owned())
-                .lock()
-                .unwrap()
-                .get_static_fn(&fnc_name)
-                .unwrap();
-            fnc.call(params, Some(self))
+            let (scope_name, fnc_name) = name.rsplit_once("::").unwrap();
+            if !self.scopes.contains_key(scope_name) {
+                bail!("Could not find scope {}!{}", scope_name, did_you_mean(scope_name, self.scopes.keys()))
+            }
+            let fnc = self.scope(scope_name).get_static_fn(fnc_name);
+            match fnc {
+                Some(fnc) => Ok(fnc),
+                None => bail!(
+                    "Could not find function {} in scope {}!{}",
+                    fnc_name,
+                    scope_name,
+                    did_you_mean(fnc_name, self.fn_names(scope_name))
+                ),
+            }
         } else {
-            let fnc = self
-                .merged_scope()
-                .lock()
-                .unwrap()
-                .get_static_fn(&name)
-                .expect(&format!(
-                    "Could not find function {} in current scope!",
-                    name
-                ));
-            fnc.call(params, Some(self))
+            let fnc = self.merged_scope().get_static_fn(name);
+            match fnc {
+                Some(fnc) => {
+                    self.mark_used(name);
+                    Ok(fnc)
+                }
+                None => bail!(
+                    "Could not find function {} in current scope!{}",
+                    name,
+                    did_you_mean(name, self.merged_scope().static_fns().map(|(name, _)| name))
+                ),
+            }
         }
     }
 
@@ -343,17 +1524,70 @@ impl ScopeProvider for Vm {
         if self.scope_level() == Scope::Struct {
             self.emit_error("Can not call functions inside a raw struct scope!")
         }
-        let fns = EXTERN_FNS.lock().unwrap();
-        if fns.len() < ptr {
-            panic!("Tried to call an nonexistent ptr-bound external function: 0x{:2x}", ptr)
+        self.trace(TraceEvent::Call(format!("0x{:2x}", ptr)));
+        let fnc = match EXTERN_FNS.lock().unwrap().get(ptr) {
+            Some(fnc) => fnc,
+            None => panic!("Tried to call an nonexistent ptr-bound external function: 0x{:2x}", ptr),
         };
         let mut params = params.clone();
         let params = params
             .iter_mut()
             .map(|it| it.as_lit_advanced(self, "Expected a literal-like!"))
-            .collect();
-        let fnc = &fns[ptr];
-        fnc.call((params, ))
+            .collect::<anyhow::Result<Vec<Literal>>>()
+            .unwrap_or_else(|err| self.emit_error(&err.to_string()));
+        fnc(params)
+    }
+
+    fn call_inst_fn(&mut self, receiver: String, name: String, params: TokenChain) -> Literal {
+        let instance = self.resolve_any_var(&receiver);
+        self._call_inst_fn(Some(&receiver), instance, &name, params)
+    }
+
+    fn call_inst_fn_on(&mut self, instance: Literal, name: String, params: TokenChain) -> Literal {
+        self._call_inst_fn(None, instance, &name, params)
+    }
+}
+
+impl Vm {
+    /// Calls an instance function of `instance`, writing changes to `this` back to `receiver` if there is one
+    fn _call_inst_fn(&mut self, receiver: Option<&str>, instance: Literal, name: &str, params: TokenChain) -> Literal {
+        if self.scope_level() == Scope::Struct {
+            self.emit_error("Can not call functions inside a raw struct scope!")
+        }
+        let instance = match instance {
+            Literal::Struct(instance) => *instance,
+            other => self.emit_error(&format!(
+                "Tried to call instance function {} on non-struct value {}!",
+                name, other
+            )),
+        };
+        let call_name = format!("{}.{}", receiver.map(str::to_owned).unwrap_or_else(|| instance.type_name()), name);
+        self.trace(TraceEvent::Call(call_name.clone()));
+
+        let template = self.resolve_type(&instance.type_name()).unwrap();
+        if let Err(err) = self.check_access(&template.name(), name) {
+            self.emit_error(&err.to_string())
+        }
+        let fnc = template.get_inst_fn(name).unwrap_or_else(|| panic!(
+            "Could not find instance function {} in structure {}!",
+            name,
+            template.name()
+        ));
+
+        let mut params = params.clone();
+        let params = params
+            .iter_mut()
+            .map(|it| it.as_lit_advanced(self, "Expected a literal-like!"))
+            .collect::<anyhow::Result<Vec<Literal>>>()
+            .unwrap_or_else(|err| self.emit_error(&err.to_string()));
+        self.in_run(|vm| {
+            vm.enter_call(&call_name);
+            vm.member_of.push(Some(template.name()));
+            let output = grow_stack(|| fnc.call(receiver, instance, params, vm));
+            vm.member_of.pop();
+            vm.call_depth -= 1;
+            output
+        })
     }
 }
 
@@ -371,22 +1605,35 @@ impl GlobalScope for Vm {
     }
 
     fn push_scope(&mut self, name: String, scope: ContainingScope) {
-        self.scopes.insert(name, Arc::new(Mutex::new(scope)));
+        let slot = match self.scope_mode {
+            ScopeMode::Shared => ScopeSlot::Shared(Arc::new(Mutex::new(scope))),
+            ScopeMode::SingleThreaded => ScopeSlot::Arena(self.arena.alloc(scope)),
+        };
+        if let Some(ScopeSlot::Arena(index)) = self.scopes.insert(name, slot) {
+            self.arena.free(index);
+        }
     }
 
 }
 
 impl LiteralStack for Vm {
     fn push_stack(&mut self, value: Literal) {
+        self.trace(TraceEvent::Push(value.clone()));
         self.lit_stack.push(value);
     }
 
-    fn pop_stack(&mut self) -> Literal {
-        self.lit_stack.pop().unwrap()
+    fn pop_stack(&mut self) -> anyhow::Result<Literal> {
+        let value = match self.lit_stack.pop() {
+            Some(value) => value,
+            None => bail!("Literal stack underflow, tried to pop a value from empty stack!"),
+        };
+        self.trace(TraceEvent::Pop(value.clone()));
+        Ok(value)
     }
 
 
     fn move_scope(&mut self, name: String) {
+        self.trace(TraceEvent::MoveScope(name.clone()));
         self.current_scope = name;
     }
 
@@ -394,12 +1641,28 @@ impl LiteralStack for Vm {
         self.current_scope.clone()
     }
 
-    fn drop_scope(&mut self, name: String) -> Arc<Mutex<ContainingScope>> {
-        self.scopes.remove(&name).unwrap()
+    fn drop_scope(&mut self, name: String) -> ContainingScope {
+        match self.scopes.remove(&name).unwrap() {
+            ScopeSlot::Shared(scope) => match Arc::try_unwrap(scope) {
+                Ok(scope) => scope.into_inner().unwrap(),
+                // the scope is still referenced by other Vms
+                Err(scope) => scope.lock().unwrap().clone(),
+            },
+            ScopeSlot::Arena(index) => self.arena.free(index),
+        }
+    }
+
+    fn get_scope(&self, name: String) -> ScopeGuard<'_> {
+        self.scope(&name)
+    }
+
+    fn has_scope(&self, name: &str) -> bool {
+        self.scopes.contains_key(name)
     }
 
-    fn get_scope(&self, name: String) -> &Arc<Mutex<ContainingScope>> {
-        self.scopes.get(&name).unwrap()
+    fn is_shared_scope(&self, name: &str) -> bool {
+        // one reference is held by this Vm, and another one by the handle itself
+        self.shared_scope(name).map(|it| Arc::strong_count(&it) > 2).unwrap_or(false)
     }
 
 }
@@ -409,27 +1672,48 @@ impl Visitor for Vm {
     where
         V: Visitable,
     {
-        visitable
-            .visit(self)
-            .expect("Found errors while visiting token!")
+        if let Err(err) = visitable.visit(self) {
+            match self.span {
+                Some(span) => panic!("Found errors while visiting token at {}: {:?}", span, err),
+                None => panic!("Found errors while visiting token!: {:?}", err),
+            }
+        }
     }
+}
 
+impl VisitorCore for Vm {
 
     fn process(&mut self) {
-        while let Some(tk) = &mut self.tks.pop_back() {
-            self.visit(tk)
+        let outermost = !self.processing;
+        let imports = if outermost {
+            self.processing = true;
+            // a previous run could have been interrupted in the middle of a call
+            self.call_depth = 0;
+            self.member_of.clear();
+            self.tks.iter().for_each(|tk| self.mark_referenced(tk));
+            self.scope("global").imports()
+        } else {
+            HashMap::new()
+        };
+        while let Some(tk) = &mut self.take_token(true) {
+            self.visit_token(tk)
+        }
+        if outermost {
+            self.processing = false;
+            self.lint_imports(&imports);
         }
     }
 
     fn process_until(&mut self, until: usize) {
         let mut amount = 0;
         let actual_amount = if until <= 0 { until } else { until - 1 };
-        while let Some(tk) = &mut self.tks.pop_front() {
+        while let Some(tk) = &mut self.take_token(false) {
             if amount > actual_amount {
                 self.tks.push_front(tk.to_owned());
+                self.spans.push_front(self.span);
                 return;
             }
-            self.visit(tk);
+            self.visit_token(tk);
             amount += 1;
         }
     }
@@ -438,6 +1722,22 @@ impl Visitor for Vm {
         let t = self.tks.range(from..to);
         let mut another = Clone::clone(self);
         another.tks = VecDeque::from(t.map(|it| it.to_owned()).collect::<Vec<Token>>());
+        another.spans = self.spans.range(from..to).copied().collect();
+        another.source = SourceSlot::default();
         another.process();
     }
+
+    fn process_isolated(&mut self, chain: &mut TokenChain) {
+        let cached = mem::take(&mut self.tks);
+        let cached_spans = mem::take(&mut self.spans);
+        let span = self.span;
+        // the chain ends where it ends, instead of continuing with the source
+        let source = mem::take(&mut self.source);
+        self.load_chain(chain);
+        self.process();
+        self.tks = cached;
+        self.spans = cached_spans;
+        self.span = span;
+        self.source = source;
+    }
 }
@@ -1,16 +1,124 @@
-use std::io::{Cursor, Read};
+//! Binary format of compiled gale programs.
+//!
+//! A compiled file starts with a [`MAGIC`] and a big-endian `u16` [`FORMAT_VERSION`],
+//! written by [`write_header`], followed by the program container described in [`crate::program`].
+//! Every value is then encoded by its [`Transmute`] impl:
+//!
+//! * integers and floats are big-endian, `bool` is a single `0x00` or `0x01` byte
+//! * `char` is its code point as a `u32`
+//! * `String` is a `u32` byte length followed by UTF-8 bytes
+//! * `Vec<T>` is a `u32` element count followed by the elements
+//! * `HashMap<String, T>` is a `u32` entry count followed by key-value pairs, sorted by key
+//! * `Option<T>` is a `bool` presence flag followed by the value if it is present
+//! * enums (tokens, literals, expressions, keywords, operators) are a `u8` tag followed
+//!   by the fields of the variant in declaration order
+//!
+//! [`Transmute::size`] is always the exact amount of bytes [`Transmute::write_to`] produces.
+//! Values are read and written sequentially, so they can be streamed from and to files
+//! or sockets without buffering the whole program in memory.
+//!
+//! Programs are always written in the current [`FORMAT_VERSION`], while programs of older
+//! versions down to [`MIN_FORMAT_VERSION`] are still read. The changes between versions
+//! are listed in [`crate::spec`].
+
+use anyhow::bail;
+use std::io::{Cursor, Read, Write};
 use std::mem;
 
+/// Bytes every compiled gale file starts with
+pub const MAGIC: [u8; 4] = *b"GALB";
+/// Version of the binary format, bumped on every incompatible change
+pub const FORMAT_VERSION: u16 = 7;
+/// Oldest version of the binary format that can still be read
+pub const MIN_FORMAT_VERSION: u16 = 1;
+/// Size of the header written by [`write_header`]
+pub const HEADER_SIZE: usize = MAGIC.len() + 2;
+
+/// Writes the magic and format version, which should precede every compiled chain
+pub fn write_header<W: Write + ?Sized>(buf: &mut W) -> anyhow::Result<()> {
+    _write_header_of(FORMAT_VERSION, buf)
+}
+
+/// Writes the header of a program in `version`, as it was read
+pub(crate) fn _write_header_of<W: Write + ?Sized>(version: u16, buf: &mut W) -> anyhow::Result<()> {
+    buf.write_all(&MAGIC)?;
+    buf.write_all(&version.to_be_bytes())?;
+    Ok(())
+}
+
+/// Reads and validates the header written by [`write_header`], returning the format version,
+/// which is anything from [`MIN_FORMAT_VERSION`] up to [`FORMAT_VERSION`]
+pub fn read_header<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<u16> {
+    let mut magic = [0u8; 4];
+    buf.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        bail!("Not a compiled gale file, invalid magic {:?}!", magic)
+    }
+    let version = u16::read_from(buf)?;
+    if version > FORMAT_VERSION {
+        bail!(
+            "Program uses format version {}, which is newer than the supported {}!",
+            version,
+            FORMAT_VERSION
+        )
+    }
+    if version < MIN_FORMAT_VERSION {
+        bail!(
+            "Unsupported format version {}, the oldest readable one is {}!",
+            version,
+            MIN_FORMAT_VERSION
+        )
+    }
+    Ok(version)
+}
+
+/// Writes the `u32` length prefix of a collection
+pub(crate) fn _write_len<W: Write + ?Sized>(len: usize, buf: &mut W) -> anyhow::Result<()> {
+    match u32::try_from(len) {
+        Ok(mut len) => len.write_to(buf),
+        Err(_) => bail!("Collection of {} elements is too long to be written!", len),
+    }
+}
+
+/// Writes a string in the same way as its [`Transmute`] impl, without needing it to be mutable
+pub(crate) fn _write_str<W: Write + ?Sized>(str: &str, buf: &mut W) -> anyhow::Result<()> {
+    _write_len(str.len(), buf)?;
+    buf.write_all(str.as_bytes())?;
+    Ok(())
+}
+
+/// Most elements preallocated for a collection before reading it
+const MAX_PREALLOC: usize = 4096;
+
+/// Capacity to preallocate for `len` elements. Length may come from corrupted input,
+/// and a stream can not tell how much of it is left, so the capacity is capped.
+pub(crate) fn _bounded_capacity(len: usize) -> usize {
+    len.min(MAX_PREALLOC)
+}
+
 pub trait TransmuteConst {
     fn const_size() -> usize;
 }
 
 pub trait Transmute {
     fn size(&mut self) -> usize;
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()>;
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()>;
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized;
+
+    /// Writes this value into an in-memory buffer
+    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+        self.write_to(buf)
+    }
+
+    /// Reads a value from an in-memory buffer
+    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::read_from(buf)
+    }
 }
 
 macro_rules! _int_alloc_impl {
@@ -27,13 +135,13 @@ macro_rules! _int_alloc_impl {
                     mem::size_of::<$typ>()
                 }
 
-                fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+                fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
                     let bytes: [u8; mem::size_of::<$typ>()] = self.to_be_bytes();
-                    buf.extend_from_slice(&bytes);
+                    buf.write_all(&bytes)?;
                     Ok(())
                 }
 
-                fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self> {
+                fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self> {
                     let mut exact = [0u8; mem::size_of::<$typ>()];
                     buf.read_exact(&mut exact)?;
                     Ok(Self::from_be_bytes(exact))
@@ -71,18 +179,20 @@ impl Transmute for bool {
         1
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        buf.extend_from_slice(&[if *self { 0x01 } else { 0x00 }]);
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        buf.write_all(&[if *self { 0x01 } else { 0x00 }])?;
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        let mut d = [0u8; 1];
-        buf.read_exact(&mut d)?;
-        Ok(d[0] == 0x01)
+        Ok(match u8::read_from(buf)? {
+            0x00 => false,
+            0x01 => true,
+            other => bail!("Invalid bool value {:#04x}!", other),
+        })
     }
 }
 
@@ -91,12 +201,12 @@ impl Transmute for f32 {
         4
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        buf.extend_from_slice(&self.to_be_bytes());
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        buf.write_all(&self.to_be_bytes())?;
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
@@ -111,12 +221,12 @@ impl Transmute for f64 {
         8
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        buf.extend_from_slice(&self.to_be_bytes());
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        buf.write_all(&self.to_be_bytes())?;
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
@@ -128,21 +238,21 @@ impl Transmute for f64 {
 
 impl Transmute for String {
     fn size(&mut self) -> usize {
-        self.len() + 2
+        self.len() + 4
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        let bytes = self.as_bytes();
-        let vec: [u8; 2] = unsafe { mem::transmute(self.len() as u16) };
-        buf.extend_from_slice(&vec);
-        buf.extend_from_slice(&bytes);
-        Ok(())
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        _write_str(self, buf)
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self> {
-        let len = u16::read(buf)?;
-        let slice = &buf.to_owned().into_inner()[1..(len as usize) + 2];
-        Ok(String::from_utf8(slice.to_vec())?)
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self> {
+        let len = u32::read_from(buf)? as usize;
+        let mut bytes = Vec::with_capacity(_bounded_capacity(len));
+        Read::take(&mut *buf, len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            bail!("Expected a string of {} bytes, got {}!", len, bytes.len())
+        }
+        Ok(String::from_utf8(bytes)?)
     }
 }
 
@@ -157,20 +267,19 @@ impl Transmute for char {
         4
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        let mut o = [0u8; 4];
-        self.encode_utf8(&mut o);
-        buf.extend_from_slice(&o);
-        Ok(())
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        (*self as u32).write_to(buf)
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(String::from_utf8(buf.to_owned().into_inner())?
-            .chars()
-            .collect::<Vec<char>>()[0])
+        let code = u32::read_from(buf)?;
+        match char::from_u32(code) {
+            Some(ch) => Ok(ch),
+            None => bail!("Invalid char code {:#x}!", code),
+        }
     }
 }
 
@@ -182,15 +291,15 @@ where
         V::size(self)
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        V::write(self, buf)
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        V::write_to(self, buf)
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(Box::new(V::read(buf)?))
+        Ok(Box::new(V::read_from(buf)?))
     }
 }
 
@@ -199,26 +308,59 @@ where
     V: Transmute,
 {
     fn size(&mut self) -> usize {
-        self.len() + 4
+        4 + self.iter_mut().map(|ele| ele.size()).sum::<usize>()
     }
 
-    fn write(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<()> {
-        (self.len() as u32).write(buf)?;
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        _write_len(self.len(), buf)?;
         for ele in self {
-            ele.write(buf)?;
+            ele.write_to(buf)?;
         }
         Ok(())
     }
 
-    fn read(buf: &mut Cursor<Vec<u8>>) -> anyhow::Result<Self>
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        let len = u32::read(buf)? as usize;
-        let mut vec = Vec::<V>::with_capacity(len);
+        let len = u32::read_from(buf)? as usize;
+        let mut vec = Vec::<V>::with_capacity(_bounded_capacity(len));
         for _ in 0..len {
-            vec.push(V::read(buf)?);
+            vec.push(V::read_from(buf)?);
         }
         Ok(vec)
     }
 }
+
+impl<V> Transmute for Option<V>
+where
+    V: Transmute,
+{
+    fn size(&mut self) -> usize {
+        1 + match self {
+            Some(v) => v.size(),
+            None => 0,
+        }
+    }
+
+    fn write_to<W: Write + ?Sized>(&mut self, buf: &mut W) -> anyhow::Result<()> {
+        match self {
+            Some(v) => {
+                true.write_to(buf)?;
+                v.write_to(buf)
+            }
+            None => false.write_to(buf),
+        }
+    }
+
+    fn read_from<R: Read + ?Sized>(buf: &mut R) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(if bool::read_from(buf)? {
+            Some(V::read_from(buf)?)
+        } else {
+            None
+        })
+    }
+}
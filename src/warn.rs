@@ -0,0 +1,52 @@
+use crate::span::Span;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum WarningCode {
+    UndeclaredFeature,
+    ShadowedVariable,
+    UnusedImport,
+    ImplicitConversion,
+}
+
+impl WarningCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WarningCode::UndeclaredFeature => "W0001",
+            WarningCode::ShadowedVariable => "W0002",
+            WarningCode::UnusedImport => "W0003",
+            WarningCode::ImplicitConversion => "W0004",
+        }
+    }
+}
+
+impl Display for WarningCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// How warnings are handled, `Deny` promotes them to errors
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Default)]
+pub enum WarningLevel {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "warning[{}] at {}: {}", self.code, span, self.message),
+            None => write!(f, "warning[{}]: {}", self.code, self.message),
+        }
+    }
+}